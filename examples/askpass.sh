@@ -0,0 +1,32 @@
+#!/bin/sh
+# Askpass helper for cargo-credential-bitwarden, for use with `--askpass` or
+# CARGO_CREDENTIAL_ASKPASS. Shows the prompt given as the first argument in a
+# dialog and prints the answer. Exits non-zero when the dialog is cancelled.
+prompt=$1
+
+case "$prompt" in
+*"[y/N]"*)
+    # Questions are answered with y or n.
+    if command -v zenity >/dev/null; then
+        zenity --question --text="$prompt" && echo y
+    elif command -v kdialog >/dev/null; then
+        kdialog --yesno "$prompt" && echo y
+    elif command -v osascript >/dev/null; then
+        osascript -e "display dialog \"$prompt\" buttons {\"No\", \"Yes\"} default button \"No\"" \
+            -e 'if button returned of result is "Yes" then return "y"'
+    else
+        exit 1
+    fi
+    ;;
+*)
+    if command -v zenity >/dev/null; then
+        zenity --password --title="$prompt"
+    elif command -v kdialog >/dev/null; then
+        kdialog --password "$prompt"
+    elif command -v osascript >/dev/null; then
+        osascript -e "text returned of (display dialog \"$prompt\" default answer \"\" with hidden answer)"
+    else
+        exit 1
+    fi
+    ;;
+esac
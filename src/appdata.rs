@@ -0,0 +1,199 @@
+//! Where the Bitwarden CLI keeps its data.
+//!
+//! `bw` stores its login state below the home directory. Minimal containers
+//! often run cargo without `HOME`, or with a read-only one, and `bw` then
+//! fails with `ENOENT` errors from deep inside Node.js. In that case `bw` is
+//! pointed at a directory of our own through `BITWARDENCLI_APPDATA_DIR`.
+//!
+//! `--appdata-dir` gives `bw` a profile of its own in the same way, with its
+//! own login, e.g. for a second account.
+
+use crate::{cargo_config, state};
+use cfg_if::cfg_if;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Environment variable `bw` takes its data directory from.
+pub const ENV: &str = "BITWARDENCLI_APPDATA_DIR";
+
+/// The data directory to give every `bw` started by this process, or `None`
+/// if `bw` finds a usable one itself.
+pub fn resolve() -> Result<Option<PathBuf>, String> {
+    static RESOLVED: OnceLock<Result<Option<PathBuf>, String>> = OnceLock::new();
+    RESOLVED.get_or_init(resolve_uncached).clone()
+}
+
+fn resolve_uncached() -> Result<Option<PathBuf>, String> {
+    resolve_with(&|name| std::env::var_os(name))
+}
+
+/// [`resolve`] for the environment variables `var` gives.
+fn resolve_with(var: &dyn Fn(&str) -> Option<OsString>) -> Result<Option<PathBuf>, String> {
+    if env_path(var, ENV).is_some() {
+        return Ok(None);
+    }
+
+    let reason = match default_dir(var) {
+        Some(dir) if is_writable(&dir) => return Ok(None),
+        Some(dir) => format!("`{}` is not writable", dir.display()),
+        None => NO_HOME.to_string(),
+    };
+
+    let candidates = [
+        state::state_dir_with(var),
+        cargo_config::cargo_home_with(var).map(|home| home.join("cargo-credential-bitwarden")),
+    ];
+    let mut tried = Vec::new();
+    for dir in candidates.into_iter().flatten() {
+        let dir = dir.join("bw-data");
+        let existed = dir.is_dir();
+        if create_private_dir(&dir) && is_writable(&dir) {
+            if !existed {
+                eprintln!(
+                    "note: {}, so the Bitwarden CLI keeps its data in `{}`; \
+                     run `bw login` with {} set to it",
+                    reason,
+                    dir.display(),
+                    ENV
+                );
+            }
+            return Ok(Some(dir));
+        }
+        tried.push(format!("`{}`", dir.display()));
+    }
+
+    if tried.is_empty() {
+        Err(no_data_dir(&reason))
+    } else {
+        Err(no_data_dir(&format!(
+            "{}, and {} is not writable either",
+            reason,
+            tried.join(" or ")
+        )))
+    }
+}
+
+/// `dir`, given with `--appdata-dir`, created if missing.
+pub fn prepare(dir: &Path) -> Result<PathBuf, String> {
+    if !dir.is_dir() && !create_private_dir(dir) {
+        return Err(format!(
+            "failed to create the `--appdata-dir` `{}`",
+            dir.display()
+        ));
+    }
+    Ok(dir.to_path_buf())
+}
+
+fn no_data_dir(reason: &str) -> String {
+    format!(
+        "the Bitwarden CLI has nowhere to keep its data: {}; \
+         mount a writable home directory, or set {} to a writable directory",
+        reason, ENV
+    )
+}
+
+fn env_path(var: &dyn Fn(&str) -> Option<OsString>, name: &str) -> Option<PathBuf> {
+    var(name).filter(|v| !v.is_empty()).map(PathBuf::from)
+}
+
+cfg_if! {
+    if #[cfg(target_os = "windows")] {
+        const NO_HOME: &str = "APPDATA is not set";
+    } else if #[cfg(target_os = "macos")] {
+        const NO_HOME: &str = "HOME is not set";
+    } else {
+        const NO_HOME: &str = "neither XDG_CONFIG_HOME nor HOME is set";
+    }
+}
+
+/// The directory `bw` uses when [`ENV`] isn't set.
+fn default_dir(var: &dyn Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    cfg_if! {
+        if #[cfg(target_os = "windows")] {
+            let base = env_path(var, "APPDATA")?;
+        } else if #[cfg(target_os = "macos")] {
+            let base = env_path(var, "HOME")?.join("Library").join("Application Support");
+        } else {
+            let base = env_path(var, "XDG_CONFIG_HOME")
+                .or_else(|| env_path(var, "HOME").map(|home| home.join(".config")))?;
+        }
+    }
+    Some(base.join("Bitwarden CLI"))
+}
+
+/// Whether files can be created in `dir`, creating it first like `bw` would.
+fn is_writable(dir: &Path) -> bool {
+    if fs::create_dir_all(dir).is_err() {
+        return false;
+    }
+    let probe = dir.join(format!(
+        ".cargo-credential-bitwarden-{}",
+        std::process::id()
+    ));
+    match fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Create `dir` readable only by the current user, as it holds the
+/// encrypted vault.
+fn create_private_dir(dir: &Path) -> bool {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake;
+
+    /// [`resolve_with`] for an environment with only `vars`.
+    fn resolve_in(vars: &[(&str, &Path)]) -> Result<Option<PathBuf>, String> {
+        resolve_with(&|name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.as_os_str().to_os_string())
+        })
+    }
+
+    #[test]
+    fn without_home() {
+        let dir = fake::temp_dir("appdata-no-home");
+
+        let e = resolve_in(&[]).unwrap_err();
+        assert!(e.contains(NO_HOME), "{}", e);
+        assert!(e.contains(ENV), "{}", e);
+
+        let chosen = resolve_in(&[("CARGO_HOME", &dir)]).unwrap().unwrap();
+        assert_eq!(
+            chosen,
+            dir.join("cargo-credential-bitwarden").join("bw-data")
+        );
+        assert!(chosen.is_dir());
+        // The same one next time.
+        assert_eq!(resolve_in(&[("CARGO_HOME", &dir)]).unwrap(), Some(chosen));
+
+        // `bw` is pointed somewhere already.
+        assert_eq!(resolve_in(&[(ENV, &dir)]).unwrap(), None);
+    }
+
+    #[test]
+    fn with_home() {
+        let home = fake::temp_dir("appdata-home");
+        let vars = [("HOME", &*home), ("APPDATA", &*home)];
+        // `bw` uses its own directory below it.
+        assert_eq!(resolve_in(&vars).unwrap(), None);
+    }
+}
@@ -0,0 +1,158 @@
+//! `@file` arguments, which read provider options from a file.
+//!
+//! Each line of the file holds one option, as `--option value`,
+//! `--option=value` or just `--flag`, also for options whose value is
+//! optional, like `--timings json`. Blank lines and lines starting with `#`
+//! are ignored.
+
+use crate::options::{self, Takes};
+use std::fs;
+
+/// Replace every `@path` in `args` with the options in that file, so that
+/// options after it still override the file.
+pub fn expand(args: &[&str]) -> Result<Vec<String>, String> {
+    let mut expanded = Vec::new();
+    for arg in args {
+        let Some(path) = arg.strip_prefix('@').filter(|path| !path.is_empty()) else {
+            expanded.push(arg.to_string());
+            continue;
+        };
+
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read provider arguments from `{}`: {}", path, e))?;
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('@') {
+                return Err(format!(
+                    "{}:{}: `@file` arguments can't be nested",
+                    path,
+                    number + 1
+                ));
+            }
+            if !line.starts_with('-') {
+                return Err(format!(
+                    "{}:{}: expected an option like `--name value`, found `{}`",
+                    path,
+                    number + 1,
+                    line
+                ));
+            }
+            // One argument, split by `options::split` as it would be on
+            // the command line, so that optional values stay attached.
+            let arg = match line.split_once(char::is_whitespace) {
+                Some((name, value)) if !name.contains('=') => {
+                    format!("{}={}", name, value.trim())
+                }
+                _ => line.to_string(),
+            };
+            let split =
+                options::split(&[&arg]).map_err(|e| format!("{}:{}: {}", path, number + 1, e))?;
+            // It would take the next argument for its value.
+            if let [name] = &split[..] {
+                if let Some((_, Takes::Value(_) | Takes::Values(_))) = options::find(name) {
+                    return Err(format!("{}:{}: `{}` needs a value", path, number + 1, name));
+                }
+            }
+            expanded.extend(split);
+        }
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake;
+
+    /// `contents` written to a file in a fresh directory for test `name`,
+    /// and its `@path` argument.
+    fn args_file(name: &str, contents: &str) -> String {
+        let path = fake::temp_dir(name).join("args.txt");
+        fs::write(&path, contents).unwrap();
+        format!("@{}", path.display())
+    }
+
+    #[test]
+    fn expands_in_place() {
+        let file = args_file(
+            "args-file-in-place",
+            "# registry options\n\
+             \n\
+             --collection Cargo tokens\n\
+             \t--uri-match=host  \n\
+             --timings json\n\
+             --timings=json\n\
+             --timings\n\
+             --alias https://a.example=https://b.example\n\
+             --sync\n",
+        );
+        let expanded = expand(&["--verbose", &file, "--uri-match", "exact"]).unwrap();
+        assert_eq!(
+            expanded,
+            [
+                "--verbose",
+                "--collection",
+                "Cargo tokens",
+                "--uri-match",
+                "host",
+                "--timings=json",
+                "--timings=json",
+                "--timings",
+                "--alias",
+                "https://a.example=https://b.example",
+                "--sync",
+                "--uri-match",
+                "exact",
+            ]
+        );
+        // Only arguments starting with `@` are files.
+        assert_eq!(
+            expand(&["--name", "a@b", "@"]).unwrap(),
+            ["--name", "a@b", "@"]
+        );
+    }
+
+    #[test]
+    fn malformed_files() {
+        let error = |name: &str, contents: &str| {
+            let file = args_file(name, contents);
+            let e = expand(&[&file]).unwrap_err();
+            assert!(e.starts_with(&file[1..]), "{}", e);
+            e
+        };
+        let e = error("args-file-positional", "--sync\nsync\n");
+        assert!(e.contains(":2: expected an option"), "{}", e);
+        let e = error("args-file-unknown", "--snyc\n");
+        assert!(e.contains(":1: ") && e.contains("--snyc"), "{}", e);
+        let e = error("args-file-flag-value", "--sync yes\n");
+        assert!(e.contains(":1: `--sync` takes no value"), "{}", e);
+        let e = error("args-file-no-value", "--collection\n--sync\n");
+        assert!(e.contains(":1: `--collection` needs a value"), "{}", e);
+        let e = error("args-file-nested", "# more\n@other.txt\n");
+        assert!(e.contains(":2: `@file` arguments can't be nested"), "{}", e);
+
+        let missing = fake::temp_dir("args-file-missing").join("args.txt");
+        let missing = format!("@{}", missing.display());
+        let e = expand(&[&missing]).unwrap_err();
+        assert!(e.contains(&missing[1..]), "{}", e);
+    }
+
+    #[test]
+    fn relative_paths_and_spaces() {
+        let path = fake::temp_dir("args file with spaces").join("my args.txt");
+        fs::write(&path, "--collection Cargo tokens\n").unwrap();
+        let expected = ["--collection", "Cargo tokens"];
+
+        let absolute = format!("@{}", path.display());
+        assert_eq!(expand(&[&absolute]).unwrap(), expected);
+        // Taken from the current directory, like cargo's own paths.
+        let relative = format!("@{}", fake::relative(&path).display());
+        assert_eq!(expand(&[&relative]).unwrap(), expected);
+
+        let e = expand(&["@my args.txt"]).unwrap_err();
+        assert!(e.contains("`my args.txt`"), "{}", e);
+    }
+}
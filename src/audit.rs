@@ -0,0 +1,90 @@
+//! Telling someone about the tokens the provider handed out or changed,
+//! for `--notify-command` and `--audit-log`.
+//!
+//! Neither ever sees a token, and neither can fail a request: what goes
+//! wrong is a warning.
+
+use crate::refresh::{INDEX_URL_ENV, NAME_ENV};
+use crate::{shell_command, trace};
+use cargo_credential::{Action, CredentialResponse, Error, Operation, RegistryInfo};
+use serde_json::json;
+use std::path::Path;
+use std::process::Stdio;
+
+/// Environment variable with the id of the item the token was read from.
+const ITEM_ID_ENV: &str = "BITWARDEN_ITEM_ID";
+
+/// Environment variable with what cargo needed the token for, like `publish`.
+const OPERATION_ENV: &str = "CARGO_REGISTRY_OPERATION";
+
+/// Environment variable with when the token was read, in RFC 3339.
+const READ_AT_ENV: &str = "CARGO_TOKEN_READ_AT";
+
+fn now() -> String {
+    time::OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_default()
+}
+
+/// Start `command` to say that the token for `registry` was read from the
+/// item `item_id` for `operation`, without waiting for it.
+pub fn notify(
+    command: &str,
+    registry: &RegistryInfo<'_>,
+    operation: &Operation<'_>,
+    item_id: Option<&str>,
+) {
+    let mut cmd = shell_command(command);
+    cmd.env(INDEX_URL_ENV, registry.index_url)
+        .env(READ_AT_ENV, now());
+    match registry.name {
+        Some(name) => cmd.env(NAME_ENV, name),
+        None => cmd.env_remove(NAME_ENV),
+    };
+    match item_id {
+        Some(id) => cmd.env(ITEM_ID_ENV, id),
+        None => cmd.env_remove(ITEM_ID_ENV),
+    };
+    if let Some(operation) = serde_json::to_value(operation)
+        .ok()
+        .and_then(|value| value["operation"].as_str().map(str::to_string))
+    {
+        cmd.env(OPERATION_ENV, operation);
+    }
+    // Away from the protocol pipes, and from cargo's stderr, which it may
+    // wait on until the command is done.
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    match cmd.spawn() {
+        Ok(_) => crate::verbose::log(|| "started `--notify-command`".to_string()),
+        Err(e) => eprintln!("warning: failed to run `--notify-command`: {}", e),
+    }
+}
+
+/// Append the request `action` for `registry`, answered with `result` from
+/// the item `item_id`, to the audit log at `path`.
+pub fn record(
+    path: &Path,
+    registry: &RegistryInfo<'_>,
+    action: &Action<'_>,
+    result: &Result<CredentialResponse, Error>,
+    item_id: Option<&str>,
+) {
+    let line = json!({
+        "time": now(),
+        "pid": std::process::id(),
+        "action": trace::action_json(action),
+        "registry-name": registry.name,
+        "index-url": registry.index_url,
+        "item-id": item_id,
+        "response": trace::response_json(result),
+    });
+    if let Err(e) = trace::append(path, &line.to_string()) {
+        eprintln!(
+            "warning: failed to write the audit log `{}`: {}",
+            path.display(),
+            e
+        );
+    }
+}
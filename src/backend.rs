@@ -0,0 +1,262 @@
+//! Ways of reading and changing vault items.
+//!
+//! By default every operation runs the `bw` CLI, which decrypts the vault
+//! from scratch each time. With `--backend serve`, a `bw serve` is started
+//! once per process instead, and spoken to over its local REST API, see
+//! [`crate::serve`]. Everything else, like logging in or encoding, always
+//! uses the CLI. `--backend rbw` replaces the CLI altogether, see
+//! [`crate::rbw`], and `--backend bws` uses Secrets Manager instead of the
+//! vault, see [`crate::bws`].
+
+use crate::json;
+use crate::version::Flag;
+use crate::{bw_error, BitwardenVault, CreateOutcome, ItemRef, Response};
+use cargo_credential::Error;
+use serde_json::Value;
+use std::process::Stdio;
+
+/// Which [`Backend`] to use, from `--backend`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Cli,
+    Serve,
+    Rbw,
+    Bws,
+}
+
+impl Kind {
+    pub fn parse(name: &str) -> Option<Kind> {
+        match name {
+            "cli" => Some(Kind::Cli),
+            "serve" => Some(Kind::Serve),
+            "rbw" => Some(Kind::Rbw),
+            "bws" => Some(Kind::Bws),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Cli => "cli",
+            Kind::Serve => "serve",
+            Kind::Rbw => "rbw",
+            Kind::Bws => "bws",
+        }
+    }
+
+    /// Whether the official CLI is replaced by another program.
+    pub fn is_other_client(self) -> bool {
+        matches!(self, Kind::Rbw | Kind::Bws)
+    }
+
+    /// Fail for the first of `options` that is given, if this backend has no
+    /// equivalent for them.
+    pub fn refuse(self, options: &[(&str, bool)]) -> Result<(), Error> {
+        match options.iter().find(|(_, given)| *given) {
+            Some((name, _)) if self.is_other_client() => Err(format!(
+                "`{}` is not supported with `--backend {}`",
+                name,
+                self.as_str()
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub trait Backend {
+    /// Items matching `filters`, which are options of `bw list items`
+    /// without the dashes, like `("url", index_url)`, or `("trash", "")`
+    /// for those without a value.
+    fn list_items(&self, filters: &[(&str, &str)]) -> Result<Vec<Value>, Error>;
+
+    /// The full item `id`. Fails with [`Error::NotFound`] if there is none.
+    fn get_item(&self, id: &str) -> Result<Value, Error>;
+
+    fn create_item(&self, item: &Value) -> Result<CreateOutcome, Error>;
+
+    fn edit_item(&self, target: &ItemRef<'_>, item: &Value) -> Result<(), Error>;
+
+    /// Move the item to the trash, or with `permanent` delete it for good.
+    fn delete_item(&self, target: &ItemRef<'_>, permanent: bool) -> Result<(), Error>;
+
+    /// Move the item out of the trash.
+    fn restore_item(&self, target: &ItemRef<'_>) -> Result<(), Error>;
+
+    /// Pull changes from the server.
+    fn sync(&self) -> Result<(), Error>;
+}
+
+/// The `bw` CLI, run for every operation.
+pub struct Cli<'a> {
+    pub vault: &'a BitwardenVault,
+    pub session: &'a Option<String>,
+}
+
+impl Cli<'_> {
+    /// Whether commands can print the `--response` envelope, which tells
+    /// failures from results without reading the CLI's messages.
+    fn response(&self) -> bool {
+        self.vault
+            .capabilities
+            .get()
+            .is_some_and(|c| c.supports(Flag::Response))
+    }
+}
+
+/// The `data` of the `--response` envelope in `buffer`, or the failure it
+/// reports.
+fn envelope_data(buffer: &str, what: &str) -> Result<Value, Error> {
+    match json::parse_output::<Response>(buffer) {
+        Ok(Response {
+            success: true,
+            data,
+            ..
+        }) => Ok(data.unwrap_or_default()),
+        Ok(Response { message, .. }) => Err(format!(
+            "`bw` failed to read the {}: {}",
+            what,
+            message.unwrap_or_default()
+        )
+        .into()),
+        Err(e) => Err(format!("failed to deserialize JSON from Bitwarden {}: {}", what, e).into()),
+    }
+}
+
+impl Backend for Cli<'_> {
+    fn list_items(&self, filters: &[(&str, &str)]) -> Result<Vec<Value>, Error> {
+        let options: Vec<String> = filters
+            .iter()
+            .map(|(name, _)| format!("--{}", name))
+            .collect();
+        let mut args = vec!["list", "items"];
+        for (option, (_, value)) in options.iter().zip(filters) {
+            args.push(option.as_str());
+            if !value.is_empty() {
+                args.push(value);
+            }
+        }
+        if self.response() {
+            args.push(Flag::Response.as_str());
+        }
+        let cmd = self.vault.make_cmd(self.session, &args)?;
+        let buffer = self.vault.run_cmd(cmd)?;
+        let failed = |e: String| format!("failed to deserialize JSON from Bitwarden list: {}", e);
+        if self.response() {
+            // The items are in the `data` of the list object.
+            let list = envelope_data(&buffer, "list")?;
+            return serde_json::from_value(list["data"].clone())
+                .map_err(|e| failed(e.to_string()).into());
+        }
+        json::parse_output(&buffer).map_err(|e| failed(e.to_string()).into())
+    }
+
+    fn get_item(&self, id: &str) -> Result<Value, Error> {
+        let mut args = vec!["get", "item", id];
+        if self.response() {
+            args.push(Flag::Response.as_str());
+        }
+        let mut cmd = self.vault.make_cmd(self.session, &args)?;
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let output = self.vault.output(&mut cmd)?;
+        let buffer = String::from_utf8_lossy(&output.stdout);
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let signals = bw_error::Signals {
+                version: self.vault.version.get(),
+                stdout: &buffer,
+                stderr: &stderr,
+                ..Default::default()
+            };
+            if bw_error::classify(&signals) == bw_error::Kind::NotFound {
+                return Err(Error::NotFound);
+            }
+            let reason =
+                bw_error::envelope_message(&buffer).unwrap_or_else(|| stderr.trim().to_string());
+            return Err(format!("`bw get item` failed: {}: {}", output.status, reason).into());
+        }
+        match self.response() {
+            true => envelope_data(&buffer, "item"),
+            false => json::parse_output(&buffer).map_err(|e| {
+                format!("failed to deserialize JSON from Bitwarden item: {}", e).into()
+            }),
+        }
+    }
+
+    fn create_item(&self, item: &Value) -> Result<CreateOutcome, Error> {
+        let data =
+            serde_json::to_vec(item).map_err(|e| format!("failed to serialize new item: {}", e))?;
+        let encoded = self.vault.encode(self.session, &data)?;
+
+        // The item holds the token, so it goes through stdin rather than
+        // the arguments, which other users can see.
+        let mut args = vec!["create", "item"];
+        if self.response() {
+            args.push(Flag::Response.as_str());
+        }
+        let mut cmd = self.vault.make_cmd(self.session, &args)?;
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+        let output = self
+            .vault
+            .output_with_input(&mut cmd, Some(encoded.as_bytes()))?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // Prefer the structured envelope, and fall back to the raw output.
+        let message = match json::parse_output::<Response>(&stdout) {
+            Ok(Response {
+                success: true,
+                data,
+                ..
+            }) => return Ok(CreateOutcome::Created(data)),
+            Ok(Response { message, .. }) => message.unwrap_or_default(),
+            Err(_) if output.status.success() => {
+                return Ok(CreateOutcome::Created(json::parse_output(&stdout).ok()))
+            }
+            Err(_) => format!("{}\n{}", stdout, stderr),
+        };
+
+        let signals = bw_error::Signals {
+            version: self.vault.version.get(),
+            stdout: &stdout,
+            stderr: &stderr,
+            ..Default::default()
+        };
+        crate::create_failed(bw_error::classify(&signals), item, &message)
+    }
+
+    fn edit_item(&self, target: &ItemRef<'_>, item: &Value) -> Result<(), Error> {
+        let data =
+            serde_json::to_vec(item).map_err(|e| format!("failed to serialize item: {}", e))?;
+        let encoded = self.vault.encode(self.session, &data)?;
+        let cmd = self
+            .vault
+            .make_cmd(self.session, &["edit", "item", target.id])?;
+        self.vault
+            .run_write_cmd(cmd, target, Some(encoded.as_bytes()))
+    }
+
+    fn delete_item(&self, target: &ItemRef<'_>, permanent: bool) -> Result<(), Error> {
+        let mut args = vec!["delete", "item", target.id];
+        if permanent {
+            args.push("--permanent");
+        }
+        let cmd = self.vault.make_cmd(self.session, &args)?;
+        self.vault.run_write_cmd(cmd, target, None)
+    }
+
+    fn restore_item(&self, target: &ItemRef<'_>) -> Result<(), Error> {
+        let cmd = self
+            .vault
+            .make_cmd(self.session, &["restore", "item", target.id])?;
+        self.vault.run_write_cmd(cmd, target, None)
+    }
+
+    fn sync(&self) -> Result<(), Error> {
+        let cmd = self.vault.make_cmd(self.session, &["sync"])?;
+        self.vault.run_cmd(cmd).map(drop)
+    }
+}
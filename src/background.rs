@@ -0,0 +1,81 @@
+//! Work done after cargo got its answer, for `--background-sync`.
+
+use crate::cancel;
+use crate::runner::CommandRunner;
+use crate::state::SyncStamp;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long the provider waits for background work before it exits.
+pub const MAX_WAIT: Duration = Duration::from_secs(30);
+
+static PENDING: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// Run `cmd`, a `bw sync`, with `runner` on another thread, recording it in
+/// `stamp` once it succeeded. Does nothing while an earlier one is still
+/// running.
+pub fn sync(runner: &dyn CommandRunner, mut cmd: Command, stamp: Option<SyncStamp>) {
+    let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+    pending.retain(|handle| !handle.is_finished());
+    if !pending.is_empty() {
+        return;
+    }
+
+    // Keep `bw` away from the protocol pipes, which cargo may already have closed.
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let wait = match runner.start(&mut cmd) {
+        Ok(wait) => wait,
+        Err(e) => {
+            eprintln!("warning: failed to run `bw sync` in the background: {}", e);
+            return;
+        }
+    };
+    pending.push(thread::spawn(move || {
+        let output = match wait() {
+            Ok(output) => output,
+            Err(e) => {
+                eprintln!(
+                    "warning: failed to wait for the background `bw sync`: {}",
+                    e
+                );
+                return;
+            }
+        };
+        if !output.status.success() {
+            eprintln!(
+                "warning: background `bw sync` failed: {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return;
+        }
+        if let Some(stamp) = stamp {
+            // Failing to record the sync only means the next process syncs again.
+            let _ = stamp.touch();
+        }
+    }));
+}
+
+/// Wait up to `max_wait` for background work to finish, and stop what's
+/// still running after that.
+pub fn finish(max_wait: Duration) {
+    let pending = std::mem::take(&mut *PENDING.lock().unwrap_or_else(|e| e.into_inner()));
+    let deadline = Instant::now() + max_wait;
+    while pending.iter().any(|handle| !handle.is_finished()) {
+        if Instant::now() >= deadline {
+            eprintln!("warning: gave up waiting for the background `bw sync`");
+            cancel::kill_background();
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    for handle in pending {
+        if handle.is_finished() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Classification of failed `bw` commands.
+//!
+//! `bw` translates some of its messages and rewords others between releases,
+//! so structured signals are trusted first: the vault state reported by
+//! `bw status`, the `success` flag of the `--response` envelope, and the
+//! error codes of Node.js, which are never translated. `bw` exits with 1 for
+//! every failure, so its exit code only tells failure from success. The
+//! messages are matched against [`KNOWN_MESSAGES`] only as a last resort, and
+//! anything else is [`Kind::Unclassified`], for which callers keep the raw
+//! output.
+
+use crate::version::Version;
+use crate::VaultState;
+use cargo_credential::Error;
+use serde::Deserialize;
+
+/// What went wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Not logged in, or the vault is locked.
+    Locked,
+    Network,
+    /// No write access to the item or collection.
+    Permission,
+    NotFound,
+    /// A password-protected Send was opened without the right password.
+    PasswordRequired,
+    /// A Send that has expired, was deleted, or was opened too often.
+    SendUnavailable,
+    /// An organization policy forbids items in the personal vault.
+    PersonalOwnershipDisabled,
+    /// `bw login` needs a two-step login code it had no way to ask for.
+    TwoStepRequired,
+    Unclassified,
+}
+
+/// Everything known about a failed `bw` command.
+#[derive(Debug, Default)]
+pub struct Signals<'a> {
+    /// Vault state from `bw status`, when it was checked after the failure.
+    pub state: Option<VaultState>,
+    /// Version of the CLI, when it is known.
+    pub version: Option<Version>,
+    /// Standard output, holding the envelope with `--response`.
+    pub stdout: &'a str,
+    pub stderr: &'a str,
+}
+
+/// A message printed by some versions of `bw`.
+struct KnownMessage {
+    kind: Kind,
+    /// Lowercase text to look for.
+    text: &'static str,
+    /// Last version printing this message, if it was reworded since.
+    until: Option<Version>,
+}
+
+const fn known(kind: Kind, text: &'static str) -> KnownMessage {
+    KnownMessage {
+        kind,
+        text,
+        until: None,
+    }
+}
+
+/// English messages of `bw`, most specific first. Last reviewed against
+/// `bw` 2024.6.0.
+const KNOWN_MESSAGES: &[KnownMessage] = &[
+    known(Kind::Locked, "vault is locked"),
+    known(Kind::Locked, "not logged in"),
+    known(Kind::Locked, "unauthenticated"),
+    known(Kind::PersonalOwnershipDisabled, "individual vault"),
+    known(Kind::PersonalOwnershipDisabled, "personal ownership"),
+    known(Kind::TwoStepRequired, "code is required"),
+    known(Kind::TwoStepRequired, "no provider selected"),
+    known(Kind::Permission, "permission"),
+    known(Kind::Permission, "read-only"),
+    known(Kind::Permission, "readonly"),
+    known(Kind::SendUnavailable, "expired"),
+    known(Kind::SendUnavailable, "deleted"),
+    known(Kind::SendUnavailable, "access count"),
+    known(Kind::SendUnavailable, "disabled"),
+    known(Kind::NotFound, "not found"),
+    known(Kind::Network, "network"),
+    known(Kind::Network, "timed out"),
+    known(Kind::Network, "socket hang up"),
+    // Generic, so after everything else that may mention the master password.
+    known(Kind::PasswordRequired, "password"),
+];
+
+/// Error codes of Node.js that `bw` passes on for failed requests.
+const NETWORK_CODES: &[&str] = &[
+    "ECONNREFUSED",
+    "ECONNRESET",
+    "ENOTFOUND",
+    "ETIMEDOUT",
+    "EAI_AGAIN",
+    "EHOSTUNREACH",
+    "ENETUNREACH",
+];
+
+/// The `--response` envelope of a failed command.
+#[derive(Deserialize)]
+struct Envelope {
+    success: bool,
+    message: Option<String>,
+}
+
+pub fn classify(signals: &Signals<'_>) -> Kind {
+    if let Some(VaultState::Locked | VaultState::Unauthenticated) = signals.state {
+        return Kind::Locked;
+    }
+
+    let output = [signals.stdout, signals.stderr];
+    let has_code = |code: &&str| {
+        output.iter().any(|output| {
+            output
+                .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .any(|word| word == *code)
+        })
+    };
+    if NETWORK_CODES.iter().any(has_code) {
+        return Kind::Network;
+    }
+    // HTTP status codes are not translated either.
+    if has_code(&"404") {
+        return Kind::NotFound;
+    }
+
+    // The envelope's message is free of the CLI's other chatter.
+    let message = envelope_message(signals.stdout)
+        .unwrap_or_else(|| output.join("\n"))
+        .to_lowercase();
+
+    KNOWN_MESSAGES
+        .iter()
+        .filter(|known| match (known.until, signals.version) {
+            (Some(until), Some(version)) => version <= until,
+            _ => true,
+        })
+        .find(|known| message.contains(known.text))
+        .map_or(Kind::Unclassified, |known| known.kind)
+}
+
+/// The message of the failed `--response` envelope in `stdout`, if there
+/// is one.
+pub fn envelope_message(stdout: &str) -> Option<String> {
+    match crate::json::parse_output::<Envelope>(stdout) {
+        Ok(Envelope {
+            success: false,
+            message: Some(message),
+        }) => Some(message),
+        _ => None,
+    }
+}
+
+/// The error to report for a failure of `kind`, so that cargo can tell
+/// what to do about it: [`Error::NotFound`] when a `lookup` found nothing,
+/// and for a locked vault, `e` with the command that fixes it, told apart
+/// by the vault `state` from `bw status`. Other failures keep `e` as it is.
+pub fn to_error(kind: Kind, state: Option<VaultState>, e: Error, lookup: bool) -> Error {
+    let remedy = match (kind, state) {
+        (Kind::NotFound, _) if lookup => return Error::NotFound,
+        (Kind::Locked, Some(VaultState::Unauthenticated)) => {
+            "not logged in to Bitwarden; run `bw login`"
+        }
+        (Kind::Locked, _) => "the Bitwarden vault is locked; run `bw unlock`",
+        _ => return e,
+    };
+    format!("{} ({})", e, remedy).into()
+}
+
+/// Classify a message that is all that's left of a failure.
+pub fn classify_message(message: &str) -> Kind {
+    classify(&Signals {
+        stderr: message,
+        ..Signals::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::fs;
+    use std::path::Path;
+
+    /// The `errors.json` of each version in `tests/fixtures`, by version.
+    fn fixtures() -> Vec<(Version, Vec<Value>)> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let mut fixtures: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.join("errors.json").is_file())
+            .map(|path| {
+                let name = path.file_name().unwrap().to_str().unwrap();
+                let version = Version::parse(name.trim_start_matches("bw-")).unwrap();
+                let contents = fs::read_to_string(path.join("errors.json")).unwrap();
+                (version, serde_json::from_str(&contents).unwrap())
+            })
+            .collect();
+        fixtures.sort_by_key(|(version, _)| *version);
+        fixtures
+    }
+
+    #[test]
+    fn fixtures_classify_alike() {
+        let fixtures = fixtures();
+        assert!(fixtures.len() >= 2);
+        let cases = |failures: &[Value]| -> Vec<String> {
+            failures
+                .iter()
+                .map(|failure| failure["case"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let first = cases(&fixtures[0].1);
+        for (version, failures) in &fixtures {
+            assert_eq!(cases(failures), first, "cases of {:?}", version);
+            for failure in failures {
+                let signals = Signals {
+                    state: serde_json::from_value(failure["state"].clone()).unwrap(),
+                    version: Some(*version),
+                    stdout: failure["stdout"].as_str().unwrap(),
+                    stderr: failure["stderr"].as_str().unwrap(),
+                };
+                assert_eq!(
+                    format!("{:?}", classify(&signals)),
+                    failure["kind"].as_str().unwrap(),
+                    "{} with {:?}",
+                    failure["case"],
+                    version
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn structured_signals_come_first() {
+        // The state wins over a message that reads like another failure.
+        let signals = Signals {
+            state: Some(VaultState::Locked),
+            stderr: "Not found.",
+            ..Signals::default()
+        };
+        assert_eq!(classify(&signals), Kind::Locked);
+        // Node.js codes over the words around them.
+        assert_eq!(
+            classify_message("permission check failed: connect ECONNRESET"),
+            Kind::Network
+        );
+        // Only whole codes count.
+        assert_eq!(classify_message("item 404ab1 is fine"), Kind::Unclassified);
+        // The envelope's message over the rest of the output.
+        let signals = Signals {
+            stdout: r#"{"success":false,"message":"Not found."}"#,
+            stderr: "(node:1) Warning: password prompts are deprecated",
+            ..Signals::default()
+        };
+        assert_eq!(classify(&signals), Kind::NotFound);
+    }
+}
@@ -0,0 +1,238 @@
+//! The Bitwarden Secrets Manager backend, for `--backend bws`.
+//!
+//! Secrets Manager keeps machine credentials apart from the password vault,
+//! and is reached through the `bws` CLI with the access token of a machine
+//! account. There is nothing to sign in to or sync, so this backend answers
+//! cargo on its own instead of going through the vault code.
+//!
+//! The token of a registry is the secret whose key is the registry's index
+//! URL, or the one given with `--secret-id`. `bws` only takes secret values
+//! as arguments, so unlike the vault backends `cargo login` passes the token
+//! on the command line of `bws`, with a warning.
+
+use crate::matching::normalize_index_url;
+use crate::{redact, BitwardenVault};
+use cargo_credential::{CredentialResponse, Error, RegistryInfo, Secret};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::process::Stdio;
+
+/// Command run when `--bw-path` isn't given.
+pub const CMD: &str = "bws";
+
+/// Environment variable `bws` takes the access token from.
+pub const ACCESS_TOKEN_ENV: &str = "BWS_ACCESS_TOKEN";
+
+/// Secret printed by `bws secret`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BwsSecret {
+    id: String,
+    key: String,
+    value: String,
+    project_id: Option<String>,
+    revision_date: Option<String>,
+}
+
+/// `bws` for the options of `vault`.
+pub struct Bws<'a> {
+    vault: &'a BitwardenVault,
+}
+
+impl<'a> Bws<'a> {
+    /// Fails if there is no access token to run `bws` with.
+    pub fn new(vault: &'a BitwardenVault) -> Result<Bws<'a>, Error> {
+        if std::env::var_os(ACCESS_TOKEN_ENV).map_or(true, |token| token.is_empty()) {
+            return Err(format!(
+                "`--backend bws` needs the access token of a machine account in {}",
+                ACCESS_TOKEN_ENV
+            )
+            .into());
+        }
+        Ok(Bws { vault })
+    }
+
+    fn run<T: DeserializeOwned>(&self, args: &[&str]) -> Result<T, Error> {
+        let mut args = args.to_vec();
+        args.extend(["--output", "json"]);
+        let mut cmd = self.vault.make_cmd(&None, &args)?;
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let output = self.vault.output(&mut cmd)?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+            if stderr.contains("not found") || stderr.contains("404") {
+                return Err(Error::NotFound);
+            }
+            return Err(crate::command_failed(&cmd, output.status, &output.stderr));
+        }
+        crate::json::parse_output(&String::from_utf8_lossy(&output.stdout))
+            .map_err(|e| format!("failed to deserialize JSON from `bws`: {}", e).into())
+    }
+
+    /// The secret holding the token for `index_url`, if there is one.
+    fn find(&self, index_url: &str) -> Result<Option<BwsSecret>, Error> {
+        if let Some(id) = &self.vault.options.secret_id {
+            return match self.run(&["secret", "get", id]) {
+                Ok(secret) => Ok(Some(secret)),
+                Err(Error::NotFound) => Ok(None),
+                Err(e) => Err(e),
+            };
+        }
+
+        let mut args = vec!["secret", "list"];
+        if let Some(project_id) = &self.vault.options.project_id {
+            args.push(project_id);
+        }
+        let url = normalize_index_url(index_url);
+        let mut secrets: Vec<BwsSecret> = self.run(&args)?;
+        secrets.retain(|secret| normalize_index_url(&secret.key).as_str() == url.as_str());
+        match secrets.len() {
+            0 => Ok(None),
+            1 => Ok(secrets.pop()),
+            _ => Err(format!(
+                "too many Bitwarden secrets are keyed `{}`, consider deleting the excess \
+                 ones, or pass `--secret-id`",
+                url
+            )
+            .into()),
+        }
+    }
+
+    pub fn get(&self, registry: &RegistryInfo<'_>) -> Result<CredentialResponse, Error> {
+        let secret = self.find(registry.index_url)?.ok_or(Error::NotFound)?;
+        redact::register(&secret.value);
+        let token = crate::stored_token(&secret.key, &secret.value)?;
+        Ok(CredentialResponse::Get {
+            token: self.vault.format_token(Secret::from(token)),
+            cache: self.vault.cache_within(None),
+            operation_independent: true,
+        })
+    }
+
+    pub fn store(
+        &self,
+        registry: &RegistryInfo<'_>,
+        read_token: impl FnOnce() -> Result<Secret<String>, Error>,
+    ) -> Result<(), Error> {
+        let existing = self.find(registry.index_url)?;
+        match &existing {
+            Some(secret) => self.vault.confirm_overwrite(
+                &secret.key,
+                secret.revision_date.as_deref(),
+                registry,
+            )?,
+            None => {
+                if let Some(id) = &self.vault.options.secret_id {
+                    return Err(format!("no Bitwarden secret with id `{}` found", id).into());
+                }
+            }
+        }
+        let project_id = match &existing {
+            Some(secret) => secret.project_id.clone(),
+            None => self.vault.options.project_id.clone(),
+        };
+
+        let token = self.vault.unformat_token(read_token()?);
+        let token = token.as_deref().expose();
+        // Neither stdin nor the environment can carry it instead.
+        eprintln!(
+            "warning: `bws` only takes the token as an argument, so while it runs, other \
+             users of this machine can read it from the process list; change it in the \
+             Secrets Manager web vault instead if that's a concern"
+        );
+        match &existing {
+            Some(secret) => {
+                self.run::<BwsSecret>(&["secret", "edit", &secret.id, "--value", token])?;
+            }
+            None => {
+                let project_id = project_id
+                    .ok_or("a new secret needs a project; pass `--project-id` to create it in")?;
+                let key = normalize_index_url(registry.index_url);
+                let note = self
+                    .vault
+                    .options
+                    .name_template
+                    .render(registry.index_url, registry.name);
+                self.run::<BwsSecret>(&[
+                    "secret",
+                    "create",
+                    key.as_str(),
+                    token,
+                    &project_id,
+                    "--note",
+                    &note,
+                ])?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn delete(&self, registry: &RegistryInfo<'_>) -> Result<(), Error> {
+        let secret = self.find(registry.index_url)?.ok_or(Error::NotFound)?;
+        let mut cmd = self
+            .vault
+            .make_cmd(&None, &["secret", "delete", &secret.id])?;
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        let output = self.vault.output(&mut cmd)?;
+        if !output.status.success() {
+            return Err(crate::command_failed(&cmd, output.status, &output.stderr));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::parse_output;
+
+    /// What `bws secret get` and `bws secret create` print with `--output json`.
+    const SECRET: &str = r#"{
+  "object": "secret",
+  "id": "be8e0ad8-d545-4017-a55a-b02f014d4158",
+  "organizationId": "10e8e0ad-d545-4017-a55a-b02f014d4158",
+  "projectId": "e325ea69-a3ab-4dff-836f-b02e013fe530",
+  "key": "sparse+https://bws.example.com/index/",
+  "value": "token-bws",
+  "note": "Cargo registry token for bws.example.com",
+  "creationDate": "2024-06-14T09:29:03.436072Z",
+  "revisionDate": "2024-06-14T09:29:03.436072Z"
+}"#;
+
+    #[test]
+    fn secret_output() {
+        let secret: BwsSecret = parse_output(SECRET).unwrap();
+        assert_eq!(secret.id, "be8e0ad8-d545-4017-a55a-b02f014d4158");
+        assert_eq!(secret.key, "sparse+https://bws.example.com/index/");
+        assert_eq!(secret.value, "token-bws");
+        assert_eq!(
+            secret.project_id.as_deref(),
+            Some("e325ea69-a3ab-4dff-836f-b02e013fe530")
+        );
+        assert_eq!(
+            secret.revision_date.as_deref(),
+            Some("2024-06-14T09:29:03.436072Z")
+        );
+    }
+
+    #[test]
+    fn secret_list_output() {
+        let unassigned = r#"{"object": "secret", "id": "b", "organizationId": "o",
+            "projectId": null, "key": "other", "value": "v", "note": ""}"#;
+        let list = format!("[{}, {}]", SECRET, unassigned);
+        let secrets: Vec<BwsSecret> = parse_output(&list).unwrap();
+        assert_eq!(secrets.len(), 2);
+        assert_eq!(secrets[1].key, "other");
+        assert_eq!(secrets[1].project_id, None);
+        assert_eq!(secrets[1].revision_date, None);
+        assert!(parse_output::<Vec<BwsSecret>>("[]").unwrap().is_empty());
+
+        // A secret without its value isn't one to read a token from.
+        let e = parse_output::<BwsSecret>(r#"{"id": "c", "key": "k"}"#).unwrap_err();
+        assert!(e.to_string().contains("value"), "{}", e);
+    }
+}
@@ -0,0 +1,308 @@
+//! Stopping `bw` when cargo goes away.
+//!
+//! When the user interrupts cargo, it closes the protocol pipes, but a
+//! `bw sync` or a login prompt started by the provider would keep running and
+//! hold on to the terminal. Every `bw` process is therefore registered while
+//! it runs, and killed when the provider gets a termination signal or notices
+//! that cargo closed its stdin mid-request.
+//!
+//! On Windows, the processes are put in a job object that the system closes
+//! when the provider exits, however it does, which kills them; Ctrl-C,
+//! Ctrl-Break and closing the console end the job right away.
+
+use std::io::{self, Read};
+use std::ops::{Deref, DerefMut};
+use std::process::{Child, Command, Output};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Most `bw` processes running at once; there is never more than one.
+const SLOTS: usize = 8;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE: AtomicU32 = AtomicU32::new(0);
+
+/// Process ids of the running `bw` processes, as atomics so that signal
+/// handlers can read them.
+static RUNNING: [AtomicU32; SLOTS] = [FREE; SLOTS];
+
+/// Like [`RUNNING`], for processes that no request waits for, which may
+/// outlive cargo's interest in the provider.
+static BACKGROUND: [AtomicU32; SLOTS] = [FREE; SLOTS];
+
+/// Set once cargo has gone away, after which nothing new is started.
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// A running `bw`, killed when dropped before it exited.
+pub struct Guarded {
+    child: Option<Child>,
+    slot: Option<&'static AtomicU32>,
+}
+
+impl Guarded {
+    pub fn wait_with_output(mut self) -> io::Result<Output> {
+        self.child
+            .take()
+            .expect("child taken twice")
+            .wait_with_output()
+    }
+
+    /// Like [`Self::wait_with_output`], but give up after `timeout`, killing
+    /// the process. `None` if it timed out.
+    pub fn wait_with_output_timeout(mut self, timeout: Duration) -> io::Result<Option<Output>> {
+        fn drain(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+            thread::spawn(move || {
+                let mut buffer = Vec::new();
+                if let Some(mut pipe) = pipe {
+                    let _ = pipe.read_to_end(&mut buffer);
+                }
+                buffer
+            })
+        }
+
+        let child = self.child.as_mut().expect("child taken");
+        let stdout = drain(child.stdout.take());
+        let stderr = drain(child.stderr.take());
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                // Dropping `self` kills it; the readers end with the pipes.
+                return Ok(None);
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        Ok(Some(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        }))
+    }
+}
+
+impl Deref for Guarded {
+    type Target = Child;
+
+    fn deref(&self) -> &Child {
+        self.child.as_ref().expect("child taken")
+    }
+}
+
+impl DerefMut for Guarded {
+    fn deref_mut(&mut self) -> &mut Child {
+        self.child.as_mut().expect("child taken")
+    }
+}
+
+impl Drop for Guarded {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            slot.store(0, Ordering::SeqCst);
+        }
+        if let Some(child) = &mut self.child {
+            if let Ok(None) = child.try_wait() {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
+}
+
+/// Spawn `cmd`, registering it to be killed on cancellation.
+pub fn spawn(cmd: &mut Command) -> io::Result<Guarded> {
+    spawn_in(cmd, &RUNNING)
+}
+
+/// Spawn `cmd` for work no request waits for. It is only killed by
+/// termination signals and [`kill_background`].
+pub fn spawn_background(cmd: &mut Command) -> io::Result<Guarded> {
+    spawn_in(cmd, &BACKGROUND)
+}
+
+fn spawn_in(cmd: &mut Command, slots: &'static [AtomicU32; SLOTS]) -> io::Result<Guarded> {
+    if CANCELLED.load(Ordering::SeqCst) {
+        return Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            "cargo cancelled the request",
+        ));
+    }
+
+    let child = cmd.spawn()?;
+    #[cfg(windows)]
+    job::assign(&child);
+    let slot = slots.iter().find(|pid| {
+        pid.compare_exchange(0, child.id(), Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    });
+    Ok(Guarded {
+        child: Some(child),
+        slot,
+    })
+}
+
+/// Like [`Command::output`], but cancellable. Unlike it, this uses the
+/// standard streams configured on `cmd` as they are.
+pub fn output(cmd: &mut Command) -> io::Result<Output> {
+    spawn(cmd)?.wait_with_output()
+}
+
+/// Terminate the background `bw` processes.
+pub fn kill_background() {
+    kill(&BACKGROUND);
+}
+
+/// Terminate the running `bw` processes. Safe to call from a signal handler.
+fn kill(slots: &[AtomicU32; SLOTS]) {
+    for pid in slots {
+        let pid = pid.load(Ordering::SeqCst);
+        if pid != 0 {
+            #[cfg(unix)]
+            unsafe {
+                libc::kill(pid as libc::pid_t, libc::SIGTERM);
+            }
+        }
+    }
+}
+
+/// Kill `bw` and exit when the provider is told to terminate.
+pub fn install_signal_handlers() {
+    #[cfg(unix)]
+    {
+        extern "C" fn handle(signal: libc::c_int) {
+            kill(&RUNNING);
+            kill(&BACKGROUND);
+            unsafe { libc::_exit(128 + signal) };
+        }
+
+        for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            unsafe {
+                libc::signal(signal, handle as *const () as libc::sighandler_t);
+            }
+        }
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::Foundation::BOOL;
+        use windows_sys::Win32::System::Console::{
+            SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT,
+        };
+
+        unsafe extern "system" fn handle(event: u32) -> BOOL {
+            if matches!(event, CTRL_C_EVENT | CTRL_BREAK_EVENT | CTRL_CLOSE_EVENT) {
+                job::terminate();
+            }
+            // Not handled, so that the default handler ends the provider.
+            0
+        }
+
+        unsafe {
+            SetConsoleCtrlHandler(Some(handle), 1);
+        }
+    }
+}
+
+/// The job object the `bw` processes run in on Windows.
+#[cfg(windows)]
+mod job {
+    use std::os::windows::io::AsRawHandle;
+    use std::process::Child;
+    use std::sync::OnceLock;
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+        SetInformationJobObject, TerminateJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    };
+
+    /// The job, `0` if it couldn't be made. Its handle is only closed by
+    /// the exit of the provider.
+    static JOB: OnceLock<HANDLE> = OnceLock::new();
+
+    fn create() -> HANDLE {
+        unsafe {
+            let job = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+            if job == 0 {
+                return 0;
+            }
+            let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+            limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            let set = SetInformationJobObject(
+                job,
+                JobObjectExtendedLimitInformation,
+                &limits as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION as *const _,
+                std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            );
+            if set == 0 {
+                CloseHandle(job);
+                return 0;
+            }
+            job
+        }
+    }
+
+    /// Put `child` in the job. Failing only leaves it to the guard.
+    pub fn assign(child: &Child) {
+        let job = *JOB.get_or_init(create);
+        if job != 0 {
+            unsafe {
+                AssignProcessToJobObject(job, child.as_raw_handle() as HANDLE);
+            }
+        }
+    }
+
+    /// Kill every process in the job. Safe to call from a console handler.
+    pub fn terminate() {
+        if let Some(&job) = JOB.get().filter(|&&job| job != 0) {
+            unsafe {
+                TerminateJobObject(job, 130);
+            }
+        }
+    }
+}
+
+/// Watch the protocol's stdin in the background: once cargo closes it, no
+/// answer can reach cargo anymore, so running `bw` processes are killed and
+/// no new ones started.
+///
+/// Cargo only closes stdin after it has read the last response, so this
+/// never cuts a request short that cargo still waits for.
+pub fn watch_protocol() {
+    #[cfg(unix)]
+    {
+        // `cargo_credential` points stdin at the terminal while a request
+        // runs, so keep our own handle on the pipe.
+        let pipe = unsafe { libc::fcntl(libc::STDIN_FILENO, libc::F_DUPFD_CLOEXEC, 0) };
+        if pipe < 0 {
+            return;
+        }
+        std::thread::spawn(move || watch(pipe));
+    }
+}
+
+#[cfg(unix)]
+fn watch(pipe: libc::c_int) {
+    loop {
+        let mut fd = libc::pollfd {
+            fd: pipe,
+            events: 0,
+            revents: 0,
+        };
+        // `POLLHUP` is reported without asking for it, and without reading.
+        let ready = unsafe { libc::poll(&mut fd, 1, 250) };
+        if ready > 0 && fd.revents & (libc::POLLHUP | libc::POLLERR | libc::POLLNVAL) != 0 {
+            CANCELLED.store(true, Ordering::SeqCst);
+            if RUNNING.iter().any(|pid| pid.load(Ordering::SeqCst) != 0) {
+                kill(&RUNNING);
+                std::process::exit(130);
+            }
+            return;
+        }
+        if ready < 0 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return;
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Reading cargo's own configuration and credential files.
+
+use cfg_if::cfg_if;
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use toml_edit::{value, DocumentMut, Item, Table};
+
+/// Index URL cargo reports for crates.io.
+pub const CRATES_IO_INDEX: &str = "https://github.com/rust-lang/crates.io-index";
+
+/// `$CARGO_HOME`, defaulting to `~/.cargo`.
+pub fn cargo_home() -> Option<PathBuf> {
+    cargo_home_with(&|name| std::env::var_os(name))
+}
+
+/// [`cargo_home`] for the environment variables `var` gives.
+pub fn cargo_home_with(var: &dyn Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    if let Some(home) = var("CARGO_HOME").filter(|v| !v.is_empty()) {
+        return Some(PathBuf::from(home));
+    }
+    let home = var("HOME").or_else(|| var("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".cargo"))
+}
+
+/// Cargo config files in the order cargo prefers them: the ones closest to
+/// the current directory first, `$CARGO_HOME` last.
+pub fn config_paths() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Ok(cwd) = std::env::current_dir() {
+        dirs.extend(cwd.ancestors().map(|dir| dir.join(".cargo")));
+    }
+    if let Some(home) = cargo_home() {
+        if !dirs.contains(&home) {
+            dirs.push(home);
+        }
+    }
+
+    dirs.into_iter()
+        .flat_map(|dir| [dir.join("config.toml"), dir.join("config")])
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Parse a TOML file, returning `None` if it doesn't exist.
+pub fn read_toml(path: &Path) -> Result<Option<DocumentMut>, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("failed to read `{}`: {}", path.display(), e)),
+    };
+    contents
+        .parse()
+        .map(Some)
+        .map_err(|e| format!("failed to parse `{}`: {}", path.display(), e))
+}
+
+/// Index URL of the registry cargo knows as `name`.
+///
+/// `None` means crates.io, the registry configured in the `[registry]` table.
+pub fn registry_index(name: Option<&str>) -> Option<String> {
+    let Some(name) = name else {
+        return Some(CRATES_IO_INDEX.to_string());
+    };
+    if name == "crates-io" {
+        return Some(CRATES_IO_INDEX.to_string());
+    }
+
+    let env = format!(
+        "CARGO_REGISTRIES_{}_INDEX",
+        name.to_uppercase().replace('-', "_")
+    );
+    if let Ok(index) = std::env::var(env) {
+        return Some(index);
+    }
+
+    config_paths().iter().find_map(|path| {
+        let doc = read_toml(path).ok()??;
+        doc.get("registries")?
+            .get(name)?
+            .get("index")?
+            .as_str()
+            .map(str::to_string)
+    })
+}
+
+/// Registries configured in the given cargo config files, as (name, index
+/// URL) pairs. crates.io is always included; the first definition of a
+/// registry wins, as in cargo.
+pub fn registries(paths: &[PathBuf]) -> Result<Vec<(String, String)>, String> {
+    let mut registries = vec![("crates-io".to_string(), CRATES_IO_INDEX.to_string())];
+    for path in paths {
+        let Some(doc) = read_toml(path)? else {
+            continue;
+        };
+        let Some(table) = doc.get("registries").and_then(Item::as_table_like) else {
+            continue;
+        };
+        for (name, registry) in table.iter() {
+            let Some(index) = registry.get("index").and_then(Item::as_str) else {
+                continue;
+            };
+            if !registries.iter().any(|(known, _)| known == name) {
+                registries.push((name.to_string(), index.to_string()));
+            }
+        }
+    }
+    Ok(registries)
+}
+
+/// `$CARGO_HOME/config.toml`, or the legacy `config` file if only that
+/// exists.
+pub fn home_config_path() -> Option<PathBuf> {
+    let home = cargo_home()?;
+    let legacy = home.join("config");
+    let path = home.join("config.toml");
+    if !path.exists() && legacy.is_file() {
+        Some(legacy)
+    } else {
+        Some(path)
+    }
+}
+
+/// The table of `registry` in a parsed cargo config: `[registry]` for
+/// `None` and crates.io, `[registries.<name>]` otherwise.
+fn registry_table<'a>(doc: &'a mut DocumentMut, registry: Option<&str>) -> &'a mut Item {
+    match registry.filter(|name| *name != "crates-io") {
+        None => doc.entry("registry"),
+        Some(name) => doc
+            .entry("registries")
+            .or_insert_with(|| {
+                let mut table = Table::new();
+                table.set_implicit(true);
+                Item::Table(table)
+            })
+            .as_table_mut()
+            .expect("`registries` is a table")
+            .entry(name),
+    }
+    .or_insert_with(|| Item::Table(Table::new()))
+}
+
+/// Set the index URL of the registry `name` in a parsed cargo config.
+pub fn set_index(doc: &mut DocumentMut, name: &str, index_url: &str) {
+    registry_table(doc, Some(name))["index"] = value(index_url);
+}
+
+/// Set the `credential-provider` of `registry` in a parsed cargo config to
+/// the program and arguments of `provider`.
+pub fn set_credential_provider(doc: &mut DocumentMut, registry: Option<&str>, provider: &[&str]) {
+    let provider: toml_edit::Array = provider.iter().copied().collect();
+    registry_table(doc, registry)["credential-provider"] = value(provider);
+}
+
+/// Make `provider` the preferred one in `registry.global-credential-providers`
+/// of a parsed cargo config, replacing the entries that run `program`.
+/// Without the setting, cargo's default `cargo:token` is kept before it.
+pub fn set_global_provider(doc: &mut DocumentMut, program: &str, provider: &str) {
+    let table = registry_table(doc, None);
+    let providers = &mut table["global-credential-providers"];
+    if providers.as_array().is_none() {
+        *providers = value(toml_edit::Array::from_iter(["cargo:token"]));
+    }
+    let providers = providers.as_array_mut().expect("just made an array");
+    providers.retain(|entry| {
+        entry
+            .as_str()
+            .and_then(|entry| entry.split_whitespace().next())
+            != Some(program)
+    });
+    // Cargo prefers the providers at the end of the list.
+    providers.push(provider);
+}
+
+/// A token found in `credentials.toml`.
+pub struct StoredToken {
+    /// Registry name, or `None` for the `[registry]` table (crates.io).
+    pub registry: Option<String>,
+    pub token: String,
+}
+
+/// `$CARGO_HOME/credentials.toml`, or the legacy `credentials` file if only
+/// that exists.
+pub fn credentials_path() -> Option<PathBuf> {
+    let home = cargo_home()?;
+    let legacy = home.join("credentials");
+    let path = home.join("credentials.toml");
+    if !path.exists() && legacy.exists() {
+        Some(legacy)
+    } else {
+        Some(path)
+    }
+}
+
+/// All plaintext tokens in a parsed `credentials.toml`.
+pub fn stored_tokens(doc: &DocumentMut) -> Vec<StoredToken> {
+    let mut tokens = Vec::new();
+    if let Some(token) = doc
+        .get("registry")
+        .and_then(|r| r.get("token"))
+        .and_then(Item::as_str)
+    {
+        tokens.push(StoredToken {
+            registry: None,
+            token: token.to_string(),
+        });
+    }
+
+    if let Some(registries) = doc.get("registries").and_then(Item::as_table_like) {
+        for (name, registry) in registries.iter() {
+            if let Some(token) = registry.get("token").and_then(Item::as_str) {
+                tokens.push(StoredToken {
+                    registry: Some(name.to_string()),
+                    token: token.to_string(),
+                });
+            }
+        }
+    }
+
+    tokens
+}
+
+/// Set the token of `registry` (as in [`StoredToken::registry`]) in a parsed
+/// `credentials.toml`.
+pub fn set_token(doc: &mut DocumentMut, registry: Option<&str>, token: &str) {
+    let table = match registry {
+        None => doc.entry("registry"),
+        Some(name) => {
+            let registries = doc
+                .entry("registries")
+                .or_insert_with(|| {
+                    let mut table = Table::new();
+                    table.set_implicit(true);
+                    Item::Table(table)
+                })
+                .as_table_mut()
+                .expect("`registries` is a table");
+            registries.entry(name)
+        }
+    };
+    table.or_insert_with(|| Item::Table(Table::new()))["token"] = value(token);
+}
+
+/// Remove the token of `registry` (as in [`StoredToken::registry`]) from a
+/// parsed `credentials.toml`, dropping tables that become empty.
+pub fn remove_token(doc: &mut DocumentMut, registry: Option<&str>) {
+    match registry {
+        None => {
+            if let Some(table) = doc.get_mut("registry").and_then(Item::as_table_like_mut) {
+                table.remove("token");
+                if table.is_empty() {
+                    doc.remove("registry");
+                }
+            }
+        }
+        Some(name) => {
+            let Some(registries) = doc.get_mut("registries").and_then(Item::as_table_like_mut)
+            else {
+                return;
+            };
+            if let Some(table) = registries.get_mut(name).and_then(Item::as_table_like_mut) {
+                table.remove("token");
+                if table.is_empty() {
+                    registries.remove(name);
+                }
+            }
+            if registries.is_empty() {
+                doc.remove("registries");
+            }
+        }
+    }
+}
+
+/// Write `contents` to a file only the current user may read, as cargo does
+/// for `credentials.toml`. An existing file is only replaced if `overwrite`.
+pub fn write_private(path: &Path, contents: &str, overwrite: bool) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if overwrite {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    cfg_if! {
+        if #[cfg(unix)] {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            options.mode(0o600);
+            let file = options.open(path)?;
+            // `mode` only applies to newly created files.
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        } else {
+            let file = options.open(path)?;
+        }
+    }
+    write_all(file, contents)
+}
+
+fn write_all(mut file: fs::File, contents: &str) -> io::Result<()> {
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()
+}
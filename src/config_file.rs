@@ -0,0 +1,235 @@
+//! The configuration file of the provider, with options for every registry
+//! and for single ones, so that they needn't be repeated in the cargo
+//! configuration of each registry and each machine.
+//!
+//! ```toml
+//! email = "me@example.com"
+//! sync = true
+//!
+//! [registries.my-registry]
+//! folder = "Cargo"
+//! collection = ["Engineering", "CI"]
+//! ```
+//!
+//! Keys are the provider options without their `--`. The options given to
+//! the provider win, then the environment variables that stand for options,
+//! then the section of the registry, by name or index URL, and the top of
+//! the file comes last.
+
+use crate::cargo_config::read_toml;
+use crate::matching::normalize_index_url;
+use crate::options::{self, Takes};
+use crate::{locate, verbose};
+use cfg_if::cfg_if;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use toml_edit::{Item, Table, Value};
+
+/// Environment variable naming the file, like `--config`.
+pub const ENV: &str = "CARGO_CREDENTIAL_BITWARDEN_CONFIG";
+
+/// Options that environment variables stand for, which beat the file.
+const ENV_OPTIONS: &[(&str, &str)] = &[("bw-path", locate::PATH_ENV), ("verbose", verbose::ENV)];
+
+/// The file, as provider arguments.
+pub struct Config {
+    global: Vec<String>,
+    /// Sections by registry name or index URL.
+    registries: Vec<(String, Vec<String>)>,
+}
+
+impl Config {
+    /// Arguments for the whole file, before those of the registry.
+    pub fn global(&self) -> &[String] {
+        &self.global
+    }
+
+    /// Arguments of the section of the registry `name` at `index_url`.
+    pub fn registry(&self, index_url: &str, name: Option<&str>) -> &[String] {
+        let url = normalize_index_url(index_url);
+        self.registries
+            .iter()
+            .find(|(key, _)| Some(key.as_str()) == name || normalize_index_url(key) == url)
+            .map_or(&[], |(_, args)| args)
+    }
+}
+
+/// `config.toml` in the configuration directory of the platform.
+fn default_path() -> Option<PathBuf> {
+    fn env_path(name: &str) -> Option<PathBuf> {
+        std::env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    }
+
+    cfg_if! {
+        if #[cfg(target_os = "windows")] {
+            let base = env_path("APPDATA")?;
+        } else if #[cfg(target_os = "macos")] {
+            let base = env_path("HOME")?.join("Library").join("Application Support");
+        } else {
+            let base = env_path("XDG_CONFIG_HOME")
+                .or_else(|| env_path("HOME").map(|home| home.join(".config")))?;
+        }
+    }
+
+    Some(base.join("cargo-credential-bitwarden").join("config.toml"))
+}
+
+/// The value of `--config` in `args`, split by [`options::split`].
+pub fn path_arg(args: &[String]) -> Option<&str> {
+    let at = args.iter().position(|arg| arg == "--config")?;
+    args.get(at + 1).map(String::as_str)
+}
+
+/// Read the file from `--config`, [`ENV`], or the default path. Only a
+/// missing file at the default path is no error.
+pub fn load(path: Option<&str>) -> Result<Option<Config>, String> {
+    let given = path.map(PathBuf::from).or_else(|| {
+        std::env::var_os(ENV)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    });
+    let Some(path) = given.clone().or_else(default_path) else {
+        return Ok(None);
+    };
+    let Some(doc) = read_toml(&path)? else {
+        return match given {
+            Some(_) => Err(format!("config file `{}` not found", path.display())),
+            None => Ok(None),
+        };
+    };
+
+    let mut config = Config {
+        global: Vec::new(),
+        registries: Vec::new(),
+    };
+    let mut unknown = Vec::new();
+    for (key, item) in doc.iter() {
+        if key != "registries" {
+            to_args(&path, key, key, item, &mut config.global, &mut unknown)?;
+            continue;
+        }
+        let sections = item
+            .as_table_like()
+            .ok_or_else(|| format!("`{}`: `registries` must be a table", path.display()))?;
+        for (registry, section) in sections.iter() {
+            let section = section.as_table().ok_or_else(|| {
+                format!(
+                    "`{}`: `registries.{}` must be a table",
+                    path.display(),
+                    registry
+                )
+            })?;
+            let prefix = format!("registries.{}", registry);
+            let args = section_args(&path, &prefix, section, &mut unknown)?;
+            config.registries.push((registry.to_string(), args));
+        }
+    }
+    warn_unknown(&path, &unknown);
+    Ok(Some(config))
+}
+
+fn section_args(
+    path: &Path,
+    prefix: &str,
+    section: &Table,
+    unknown: &mut Vec<String>,
+) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for (key, item) in section.iter() {
+        let name = format!("{}.{}", prefix, key);
+        to_args(path, &name, key, item, &mut args, unknown)?;
+    }
+    Ok(args)
+}
+
+/// Add the option `key`, spelled `name` in messages, to `args`.
+fn to_args(
+    path: &Path,
+    name: &str,
+    key: &str,
+    item: &Item,
+    args: &mut Vec<String>,
+    unknown: &mut Vec<String>,
+) -> Result<(), String> {
+    let option = format!("--{}", key);
+    let takes = match options::find(&option) {
+        Some((_, takes)) if key != "config" => takes,
+        _ => {
+            unknown.push(name.to_string());
+            return Ok(());
+        }
+    };
+    let overridden = ENV_OPTIONS.iter().any(|(option, env)| {
+        *option == key && std::env::var_os(env).is_some_and(|v| !v.is_empty())
+    });
+    if overridden {
+        return Ok(());
+    }
+
+    let invalid = |expected: &str| format!("`{}`: `{}` must be {}", path.display(), name, expected);
+    let value = item.as_value();
+    match takes {
+        Takes::Nothing => match value.and_then(Value::as_bool) {
+            Some(true) => args.push(option),
+            Some(false) => {}
+            None => return Err(invalid("`true` or `false`")),
+        },
+        Takes::Optional(_) => match value {
+            Some(Value::Boolean(b)) => {
+                if *b.value() {
+                    args.push(option);
+                }
+            }
+            Some(value) => {
+                let value = scalar(value).ok_or_else(|| invalid("`true`, `false` or a string"))?;
+                args.push(format!("{}={}", option, value));
+            }
+            None => return Err(invalid("`true`, `false` or a string")),
+        },
+        Takes::Value(_) => {
+            let value = value.and_then(scalar).ok_or_else(|| invalid("a string"))?;
+            args.extend([option, value]);
+        }
+        Takes::Values(_) => {
+            let values = match value {
+                Some(Value::Array(array)) => array.iter().map(scalar).collect(),
+                Some(value) => scalar(value).map(|value| vec![value]),
+                None => None,
+            }
+            .ok_or_else(|| invalid("a string or an array of strings"))?;
+            for value in values {
+                args.extend([option.clone(), value]);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A string or a number.
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.value().clone()),
+        Value::Integer(i) => Some(i.value().to_string()),
+        Value::Float(f) => Some(f.value().to_string()),
+        _ => None,
+    }
+}
+
+/// Warn about the unknown keys of the file at `path`, once per process.
+fn warn_unknown(path: &Path, unknown: &[String]) {
+    static WARNED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+    let mut warned = WARNED.lock().unwrap_or_else(|e| e.into_inner());
+    if unknown.is_empty() || warned.iter().any(|warned| warned == path) {
+        return;
+    }
+    warned.push(path.to_path_buf());
+    for key in unknown {
+        eprintln!(
+            "warning: `{}`: unknown key `{}`, ignored",
+            path.display(),
+            key
+        );
+    }
+}
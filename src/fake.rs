@@ -0,0 +1,746 @@
+//! An in-memory Bitwarden CLI for the tests, answering the commands of
+//! [`BitwardenVault`] through its [`CommandRunner`].
+//!
+//! It knows the commands the provider runs, with the `--response` envelope
+//! when asked for, and keeps what they did to the vault, so that a test can
+//! check the items afterwards and which commands ran.
+
+use crate::runner::{CommandRunner, Pending};
+use crate::BitwardenVault;
+use serde_json::{json, Value};
+use std::cell::{RefCell, RefMut};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+/// Flags of `bw` that don't change what a command does here.
+const GLOBAL_FLAGS: &[&str] = &[
+    "--nointeraction",
+    "--cleanexit",
+    "--raw",
+    "--response",
+    "--pretty",
+    "--quiet",
+];
+
+/// Options of `bw` that take a value.
+const VALUE_OPTIONS: &[&str] = &[
+    "--session",
+    "--url",
+    "--search",
+    "--organizationid",
+    "--folderid",
+    "--collectionid",
+    "--method",
+    "--code",
+    "--passwordenv",
+    "--api",
+    "--identity",
+];
+
+/// A command the provider ran.
+#[derive(Debug, Clone)]
+pub struct Call {
+    /// Its arguments without the [`GLOBAL_FLAGS`].
+    pub args: Vec<String>,
+    /// What it got on stdin.
+    pub input: Option<Vec<u8>>,
+    /// The session key it got in `BW_SESSION`.
+    pub session: Option<String>,
+}
+
+impl Call {
+    /// Whether it is the command starting with `words`, like
+    /// `["list", "items"]`.
+    pub fn is(&self, words: &[impl AsRef<str>]) -> bool {
+        self.args.len() >= words.len()
+            && self
+                .args
+                .iter()
+                .zip(words)
+                .all(|(arg, word)| arg == word.as_ref())
+    }
+
+    /// The value of option `name`, like `--url`.
+    pub fn option(&self, name: &str) -> Option<&str> {
+        let index = self.args.iter().position(|arg| arg == name)?;
+        self.args.get(index + 1).map(String::as_str)
+    }
+
+    /// Whether it has flag `name`, like `--permanent`.
+    pub fn has(&self, name: &str) -> bool {
+        self.args.iter().any(|arg| arg == name)
+    }
+
+    /// Its stdin decoded as [`FakeBw`] decodes it for `bw create` and
+    /// `bw edit`: base64 encoded JSON.
+    pub fn payload(&self) -> Option<Value> {
+        decode_payload(self.input.as_deref()?)
+    }
+}
+
+/// The vault behind a [`FakeBw`].
+pub struct State {
+    pub items: Vec<Value>,
+    /// Items added on another device, which the next `bw sync` brings
+    /// into `items`.
+    pub remote: Vec<Value>,
+    pub trash: Vec<Value>,
+    pub folders: Vec<Value>,
+    pub collections: Vec<Value>,
+    /// `unlocked`, `locked` or `unauthenticated`.
+    pub status: String,
+    pub server: Option<String>,
+    pub email: String,
+    /// What `bw --version` prints.
+    pub version: String,
+    /// Text Sends by their URL.
+    pub sends: Vec<(String, Value)>,
+    /// What programs other than `bw` print, by their name and first
+    /// arguments, like the shell of `--password-command`.
+    programs: Vec<(Vec<String>, String)>,
+    /// Every command run, in order.
+    pub calls: Vec<Call>,
+    /// `BW_SESSION` of the environment of the provider.
+    pub env_session: Option<String>,
+    /// How long commands started in the background take to finish.
+    pub background_delay: Duration,
+    /// How many commands started in the background have finished.
+    pub background_finished: Arc<AtomicUsize>,
+    /// Commands to fail, by their first words, with the message and how
+    /// many more times.
+    failures: Vec<(Vec<String>, String, usize)>,
+    /// What commands print instead, by their first words.
+    printed: Vec<(Vec<String>, String)>,
+    /// How long commands take, by their first words.
+    delays: Vec<(Vec<String>, Duration)>,
+    next_id: usize,
+}
+
+/// The fake CLI, shared by the vault using it and the test.
+#[derive(Clone)]
+pub struct FakeBw(Rc<RefCell<State>>);
+
+impl FakeBw {
+    /// An unlocked, empty vault.
+    pub fn new() -> FakeBw {
+        FakeBw(Rc::new(RefCell::new(State {
+            items: Vec::new(),
+            remote: Vec::new(),
+            trash: Vec::new(),
+            folders: Vec::new(),
+            collections: Vec::new(),
+            status: "unlocked".to_string(),
+            server: None,
+            email: "me@example.com".to_string(),
+            version: "2024.6.0".to_string(),
+            sends: Vec::new(),
+            programs: Vec::new(),
+            calls: Vec::new(),
+            env_session: None,
+            background_delay: Duration::ZERO,
+            background_finished: Arc::new(AtomicUsize::new(0)),
+            failures: Vec::new(),
+            printed: Vec::new(),
+            delays: Vec::new(),
+            next_id: 1,
+        })))
+    }
+
+    /// An unlocked vault holding `items`.
+    pub fn with_items(items: Vec<Value>) -> FakeBw {
+        let bw = FakeBw::new();
+        bw.state().items = items;
+        bw
+    }
+
+    pub fn state(&self) -> RefMut<'_, State> {
+        self.0.borrow_mut()
+    }
+
+    /// A vault configured with the provider arguments `args`, running its
+    /// commands here, with `env_session` for `BW_SESSION` rather than that
+    /// of the test process.
+    pub fn vault(&self, args: &[&str]) -> BitwardenVault {
+        let bw_path = bw_path().to_string_lossy().into_owned();
+        let mut args = args.to_vec();
+        args.extend(["--bw-path", &bw_path]);
+        let mut vault = BitwardenVault::new(&args)
+            .unwrap_or_else(|e| panic!("bad arguments {:?}: {}", args, e))
+            .with_runner(self.clone());
+        vault.env_session = self.state().env_session.clone();
+        vault
+    }
+
+    /// Make the commands starting with `words` print `output` as it is,
+    /// like a captured fixture, instead of what they printed before.
+    pub fn print(&self, words: &[&str], output: &str) {
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        let mut state = self.state();
+        state.printed.retain(|(printed, _)| *printed != words);
+        state.printed.push((words, output.to_string()));
+    }
+
+    /// Make program `words[0]`, run with arguments starting with the rest
+    /// of `words`, print `output`.
+    pub fn program(&self, words: &[&str], output: &str) {
+        let words = words.iter().map(|word| word.to_string()).collect();
+        self.state().programs.push((words, output.to_string()));
+    }
+
+    /// Fail the commands starting with `words` with `message`, as `bw`
+    /// reports it, `times` times.
+    pub fn fail(&self, words: &[&str], message: &str, times: usize) {
+        let words = words.iter().map(|word| word.to_string()).collect();
+        self.state()
+            .failures
+            .push((words, message.to_string(), times));
+    }
+
+    /// Make the commands starting with `words` take `delay` instead of
+    /// what they took before. Those with a shorter timeout are killed right
+    /// away instead.
+    pub fn delay(&self, words: &[&str], delay: Duration) {
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        let mut state = self.state();
+        state.delays.retain(|(delayed, _)| *delayed != words);
+        state.delays.push((words, delay));
+    }
+
+    /// The item with `id`, live or in the trash.
+    pub fn item(&self, id: &str) -> Option<Value> {
+        let state = self.0.borrow();
+        state
+            .items
+            .iter()
+            .chain(&state.trash)
+            .find(|item| item["id"] == id)
+            .cloned()
+    }
+
+    /// The commands run so far.
+    pub fn calls(&self) -> Vec<Call> {
+        self.0.borrow().calls.clone()
+    }
+
+    /// The commands run so far starting with `words`.
+    pub fn calls_of(&self, words: &[&str]) -> Vec<Call> {
+        self.calls()
+            .into_iter()
+            .filter(|call| call.is(words))
+            .collect()
+    }
+}
+
+impl CommandRunner for FakeBw {
+    fn run(
+        &self,
+        cmd: &mut Command,
+        input: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Output>, String> {
+        let program = std::path::Path::new(cmd.get_program())
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        let all: Vec<String> = cmd
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let response = all.iter().any(|arg| arg == "--response");
+        let args: Vec<String> = all
+            .into_iter()
+            .filter(|arg| !GLOBAL_FLAGS.contains(&arg.as_str()))
+            .collect();
+        let env = |name: &str| {
+            cmd.get_envs()
+                .find(|(key, _)| *key == name)
+                .and_then(|(_, value)| value)
+                .map(|value| value.to_string_lossy().into_owned())
+        };
+        let call = Call {
+            args,
+            input: input.map(<[u8]>::to_vec),
+            session: env(crate::SESSION_ENV),
+        };
+        let mut state = self.state();
+        state.calls.push(call.clone());
+
+        if program != "bw" {
+            let printed = state
+                .programs
+                .iter()
+                .find(|(words, _)| words[0] == program && call.is(&words[1..]))
+                .map(|(_, printed)| printed.clone());
+            return match printed {
+                Some(printed) => Ok(Some(output(0, printed, String::new()))),
+                None => Err(format!("failed to spawn `{}`: not found", program)),
+            };
+        }
+
+        let delay = state.delays.iter().find(|(words, _)| call.is(words));
+        if let Some(&(_, delay)) = delay {
+            if timeout.is_some_and(|timeout| timeout < delay) {
+                return Ok(None);
+            }
+            std::thread::sleep(delay);
+        }
+
+        let failure = state
+            .failures
+            .iter_mut()
+            .find(|(words, _, times)| *times > 0 && call.is(words));
+        if let Some((_, message, times)) = failure {
+            *times -= 1;
+            return Ok(Some(fail(response, message)));
+        }
+
+        let printed = state.printed.iter().find(|(words, _)| call.is(words));
+        if let Some((_, printed)) = printed {
+            return Ok(Some(output(0, printed.clone(), String::new())));
+        }
+
+        let answer = state.answer(&call, &env);
+        Ok(Some(match answer {
+            Ok(Answer::Text(text)) => output(0, format!("{}\n", text), String::new()),
+            Ok(Answer::Json(data)) if response => {
+                let data = match data {
+                    Value::Array(items) => json!({ "object": "list", "data": items }),
+                    data => data,
+                };
+                let envelope = json!({ "success": true, "data": data });
+                output(0, envelope.to_string(), String::new())
+            }
+            Ok(Answer::Json(data)) => output(0, data.to_string(), String::new()),
+            Err(message) => fail(response, &message),
+        }))
+    }
+
+    /// Does what [`Self::run`] does right away, and only takes
+    /// `background_delay` to report it.
+    fn start(&self, cmd: &mut Command) -> Result<Pending, String> {
+        let output = self.run(cmd, None, None)?.ok_or("stopped")?;
+        let state = self.state();
+        let delay = state.background_delay;
+        let finished = Arc::clone(&state.background_finished);
+        Ok(Box::new(move || {
+            std::thread::sleep(delay);
+            finished.fetch_add(1, Ordering::SeqCst);
+            Ok(output)
+        }))
+    }
+}
+
+/// What a command printed when it succeeded.
+enum Answer {
+    /// Printed as is, like a session key.
+    Text(String),
+    /// Printed as JSON, in the `--response` envelope when asked for.
+    Json(Value),
+}
+
+impl State {
+    fn answer(
+        &mut self,
+        call: &Call,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Answer, String> {
+        let mut positional = Vec::new();
+        let mut args = call.args.iter();
+        while let Some(arg) = args.next() {
+            if VALUE_OPTIONS.contains(&arg.as_str()) {
+                args.next();
+            } else if !arg.starts_with("--") {
+                positional.push(arg.as_str());
+            }
+        }
+        if call.has("--version") {
+            return Ok(Answer::Text(self.version.clone()));
+        }
+        if call.has("--help") {
+            return Ok(Answer::Text(
+                "Options:\n  --raw\n  --response\n  --nointeraction\n  --cleanexit".into(),
+            ));
+        }
+
+        let now = now();
+        match positional.as_slice() {
+            ["status"] => {
+                return Ok(Answer::Json(json!({
+                    "serverUrl": self.server,
+                    "lastSync": now,
+                    "userEmail": self.email,
+                    "userId": "u1",
+                    "status": self.status,
+                })))
+            }
+            ["encode"] => {
+                return Ok(Answer::Text(encode(
+                    call.input.as_deref().unwrap_or_default(),
+                )));
+            }
+            ["config", "server", url, ..] => {
+                self.server = Some(url.to_string());
+                return Ok(Answer::Text("Saved setting `config`.".into()));
+            }
+            ["sync", ..] => {
+                self.items.append(&mut self.remote);
+                return Ok(Answer::Text("Syncing complete.".into()));
+            }
+            ["login", ..] => {
+                if self.status != "unauthenticated" {
+                    return Err(format!("You are already logged in as {}.", self.email));
+                }
+                self.status = "unlocked".into();
+                return Ok(Answer::Text("fake-session-key".into()));
+            }
+            ["unlock", ..] => {
+                let password = call.option("--passwordenv").and_then(env);
+                if password.as_deref() == Some("wrong") {
+                    return Err("Invalid master password.".into());
+                }
+                self.status = "unlocked".into();
+                return Ok(Answer::Text("fake-session-key".into()));
+            }
+            ["lock"] => {
+                self.status = "locked".into();
+                return Ok(Answer::Text("Your vault is locked.".into()));
+            }
+            ["logout"] => {
+                self.status = "unauthenticated".into();
+                return Ok(Answer::Text("You have logged out.".into()));
+            }
+            _ => {}
+        }
+        if self.status != "unlocked" && call.session.is_none() {
+            return Err("Vault is locked.".into());
+        }
+
+        match positional.as_slice() {
+            ["list", "items"] => {
+                let items = match call.has("--trash") {
+                    true => &self.trash,
+                    false => &self.items,
+                };
+                let uris = |item: &Value| -> Vec<String> {
+                    item["login"]["uris"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|uri| uri["uri"].as_str())
+                        .map(str::to_lowercase)
+                        .collect()
+                };
+                let host = |url: &str| url.split('/').nth(2).map(str::to_string);
+                let found: Vec<Value> = items
+                    .iter()
+                    .filter(|item| {
+                        call.option("--url").map_or(true, |url| {
+                            let wanted = host(&url.to_lowercase());
+                            uris(item).iter().any(|uri| host(uri) == wanted)
+                        })
+                    })
+                    .filter(|item| {
+                        call.option("--search").map_or(true, |term| {
+                            let term = term.to_lowercase();
+                            let name = item["name"].as_str().unwrap_or_default();
+                            name.to_lowercase().contains(&term)
+                                || uris(item).iter().any(|uri| uri.contains(&term))
+                        })
+                    })
+                    .filter(|item| {
+                        call.option("--organizationid")
+                            .map_or(true, |id| item["organizationId"] == id)
+                    })
+                    .filter(|item| {
+                        call.option("--folderid")
+                            .map_or(true, |id| item["folderId"] == id)
+                    })
+                    .cloned()
+                    .collect();
+                Ok(Answer::Json(found.into()))
+            }
+            ["list", "folders"] => Ok(Answer::Json(self.folders.clone().into())),
+            ["list", "org-collections"] => Ok(Answer::Json(self.collections.clone().into())),
+            ["list", "organizations"] => Ok(Answer::Json(json!([]))),
+            ["get", "item", id] => self
+                .items
+                .iter()
+                .chain(&self.trash)
+                .find(|item| item["id"] == *id)
+                .cloned()
+                .map(Answer::Json)
+                .ok_or_else(|| "Not found.".into()),
+            ["get", "template", "item"] => Ok(Answer::Json(json!({
+                "organizationId": null,
+                "collectionIds": null,
+                "folderId": null,
+                "type": 1,
+                "name": "Item name",
+                "notes": "Some notes about this item.",
+                "favorite": false,
+                "fields": [],
+                "login": null,
+                "secureNote": null,
+                "card": null,
+                "identity": null,
+                "reprompt": 0,
+            }))),
+            ["get", "template", "item.login"] => Ok(Answer::Json(json!({
+                "uris": [],
+                "username": "jdoe",
+                "password": "myp@ssword123",
+                "totp": "JBSWY3DPEHPK3PXP",
+            }))),
+            ["create", what, rest @ ..] => {
+                let mut data = payload(call, rest.first())?;
+                data["id"] = self.new_id().into();
+                data["revisionDate"] = now.into();
+                data["object"] = (*what).into();
+                match *what {
+                    "item" => self.items.push(data.clone()),
+                    "folder" => self.folders.push(data.clone()),
+                    _ => return Err(format!("unknown object: {}", what)),
+                }
+                Ok(Answer::Json(data))
+            }
+            ["edit", "item", id, rest @ ..] => {
+                let index = self.position(id).ok_or("Not found.")?;
+                let mut data = payload(call, rest.first())?;
+                data["id"] = (*id).into();
+                data["revisionDate"] = now.into();
+                self.items[index] = data.clone();
+                Ok(Answer::Json(data))
+            }
+            ["delete", "item", id] => {
+                let index = self.position(id).ok_or("Not found.")?;
+                let mut item = self.items.remove(index);
+                if !call.has("--permanent") {
+                    item["deletedDate"] = now.into();
+                    self.trash.push(item);
+                }
+                Ok(Answer::Text(String::new()))
+            }
+            ["restore", "item", id] => {
+                let index = self
+                    .trash
+                    .iter()
+                    .position(|item| item["id"] == *id)
+                    .ok_or("Not found.")?;
+                let mut item = self.trash.remove(index);
+                item["deletedDate"] = Value::Null;
+                self.items.push(item);
+                Ok(Answer::Text(String::new()))
+            }
+            ["share" | "move", id, organization_id, rest @ ..] => {
+                let index = self.position(id).ok_or("Not found.")?;
+                let collections = match rest.first() {
+                    Some(encoded) => decode_payload(encoded.as_bytes()).ok_or("bad payload")?,
+                    None => json!([]),
+                };
+                let item = &mut self.items[index];
+                item["organizationId"] = (*organization_id).into();
+                item["collectionIds"] = collections;
+                Ok(Answer::Json(item.clone()))
+            }
+            ["receive", url] => {
+                let send = self
+                    .sends
+                    .iter()
+                    .find(|(send_url, _)| send_url == url)
+                    .map(|(_, send)| send.clone())
+                    .ok_or("Not found.")?;
+                if let Some(password) = send["password"].as_str() {
+                    match call.option("--passwordenv").and_then(env) {
+                        None => return Err("Password is required.".into()),
+                        Some(given) if given != password => {
+                            return Err("Password is incorrect.".into())
+                        }
+                        Some(_) => {}
+                    }
+                }
+                Ok(Answer::Json(send["obj"].clone()))
+            }
+            _ => Err(format!("unknown command: {:?}", call.args)),
+        }
+    }
+
+    fn position(&self, id: &str) -> Option<usize> {
+        self.items.iter().position(|item| item["id"] == id)
+    }
+
+    fn new_id(&mut self) -> String {
+        let id = format!("00000000-0000-4000-8000-{:012}", self.next_id);
+        self.next_id += 1;
+        id
+    }
+}
+
+/// A login item with one URI, last changed on `revised`.
+pub fn login(id: &str, name: &str, uri: &str, password: &str, revised: &str) -> Value {
+    json!({
+        "object": "item",
+        "id": id,
+        "organizationId": null,
+        "folderId": null,
+        "type": 1,
+        "reprompt": 0,
+        "name": name,
+        "notes": null,
+        "favorite": false,
+        "fields": [],
+        "login": {
+            "uris": [{ "match": null, "uri": uri }],
+            "username": null,
+            "password": password,
+            "totp": null,
+            "passwordRevisionDate": null,
+        },
+        "collectionIds": [],
+        "revisionDate": revised,
+        "deletedDate": null,
+    })
+}
+
+/// The item data `bw create` or `bw edit` got, on stdin or as `argument`.
+fn payload(call: &Call, argument: Option<&&str>) -> Result<Value, String> {
+    let encoded = match argument {
+        Some(argument) => argument.as_bytes(),
+        None => call.input.as_deref().unwrap_or_default(),
+    };
+    decode_payload(encoded).ok_or_else(|| "Error parsing the encoded request data.".into())
+}
+
+fn decode_payload(encoded: &[u8]) -> Option<Value> {
+    let text = std::str::from_utf8(encoded).ok()?;
+    serde_json::from_slice(&decode(text.trim())?).ok()
+}
+
+fn now() -> String {
+    time::OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap()
+}
+
+fn output(code: i32, stdout: String, stderr: String) -> Output {
+    Output {
+        status: exit_status(code),
+        stdout: stdout.into_bytes(),
+        stderr: stderr.into_bytes(),
+    }
+}
+
+/// A failure as `bw` reports it: in the envelope with `--response`, and
+/// on stderr otherwise.
+fn fail(response: bool, message: &str) -> Output {
+    match response {
+        true => output(
+            1,
+            json!({ "success": false, "message": message }).to_string(),
+            String::new(),
+        ),
+        false => output(1, String::new(), format!("{}\n", message)),
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+
+const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Base64, as `bw encode` prints it.
+pub fn encode(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+pub fn decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut bits = 0u32;
+    let mut count = 0;
+    for byte in encoded.bytes().filter(|&byte| byte != b'=') {
+        let value = ALPHABET.iter().position(|&c| c == byte)? as u32;
+        bits = bits << 6 | value;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            data.push((bits >> count) as u8);
+        }
+    }
+    Some(data)
+}
+
+/// A file named `bw` for `--bw-path`, which must exist, in a directory of
+/// this test process that also holds the provider's state.
+///
+/// The state and config directories point there, so that tests don't see
+/// the caches, locks and config of whoever runs them.
+pub fn bw_path() -> &'static PathBuf {
+    static PATH: OnceLock<PathBuf> = OnceLock::new();
+    PATH.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!(
+            "cargo-credential-bitwarden-tests-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("XDG_STATE_HOME", dir.join("state"));
+        std::env::set_var("XDG_CONFIG_HOME", dir.join("config"));
+        std::env::remove_var(crate::SESSION_ENV);
+        let path = dir.join("bw");
+        std::fs::write(&path, "").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        path
+    })
+}
+
+/// A fresh directory `name` in the directory of this test process.
+/// `path` relative to the current directory.
+pub fn relative(path: &Path) -> PathBuf {
+    let normal = |path: &Path| {
+        path.components()
+            .filter(|part| matches!(part, Component::Normal(_)))
+            .map(|part| part.as_os_str().to_os_string())
+            .collect::<Vec<_>>()
+    };
+    let up = normal(&std::env::current_dir().unwrap()).len();
+    let mut relative: PathBuf = std::iter::repeat("..").take(up).collect();
+    relative.extend(normal(path));
+    relative
+}
+
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = bw_path().parent().unwrap().join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,154 @@
+//! Asking another credential provider for the token when the vault has none,
+//! for `--fallback-provider`.
+
+use crate::matching::normalize_index_url;
+use crate::{cancel, cargo_config, redact};
+use cargo_credential::{
+    Action, CacheControl, CredentialHello, CredentialRequest, CredentialResponse, Error, Operation,
+    RegistryInfo, Secret,
+};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+/// Set for fallback providers, so that we notice being chained to ourselves.
+const CHAINED_ENV: &str = "CARGO_CREDENTIAL_BITWARDEN_CHAINED";
+
+/// Get the token for `registry` from the provider `command`, written like
+/// in cargo's `credential-provider` setting.
+pub fn get(
+    command: &str,
+    registry: &RegistryInfo<'_>,
+    operation: &Operation<'_>,
+) -> Result<CredentialResponse, Error> {
+    if std::env::var_os(CHAINED_ENV).is_some() {
+        return Err(
+            "`--fallback-provider` leads back to cargo-credential-bitwarden; \
+                    refusing to chain to ourselves"
+                .into(),
+        );
+    }
+
+    let mut words = command.split_whitespace();
+    let program = words.next().ok_or("`--fallback-provider` is empty")?;
+    let args: Vec<&str> = words.collect();
+    match program {
+        "cargo:token" => return stored_token(registry).map_err(|e| relayed(program, e)),
+        builtin if builtin.starts_with("cargo:") => {
+            return Err(format!(
+                "`--fallback-provider {}` is built into cargo and can't be run by another \
+                 provider; list it after this provider in `credential-provider` instead",
+                builtin
+            )
+            .into())
+        }
+        _ => {}
+    }
+
+    let mut cmd = Command::new(program);
+    cmd.args(&args)
+        .arg("--cargo-plugin")
+        .env(CHAINED_ENV, "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped());
+    let mut child = cancel::spawn(&mut cmd)
+        .map_err(|e| format!("failed to run fallback provider `{}`: {}", program, e))?;
+    let mut input = child.stdin.take().ok_or("fallback provider has no stdin")?;
+    let mut output = BufReader::new(
+        child
+            .stdout
+            .take()
+            .ok_or("fallback provider has no stdout")?,
+    );
+    let protocol_error = |e: &dyn std::fmt::Display| -> Error {
+        format!("fallback provider `{}` failed: {}", program, e).into()
+    };
+
+    let mut line = String::new();
+    output
+        .read_line(&mut line)
+        .map_err(|e| protocol_error(&e))?;
+    let hello: CredentialHello = serde_json::from_str(&line).map_err(|e| protocol_error(&e))?;
+    if !hello.v.contains(&cargo_credential::PROTOCOL_VERSION_1) {
+        return Err(protocol_error(&"it doesn't speak protocol version 1"));
+    }
+
+    let request = CredentialRequest {
+        v: cargo_credential::PROTOCOL_VERSION_1,
+        registry: registry.clone(),
+        action: Action::Get(operation.clone()),
+        args,
+    };
+    let request = serde_json::to_string(&request).map_err(|e| protocol_error(&e))?;
+    writeln!(input, "{}", request).map_err(|e| protocol_error(&e))?;
+
+    line.clear();
+    output
+        .read_line(&mut line)
+        .map_err(|e| protocol_error(&e))?;
+    // Like cargo, only close stdin once the answer is in.
+    drop(input);
+    let _ = child.wait();
+
+    let response: Result<CredentialResponse, Error> =
+        serde_json::from_str(&line).map_err(|e| protocol_error(&e))?;
+    if let Ok(CredentialResponse::Get { token, .. }) = &response {
+        redact::register(token.as_deref().expose());
+    }
+    response.map_err(|e| relayed(program, e))
+}
+
+/// An error the fallback provider `program` answered with, naming it so
+/// that it isn't taken for one of the vault. Cargo words the errors without
+/// a message itself.
+fn relayed(program: &str, e: Error) -> Error {
+    match e {
+        Error::NotFound | Error::UrlNotSupported | Error::OperationNotSupported => e,
+        e => format!("fallback provider `{}`: {}", program, e).into(),
+    }
+}
+
+/// What cargo's own `cargo:token` provider would answer: the token from
+/// the environment or `credentials.toml`.
+fn stored_token(registry: &RegistryInfo<'_>) -> Result<CredentialResponse, Error> {
+    let name = match registry.name {
+        Some(name) => Some(name.to_string()),
+        None => {
+            let url = normalize_index_url(registry.index_url);
+            cargo_config::registries(&cargo_config::config_paths())?
+                .into_iter()
+                .find(|(_, index)| normalize_index_url(index) == url)
+                .map(|(name, _)| name)
+        }
+    };
+    let name = name.ok_or(Error::NotFound)?;
+    let crates_io = name == "crates-io";
+
+    let env_name = if crates_io {
+        String::from("CARGO_REGISTRY_TOKEN")
+    } else {
+        format!(
+            "CARGO_REGISTRIES_{}_TOKEN",
+            name.to_uppercase().replace('-', "_")
+        )
+    };
+    let token = match std::env::var(env_name) {
+        Ok(token) => token,
+        Err(_) => {
+            let path = cargo_config::credentials_path().ok_or(Error::NotFound)?;
+            let doc = cargo_config::read_toml(&path)?.ok_or(Error::NotFound)?;
+            let wanted = (!crates_io).then_some(name.as_str());
+            cargo_config::stored_tokens(&doc)
+                .into_iter()
+                .find(|stored| stored.registry.as_deref() == wanted)
+                .ok_or(Error::NotFound)?
+                .token
+        }
+    };
+
+    redact::register(&token);
+    Ok(CredentialResponse::Get {
+        token: Secret::from(token),
+        cache: CacheControl::Session,
+        operation_independent: true,
+    })
+}
@@ -0,0 +1,209 @@
+//! Sanitizing `bw` output for `--capture-fixture`.
+//!
+//! Captured output keeps its shape: every key, every `null`, number and
+//! boolean, and which strings are equal to each other. The strings
+//! themselves are replaced, except for the ones describing that shape, such
+//! as dates, vault states and the names of the custom fields this provider
+//! uses.
+
+use crate::scope::Scope;
+use crate::ITEM_NAME_PREFIX;
+use serde_json::Value;
+use std::collections::HashMap;
+use url::Url;
+
+/// Keys whose string values are kept as they are.
+const KEPT: &[&str] = &[
+    "object",
+    "status",
+    "creationDate",
+    "revisionDate",
+    "deletedDate",
+    "lastSync",
+    "lastUsedDate",
+    "passwordRevisionDate",
+];
+
+/// Replaces the strings of captured output, consistently across captures.
+#[derive(Default)]
+pub struct Sanitizer {
+    ids: HashMap<String, String>,
+    hosts: HashMap<String, String>,
+    strings: HashMap<String, String>,
+}
+
+impl Sanitizer {
+    /// Sanitize `output`, as printed by a `bw` command. Output that isn't
+    /// JSON is replaced as a whole.
+    pub fn sanitize_output(&mut self, output: &str) -> String {
+        match serde_json::from_str::<Value>(output.trim()) {
+            Ok(mut value) => {
+                self.sanitize(None, &mut value);
+                let mut json = serde_json::to_string_pretty(&value).unwrap();
+                json.push('\n');
+                json
+            }
+            Err(_) => format!("{}\n", self.replace(output.trim())),
+        }
+    }
+
+    fn sanitize(&mut self, key: Option<&str>, value: &mut Value) {
+        match value {
+            Value::Array(values) => {
+                for value in values {
+                    self.sanitize(key, value);
+                }
+            }
+            Value::Object(object) => {
+                for (key, value) in object.iter_mut() {
+                    self.sanitize(Some(key), value);
+                }
+            }
+            Value::String(s) => *s = self.sanitize_string(key.unwrap_or_default(), s),
+            Value::Null | Value::Bool(_) | Value::Number(_) => {}
+        }
+    }
+
+    fn sanitize_string(&mut self, key: &str, s: &str) -> String {
+        if s.is_empty() || KEPT.contains(&key) {
+            return s.to_string();
+        }
+        if key == "id" || key.ends_with("Id") {
+            let n = self.ids.len() + 1;
+            return self
+                .ids
+                .entry(s.to_string())
+                .or_insert_with(|| format!("00000000-0000-0000-0000-{:012}", n))
+                .clone();
+        }
+        if key == "name" && s.starts_with("cargo-") {
+            return s.to_string();
+        }
+        if key == "name" {
+            if let Some(registry) = s.strip_prefix(ITEM_NAME_PREFIX) {
+                // The scope suffix of `--scoped` items says which token it is.
+                let (registry, suffix) = match Scope::from_item_name(registry) {
+                    Some(scope) => {
+                        let suffix = format!(" ({})", scope.as_str());
+                        let cut = registry.len().saturating_sub(suffix.len());
+                        match registry.get(cut..) {
+                            Some(end) if end.eq_ignore_ascii_case(&suffix) => {
+                                (&registry[..cut], suffix)
+                            }
+                            _ => (registry, String::new()),
+                        }
+                    }
+                    None => (registry, String::new()),
+                };
+                let registry = self.replace(registry);
+                return format!("{}{}{}", ITEM_NAME_PREFIX, registry, suffix);
+            }
+        }
+        // Custom field values hold URLs too, like `cargo-registry-url`.
+        if key == "uri" || key == "value" || key.ends_with("Url") {
+            if let Some(url) = self.sanitize_url(s) {
+                return url;
+            }
+        }
+        self.replace(s)
+    }
+
+    /// Replace the host of a URL and drop its credentials, keeping its
+    /// scheme, port and path, and the `sparse+` prefix cargo uses.
+    fn sanitize_url(&mut self, s: &str) -> Option<String> {
+        let (prefix, raw) = match s.strip_prefix("sparse+") {
+            Some(raw) => ("sparse+", raw),
+            None => ("", s),
+        };
+        let mut url = Url::parse(raw).ok()?;
+        let host = url.host_str()?.to_string();
+        let n = self.hosts.len() + 1;
+        let replaced = self
+            .hosts
+            .entry(host)
+            .or_insert_with(|| format!("host-{}.example", n))
+            .clone();
+        url.set_host(Some(&replaced)).ok()?;
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+        // `Url` adds a slash to bare hosts; keep the original spelling.
+        let mut url = url.to_string();
+        if !raw.ends_with('/') && url.ends_with('/') {
+            url.pop();
+        }
+        Some(format!("{}{}", prefix, url))
+    }
+
+    fn replace(&mut self, s: &str) -> String {
+        let n = self.strings.len() + 1;
+        self.strings
+            .entry(s.to_string())
+            .or_insert_with(|| format!("redacted-{}", n))
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn keeps_shape() {
+        let mut sanitizer = Sanitizer::default();
+        let output = json!({
+            "id": "abc",
+            "organizationId": "abc",
+            "folderId": null,
+            "type": 1,
+            "favorite": true,
+            "name": "cargo-token-read",
+            "revisionDate": "2024-01-01T00:00:00.000Z",
+            "notes": "",
+            "login": { "password": "secret", "username": "secret" },
+        })
+        .to_string();
+        let sanitized: Value = serde_json::from_str(&sanitizer.sanitize_output(&output)).unwrap();
+        assert_eq!(
+            sanitized,
+            json!({
+                "id": "00000000-0000-0000-0000-000000000001",
+                "organizationId": "00000000-0000-0000-0000-000000000001",
+                "folderId": null,
+                "type": 1,
+                "favorite": true,
+                "name": "cargo-token-read",
+                "revisionDate": "2024-01-01T00:00:00.000Z",
+                "notes": "",
+                "login": { "password": "redacted-1", "username": "redacted-1" },
+            })
+        );
+    }
+
+    #[test]
+    fn replaces_hosts() {
+        let mut sanitizer = Sanitizer::default();
+        let cases = [
+            (
+                "sparse+https://user:pw@registry.corp.example:8443/index/",
+                "sparse+https://host-1.example:8443/index/",
+            ),
+            ("https://registry.corp.example", "https://host-1.example"),
+            (
+                "https://other.example/login",
+                "https://host-2.example/login",
+            ),
+        ];
+        for (url, sanitized) in cases {
+            assert_eq!(sanitizer.sanitize_string("uri", url), sanitized);
+        }
+        assert_eq!(
+            sanitizer.sanitize_string("name", "Cargo registry token for corp (read)"),
+            "Cargo registry token for redacted-1 (read)"
+        );
+        assert_eq!(
+            sanitizer.sanitize_output("Vault is locked.\n"),
+            "redacted-2\n"
+        );
+    }
+}
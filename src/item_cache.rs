@@ -0,0 +1,60 @@
+//! Which item held the token of each registry, so that the next request
+//! can fetch that item by its id instead of searching the vault.
+//!
+//! The file, in the user's cache directory, only maps index URLs to item
+//! ids, nothing secret. An id that no longer leads to a matching item is
+//! searched for again and replaced.
+
+use crate::state;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::PathBuf;
+
+fn path() -> Option<PathBuf> {
+    Some(state::cache_dir()?.join("item-ids.json"))
+}
+
+fn read() -> Map<String, Value> {
+    path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn write(ids: &Map<String, Value>) {
+    let Some(path) = path() else {
+        return;
+    };
+    // A cache that can't be written is only slower.
+    let _ = state::write_atomic(&path, Value::Object(ids.clone()).to_string().as_bytes());
+}
+
+/// The id of the item last found for `key`.
+pub fn get(key: &str) -> Option<String> {
+    read().get(key)?.as_str().map(str::to_string)
+}
+
+/// Remember that the item for `key` is `id`.
+pub fn set(key: &str, id: &str) {
+    let mut ids = read();
+    if ids.get(key).and_then(Value::as_str) == Some(id) {
+        return;
+    }
+    ids.insert(key.to_string(), id.into());
+    write(&ids);
+}
+
+/// Forget the items of `key`, and of the keys made of it and a scope.
+pub fn evict(key: &str) {
+    let mut ids = read();
+    let before = ids.len();
+    ids.retain(|cached, _| {
+        cached != key
+            && !cached
+                .strip_prefix(key)
+                .is_some_and(|rest| rest.starts_with(' '))
+    });
+    if ids.len() != before {
+        write(&ids);
+    }
+}
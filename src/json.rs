@@ -0,0 +1,207 @@
+//! Helpers for the raw item JSON that `bw` reads and writes, and for
+//! parsing what it prints.
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fmt;
+
+/// Merge `patch` into `base`: objects are merged key by key, anything else
+/// in `patch` replaces what `base` has.
+pub fn merge(base: &mut Value, patch: &Value) {
+    match (base, patch) {
+        (Value::Object(base), Value::Object(patch)) => {
+            for (key, value) in patch {
+                merge(base.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (base, patch) => *base = patch.clone(),
+    }
+}
+
+/// Strip the example values from a `bw get template` object, keeping its
+/// shape and the non-text defaults (item type, flags).
+pub fn blank(template: &mut Value) {
+    match template {
+        Value::Object(object) => object.values_mut().for_each(blank),
+        Value::Array(array) => array.clear(),
+        Value::String(_) => *template = Value::Null,
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+/// Longest excerpt of unparseable output to put in an error.
+const EXCERPT_LEN: usize = 200;
+
+/// Output of a `bw` command that isn't the JSON it should be.
+#[derive(Debug)]
+pub struct ParseError {
+    error: serde_json::Error,
+    /// What was printed instead, or before the JSON.
+    noise: String,
+    /// Whether noise is followed by something that looked like JSON.
+    json_follows: bool,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", without_strings(&self.error.to_string()))?;
+        if self.noise.is_empty() {
+            return Ok(());
+        }
+        // The JSON itself may hold the passwords of other items, so only
+        // what came before it is shown.
+        write!(f, "; the CLI printed `{}`", self.noise)?;
+        if self.json_follows {
+            f.write_str(" before the JSON")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse the JSON printed by a `bw` command, skipping what else the CLI may
+/// print around it, like update notices and terminal escape sequences.
+pub fn parse_output<T: DeserializeOwned>(output: &str) -> Result<T, ParseError> {
+    let output = strip_ansi(output);
+    let mut error = match serde_json::from_str(output.trim()) {
+        Ok(value) => return Ok(value),
+        Err(error) => error,
+    };
+
+    // Try every line that starts like JSON, ignoring whatever follows it.
+    let starts = output
+        .match_indices(['[', '{'])
+        .map(|(i, _)| i)
+        .filter(|&i| {
+            output[..i]
+                .chars()
+                .next_back()
+                .map_or(true, |c| c == '\n' || c == '\r')
+        });
+    let mut first_start = None;
+    for start in starts {
+        let mut values = serde_json::Deserializer::from_str(&output[start..]).into_iter::<T>();
+        match values.next() {
+            Some(Ok(value)) => return Ok(value),
+            // Why the first candidate failed says the most.
+            Some(Err(e)) if first_start.is_none() => error = e,
+            _ => {}
+        }
+        first_start.get_or_insert(start);
+    }
+
+    let noise = &output[..first_start.unwrap_or(output.len())];
+    Err(ParseError {
+        error,
+        noise: excerpt(noise.trim()),
+        json_follows: first_start.is_some(),
+    })
+}
+
+/// `message` of serde with the strings it quotes from the JSON left out,
+/// like in `invalid type: string "<a password>", expected u32`.
+fn without_strings(message: &str) -> String {
+    let mut parts = message.split('"');
+    let mut without = parts.next().unwrap_or_default().to_string();
+    while let (Some(_), Some(rest)) = (parts.next(), parts.next()) {
+        without.push_str("\"…\"");
+        without.push_str(rest);
+    }
+    without
+}
+
+/// `text` on one line, and shortened to [`EXCERPT_LEN`] characters.
+fn excerpt(text: &str) -> String {
+    let text = crate::redact::redact(&text.split_whitespace().collect::<Vec<_>>().join(" "));
+    match text.char_indices().nth(EXCERPT_LEN) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+/// `text` without ANSI escape sequences, like colors.
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            stripped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // Control sequences end with a byte in `@`..=`~`.
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // Operating system commands end with BEL or ESC `\`.
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\u{7}' || (c == '\u{1b}' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            // Other escapes are two characters long.
+            _ => {}
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_noise() {
+        let parse = |output: &str| parse_output::<Value>(output).unwrap();
+        assert_eq!(parse("  [1, 2]\n"), serde_json::json!([1, 2]));
+        assert_eq!(
+            parse("A new version is available\n{\"a\": 1}\n"),
+            serde_json::json!({ "a": 1 })
+        );
+        assert_eq!(
+            parse("\u{1b}[33m[notice] update\u{1b}[39m\n\u{1b}]0;bw\u{7}[true]"),
+            serde_json::json!([true])
+        );
+        // Whatever follows the JSON.
+        assert_eq!(
+            parse("{\"a\": 1}\n(node:1) ExperimentalWarning"),
+            serde_json::json!({ "a": 1 })
+        );
+    }
+
+    #[test]
+    fn errors_show_the_noise() {
+        let e = parse_output::<Value>("\u{1b}[31mERROR\u{1b}[0m\n  Vault   is\n locked")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            e.ends_with("; the CLI printed `ERROR Vault is locked`"),
+            "{}",
+            e
+        );
+
+        let e = parse_output::<Vec<u32>>("Notice\n[\"not a number\"]")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            e.starts_with("invalid type: string \"…\", expected u32"),
+            "{}",
+            e
+        );
+        assert!(e.ends_with("printed `Notice` before the JSON"), "{}", e);
+        assert!(!e.contains("not a number"), "{}", e);
+
+        let long = "x".repeat(EXCERPT_LEN * 2);
+        let e = parse_output::<Value>(&long).unwrap_err().to_string();
+        assert!(
+            e.contains(&format!("`{}…`", "x".repeat(EXCERPT_LEN))),
+            "{}",
+            e
+        );
+    }
+}
@@ -0,0 +1,3403 @@
+//! The Bitwarden credential provider for cargo, and the vault access it is
+//! built on.
+//!
+//! [`BitwardenCredential`] is the provider as cargo talks to it; [`run`] is
+//! the whole binary. [`BitwardenVault`] reads, stores and removes registry
+//! tokens directly, for tools that provision tokens without cargo:
+//!
+//! ```no_run
+//! use cargo_credential_bitwarden::BitwardenVault;
+//!
+//! let vault = BitwardenVault::new(&["--sync"])?;
+//! let session = vault.connect()?;
+//! if let Some(found) = vault.find_token(&session, "https://index.crates.io/")? {
+//!     println!("the token is in `{}`", found.item_name);
+//! }
+//! # Ok::<(), cargo_credential::Error>(())
+//! ```
+//!
+//! Commands go through a [`runner::CommandRunner`], which tests can replace
+//! with [`BitwardenVault::with_runner`] to answer them without a real CLI.
+//!
+//! [`matching`] is also useful on its own, e.g. to other credential
+//! providers.
+
+use cargo_credential::{
+    Action, CacheControl, Credential, CredentialResponse, Error, LoginOptions, Operation,
+    RegistryInfo, Secret,
+};
+use matching::{normalize_index_url, MatchMode, Selection, Uri};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::process::{Command, ExitCode, Output, Stdio};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+mod appdata;
+mod args_file;
+mod audit;
+mod backend;
+mod background;
+mod bw_error;
+mod bws;
+mod cancel;
+mod cargo_config;
+mod config_file;
+#[cfg(test)]
+mod fake;
+mod fallback;
+mod fingerprint;
+mod fixture;
+mod item_cache;
+mod json;
+mod locate;
+mod lock;
+mod logout;
+mod maintenance;
+pub mod matching;
+mod name_template;
+mod options;
+mod provenance;
+mod rbw;
+mod redact;
+mod refresh;
+pub mod runner;
+mod scope;
+mod serve;
+mod session;
+mod session_store;
+mod state;
+mod store;
+mod sync;
+#[cfg(test)]
+mod tests;
+mod timings;
+mod token_format;
+mod trace;
+mod tty;
+mod two_step;
+mod verbose;
+mod verify;
+mod version;
+mod wsl;
+
+use backend::Backend;
+use scope::Scope;
+use session::{Cached, SessionCache};
+use version::{Capabilities, Flag, Version};
+
+/// Implementation of Bitwarden Vault access for Cargo registries.
+///
+/// Built from the same arguments the provider takes in cargo's
+/// configuration, see [`BitwardenVault::new`].
+pub struct BitwardenVault {
+    /// What the arguments ask for.
+    options: options::Options,
+    /// The CLI, found before the first command.
+    setup: OnceCell<Setup>,
+    capabilities: OnceCell<Capabilities>,
+    /// Version of the CLI, when `bw --version` could be parsed.
+    version: Cell<Option<Version>>,
+    resolved_collection_ids: OnceCell<Vec<String>>,
+    resolved_folder_id: OnceCell<String>,
+    /// Time spent in each `bw` command, for [`Self::slow_hint`].
+    timings: RefCell<Vec<(String, Duration)>>,
+    /// Blank `bw get template item` with its login, `None` if unavailable.
+    item_template: OnceCell<Option<Value>>,
+    /// `BW_SESSION` of the environment the provider was started in.
+    env_session: Option<String>,
+    /// What `BW_SESSION` does to the vault, see [`Self::session_state`].
+    session_state: OnceCell<VaultState>,
+    /// Set while several registries are updated in one go.
+    batch: Cell<Option<BatchSync>>,
+    /// Set while reads use the local vault, to sync in the background after.
+    defer_sync: Cell<bool>,
+    /// Set for requests that don't change the vault, which read the local
+    /// vault when a sync fails.
+    only_reading: Cell<bool>,
+    /// Session from unlocking again after the vault locked itself during
+    /// this run, used instead of the one commands are made with.
+    renewed_session: RefCell<Option<String>>,
+    /// Whether the vault was unlocked again already, which happens once.
+    session_renewed: Cell<bool>,
+    /// Whether the master password was entered again for an item, which
+    /// is asked once per run.
+    reprompted: Cell<bool>,
+    /// Name cargo gave the registry of the current request, for
+    /// `--match-by-name`.
+    registry_name: RefCell<Option<String>>,
+    /// Id of the item the current request read the token from.
+    read_item: RefCell<Option<String>>,
+    /// Username split off the token of the current `cargo login`, for
+    /// `--split-token`.
+    split_username: RefCell<Option<String>>,
+    /// Runs the `bw` commands, see [`BitwardenVault::with_runner`].
+    runner: Box<dyn runner::CommandRunner>,
+}
+
+/// The CLI commands run, see [`BitwardenVault::setup`].
+struct Setup {
+    cmd_name: String,
+    /// Arguments before those of every command, when the CLI runs through
+    /// `npx` or PowerShell.
+    cmd_args: Vec<String>,
+    /// Whether the CLI is the Windows `bw.exe`, run from WSL, see [`wsl`].
+    windows_bw: bool,
+    /// `BITWARDENCLI_APPDATA_DIR` for every `bw`, see [`appdata`].
+    appdata_dir: Option<std::path::PathBuf>,
+}
+
+/// How long cargo may keep tokens, from `--cache` and `--cache-ttl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenCache {
+    /// For the rest of the cargo invocation.
+    Session,
+    Never,
+    /// For this long, but at most for the cargo invocation.
+    Expires(Duration),
+}
+
+/// What is done to the vault after each request, from `--lock-after` and
+/// `--logout-after`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Teardown {
+    Lock,
+    Logout,
+}
+
+impl Teardown {
+    fn command(self) -> &'static str {
+        match self {
+            Teardown::Lock => "lock",
+            Teardown::Logout => "logout",
+        }
+    }
+}
+
+/// Kind of item tokens are kept in, from `--item-type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ItemType {
+    /// The password of a login, matched by its URIs.
+    Login,
+    /// The notes of a secure note, matched by its [`NOTE_URL_FIELD`].
+    Note,
+}
+
+/// Syncing state of a batch of updates, which syncs only before the first
+/// read and after the last write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BatchSync {
+    NotSynced,
+    Synced,
+    Pending,
+}
+
+/// Bitwarden server given with `--server` or `--region`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Server {
+    url: String,
+    /// Separate API endpoint, for servers that don't serve it under `url`.
+    api: Option<String>,
+    /// Separate identity endpoint, for servers that don't serve it under `url`.
+    identity: Option<String>,
+}
+
+impl Server {
+    fn from_url(url: &str) -> Server {
+        Server {
+            url: url.to_string(),
+            api: None,
+            identity: None,
+        }
+    }
+
+    /// Expand a `--region` name to the matching Bitwarden cloud.
+    fn from_region(region: &str) -> Result<Server, Error> {
+        let domain = match region.to_lowercase().as_str() {
+            "us" => "bitwarden.com",
+            "eu" => "bitwarden.eu",
+            _ => return Err(format!("unknown region `{}`, expected `us` or `eu`", region).into()),
+        };
+
+        Ok(Server {
+            url: format!("https://vault.{}", domain),
+            api: Some(format!("https://api.{}", domain)),
+            identity: Some(format!("https://identity.{}", domain)),
+        })
+    }
+
+    /// Arguments for `bw config server`.
+    fn config_args(&self) -> Vec<&str> {
+        let mut args = vec!["config", "server", self.url.as_str()];
+        if let Some(api) = &self.api {
+            args.extend(["--api", api.as_str()]);
+        }
+        if let Some(identity) = &self.identity {
+            args.extend(["--identity", identity.as_str()]);
+        }
+        args
+    }
+}
+
+/// Bitwarden item from `bw list items`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListItem {
+    id: String,
+    r#type: u32,
+    name: String,
+    organization_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    folder_id: Option<String>,
+    login: LoginItem,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fields: Option<Vec<Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    revision_date: Option<String>,
+    /// 1 when the item asks for the master password again before it is
+    /// used.
+    #[serde(default)]
+    reprompt: u32,
+    /// Found by its name with `--match-by-name`, not by its URIs.
+    #[serde(skip)]
+    by_name: bool,
+}
+impl ListItem {
+    fn has_uri(&self, index_url: &str) -> bool {
+        let url = normalize_index_url(index_url);
+        self.login
+            .uris
+            .iter()
+            .any(|uri| matching::uri_matches(&url, uri, MatchMode::Exact))
+    }
+
+    /// Value of the custom field `name`.
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .flatten()
+            .find(|field| field["name"].as_str() == Some(name))
+            .and_then(|field| field["value"].as_str())
+    }
+}
+
+/// Bitwarden login item from `ListItem::login`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoginItem {
+    username: Option<String>,
+    /// `null` for items without a password, e.g. ones that only hold scoped tokens.
+    #[serde(default, deserialize_with = "null_as_default")]
+    password: String,
+    /// `null` for logins without any URI.
+    #[serde(default, deserialize_with = "null_as_default")]
+    uris: Vec<Uri>,
+}
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Result of a `bw create item` that didn't fail outright.
+enum CreateOutcome {
+    /// The new item, as printed by `bw`, if it could be parsed.
+    Created(Option<Value>),
+    /// The "remove individual vault" organization policy is in effect.
+    PersonalOwnershipDisabled,
+}
+
+/// Identifies an item in error messages.
+struct ItemRef<'a> {
+    id: &'a str,
+    name: &'a str,
+    organization_id: Option<&'a str>,
+}
+
+/// Output of `bw status`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Status {
+    server_url: Option<String>,
+    #[serde(default)]
+    user_email: Option<String>,
+    /// When the vault was last synced, `None` if never.
+    #[serde(default)]
+    last_sync: Option<String>,
+    status: VaultState,
+}
+/// Lock state of the vault reported by `bw status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum VaultState {
+    Unauthenticated,
+    Locked,
+    Unlocked,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Prefix of the names of items created with the default `--name-template`.
+const ITEM_NAME_PREFIX: &str = "Cargo registry token for ";
+
+/// Server the `bw` CLI talks to when none has been configured.
+const DEFAULT_SERVER: &str = "https://vault.bitwarden.com";
+
+/// Number of entries of the password history kept by `--keep-previous`, as
+/// many as Bitwarden keeps of passwords changed in its apps.
+const MAX_HISTORY: usize = 5;
+
+/// Number of URIs on an item above which editing it warns.
+const MAX_URIS: usize = 20;
+
+/// Custom field recording when the token of an item was last rotated.
+const ROTATED_FIELD: &str = "cargo-last-rotated";
+
+/// Custom field holding the fingerprint of the token, for `--fingerprint`.
+const FINGERPRINT_FIELD: &str = "cargo-token-fingerprint";
+
+/// Custom field holding when the token of an item expires, for `--expires`.
+const EXPIRES_FIELD: &str = "cargo-token-expires";
+
+/// Custom field holding the registry URL of a secure note, which has no
+/// URIs, for `--item-type note`.
+const NOTE_URL_FIELD: &str = "cargo-registry-url";
+
+/// How long a command runs before the provider says it is waiting on it.
+const HEARTBEAT_AFTER: Duration = Duration::from_secs(2);
+
+/// Environment variable `bw` takes the session key from.
+const SESSION_ENV: &str = "BW_SESSION";
+
+/// Wait before the first retry, doubled for each one after it. Short in
+/// tests, which retry against a fake `bw`.
+const RETRY_DELAY: Duration = Duration::from_millis(if cfg!(test) { 1 } else { 500 });
+
+/// Environment variable passing the askpass helper's master password to `bw`.
+const PASSWORD_ENV: &str = "CARGO_CREDENTIAL_BITWARDEN_PASSWORD";
+
+/// Environment variable passing a prompted Send password to `bw receive`.
+const SEND_PASSWORD_ENV: &str = "CARGO_CREDENTIAL_BITWARDEN_SEND_PASSWORD";
+
+/// Bitwarden item for `bw create item`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListItemCreateRequest {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    login: Option<LoginItem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secure_note: Option<Value>,
+    r#type: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    organization_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    collection_ids: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    folder_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<Field>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+}
+
+/// Custom field of a Bitwarden item.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Field {
+    name: String,
+    value: Option<String>,
+    /// 0 for text, 1 for hidden.
+    r#type: u32,
+    linked_id: Option<u32>,
+}
+
+/// Bitwarden collection from `bw list org-collections`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Collection {
+    id: String,
+    name: String,
+}
+
+/// Bitwarden folder from `bw list folders`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Folder {
+    /// `None` for the "No Folder" entry.
+    id: Option<String>,
+    name: String,
+}
+
+/// Envelope printed by commands run with `--response`.
+#[derive(Debug, Clone, Deserialize)]
+struct Response {
+    success: bool,
+    message: Option<String>,
+    #[serde(default)]
+    data: Option<Value>,
+}
+
+impl BitwardenVault {
+    /// Parse the provider arguments, like `--sync` or `--collection <name>`,
+    /// as they follow `cargo-credential-bitwarden` in cargo's configuration.
+    /// Nothing is looked up or run yet: the CLI is found before the first
+    /// command.
+    pub fn new(args: &[&str]) -> Result<BitwardenVault, Error> {
+        Ok(BitwardenVault {
+            options: options::Options::parse(args)?,
+            setup: OnceCell::new(),
+            capabilities: OnceCell::new(),
+            version: Cell::new(None),
+            resolved_collection_ids: OnceCell::new(),
+            resolved_folder_id: OnceCell::new(),
+            timings: RefCell::new(Vec::new()),
+            item_template: OnceCell::new(),
+            env_session: std::env::var_os(SESSION_ENV)
+                .map(|session| session.to_string_lossy().into_owned()),
+            session_state: OnceCell::new(),
+            batch: Cell::new(None),
+            defer_sync: Cell::new(false),
+            only_reading: Cell::new(false),
+            renewed_session: RefCell::new(None),
+            session_renewed: Cell::new(false),
+            reprompted: Cell::new(false),
+            registry_name: RefCell::new(None),
+            read_item: RefCell::new(None),
+            split_username: RefCell::new(None),
+            runner: Box::new(runner::Process),
+        })
+    }
+
+    /// Find the CLI and apply the options that hold for the whole process,
+    /// like `--verbose`, once, before the first command.
+    fn setup(&self) -> Result<&Setup, Error> {
+        if let Some(setup) = self.setup.get() {
+            return Ok(setup);
+        }
+        let options = &self.options;
+        if let Some(askpass) = &options.askpass {
+            tty::set_askpass(askpass);
+        }
+        verbose::init(options.verbose);
+        timings::init(options.timings);
+        if !options.bw_args.is_empty() {
+            verbose::log(|| {
+                format!(
+                    "adding `{}` to every `bw` command",
+                    options.bw_args.join(" ")
+                )
+            });
+        }
+        let bw_path = options.bw_path.as_deref();
+        let located = match options.backend {
+            backend::Kind::Rbw => locate::Located {
+                program: bw_path.unwrap_or(rbw::CMD).to_string(),
+                args: Vec::new(),
+            },
+            backend::Kind::Bws => locate::Located {
+                program: bw_path.unwrap_or(bws::CMD).to_string(),
+                args: Vec::new(),
+            },
+            _ => locate::find_cmd(bw_path, options.allow_npx, options.prefer_windows_bw)?,
+        };
+        verbose::log(|| {
+            let mut words = vec![located.program.as_str()];
+            words.extend(located.args.iter().map(String::as_str));
+            format!(
+                "using `{}` for `--backend {}`",
+                words.join(" "),
+                options.backend.as_str()
+            )
+        });
+
+        let windows_bw = wsl::is_windows_program(&located.program);
+        let appdata_dir = match (options.backend.is_other_client(), &options.appdata_dir) {
+            (true, _) => None,
+            // `bw.exe` keeps its data on the Windows side.
+            (false, None) if windows_bw => None,
+            (false, Some(dir)) => Some(appdata::prepare(dir)?),
+            (false, None) => appdata::resolve()?,
+        };
+        Ok(self.setup.get_or_init(|| Setup {
+            cmd_name: located.program,
+            cmd_args: located.args,
+            windows_bw,
+            appdata_dir,
+        }))
+    }
+
+    /// Take the lock against other provider processes running `bw`, held
+    /// until the result is dropped. rbw's agent needs none.
+    fn lock(&self, mode: lock::Mode) -> Result<Option<lock::Lock>, Error> {
+        let Some(dir) = state::state_dir() else {
+            return Ok(None);
+        };
+        if self.options.backend.is_other_client() {
+            return Ok(None);
+        }
+        let path = lock::path(&dir);
+        verbose::log(|| format!("taking the {:?} lock `{}`", mode, path.display()));
+        Ok(lock::acquire(&path, mode, self.options.lock_timeout)?)
+    }
+
+    /// Detect which global flags the CLI supports, once per process.
+    fn capabilities(&self) -> Result<Capabilities, Error> {
+        if let Some(capabilities) = self.capabilities.get() {
+            return Ok(*capabilities);
+        }
+        if self.options.backend == backend::Kind::Rbw {
+            return Ok(*self.capabilities.get_or_init(|| Capabilities::ALL));
+        }
+
+        let mut cmd = self.bw()?;
+        cmd.arg("--version");
+        let output = self.run_cmd(cmd)?;
+        let version = Version::parse(&output);
+        verbose::log(|| format!("CLI version: {}", output.trim()));
+        self.version.set(version);
+        let capabilities = match version {
+            Some(version) if version < version::MINIMUM => {
+                return Err(format!(
+                    "`bw` {} is too old, this provider needs version {} or later; \
+                     please update the Bitwarden CLI, see https://bitwarden.com/download/",
+                    version,
+                    version::MINIMUM
+                )
+                .into())
+            }
+            Some(version) => Capabilities::for_version(version),
+            None => {
+                let mut cmd = self.bw()?;
+                cmd.arg("--help");
+                Capabilities::from_help(&self.run_cmd(cmd)?)
+            }
+        };
+
+        // Everything else builds on these; fail before touching the vault.
+        for flag in [Flag::Raw] {
+            if !capabilities.supports(flag) {
+                return Err(format!(
+                    "your `bw` version lacks the `{}` flag, please update the Bitwarden CLI",
+                    flag.as_str()
+                )
+                .into());
+            }
+        }
+
+        Ok(*self.capabilities.get_or_init(|| capabilities))
+    }
+
+    fn status(&self) -> Result<Status, Error> {
+        self.status_with(&None)
+    }
+
+    /// `bw status` as seen with `session`, which tells whether it still works.
+    fn status_with(&self, session: &Option<String>) -> Result<Status, Error> {
+        let cmd = self.make_cmd(session, &["status"])?;
+        let buffer = self.run_cmd(cmd)?;
+        json::parse_output(&buffer)
+            .map_err(|e| format!("failed to deserialize JSON from `bw status`: {}", e).into())
+    }
+
+    /// Make sure the CLI points at the server given with `--server`.
+    fn verify_server(&self) -> Result<(), Error> {
+        let Some(wanted) = &self.options.server else {
+            return Ok(());
+        };
+
+        let status = self.status()?;
+        let current = status.server_url.as_deref().unwrap_or(DEFAULT_SERVER);
+        if same_server(current, &wanted.url) {
+            return Ok(());
+        }
+
+        if self.options.force_server && status.status == VaultState::Unauthenticated {
+            let cmd = self.make_cmd(&None, &wanted.config_args())?;
+            self.run_cmd(cmd)?;
+            return Ok(());
+        }
+
+        Err(format!(
+            "the Bitwarden CLI is configured for server `{}`, but the provider expects `{}`; \
+             run `bw logout && {} && bw login` to switch servers{}",
+            current,
+            wanted.url,
+            std::iter::once("bw")
+                .chain(wanted.config_args())
+                .collect::<Vec<_>>()
+                .join(" "),
+            if self.options.force_server {
+                ""
+            } else {
+                " (or pass `--force-server` to reconfigure automatically while logged out)"
+            }
+        )
+        .into())
+    }
+
+    fn signin(&self) -> Result<Option<String>, Error> {
+        if self.options.backend == backend::Kind::Rbw {
+            return rbw::unlock(self).map(|()| None);
+        }
+        // If there are any session env vars, we'll assume that this is the correct account,
+        // and that the user knows what they are doing, unless `--email` says otherwise.
+        if let Some(session) = &self.env_session {
+            redact::register(session);
+            verbose::log(|| "using the session from BW_SESSION".to_string());
+            let switched = match &self.options.email_address {
+                Some(_) => self.check_account(&self.status()?)?,
+                None => false,
+            };
+            match self.session_state() {
+                _ if switched => {}
+                VaultState::Locked | VaultState::Unauthenticated => {
+                    eprintln!(
+                        "note: BW_SESSION doesn't unlock the vault anymore, signing in again"
+                    );
+                }
+                VaultState::Unlocked | VaultState::Unknown => return Ok(None),
+            }
+        }
+        self.signin_again()
+    }
+
+    /// Log in or unlock without looking at `BW_SESSION`, which doesn't
+    /// unlock the vault.
+    fn signin_again(&self) -> Result<Option<String>, Error> {
+        if self.options.apikey {
+            return self.signin_apikey();
+        }
+
+        // `bw login` refuses to run while logged in, so only unlock then.
+        let mut status = self.status()?;
+        if self.check_account(&status)? {
+            status = self.status()?;
+        }
+        if status.status == VaultState::Unlocked {
+            return Ok(None);
+        }
+        if self.options.no_login {
+            let option = match self.options.offline {
+                true => "--offline",
+                false => "--no-login",
+            };
+            return Err(match status.status {
+                VaultState::Unauthenticated => format!(
+                    "not logged in to Bitwarden and `{}` was given; \
+                     run `bw login` and export BW_SESSION",
+                    option
+                ),
+                _ => format!(
+                    "the Bitwarden vault is not unlocked and `{}` was given; \
+                     export BW_SESSION or run `bw unlock`",
+                    option
+                ),
+            }
+            .into());
+        }
+        if self.options.sso && status.status == VaultState::Unauthenticated {
+            return self.signin_sso();
+        }
+        if let Some(var) = &self.options.password_env {
+            if status.status == VaultState::Unauthenticated {
+                return Err(
+                    "`--passwordenv` only unlocks the vault, and this machine is not \
+                     logged in to Bitwarden; run `bw login` first, or pass `--apikey`"
+                        .into(),
+                );
+            }
+            return self.unlock_from_env(var);
+        }
+        if let Some(command) = &self.options.password_command {
+            if status.status == VaultState::Unauthenticated {
+                return Err(
+                    "`--password-command` only unlocks the vault, and this machine is not \
+                     logged in to Bitwarden; run `bw login` first, or pass `--apikey`"
+                        .into(),
+                );
+            }
+            return self.unlock_from_command(command);
+        }
+        let command = if status.status == VaultState::Locked {
+            "unlock"
+        } else {
+            "login"
+        };
+        // `bw` prompts on the terminal; without one, ask the askpass helper.
+        if !tty::is_available() && tty::can_prompt() {
+            return self.signin_askpass(&status);
+        }
+
+        let mut args = vec![command, "--raw"];
+        if command == "login" {
+            if let Some(email_address) = &self.options.email_address {
+                args.push(email_address);
+            }
+            args.extend(self.two_step_args());
+        }
+        match self.run_interactive(&args) {
+            // "You are already logged in": another process logged in since
+            // `bw status`, or the status was wrong.
+            Err(e) if command == "login" => match self.status() {
+                Ok(status) if status.status == VaultState::Locked => {
+                    if self.check_account(&status)? {
+                        return self.signin();
+                    }
+                    eprintln!("note: the Bitwarden CLI is logged in already, unlocking instead");
+                    self.run_interactive(&["unlock", "--raw"]).map(Some)
+                }
+                _ => Err(e),
+            },
+            result => result.map(Some),
+        }
+    }
+
+    /// Refuse to use an account other than the one given with `--email`,
+    /// or with `--switch-account` log out of it. `true` if it logged out.
+    fn check_account(&self, status: &Status) -> Result<bool, Error> {
+        let (Some(wanted), Some(actual)) = (&self.options.email_address, &status.user_email) else {
+            return Ok(false);
+        };
+        if status.status == VaultState::Unauthenticated || wanted.eq_ignore_ascii_case(actual) {
+            return Ok(false);
+        }
+        if !self.options.switch_account {
+            return Err(format!(
+                "the Bitwarden CLI is logged in as {}, not {} from `--email`; run `bw logout` \
+                 or pass `--switch-account` to switch accounts, or pass `--appdata-dir <path>` \
+                 to keep a separate login for {}",
+                actual, wanted, wanted
+            )
+            .into());
+        }
+        eprintln!(
+            "note: the Bitwarden CLI is logged in as {}, logging out to log in as {}",
+            actual, wanted
+        );
+        let mut cmd = self.make_cmd(&None, &["logout"])?;
+        cmd.stdin(Stdio::null());
+        self.run_cmd(cmd)
+            .map_err(|e| format!("failed to log out of {}: {}", actual, e))?;
+        Ok(true)
+    }
+
+    /// `--method` and `--code` for `bw login`, the authenticator app unless
+    /// `--method` says otherwise.
+    fn two_step_args(&self) -> Vec<&str> {
+        let mut args = Vec::new();
+        if self.options.two_step_method.is_some() || self.options.two_step_code.is_some() {
+            let method = self
+                .options
+                .two_step_method
+                .unwrap_or(two_step::Method::Authenticator);
+            args.extend(["--method", method.as_arg()]);
+        }
+        if let Some(code) = &self.options.two_step_code {
+            args.extend(["--code", code.as_str()]);
+        }
+        args
+    }
+
+    /// `bw login --sso`, which opens the browser for the identity provider
+    /// and then asks for the master password, or `bw unlock` after it when
+    /// the login gave no session key.
+    fn signin_sso(&self) -> Result<Option<String>, Error> {
+        if !tty::is_available() {
+            return Err(
+                "SSO login requires an interactive terminal; run `bw login --sso` \
+                 in one, or export BW_SESSION"
+                    .into(),
+            );
+        }
+        let session = self.run_interactive(&["login", "--sso", "--raw"])?;
+        if !session.is_empty() {
+            return Ok(Some(session));
+        }
+        match &self.options.password_env {
+            Some(var) => self.unlock_from_env(var),
+            None => self.run_interactive(&["unlock", "--raw"]).map(Some),
+        }
+    }
+
+    /// Run `bw` with `args` on the terminal, for it to prompt there, and
+    /// return the first line it printed, the session key.
+    fn run_interactive(&self, args: &[&str]) -> Result<String, Error> {
+        let mut cmd = self.bw()?;
+        cmd.args(args).args(&self.options.bw_args);
+        let command = subcommand(&cmd);
+
+        // Cargo owns stdin and stdout for the protocol, so `bw` reads and
+        // prompts on the terminal itself. Its stderr goes there too: `bw`
+        // only hides the master password when it is a terminal, and the
+        // reason for a failure is shown right where it was asked for.
+        let Some((input, output)) = tty::child_stdio() else {
+            return Err(format!(
+                "`bw {}` needs a terminal to prompt on; run it in one and export BW_SESSION, \
+                 or pass `--passwordenv` or `--askpass`",
+                command
+            )
+            .into());
+        };
+        cmd.stdin(input);
+        cmd.stderr(output);
+        cmd.stdout(Stdio::piped());
+
+        // No `--timeout`: the user may take as long as they like to answer.
+        let Output { status, stdout, .. } = self
+            .runner
+            .run(&mut cmd, None, None)?
+            .map(normalize_newlines)
+            .ok_or_else(|| format!("`bw {}` was stopped", command))?;
+        let mut buffer = String::from_utf8(stdout)
+            .map_err(|e| format!("failed to get session from `bw`: {}", e))?;
+
+        if let Some(end) = buffer.find('\n') {
+            buffer.truncate(end);
+        }
+
+        if !status.success() {
+            return Err(
+                format!("`bw {}` failed: {}; see its output above", command, status).into(),
+            );
+        }
+
+        redact::register(&buffer);
+        Ok(buffer)
+    }
+
+    /// Whether `BW_SESSION` unlocks the vault, checked once per vault.
+    /// `Unknown` if `bw status` failed.
+    fn session_state(&self) -> VaultState {
+        *self.session_state.get_or_init(|| {
+            self.status()
+                .map_or(VaultState::Unknown, |status| status.status)
+        })
+    }
+
+    /// `bw login --apikey` and `bw unlock`, reading the API key and master
+    /// password from the environment so that `bw` never prompts.
+    fn signin_apikey(&self) -> Result<Option<String>, Error> {
+        let password_env = self
+            .options
+            .password_env
+            .as_deref()
+            .unwrap_or("BW_PASSWORD");
+        let missing: Vec<_> = ["BW_CLIENTID", "BW_CLIENTSECRET", password_env]
+            .into_iter()
+            .filter(|name| *name != password_env || self.options.password_command.is_none())
+            .filter(|name| std::env::var_os(name).map_or(true, |v| v.is_empty()))
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "`--apikey` needs the environment variable{} {}",
+                if missing.len() > 1 { "s" } else { "" },
+                missing.join(", ")
+            )
+            .into());
+        }
+
+        if self.status()?.status == VaultState::Unauthenticated {
+            let mut cmd = self.make_cmd(&None, &["login", "--apikey"])?;
+            cmd.stdin(Stdio::null());
+            self.run_cmd(cmd)
+                .map_err(|e| format!("failed to run `bw login --apikey`: {}", e))?;
+        }
+        match &self.options.password_command {
+            Some(command) => self.unlock_from_command(command),
+            None => self.unlock_from_env(password_env),
+        }
+    }
+
+    /// `bw unlock` with the master password `command` prints, passed to
+    /// `bw` in an environment variable of its own rather than as an
+    /// argument. The printed password is wiped once `bw` exited; the copy
+    /// [`Command`] made for the environment of `bw` can only be freed, which
+    /// happens before that.
+    fn unlock_from_command(&self, command: &str) -> Result<Option<String>, Error> {
+        let mut shell = shell_command(command);
+        // Its stderr is left alone, for helpers like `pass` to ask for the
+        // passphrase of their key on the terminal.
+        shell.stdin(Stdio::null()).stdout(Stdio::piped());
+        // No `--timeout`, as it may be waiting for that passphrase.
+        let output = self
+            .runner
+            .run(&mut shell, None, None)
+            .map_err(|e| format!("failed to run `--password-command`: {}", e))?
+            .ok_or("`--password-command` was stopped")?;
+        if !output.status.success() {
+            redact::wipe(output.stdout);
+            return Err(format!("`--password-command` failed: {}", output.status).into());
+        }
+        let password = match String::from_utf8(output.stdout) {
+            Ok(password) => password,
+            Err(e) => {
+                redact::wipe(e.into_bytes());
+                return Err("`--password-command` printed a password that isn't UTF-8".into());
+            }
+        };
+        let trimmed = password.trim_end_matches(['\r', '\n']);
+        if trimmed.is_empty() {
+            return Err("`--password-command` printed no password".into());
+        }
+        redact::register(trimmed);
+
+        let mut cmd = self.make_cmd(&None, &["unlock", "--passwordenv", PASSWORD_ENV, "--raw"])?;
+        cmd.env(PASSWORD_ENV, trimmed).stdin(Stdio::null());
+        // Drops `cmd`, and the copy of the password in it.
+        let session = self.run_cmd(cmd);
+        redact::wipe(password);
+        let session = session.map_err(|e| format!("failed to run `bw unlock`: {}", e))?;
+        let session = session.lines().next().unwrap_or_default().to_string();
+        redact::register(&session);
+        Ok(Some(session))
+    }
+
+    /// `bw unlock` with the master password from the environment variable
+    /// `var`, whose value is only ever read by `bw`.
+    fn unlock_from_env(&self, var: &str) -> Result<Option<String>, Error> {
+        if std::env::var_os(var).map_or(true, |value| value.is_empty()) {
+            return Err(format!(
+                "the environment variable `{}` for the master password is not set",
+                var
+            )
+            .into());
+        }
+        let mut cmd = self.make_cmd(&None, &["unlock", "--passwordenv", var, "--raw"])?;
+        cmd.stdin(Stdio::null());
+        let session = self
+            .run_cmd(cmd)
+            .map_err(|e| format!("failed to run `bw unlock`: {}", e))?;
+        let session = session.lines().next().unwrap_or_default().to_string();
+        redact::register(&session);
+        Ok(Some(session))
+    }
+
+    /// `bw login`, or `bw unlock` for a locked vault, with the email address,
+    /// master password and two-step login code from the askpass helper. The
+    /// code is only asked for when `bw` says it needs one. The password is
+    /// wiped when signing in succeeded or failed for good, after the
+    /// commands that got a copy of it in their environment are dropped.
+    fn signin_askpass(&self, status: &Status) -> Result<Option<String>, Error> {
+        let cancelled = || -> Error { "the askpass helper gave no answer".into() };
+
+        let locked = status.status == VaultState::Locked;
+        let email = match (&self.options.email_address, &status.user_email) {
+            (_, Some(email)) if locked => email.clone(),
+            (Some(email), _) => email.clone(),
+            _ => self
+                .askpass("Bitwarden email address")
+                .ok_or_else(cancelled)?,
+        };
+        let password = self
+            .askpass(&format!("Bitwarden master password for {}", email))
+            .ok_or_else(cancelled)?;
+        redact::register(&password);
+
+        // Not returned from directly, so that the password is always wiped.
+        let signin = || -> Result<Option<String>, Error> {
+            let mut code = self.options.two_step_code.clone();
+            loop {
+                let mut args = if locked {
+                    vec!["unlock", "--passwordenv", PASSWORD_ENV, "--raw"]
+                } else {
+                    vec![
+                        "login",
+                        email.as_str(),
+                        "--passwordenv",
+                        PASSWORD_ENV,
+                        "--raw",
+                    ]
+                };
+                if let (Some(code), false) = (&code, locked) {
+                    let method = self
+                        .options
+                        .two_step_method
+                        .unwrap_or(two_step::Method::Authenticator);
+                    args.extend(["--method", method.as_arg(), "--code", code.as_str()]);
+                }
+                let mut cmd = self.make_cmd(&None, &args)?;
+                cmd.env(PASSWORD_ENV, &password)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped());
+                let output = self.output(&mut cmd)?;
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                if output.status.success() {
+                    let session = stdout.lines().next().unwrap_or_default().to_string();
+                    redact::register(&session);
+                    return Ok(Some(session));
+                }
+
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let kind = bw_error::classify(&bw_error::Signals {
+                    version: self.version.get(),
+                    stdout: &stdout,
+                    stderr: &stderr,
+                    ..bw_error::Signals::default()
+                });
+                if !locked && code.is_none() && kind == bw_error::Kind::TwoStepRequired {
+                    let answer = self
+                        .askpass("Bitwarden two-step login code")
+                        .ok_or_else(cancelled)?;
+                    redact::register(&answer);
+                    code = Some(answer.trim().to_string());
+                    continue;
+                }
+                return Err(format!(
+                    "failed to run `bw {}`: {}: {}",
+                    args[0],
+                    output.status,
+                    redact::redact(stderr.trim())
+                )
+                .into());
+            }
+        };
+        let result = signin();
+        redact::wipe(password);
+        result
+    }
+
+    /// Ask the askpass helper `question`, running it like `bw`. `None` when
+    /// there is no helper, or it gave no answer.
+    fn askpass(&self, question: &str) -> Option<String> {
+        let mut cmd = tty::askpass_command(&format!("{}: ", question))?;
+        let output = self
+            .runner
+            .run(&mut cmd, None, None)
+            .map_err(|e| eprintln!("warning: failed to run the askpass helper: {}", e))
+            .ok()??;
+        tty::askpass_answer(output)
+    }
+
+    /// Days since the token in `item` was last rotated, if that is more
+    /// than `--warn-stale` allows.
+    fn stale_days(&self, item: &ListItem, now: time::OffsetDateTime) -> Option<i64> {
+        let max_days = self.options.warn_stale?;
+        let rotated = time::OffsetDateTime::parse(
+            &last_rotated(item)?,
+            &time::format_description::well_known::Rfc3339,
+        )
+        .ok()?;
+        let days = (now - rotated).whole_days();
+        (days > i64::from(max_days)).then_some(days)
+    }
+
+    /// Whether the token in `item` is due for `--refresh-command`: it has
+    /// expired, or was last rotated longer ago than `--refresh-after`.
+    fn refresh_due(
+        &self,
+        item: &ListItem,
+        expires: Option<time::OffsetDateTime>,
+        now: time::OffsetDateTime,
+    ) -> bool {
+        let Some(max_days) = self.options.refresh_after else {
+            return false;
+        };
+        if expires.is_some_and(|expires| expires <= now) {
+            return true;
+        }
+        last_rotated(item)
+            .and_then(|rotated| {
+                time::OffsetDateTime::parse(
+                    &rotated,
+                    &time::format_description::well_known::Rfc3339,
+                )
+                .ok()
+            })
+            .is_some_and(|rotated| (now - rotated).whole_days() > i64::from(max_days))
+    }
+
+    /// A new token for `item` from `--refresh-command`, stored in the vault
+    /// before cargo gets it. Failures are warnings, leaving cargo with the
+    /// stored token; a new token that couldn't be stored is still returned,
+    /// as minting it may have revoked the old one.
+    fn refresh(
+        &self,
+        session: &Option<String>,
+        registry: &RegistryInfo<'_>,
+        item: &ListItem,
+    ) -> Option<String> {
+        let command = self.options.refresh_command.as_deref()?;
+        let name = registry.name.unwrap_or(registry.index_url);
+        let token = match refresh::mint(command, registry) {
+            Ok(token) => token,
+            Err(e) => {
+                eprintln!(
+                    "warning: couldn't refresh the token for {}: {}; using the stored one",
+                    name, e
+                );
+                return None;
+            }
+        };
+        match self.modify(session, item, Secret::from(token.as_str()), registry) {
+            Ok(()) => verbose::log(|| format!("refreshed the token in `{}`", item.name)),
+            Err(e) => eprintln!(
+                "warning: couldn't store the refreshed token for {} in `{}`: {}",
+                name, item.name, e
+            ),
+        }
+        Some(token)
+    }
+
+    /// Record the request `action` answered with `result` in the
+    /// `--audit-log`, and for a token read start the `--notify-command`.
+    fn audit(
+        &self,
+        registry: &RegistryInfo<'_>,
+        action: &Action<'_>,
+        result: &Result<CredentialResponse, Error>,
+    ) {
+        let item_id = self.read_item.take();
+        if let Some(path) = &self.options.audit_log {
+            audit::record(path, registry, action, result, item_id.as_deref());
+        }
+        if let (Some(command), Action::Get(operation), Ok(_)) =
+            (&self.options.notify_command, action, result)
+        {
+            audit::notify(command, registry, operation, item_id.as_deref());
+        }
+    }
+
+    /// A `bw` command, without any arguments yet.
+    fn bw(&self) -> Result<Command, Error> {
+        let setup = self.setup()?;
+        let mut cmd = Command::new(&setup.cmd_name);
+        cmd.args(&setup.cmd_args);
+        if let Some(dir) = &setup.appdata_dir {
+            cmd.env(appdata::ENV, dir);
+        }
+        if setup.windows_bw {
+            let password_env = self
+                .options
+                .password_env
+                .as_deref()
+                .unwrap_or("BW_PASSWORD");
+            wsl::share_env(
+                &mut cmd,
+                &[
+                    SESSION_ENV,
+                    PASSWORD_ENV,
+                    SEND_PASSWORD_ENV,
+                    password_env,
+                    "BW_CLIENTID",
+                    "BW_CLIENTSECRET",
+                    "BITWARDENCLI_APPDATA_DIR/p",
+                    "NODE_EXTRA_CA_CERTS/p",
+                ],
+            );
+        }
+        Ok(cmd)
+    }
+
+    fn make_cmd(&self, session: &Option<String>, args: &[&str]) -> Result<Command, Error> {
+        let mut cmd = self.bw()?;
+        // rbw and bws have neither these flags nor sessions.
+        if self.options.backend.is_other_client() {
+            cmd.args(args);
+            return Ok(cmd);
+        }
+        let capabilities = self
+            .capabilities
+            .get()
+            .copied()
+            .unwrap_or(Capabilities::ALL);
+        for flag in [Flag::NoInteraction, Flag::CleanExit] {
+            if capabilities.supports(flag) {
+                cmd.arg(flag.as_str());
+            }
+        }
+
+        // Not `--session`: arguments can be read by other users of the machine.
+        if let Some(session) = self.renewed_session.borrow().as_ref().or(session.as_ref()) {
+            cmd.env(SESSION_ENV, session);
+        }
+
+        cmd.args(args).args(&self.options.bw_args);
+        Ok(cmd)
+    }
+
+    fn run_cmd(&self, mut cmd: Command) -> Result<String, Error> {
+        // Name the phase after the `bw` command, skipping global flags.
+        let args: Vec<_> = cmd.get_args().map(|arg| arg.to_string_lossy()).collect();
+        let phase = subcommand(&cmd)
+            .split(' ')
+            .next()
+            .filter(|name| !name.is_empty())
+            .or(args.first().map(|arg| arg.as_ref()))
+            .map_or_else(String::new, str::to_string);
+
+        let started = Instant::now();
+        let result = self.run_cmd_retrying(&mut cmd, &phase);
+        self.timings
+            .borrow_mut()
+            .push((phase.clone(), started.elapsed()));
+
+        let Err(e) = result else {
+            return result;
+        };
+        if self.options.backend.is_other_client()
+            || ["status", "--version", "--help", "config"].contains(&phase.as_str())
+        {
+            return Err(e);
+        }
+
+        // Ask `bw status` why it failed, rather than guessing from its messages.
+        let session = session_of(&cmd);
+        let state = self.status_with(&session).ok().map(|status| status.status);
+        let message = e.to_string();
+        let signals = bw_error::Signals {
+            state,
+            version: self.version.get(),
+            stderr: &message,
+            ..Default::default()
+        };
+        let kind = bw_error::classify(&signals);
+        if kind == bw_error::Kind::Locked && state == Some(VaultState::Locked) {
+            if let Some(renewed) = self.renew_session()? {
+                cmd.env(SESSION_ENV, renewed);
+                return self.run_cmd_retrying(&mut cmd, &phase);
+            }
+        }
+        let lookup = ["list", "get"].contains(&phase.as_str());
+        let mut e = bw_error::to_error(kind, state, e, lookup);
+        if kind == bw_error::Kind::Locked && !self.options.no_login && !self.can_get_password() {
+            e = format!(
+                "{}\nnote: there is no terminal or `--askpass` helper to ask for the master \
+                 password on, so the provider couldn't unlock it",
+                e
+            )
+            .into();
+        }
+        if self.options.offline && kind == bw_error::Kind::Network {
+            return Err(format!(
+                "{}\nnote: `--offline` was given, but `bw {}` tried to reach the server",
+                e, phase
+            )
+            .into());
+        }
+        Err(e)
+    }
+
+    /// Whether there is a way to the master password: the terminal, an
+    /// askpass helper, or `--passwordenv` and `--password-command`.
+    fn can_get_password(&self) -> bool {
+        tty::is_available()
+            || tty::can_prompt()
+            || self.options.password_env.is_some()
+            || self.options.password_command.is_some()
+    }
+
+    /// Unlock the vault again after it locked itself since signing in, as
+    /// on a short vault timeout during a slow `cargo login`. Only once per
+    /// run, and only where signing in could ask; `None` otherwise.
+    fn renew_session(&self) -> Result<Option<String>, Error> {
+        if self.session_renewed.replace(true)
+            || self.options.no_login
+            || self.options.backend != backend::Kind::Cli
+            || !self.can_get_password()
+        {
+            return Ok(None);
+        }
+        eprintln!(
+            "note: the Bitwarden vault locked itself during the operation, unlocking it again"
+        );
+        let session = self.signin_again()?;
+        if let Some(session) = &session {
+            *self.renewed_session.borrow_mut() = Some(session.clone());
+        }
+        Ok(session)
+    }
+
+    /// Whether a write that failed with `kind` did so as the vault locked
+    /// itself, and it was unlocked again for a retry. `bw` checks the lock
+    /// before changing anything, so the write can be retried as it is.
+    fn renewed_after_write(
+        &self,
+        session: &Option<String>,
+        kind: bw_error::Kind,
+    ) -> Result<bool, Error> {
+        if kind != bw_error::Kind::Locked {
+            return Ok(false);
+        }
+        let current = self.renewed_session.borrow().clone().or(session.clone());
+        if self.status_with(&current)?.status != VaultState::Locked {
+            return Ok(false);
+        }
+        Ok(self.renew_session()?.is_some())
+    }
+
+    /// Run `cmd` with the standard streams configured on it, killing it
+    /// after `--timeout`.
+    fn output(&self, cmd: &mut Command) -> Result<Output, Error> {
+        self.output_with_input(cmd, None)
+    }
+
+    /// Like [`Self::output`], writing `input` to the stdin of `cmd`. Secrets
+    /// are passed this way, as arguments can be read by other users.
+    fn output_with_input(&self, cmd: &mut Command, input: Option<&[u8]>) -> Result<Output, Error> {
+        if self.options.read_only && changes_vault(cmd) {
+            return Err(format!(
+                "refusing to run `{} {}`: the provider is configured with `--read-only`",
+                program(cmd),
+                subcommand(cmd)
+            )
+            .into());
+        }
+        verbose::log(|| format!("running `{}`", verbose::command(cmd)));
+        let started = Instant::now();
+        let output =
+            self.with_heartbeat(cmd, |cmd| self.runner.run(cmd, input, self.options.timeout))?;
+        timings::record(
+            || match subcommand(cmd) {
+                // Like `bw --version`.
+                words if words.is_empty() => {
+                    format!("{} {}", program(cmd), own_args(cmd).1.join(" "))
+                }
+                words => format!("{} {}", program(cmd), words),
+            },
+            started.elapsed(),
+            output.as_ref().map(|output| output.status),
+        );
+        let output = output.map(normalize_newlines).ok_or_else(|| {
+            format!(
+                "`{} {}` timed out after {} seconds; pass a larger `--timeout`, \
+                 or `--timeout 0` to wait as long as it takes",
+                program(cmd),
+                subcommand(cmd),
+                self.options.timeout.unwrap_or_default().as_secs()
+            )
+        })?;
+        verbose::log(|| {
+            format!(
+                "finished after {} ms, {}",
+                started.elapsed().as_millis(),
+                output.status
+            )
+        });
+        Ok(output)
+    }
+
+    /// `run(cmd)`, saying so on stderr when it takes longer than
+    /// [`HEARTBEAT_AFTER`], and when it is done then, so that a slow sync
+    /// doesn't look like a hang. Only on a terminal, or with `--verbose`.
+    fn with_heartbeat<T>(&self, cmd: &mut Command, run: impl FnOnce(&mut Command) -> T) -> T {
+        if self.options.quiet || !(std::io::stderr().is_terminal() || verbose::enabled()) {
+            return run(cmd);
+        }
+        let name = format!("{} {}", program(cmd), subcommand(cmd));
+        let started = Instant::now();
+        let (done, finished) = mpsc::channel::<()>();
+        let waiting = {
+            let name = name.clone();
+            std::thread::spawn(move || {
+                // Dropping `done` ends the wait early.
+                let waited =
+                    finished.recv_timeout(HEARTBEAT_AFTER) == Err(mpsc::RecvTimeoutError::Timeout);
+                if waited {
+                    eprintln!("waiting on `{}`...", name);
+                }
+                waited
+            })
+        };
+        let result = run(cmd);
+        drop(done);
+        if waiting.join().unwrap_or(false) {
+            eprintln!(
+                "`{}` finished after {:.1}s",
+                name,
+                started.elapsed().as_secs_f64()
+            );
+        }
+        result
+    }
+
+    /// Run `cmd` up to `--retries` more times while it fails in a way that
+    /// looks transient: a network error, or any failure of `bw sync` other
+    /// than a locked vault. Waits twice as long before each retry.
+    fn run_cmd_retrying(&self, cmd: &mut Command, phase: &str) -> Result<String, Error> {
+        let mut delay = RETRY_DELAY;
+        let mut attempts = 1;
+        loop {
+            let e = match self.run_cmd_untimed(cmd) {
+                Ok(output) => return Ok(output),
+                Err(e) => e,
+            };
+            let kind = bw_error::classify_message(&e.to_string());
+            let transient = !self.options.backend.is_other_client()
+                && (kind == bw_error::Kind::Network
+                    || (phase == "sync" && kind != bw_error::Kind::Locked));
+            if !transient || attempts > self.options.retries {
+                if attempts == 1 {
+                    return Err(e);
+                }
+                return Err(format!("{} (gave up after {} attempts)", e, attempts).into());
+            }
+            verbose::log(|| format!("retrying in {} ms: {}", delay.as_millis(), e));
+            std::thread::sleep(delay);
+            delay *= 2;
+            attempts += 1;
+        }
+    }
+
+    fn run_cmd_untimed(&self, cmd: &mut Command) -> Result<String, Error> {
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        let Output {
+            status,
+            stdout,
+            stderr,
+        } = self.output(cmd)?;
+
+        if !status.success() {
+            // With `--response`, the envelope says why without the chatter.
+            let message = bw_error::envelope_message(&String::from_utf8_lossy(&stdout));
+            let reason = message
+                .as_ref()
+                .map_or(&stderr[..], |message| message.as_bytes());
+            return Err(command_failed(cmd, status, reason));
+        }
+
+        String::from_utf8(stdout).map_err(|e| format!("failed to read `bw` output: {}", e).into())
+    }
+
+    /// The index URL `index_url` stands for with `--alias`, or itself.
+    fn canonical_url(&self, index_url: &str) -> String {
+        let key = uri_key(index_url);
+        self.options
+            .aliases
+            .iter()
+            .find(|(from, _)| *from == key)
+            .map_or(index_url, |(_, to)| to)
+            .to_string()
+    }
+
+    /// The item holding the token for `index_url`: the one given with
+    /// `--item-id`, or else the one matching the URL.
+    fn find(&self, session: &Option<String>, index_url: &str) -> Result<Option<ListItem>, Error> {
+        self.find_for(session, index_url, self.options.scope)
+    }
+
+    /// Like [`Self::find`], preferring an item of its own for `scope`.
+    fn find_for(
+        &self,
+        session: &Option<String>,
+        index_url: &str,
+        scope: Option<Scope>,
+    ) -> Result<Option<ListItem>, Error> {
+        let Some(item_id) = &self.options.item_id else {
+            return self.search_for(session, index_url, scope);
+        };
+        let item = self.resolve_item(session, item_id)?;
+        let item = match self.options.item_type {
+            ItemType::Login => item,
+            ItemType::Note => note_as_login(item)
+                .ok_or_else(|| format!("Bitwarden item `{}` is not a registry's note", item_id))?,
+        };
+        serde_json::from_value(item)
+            .map(Some)
+            .map_err(|e| format!("Bitwarden item `{}` is not a login item: {}", item_id, e).into())
+    }
+
+    /// Like [`Self::find_for`], trying the item [`item_cache`] remembers
+    /// for `index_url` first: fetching one item is much cheaper than
+    /// listing the matching ones.
+    fn find_cached(
+        &self,
+        session: &Option<String>,
+        index_url: &str,
+        scope: Option<Scope>,
+    ) -> Result<Option<ListItem>, Error> {
+        // Items found some other way than by their URIs aren't remembered.
+        if self.options.item_id.is_some()
+            || self.options.item_type == ItemType::Note
+            || self.options.folder.is_some()
+            || self.options.match_by_name
+        {
+            return self.find_for(session, index_url, scope);
+        }
+        let key = match scope {
+            Some(scope) => format!("{} {}", uri_key(index_url), scope.as_str()),
+            None => uri_key(index_url),
+        };
+        if let Some(item) = self.cached_item(session, index_url, &key)? {
+            return Ok(Some(item));
+        }
+        let found = self.find_for(session, index_url, scope)?;
+        match &found {
+            Some(item) => item_cache::set(&key, &item.id),
+            None => item_cache::evict(&key),
+        }
+        Ok(found)
+    }
+
+    /// The item remembered for `key`, if it still holds the token for
+    /// `index_url`.
+    ///
+    /// This is `bw get item` rather than `bw get password`: expiry, scopes,
+    /// `--field`, `--compose-token` and reprompt all need the rest of the
+    /// item, and it is one command all the same. No sync comes before it,
+    /// as lookups only sync when they find nothing, see
+    /// [`Self::read_or_sync`].
+    ///
+    /// The id was remembered after a search with its checks for duplicates
+    /// and ambiguity. A matching item added since is not noticed while the
+    /// remembered one still matches; when it stops matching, the search
+    /// runs again, with those checks.
+    fn cached_item(
+        &self,
+        session: &Option<String>,
+        index_url: &str,
+        key: &str,
+    ) -> Result<Option<ListItem>, Error> {
+        let Some(id) = item_cache::get(key) else {
+            return Ok(None);
+        };
+        let item = match self.get_item(session, &id) {
+            Ok(item) if item["deletedDate"].is_null() => item,
+            Ok(_) | Err(Error::NotFound) => {
+                verbose::log(|| format!("the remembered item {} is gone, searching", id));
+                return Ok(None);
+            }
+            Err(e) => {
+                verbose::log(|| format!("failed to get the remembered item {}: {}", id, e));
+                return Ok(None);
+            }
+        };
+        let Ok(item) = serde_json::from_value::<ListItem>(item) else {
+            return Ok(None);
+        };
+        let url = normalize_index_url(index_url);
+        let matches = item
+            .login
+            .uris
+            .iter()
+            .any(|uri| matching::uri_matches(&url, uri, self.options.uri_match));
+        let in_organization = match (&self.options.organization_id, self.options.include_personal) {
+            (Some(wanted), false) => item.organization_id.as_ref() == Some(wanted),
+            (Some(wanted), true) => item
+                .organization_id
+                .as_ref()
+                .map_or(true, |id| id == wanted),
+            (None, _) => true,
+        };
+        if !matches || !in_organization {
+            verbose::log(|| {
+                format!(
+                    "the remembered item {} no longer matches `{}`, searching",
+                    id, url
+                )
+            });
+            return Ok(None);
+        }
+        verbose::log(|| format!("using the remembered item {} for `{}`", id, url));
+        Ok(Some(item))
+    }
+
+    /// The backend for `--backend`, which reads and changes items.
+    fn backend<'a>(&'a self, session: &'a Option<String>) -> Result<Box<dyn Backend + 'a>, Error> {
+        Ok(match self.options.backend {
+            backend::Kind::Cli => Box::new(backend::Cli {
+                vault: self,
+                session,
+            }),
+            backend::Kind::Serve => Box::new(serve::connect(self, session)?),
+            backend::Kind::Rbw => Box::new(rbw::Rbw { vault: self }),
+            backend::Kind::Bws => unreachable!("bws answers cargo by itself"),
+        })
+    }
+
+    /// Items `bw` matches to `index_url`, which may include ones that only
+    /// share its host.
+    fn list_matches(
+        &self,
+        session: &Option<String>,
+        index_url: &str,
+    ) -> Result<Vec<ListItem>, Error> {
+        self.list_matches_in(session, index_url, false)
+    }
+
+    /// Like [`Self::list_matches`], or with `trash` the items in the trash.
+    fn list_matches_in(
+        &self,
+        session: &Option<String>,
+        index_url: &str,
+        trash: bool,
+    ) -> Result<Vec<ListItem>, Error> {
+        self.sync_if_stale(session)?;
+
+        // Give `bw` a plain URL, without cargo's `sparse+` prefix.
+        let url = normalize_index_url(index_url);
+        // `bw` only searches the URIs of logins; notes are matched later.
+        let search = match self.options.item_type {
+            // `bw` finds logins for `--url` by its host, which registries on
+            // disk don't have, but its search also looks at their URIs.
+            ItemType::Login if url.is_local() => Some(("search", url.as_str())),
+            ItemType::Login => Some(("url", url.as_str())),
+            ItemType::Note => None,
+        };
+        let mut search = Vec::from_iter(search);
+        if trash {
+            search.push(("trash", ""));
+        }
+        self.list_with(session, &search)
+    }
+
+    /// Items `bw` lists for the filters `search`, in the organization and
+    /// folder of the options.
+    fn list_with(
+        &self,
+        session: &Option<String>,
+        search: &[(&str, &str)],
+    ) -> Result<Vec<ListItem>, Error> {
+        let folder_id = match &self.options.folder {
+            Some(_) => match self.find_folder(session)? {
+                Some(id) => Some(id),
+                // Nothing is in a folder that doesn't exist yet.
+                None if !self.options.no_create_folder => return Ok(Vec::new()),
+                None => return Err(self.missing_folder()),
+            },
+            None => None,
+        };
+        let mut filters = search.to_vec();
+        if let (Some(organization_id), false) =
+            (&self.options.organization_id, self.options.include_personal)
+        {
+            filters.push(("organizationid", organization_id));
+        }
+        if let Some(folder_id) = &folder_id {
+            filters.push(("folderid", folder_id));
+        }
+        let items = self.backend(session)?.list_items(&filters)?;
+
+        Ok(self
+            .login_items(items)
+            .filter(|item| folder_id.is_none() || item.folder_id == folder_id)
+            .filter(|item| {
+                // With `--include-personal`, keep personal items and those of our organization.
+                match (&self.options.organization_id, &item.organization_id) {
+                    (Some(wanted), Some(actual)) => wanted == actual,
+                    _ => true,
+                }
+            })
+            .collect())
+    }
+
+    fn search(&self, session: &Option<String>, index_url: &str) -> Result<Option<ListItem>, Error> {
+        self.search_for(session, index_url, self.options.scope)
+    }
+
+    /// Like [`Self::search`], with several matches settled by an item of
+    /// its own for `scope`, or a scope covering it, in `--scoped` mode.
+    fn search_for(
+        &self,
+        session: &Option<String>,
+        index_url: &str,
+        scope: Option<Scope>,
+    ) -> Result<Option<ListItem>, Error> {
+        let items = self.list_matches(session, index_url)?;
+        let url = normalize_index_url(index_url);
+        let candidates = items.len();
+        verbose::log(|| {
+            format!(
+                "{} login item(s) share the host of `{}`, matching them by {:?}",
+                candidates, url, self.options.uri_match
+            )
+        });
+        let selection = match (
+            matching::select(&url, items, |item| &item.login.uris, self.options.uri_match),
+            scope,
+        ) {
+            (Selection::None, _)
+                if candidates > 0 && self.options.uri_match == MatchMode::Exact =>
+            {
+                if !self.options.quiet {
+                    eprintln!(
+                        "note: Bitwarden has {} login(s) for the site of `{}`, but none with \
+                         exactly its URL; `--uri-match base` or `--uri-match host` would \
+                         also accept those",
+                        candidates, url
+                    );
+                }
+                Selection::None
+            }
+            (Selection::Ambiguous(items), Some(scope)) => select_scope(items, scope),
+            (selection, _) => selection,
+        };
+        match selection {
+            Selection::One(item) => Ok(Some(item)),
+            Selection::None
+                if self.options.match_by_name && self.options.item_type == ItemType::Login =>
+            {
+                self.search_by_name(session, index_url)
+            }
+            Selection::None => Ok(None),
+            // A hand-made item for the registry's website may share the URL
+            // with the item holding the token; the one we created wins.
+            Selection::Ambiguous(items)
+                if items.iter().filter(|item| self.is_managed(item)).count() == 1 =>
+            {
+                Ok(items.into_iter().find(|item| self.is_managed(item)))
+            }
+            Selection::Ambiguous(mut items) if self.options.prefer_newest => {
+                // Items without a parseable date sort last.
+                items.sort_by_key(|item| std::cmp::Reverse(revised_at(item)));
+                let newest = items.remove(0);
+                let ignored: Vec<&str> = items.iter().map(|item| item.id.as_str()).collect();
+                eprintln!(
+                    "note: {} Bitwarden logins match registry `{}`; using the most recently \
+                     updated one ({}) and ignoring {}",
+                    ignored.len() + 1,
+                    index_url,
+                    newest.id,
+                    ignored.join(", ")
+                );
+                Ok(Some(newest))
+            }
+            // Picking one silently could hand cargo the wrong token.
+            Selection::Ambiguous(items) => Err(self.too_many_matches(session, index_url, &items)),
+        }
+    }
+
+    /// The item named after the registry of `index_url`, by the name cargo
+    /// gave it or as [`name_template::NameTemplate`] names it, for items
+    /// made by hand without its URL.
+    fn search_by_name(
+        &self,
+        session: &Option<String>,
+        index_url: &str,
+    ) -> Result<Option<ListItem>, Error> {
+        let registry_name = self.registry_name.borrow().clone();
+        let host = normalize_index_url(index_url).host().map(str::to_string);
+        let Some(term) = registry_name.clone().or(host) else {
+            return Ok(None);
+        };
+        let mut names = Vec::from_iter(registry_name.clone());
+        names.push(
+            self.options
+                .name_template
+                .render(index_url, registry_name.as_deref()),
+        );
+        names.push(self.options.name_template.render(index_url, None));
+
+        let mut items: Vec<ListItem> = self
+            .list_with(session, &[("search", &term)])?
+            .into_iter()
+            .filter(|item| {
+                names
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(&item.name))
+            })
+            .collect();
+        verbose::log(|| format!("{} login item(s) are named after `{}`", items.len(), term));
+        match items.len() {
+            0 => Ok(None),
+            1 => {
+                let mut item = items.remove(0);
+                item.by_name = true;
+                Ok(Some(item))
+            }
+            _ if self.options.prefer_newest => {
+                items.sort_by_key(|item| std::cmp::Reverse(revised_at(item)));
+                let mut newest = items.remove(0);
+                newest.by_name = true;
+                Ok(Some(newest))
+            }
+            _ => Err(self.too_many_matches(session, index_url, &items)),
+        }
+    }
+
+    /// The error for several `items` matching `index_url`, listing them so
+    /// that the user can tell which one to keep.
+    fn too_many_matches(
+        &self,
+        session: &Option<String>,
+        index_url: &str,
+        items: &[ListItem],
+    ) -> Error {
+        const SHOWN: usize = 10;
+        let folders = self.folder_names(session);
+        let mut lines: Vec<String> = items
+            .iter()
+            .take(SHOWN)
+            .map(|item| {
+                let folder = match &item.folder_id {
+                    Some(id) => match folders.get(id) {
+                        Some(name) => format!("folder `{}`", name),
+                        None => format!("folder {}", id),
+                    },
+                    None => "no folder".to_string(),
+                };
+                let changed = item.revision_date.as_deref().unwrap_or("unknown");
+                format!(
+                    "\n  {}  `{}` ({}, last changed {})",
+                    item.id, item.name, folder, changed
+                )
+            })
+            .collect();
+        if items.len() > SHOWN {
+            lines.push(format!("\n  and {} more", items.len() - SHOWN));
+        }
+        format!(
+            "{} Bitwarden logins match registry `{}`:{}\n\
+             pass `--item-id <id>` to use one of them, delete the others with \
+             `bw delete item <id>`, or pass `--prefer-newest` to use the most recently \
+             updated one",
+            items.len(),
+            index_url,
+            lines.concat()
+        )
+        .into()
+    }
+
+    /// Names of the folders by id, or none if they can't be listed.
+    fn folder_names(&self, session: &Option<String>) -> HashMap<String, String> {
+        if self.options.backend.is_other_client() {
+            return HashMap::new();
+        }
+        self.make_cmd(session, &["list", "folders"])
+            .and_then(|cmd| self.run_cmd(cmd))
+            .ok()
+            .and_then(|buffer| json::parse_output::<Vec<Folder>>(&buffer).ok())
+            .into_iter()
+            .flatten()
+            .filter_map(|folder| Some((folder.id?, folder.name)))
+            .collect()
+    }
+
+    /// The login items of a list. Cards, identities and notes can share
+    /// the URL or name searched for, and are skipped, as are logins that
+    /// can't be read, so that they don't hide the item holding the token.
+    /// With `--item-type note` these are the notes for a registry instead,
+    /// see [`note_as_login`].
+    fn login_items(&self, items: Vec<Value>) -> impl Iterator<Item = ListItem> + '_ {
+        items.into_iter().filter_map(|item| {
+            let item = match self.options.item_type {
+                ItemType::Login if item["type"] == 1 && item["login"].is_object() => item,
+                ItemType::Note if item["type"] == 2 => note_as_login(item)?,
+                _ => return None,
+            };
+            match serde_json::from_value::<ListItem>(item.clone()) {
+                Ok(item) => Some(item),
+                Err(e) => {
+                    if !self.options.quiet {
+                        eprintln!(
+                            "note: skipping Bitwarden item `{}` ({}), which can't be read: {}",
+                            item["name"].as_str().unwrap_or("<unnamed>"),
+                            item["id"].as_str().unwrap_or("<no id>"),
+                            e
+                        );
+                    }
+                    None
+                }
+            }
+        })
+    }
+
+    /// Whether this provider created the item, going by its name.
+    fn is_managed(&self, item: &ListItem) -> bool {
+        self.options.name_template.matches(&item.name)
+    }
+
+    /// Items this provider created, recognised by their name.
+    fn managed_items(&self, session: &Option<String>) -> Result<Vec<ListItem>, Error> {
+        self.sync_if_stale(session)?;
+
+        let mut filters = Vec::new();
+        if let Some(term) = self.options.name_template.search_term() {
+            filters.push(("search", term));
+        }
+        if let (Some(organization_id), false) =
+            (&self.options.organization_id, self.options.include_personal)
+        {
+            filters.push(("organizationid", organization_id));
+        }
+        let items = self.backend(session)?.list_items(&filters)?;
+
+        Ok(self
+            .login_items(items)
+            .filter(|item| self.is_managed(item))
+            .filter(
+                |item| match (&self.options.organization_id, &item.organization_id) {
+                    (Some(wanted), Some(actual)) => wanted == actual,
+                    _ => true,
+                },
+            )
+            .collect())
+    }
+
+    /// Items holding registry tokens: the ones named like this provider
+    /// names them, and the ones with a URI `is_index` takes for an index
+    /// URL.
+    fn registry_items(
+        &self,
+        session: &Option<String>,
+        is_index: impl Fn(&str) -> bool,
+    ) -> Result<Vec<ListItem>, Error> {
+        self.sync_if_stale(session)?;
+
+        let mut filters = Vec::new();
+        if let (Some(organization_id), false) =
+            (&self.options.organization_id, self.options.include_personal)
+        {
+            filters.push(("organizationid", organization_id.as_str()));
+        }
+        let items = self.backend(session)?.list_items(&filters)?;
+
+        Ok(self
+            .login_items(items)
+            .filter(|item| {
+                self.is_managed(item) || item.login.uris.iter().any(|uri| is_index(&uri.uri))
+            })
+            .collect())
+    }
+
+    /// `token` from the vault as cargo gets it, see [`token_format`].
+    fn format_token(&self, token: Secret<String>) -> Secret<String> {
+        match &self.options.token_format {
+            Some(format) => format.apply(token),
+            None => token,
+        }
+    }
+
+    /// `token` from `cargo login`, after the registry accepted it as cargo
+    /// will send it, with `--verify`.
+    fn verify_token(
+        &self,
+        registry: &RegistryInfo<'_>,
+        token: Secret<String>,
+    ) -> Result<Secret<String>, Error> {
+        if self.options.verify && self.options.offline {
+            eprintln!(
+                "warning: not verifying the token with the registry, as `--offline` was given"
+            );
+        } else if self.options.verify {
+            let sent = self.format_token(self.unformat_token(token.clone()));
+            verify::check(registry.index_url, sent.as_deref().expose())?;
+        }
+        Ok(token)
+    }
+
+    /// `token` from `cargo login` as it is stored, see [`token_format`].
+    fn unformat_token(&self, token: Secret<String>) -> Secret<String> {
+        let Some(format) = &self.options.token_format else {
+            return token;
+        };
+        let token = format.strip(token);
+        redact::register(token.as_deref().expose());
+        token
+    }
+
+    /// The password of `token` from `cargo login`, with its username kept
+    /// for the item, see [`token_format::split`].
+    fn split_token(&self, token: Secret<String>) -> Result<Secret<String>, Error> {
+        let Some(separator) = &self.options.split_token else {
+            return Ok(token);
+        };
+        let (username, password) = token_format::split(token, separator)?;
+        redact::register(password.as_deref().expose());
+        self.split_username.replace(Some(username));
+        Ok(password)
+    }
+
+    /// `token` of `item` as made by `--compose-token`, if given.
+    fn compose_token(&self, item: &ListItem, token: String) -> Result<Secret<String>, Error> {
+        let token = Secret::from(token);
+        let Some(compose) = &self.options.compose_token else {
+            return Ok(token);
+        };
+        let username = item
+            .login
+            .username
+            .as_deref()
+            .filter(|username| !username.trim().is_empty());
+        let username = match (username, compose.needs_username()) {
+            (Some(username), _) => username,
+            (None, false) => "",
+            (None, true) => {
+                return Err(format!(
+                    "Bitwarden item `{}` has no username, which `--compose-token` needs",
+                    item.name
+                )
+                .into())
+            }
+        };
+        let token = compose.apply(username, token);
+        redact::register(token.as_deref().expose());
+        Ok(token)
+    }
+
+    /// Custom field the token for `scope` is kept in: the scope's field, or
+    /// for `all` the one of `--field`; `None` for the password.
+    fn secret_field(&self, scope: Scope) -> Option<String> {
+        match scope {
+            Scope::All => self.options.token_field.clone(),
+            scope => scope.field_name(),
+        }
+    }
+
+    /// Ask for the master password again before the token of `item` is
+    /// used, when the item is set to, like Bitwarden's own clients. `bw
+    /// unlock` checks the password, and the new session key it gives the
+    /// vault is used for the rest of the run and kept like a renewed one.
+    fn reprompt(&self, item: &ListItem) -> Result<(), Error> {
+        if item.reprompt == 0 || self.options.ignore_reprompt || self.reprompted.get() {
+            return Ok(());
+        }
+        let password =
+            tty::prompt_secret(&format!("Bitwarden master password to use `{}`", item.name))
+                .ok_or_else(|| {
+                    format!(
+                "`{}` asks for the master password again before its token is used, and there \
+                 is no terminal or askpass helper to ask on; pass `--ignore-reprompt` to use it \
+                 anyway",
+                item.name
+            )
+                })?;
+        redact::register(&password);
+
+        let mut cmd = self.make_cmd(&None, &["unlock", "--passwordenv", PASSWORD_ENV, "--raw"])?;
+        cmd.env(PASSWORD_ENV, &password)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let output = self.output(&mut cmd);
+        drop(cmd);
+        redact::wipe(password);
+        let output = output?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!(
+                "the master password to use `{}` wasn't accepted: {}",
+                item.name,
+                redact::redact(stderr.trim())
+            )
+            .into());
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let session = stdout.lines().next().unwrap_or_default().to_string();
+        redact::register(&session);
+        *self.renewed_session.borrow_mut() = Some(session);
+        self.reprompted.set(true);
+        Ok(())
+    }
+
+    /// The token to answer a Get for `operation` with: the password or the
+    /// field of `--field`, or in `--scoped` mode the narrowest stored scope covering the operation.
+    fn token_for(&self, item: &ListItem, operation: &Operation<'_>) -> Option<String> {
+        if self.options.scope.is_none() {
+            return match &self.options.token_field {
+                Some(field) => stored_secret(item, Some(field)),
+                None => Some(item.login.password.clone()),
+            };
+        }
+
+        let wanted = Scope::for_operation(operation);
+        if let Some(own) = Scope::from_item_name(&item.name) {
+            // An item of its own for a scope that doesn't cover `wanted`
+            // is the only match, so there is no token for it.
+            return wanted
+                .covering()
+                .contains(&own)
+                .then(|| stored_secret(item, None))
+                .flatten();
+        }
+        let (scope, token) = wanted.covering().iter().find_map(|&scope| {
+            stored_secret(item, scope.field_name().as_deref()).map(|token| (scope, token))
+        })?;
+        if scope != wanted {
+            eprintln!(
+                "note: no `{}` token stored in `{}`, using the `{}` token",
+                wanted, item.name, scope
+            );
+        }
+        Some(token)
+    }
+
+    /// Fetch the token shared through the Bitwarden Send at `url`.
+    ///
+    /// The Send password is taken from `password_env` if given, and otherwise
+    /// asked for on the terminal once `bw` reports that one is needed.
+    fn receive(&self, url: &str, password_env: Option<&str>) -> Result<Secret<String>, Error> {
+        if self.options.offline {
+            return Err("`--from-send` needs the network, and `--offline` was given".into());
+        }
+        let mut password = None;
+        loop {
+            let mut args = vec!["receive", "--obj", url];
+            if let Some(var) = password_env {
+                args.extend(["--passwordenv", var]);
+            } else if password.is_some() {
+                args.extend(["--passwordenv", SEND_PASSWORD_ENV]);
+            }
+            let mut cmd = self.make_cmd(&None, &args)?;
+            if let Some(password) = &password {
+                cmd.env(SEND_PASSWORD_ENV, password);
+            }
+
+            cmd.stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            let output = self.output(&mut cmd)?;
+            if output.status.success() {
+                let send: Value = json::parse_output(&String::from_utf8_lossy(&output.stdout))
+                    .map_err(|e| {
+                        format!("failed to deserialize JSON from Bitwarden Send: {}", e)
+                    })?;
+                // Type 0 is a text Send, type 1 a file Send.
+                let Some(text) = send["text"]["text"].as_str().filter(|_| send["type"] == 0) else {
+                    return Err(format!("the Send at {} isn't a text Send", url).into());
+                };
+                let token = validate_token(text)
+                    .map_err(|e| format!("the Send at {} doesn't hold a token: {}", url, e))?;
+                redact::register(token);
+                return Ok(Secret::from(token.to_string()));
+            }
+
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let kind = bw_error::classify(&bw_error::Signals {
+                version: self.version.get(),
+                stdout: &stdout,
+                stderr: &stderr,
+                ..Default::default()
+            });
+            if kind == bw_error::Kind::PasswordRequired && password_env.is_none() {
+                if password.is_some() {
+                    eprintln!("the Send password is incorrect, try again");
+                }
+                password = Some(tty::prompt_secret("Send password").ok_or(
+                    "the Send is password protected; pass --send-password-env <var> \
+                     when there is no terminal to ask for it",
+                )?);
+                continue;
+            }
+            if let bw_error::Kind::NotFound | bw_error::Kind::SendUnavailable = kind {
+                return Err(format!(
+                    "the Send at {} is no longer available: it has expired, \
+                     was deleted, or was already opened as often as allowed",
+                    url
+                )
+                .into());
+            }
+            return Err(
+                format!("`bw receive` failed: {}: {}", output.status, stderr.trim()).into(),
+            );
+        }
+    }
+
+    /// How long cargo may keep a token read with `session`.
+    ///
+    /// A session this provider signed in for lives as long as the provider,
+    /// but a `BW_SESSION` from the environment may be shared with shells
+    /// where the user locks the vault at any time, so cargo shouldn't keep
+    /// using the token for long after.
+    fn cache_control(&self, session: &Option<String>) -> CacheControl {
+        let limit = session.is_none().then_some(self.options.env_session_cache);
+        self.cache_within(limit)
+    }
+
+    /// How long cargo may keep a token, following `--cache` and
+    /// `--cache-ttl`, but for at most `limit` if given.
+    fn cache_within(&self, limit: Option<Duration>) -> CacheControl {
+        let ttl = match (self.options.cache, limit) {
+            (TokenCache::Never, _) => return CacheControl::Never,
+            (TokenCache::Session, None) => return CacheControl::Session,
+            (TokenCache::Session, Some(limit)) => limit,
+            (TokenCache::Expires(ttl), None) => ttl,
+            (TokenCache::Expires(ttl), Some(limit)) => ttl.min(limit),
+        };
+        if ttl.is_zero() {
+            return CacheControl::Never;
+        }
+        match expiry(time::OffsetDateTime::now_utc(), ttl) {
+            Some(expiration) => CacheControl::Expires { expiration },
+            // Too far in the future to represent, which is forever anyway.
+            None => CacheControl::Session,
+        }
+    }
+
+    /// A hint on how to speed things up, if `bw` took longer than
+    /// `--slow-threshold` in total.
+    fn slow_hint(&self) -> Option<String> {
+        let timings = self.timings.borrow();
+        let total: Duration = timings.iter().map(|(_, duration)| *duration).sum();
+        if total < self.options.slow_threshold {
+            return None;
+        }
+
+        let mut phases: Vec<(&str, Duration)> = Vec::new();
+        for (phase, duration) in timings.iter() {
+            match phases.iter_mut().find(|(known, _)| known == phase) {
+                Some((_, sum)) => *sum += *duration,
+                None => phases.push((phase, *duration)),
+            }
+        }
+        let (phase, duration) = phases.into_iter().max_by_key(|(_, duration)| *duration)?;
+
+        let remedy = match phase {
+            "sync" if self.options.sync_interval.is_zero() => {
+                "; consider `--sync-interval` to sync less often, or dropping `--sync`"
+            }
+            "sync" => "; consider a larger `--sync-interval`, or dropping `--sync`",
+            "list" if self.options.item_id.is_none() => {
+                "; consider `--item-id` to skip searching the vault"
+            }
+            "status" => "; the CLI may be slow to start, check that it is up to date",
+            _ => "",
+        };
+        Some(format!(
+            "note: Bitwarden took {:.1}s, most of it in `bw {}` ({:.1}s){}",
+            total.as_secs_f64(),
+            phase,
+            duration.as_secs_f64(),
+            remedy
+        ))
+    }
+
+    fn encode(&self, session: &Option<String>, data: &[u8]) -> Result<String, Error> {
+        let mut cmd = self.make_cmd(session, &["encode"])?;
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+        let output = self.output_with_input(&mut cmd, Some(data))?;
+
+        if !output.status.success() {
+            return Err(command_failed(&cmd, output.status, &output.stderr));
+        }
+        let buffer = String::from_utf8(output.stdout)
+            .map_err(|e| format!("failed to read `bw` output: {}", e))?;
+        // Without the line break `bw` ends its output with.
+        let encoded = buffer.trim_end().to_string();
+
+        // The encoded item contains the token.
+        redact::register(&encoded);
+        Ok(encoded)
+    }
+}
+
+/// The `bw` subcommand `cmd` runs, like `list items`, without its global
+/// flags or any arguments that could hold a secret.
+fn subcommand(cmd: &Command) -> String {
+    // Commands of `bw` whose first argument is the kind of object they act on.
+    const TAKES_OBJECT: &[&str] = &["list", "get", "create", "edit", "delete", "restore"];
+    let program = program(cmd);
+
+    let mut words = Vec::new();
+    for arg in own_args(cmd).1 {
+        if !arg.starts_with("--") {
+            words.push(arg);
+            let done = match program.as_str() {
+                "bw" => words.len() == 2 || !TAKES_OBJECT.contains(&words[0].as_ref()),
+                // Like `bws secret list`.
+                "bws" => words.len() == 2,
+                _ => true,
+            };
+            if done {
+                break;
+            }
+        }
+    }
+    words.join(" ")
+}
+
+/// `args` after the options of the environment, and those of the section
+/// of `registry` in the configuration file below them, see
+/// [`config_file`]. The top of the file is for [`BitwardenVault::new`].
+fn registry_args(registry: &RegistryInfo<'_>, args: &[&str]) -> Result<Vec<String>, Error> {
+    let split = options::split(args)?;
+    let env = options::from_env()?;
+    let below = match config_file::load(config_file::path_arg(&split))? {
+        Some(config) => options::layer(config.registry(registry.index_url, registry.name), &env),
+        None => env,
+    };
+    Ok(options::layer(&below, &split))
+}
+
+/// Whether `cmd` changes the items of the vault or Secrets Manager.
+fn changes_vault(cmd: &Command) -> bool {
+    let subcommand = subcommand(cmd);
+    let mut words = subcommand.split(' ');
+    let first = words.next().unwrap_or_default();
+    match program(cmd).as_str() {
+        "bw" => matches!(
+            first,
+            "create" | "edit" | "delete" | "restore" | "move" | "share" | "import"
+        ),
+        "rbw" => matches!(first, "add" | "edit" | "remove" | "rm" | "generate" | "gen"),
+        "bws" => matches!(words.next(), Some("create" | "edit" | "delete")),
+        _ => false,
+    }
+}
+
+/// The program `cmd` really runs and its arguments, without those that
+/// only launch the CLI, see [`locate::unwrap_launcher`].
+fn own_args(cmd: &Command) -> (String, Vec<String>) {
+    let args: Vec<String> = cmd
+        .get_args()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let (program, skip) = locate::unwrap_launcher(&cmd.get_program().to_string_lossy(), &args);
+    (program, args.into_iter().skip(skip).collect())
+}
+
+/// Name of the program `cmd` runs, like `bw`.
+fn program(cmd: &Command) -> String {
+    std::path::Path::new(&own_args(cmd).0)
+        .file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned())
+}
+
+/// Error for `cmd` exiting with `status`, with the reason `bw` gave on
+/// stderr.
+fn command_failed(cmd: &Command, status: std::process::ExitStatus, stderr: &[u8]) -> Error {
+    let stderr = String::from_utf8_lossy(stderr);
+    let reason = match stderr.trim() {
+        "" => status.to_string(),
+        reason => redact::redact(reason),
+    };
+    format!("`{} {}` failed: {}", program(cmd), subcommand(cmd), reason).into()
+}
+
+/// `ttl` after `now`, unless that can't be represented.
+fn expiry(now: time::OffsetDateTime, ttl: Duration) -> Option<time::OffsetDateTime> {
+    now.checked_add(time::Duration::try_from(ttl).ok()?)
+}
+
+/// When the token in `item` expires, from its `cargo-token-expires` field.
+fn token_expiry(item: &ListItem) -> Result<Option<time::OffsetDateTime>, Error> {
+    let Some(expires) = item.field(EXPIRES_FIELD) else {
+        return Ok(None);
+    };
+    time::OffsetDateTime::parse(
+        expires.trim(),
+        &time::format_description::well_known::Rfc3339,
+    )
+    .map(Some)
+    .map_err(|_| {
+        format!(
+            "the `{}` field of `{}` is `{}`, not an RFC 3339 date",
+            EXPIRES_FIELD, item.name, expires
+        )
+        .into()
+    })
+}
+
+/// `cache`, but no longer than until the token `expires`.
+fn cache_until(cache: CacheControl, expires: Option<time::OffsetDateTime>) -> CacheControl {
+    match (cache, expires) {
+        (CacheControl::Expires { expiration }, Some(expires)) => CacheControl::Expires {
+            expiration: expiration.min(expires),
+        },
+        (CacheControl::Session, Some(expires)) => CacheControl::Expires {
+            expiration: expires,
+        },
+        (cache, _) => cache,
+    }
+}
+
+/// When `item` was last changed, from its revision date.
+fn revised_at(item: &ListItem) -> Option<time::OffsetDateTime> {
+    time::OffsetDateTime::parse(
+        item.revision_date.as_deref()?,
+        &time::format_description::well_known::Rfc3339,
+    )
+    .ok()
+}
+
+/// Explain a failed creation of `item`, reported by `bw` as `message`.
+fn create_failed(
+    kind: bw_error::Kind,
+    item: &Value,
+    message: &str,
+) -> Result<CreateOutcome, Error> {
+    match kind {
+        bw_error::Kind::PersonalOwnershipDisabled => Ok(CreateOutcome::PersonalOwnershipDisabled),
+        bw_error::Kind::Permission => match item["organizationId"].as_str() {
+            Some(organization_id) => Err(format!(
+                "you may not create items in the collections of organization {} \
+                 (read-only access?); ask an admin for edit access, or to store the token: {}",
+                organization_id,
+                message.trim()
+            )
+            .into()),
+            None => Err(format!("`bw create item` failed: {}", message.trim()).into()),
+        },
+        _ => Err(format!("`bw create item` failed: {}", message.trim()).into()),
+    }
+}
+
+/// Error for a change to `target` that `bw` refused for lack of permission.
+fn read_only_error(target: &ItemRef<'_>) -> Error {
+    let owner = match target.organization_id {
+        Some(org) => format!(" in organization {}", org),
+        None => String::new(),
+    };
+    format!(
+        "you have read-only access to item '{}'{} (id {}); \
+         ask an admin to update the token or grant edit access",
+        target.name, owner, target.id
+    )
+    .into()
+}
+
+fn list_collections(collections: &[&Collection]) -> String {
+    collections
+        .iter()
+        .map(|c| format!("\n  {} ({})", c.name, c.id))
+        .collect()
+}
+
+/// Compare two server URLs in the form [`normalize_index_url`] gives them.
+fn same_server(a: &str, b: &str) -> bool {
+    uri_key(a) == uri_key(b)
+}
+
+/// Read the token like [`cargo_credential::read_token`], registering it for
+/// redaction.
+fn read_token(
+    options: &LoginOptions<'_>,
+    registry: &RegistryInfo<'_>,
+) -> Result<Secret<String>, Error> {
+    // Without a terminal `cargo_credential` can't ask, but a helper may.
+    if options.token.is_none() && !tty::is_available() {
+        if let Some(token) = tty::prompt_secret(&format!("Token for {}", registry.index_url)) {
+            let token = validate_token(&token)?;
+            redact::register(token);
+            warn_if_header(token);
+            return Ok(Secret::from(token.to_string()));
+        }
+    }
+    let token = cargo_credential::read_token(options, registry)?;
+    redact::register(token.as_deref().expose());
+    let token = validate_token(token.as_deref().expose())?;
+    warn_if_header(token);
+    Ok(Secret::from(token.to_string()))
+}
+
+/// Form of a URI that equal URIs share, see [`normalize_index_url`].
+fn uri_key(uri: &str) -> String {
+    normalize_index_url(uri).as_str().to_string()
+}
+
+/// Key of the sessions kept for the provider arguments `args`. Forgetting
+/// the session or logging mustn't make the next one stored for other
+/// arguments.
+fn session_key(args: &[&str]) -> String {
+    let key_args: Vec<&str> = args
+        .iter()
+        .copied()
+        .filter(|&arg| arg != "--forget-session" && arg != "--verbose")
+        .collect();
+    key_args.join("\0")
+}
+
+/// Where sessions are kept for later processes, with `--persist-session` or
+/// `--session-file`.
+fn session_store_for(op: &BitwardenVault) -> Option<Box<dyn session_store::SessionStore>> {
+    match &op.options.session_file {
+        Some(path) => Some(session_store::file_store(path)),
+        None => op
+            .options
+            .persist_session
+            .then(session_store::default_store)
+            .flatten(),
+    }
+}
+
+/// Runs `--lock-after` or `--logout-after` when dropped, at the end of a
+/// request however it ended.
+struct TeardownGuard<'a> {
+    credential: &'a BitwardenCredential,
+    op: &'a BitwardenVault,
+    args: &'a [&'a str],
+    teardown: Teardown,
+}
+
+impl Drop for TeardownGuard<'_> {
+    fn drop(&mut self) {
+        let key = session_key(self.args);
+        let cached = self.credential.sessions.borrow().get(&key, None);
+        self.credential.sessions.borrow_mut().clear();
+        if let Some(store) = session_store_for(self.op) {
+            if let Err(e) = store.clear() {
+                eprintln!("note: failed to remove the stored Bitwarden session: {}", e);
+            }
+        }
+        // Without a session of its own, the provider used one from
+        // `BW_SESSION`, or a vault that was unlocked already, and leaves
+        // it to whoever unlocked it.
+        let session = self.op.renewed_session.take().or(match cached {
+            Cached::Fresh(session) | Cached::Stale(session) => session,
+            Cached::Missing => None,
+        });
+        if session.is_none() {
+            return;
+        }
+        let command = self.teardown.command();
+        let result = self.op.make_cmd(&session, &[command]).and_then(|mut cmd| {
+            cmd.stdin(Stdio::null());
+            self.op.run_cmd(cmd)
+        });
+        match result {
+            Ok(_) => verbose::log(|| format!("ran `bw {}` after the request", command)),
+            Err(e) => eprintln!("warning: failed to run `bw {}`: {}", command, e),
+        }
+    }
+}
+
+/// `command` run by the shell: `sh -c`, or `cmd /C` on Windows.
+fn shell_command(command: &str) -> Command {
+    cfg_if::cfg_if! {
+        if #[cfg(windows)] {
+            let mut cmd = Command::new("cmd");
+            cmd.args(["/C", command]);
+        } else {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", command]);
+        }
+    }
+    cmd
+}
+
+/// The session key `cmd` runs with.
+fn session_of(cmd: &Command) -> Option<String> {
+    cmd.get_envs()
+        .find(|(name, _)| *name == SESSION_ENV)
+        .and_then(|(_, session)| session)
+        .map(|session| session.to_string_lossy().into_owned())
+}
+
+/// `output` with the `\r\n` line endings of Windows programs, like `bw.exe`
+/// run from WSL, turned into `\n`.
+fn normalize_newlines(mut output: Output) -> Output {
+    let crlf = |bytes: Vec<u8>| -> Vec<u8> {
+        if !bytes.contains(&b'\r') {
+            return bytes;
+        }
+        let mut normalized = Vec::with_capacity(bytes.len());
+        for (i, &byte) in bytes.iter().enumerate() {
+            if byte != b'\r' || bytes.get(i + 1) != Some(&b'\n') {
+                normalized.push(byte);
+            }
+        }
+        normalized
+    };
+    output.stdout = crlf(output.stdout);
+    output.stderr = crlf(output.stderr);
+    output
+}
+
+/// Set custom field `name` of a full item, replacing any field of that name.
+fn set_field(item: &mut Value, name: &str, value: &str, r#type: u32) {
+    let Some(item) = item.as_object_mut() else {
+        return;
+    };
+    let fields = item
+        .entry("fields")
+        .or_insert_with(|| Value::Array(Vec::new()));
+    if !fields.is_array() {
+        *fields = Value::Array(Vec::new());
+    }
+    let fields = fields.as_array_mut().unwrap();
+    fields.retain(|field| field["name"].as_str() != Some(name));
+    fields.push(serde_json::json!({
+        "name": name,
+        "value": value,
+        "type": r#type,
+        "linkedId": null,
+    }));
+}
+
+/// Remove custom field `name` from a full item.
+fn remove_field(item: &mut Value, name: &str) {
+    if let Some(fields) = item.get_mut("fields").and_then(Value::as_array_mut) {
+        fields.retain(|field| field["name"].as_str() != Some(name));
+    }
+}
+
+/// The secret a full item keeps in custom field `field`, or else in its
+/// password.
+fn full_secret(item: &Value, field: Option<&str>) -> Option<String> {
+    match field {
+        None if is_note(item) => item["notes"].as_str().map(str::to_string),
+        None => item["login"]["password"].as_str().map(str::to_string),
+        Some(field_name) => item["fields"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|field| field["name"].as_str() == Some(field_name))
+            .and_then(|field| field["value"].as_str())
+            .map(str::to_string),
+    }
+}
+
+/// Move the current secret in `field`, or else the password, into the
+/// item's password history, where Bitwarden also keeps earlier values of
+/// hidden fields.
+fn keep_previous(item: &mut Value, field: Option<&str>) {
+    let previous = full_secret(item, field).map(|value| match field {
+        Some(field_name) => format!("{}: {}", field_name, value),
+        None => value,
+    });
+    let Some(previous) = previous.filter(|previous| !previous.is_empty()) else {
+        return;
+    };
+    let Some(item) = item.as_object_mut() else {
+        return;
+    };
+
+    let now = time::OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_default();
+    let history = item
+        .entry("passwordHistory")
+        .or_insert_with(|| Value::Array(Vec::new()));
+    if !history.is_array() {
+        *history = Value::Array(Vec::new());
+    }
+    let history = history.as_array_mut().unwrap();
+    history.insert(
+        0,
+        serde_json::json!({
+            "lastUsedDate": now,
+            "password": previous,
+        }),
+    );
+    history.truncate(MAX_HISTORY);
+}
+
+/// Whether a full item is a secure note, whose notes take the place of the
+/// password.
+fn is_note(item: &Value) -> bool {
+    item["type"] == 2
+}
+
+/// A secure note with a [`NOTE_URL_FIELD`] as the login this provider
+/// handles otherwise: its notes as the password, and the URL in the field
+/// as its only URI, so that it is matched like a login's URIs are.
+fn note_as_login(mut item: Value) -> Option<Value> {
+    let url = item["fields"]
+        .as_array()?
+        .iter()
+        .find(|field| field["name"] == NOTE_URL_FIELD)
+        .and_then(|field| field["value"].as_str())?
+        .to_string();
+    item["login"] = serde_json::json!({
+        "username": null,
+        "password": item["notes"],
+        "uris": [{ "uri": url, "match": null }],
+    });
+    Some(item)
+}
+
+/// The item for the narrowest of the scopes covering `scope` that exactly
+/// one of `items` is for: an item of its own, or for `all` one without a
+/// scope suffix.
+fn select_scope(items: Vec<ListItem>, scope: Scope) -> Selection<ListItem> {
+    for &covering in scope.covering() {
+        let own = |item: &ListItem| match covering {
+            Scope::All => Scope::from_item_name(&item.name).is_none(),
+            covering => Scope::from_item_name(&item.name) == Some(covering),
+        };
+        if items.iter().filter(|item| own(item)).count() == 1 {
+            return Selection::One(items.into_iter().find(own).unwrap());
+        }
+    }
+    Selection::Ambiguous(items)
+}
+
+/// The non-empty secret `item` keeps in custom field `field`, or else in
+/// its password.
+fn stored_secret(item: &ListItem, field: Option<&str>) -> Option<String> {
+    let secret = match field {
+        None => Some(item.login.password.clone()),
+        Some(field_name) => item.field(field_name).map(str::to_string),
+    };
+    secret.filter(|secret| !secret.is_empty())
+}
+
+/// When the token in `item` was last replaced: the `cargo-last-rotated`
+/// field, or else the item's revision date.
+fn last_rotated(item: &ListItem) -> Option<String> {
+    item.field(ROTATED_FIELD)
+        .map(str::to_string)
+        .or_else(|| item.revision_date.clone())
+}
+
+/// Warn if the `token` found in the item doesn't match its recorded
+/// fingerprint, which means one of them was edited without the other.
+fn check_fingerprint(item: &ListItem, token: &str) {
+    let Some(stored) = item.field(FINGERPRINT_FIELD) else {
+        return;
+    };
+    if token.is_empty() || fingerprint::fingerprint(token) == stored {
+        return;
+    }
+    eprintln!(
+        "warning: the token in `{}` doesn't match its `{}` field; it may have been changed \
+         elsewhere, run `cargo login` again to bring both up to date",
+        item.name, FINGERPRINT_FIELD
+    );
+}
+
+/// `token` without the whitespace around it, such as the line break of a
+/// paste or the `\r` of a Windows clipboard. Spaces inside are kept, for
+/// registries that want `Bearer <token>`, but any other whitespace or control
+/// character is refused.
+fn validate_token(token: &str) -> Result<&str, String> {
+    let token = token.trim();
+    if token.is_empty() {
+        return Err("the token is empty".to_string());
+    }
+    if let Some(c) = token
+        .chars()
+        .find(|&c| c.is_control() || (c.is_whitespace() && c != ' '))
+    {
+        return Err(format!("the token contains the character {:?}", c));
+    }
+    Ok(token)
+}
+
+/// Warn about a token that looks like a whole HTTP header: cargo sends the
+/// token as the value of the `Authorization` header itself.
+fn warn_if_header(token: &str) {
+    let name = token.split(':').next().unwrap_or_default();
+    if token.contains(':') && name.trim().eq_ignore_ascii_case("authorization") {
+        eprintln!(
+            "warning: the token starts with `{}:`; cargo sends the token as the \
+             `Authorization` header's value, so it likely should only be what follows",
+            name
+        );
+    }
+}
+
+/// The token stored in `item_name`, checked before cargo sends it.
+fn stored_token(item_name: &str, token: &str) -> Result<String, Error> {
+    validate_token(token).map(str::to_string).map_err(|e| {
+        format!(
+            "the token stored in `{}` can't be used: {}; run `cargo login` to replace it",
+            item_name, e
+        )
+        .into()
+    })
+}
+
+/// Report an action this provider doesn't handle.
+///
+/// `OperationNotSupported` can't carry a message, so the details go to stderr
+/// where they end up in bug reports.
+fn unsupported_action(action: &Action<'_>) -> Error {
+    eprintln!("{}", unsupported_note(action));
+    Error::OperationNotSupported
+}
+
+fn unsupported_note(action: &Action<'_>) -> String {
+    format!(
+        "note: cargo-credential-bitwarden does not support the `{}` credential action ({:?}); \
+         please report this at {}/issues together with your cargo version",
+        action,
+        action,
+        env!("CARGO_PKG_REPOSITORY")
+    )
+}
+
+/// A Bitwarden CLI session, from [`BitwardenVault::connect`].
+pub struct Session(Option<String>);
+
+/// A token found by [`BitwardenVault::find_token`].
+pub struct RegistryToken {
+    /// Id of the item holding the token.
+    pub item_id: String,
+    /// Name of the item holding the token.
+    pub item_name: String,
+    pub token: Secret<String>,
+}
+
+/// Access for tools other than cargo, with the arguments' behavior.
+impl BitwardenVault {
+    /// Run the `bw` commands through `runner` instead of starting them.
+    pub fn with_runner(mut self, runner: impl runner::CommandRunner + 'static) -> BitwardenVault {
+        self.runner = Box::new(runner);
+        self
+    }
+
+    /// Check the CLI and the server, and sign in or unlock the vault,
+    /// prompting as `cargo login` would.
+    pub fn connect(&self) -> Result<Session, Error> {
+        self.refuse_bws()?;
+        self.setup()?;
+        let _lock = self.lock(lock::Mode::Exclusive)?;
+        self.capabilities()?;
+        self.verify_server()?;
+        self.signin().map(Session)
+    }
+
+    /// The token cargo would get for reading from the registry at
+    /// `index_url`, if the vault has one.
+    pub fn find_token(
+        &self,
+        session: &Session,
+        index_url: &str,
+    ) -> Result<Option<RegistryToken>, Error> {
+        self.refuse_bws()?;
+        let mode = match self.options.auto_sync {
+            true => lock::Mode::Exclusive,
+            false => lock::Mode::Shared,
+        };
+        let _lock = self.lock(mode)?;
+        let session = &session.0;
+        let found = self.read_then_sync(session, || {
+            self.read_or_sync(session, || self.find(session, index_url))
+        })?;
+        Ok(found.and_then(|item| {
+            let token = self.token_for(&item, &Operation::Read)?;
+            redact::register(&token);
+            Some(RegistryToken {
+                item_id: item.id,
+                item_name: item.name,
+                token: Secret::from(token),
+            })
+        }))
+    }
+
+    /// Store `token` for `registry`, as `cargo login` does.
+    pub fn save_token(
+        &self,
+        session: &Session,
+        registry: &RegistryInfo<'_>,
+        token: Secret<String>,
+    ) -> Result<(), Error> {
+        self.refuse_bws()?;
+        let _lock = self.lock(lock::Mode::Exclusive)?;
+        let session = &session.0;
+        self.begin_batch();
+        let result = self.store(session, registry, || Ok(token));
+        let synced = self.finish_batch(session);
+        result?;
+        synced
+    }
+
+    /// Remove the token for the registry at `index_url`, as `cargo logout`
+    /// does. Fails with [`Error::NotFound`] without one.
+    pub fn delete_token(&self, session: &Session, index_url: &str) -> Result<(), Error> {
+        self.refuse_bws()?;
+        let _lock = self.lock(lock::Mode::Exclusive)?;
+        let session = &session.0;
+        self.begin_batch();
+        let result = match self.find(session, index_url) {
+            Ok(Some(item)) => self.logout(session, &item, index_url),
+            Ok(None) => Err(Error::NotFound),
+            Err(e) => Err(e),
+        };
+        let synced = self.finish_batch(session);
+        result?;
+        synced
+    }
+
+    /// Secrets Manager has no vault sessions, so only cargo can use it.
+    fn refuse_bws(&self) -> Result<(), Error> {
+        match self.options.backend {
+            backend::Kind::Bws => Err("`--backend bws` is only supported through cargo".into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The credential provider cargo talks to, for
+/// [`cargo_credential::main`].
+#[derive(Default)]
+pub struct BitwardenCredential {
+    /// Tokens looked up earlier in this process, keyed by [`lookup_key`].
+    lookups: RefCell<HashMap<(String, String), Lookup>>,
+    /// Vaults made for earlier requests, by their provider arguments, which
+    /// keep the CLI that was found and its version.
+    vaults: RefCell<HashMap<String, Rc<BitwardenVault>>>,
+    sessions: RefCell<SessionCache>,
+    /// Whether the hint about slow lookups was shown already.
+    slow_hint_shown: Cell<bool>,
+    /// Whether a `--warn-stale` warning was shown already.
+    stale_warned: Cell<bool>,
+}
+
+/// Outcome of looking up the token for a registry, for a Get needing
+/// `scope`.
+#[derive(Clone)]
+enum Lookup {
+    Found {
+        token: Secret<String>,
+        cache: CacheControl,
+        operation_independent: bool,
+        scope: Scope,
+    },
+    Missing {
+        scope: Scope,
+    },
+}
+
+/// Whether a token returned with `cache` may still be reused.
+fn is_cacheable(cache: &CacheControl) -> bool {
+    match cache {
+        CacheControl::Session => true,
+        CacheControl::Expires { expiration } => time::OffsetDateTime::now_utc() < *expiration,
+        _ => false,
+    }
+}
+
+/// Key for [`BitwardenCredential::lookups`], with the index URL as a
+/// [`uri_key`], so that the ways of writing one registry share it.
+///
+/// The provider arguments are part of the key, because they can change which
+/// item is found. The scope a Get needs for `--scoped` is kept in the
+/// [`Lookup`], so that a token for any operation answers the others too.
+fn lookup_key(index_url: &str, args: &[&str]) -> (String, String) {
+    (uri_key(index_url), args.join("\0"))
+}
+
+impl Credential for BitwardenCredential {
+    fn perform(
+        &self,
+        registry: &RegistryInfo<'_>,
+        action: &Action<'_>,
+        args: &[&str],
+    ) -> Result<CredentialResponse, Error> {
+        let started = Instant::now();
+        let (result, cached) = self.perform_cached(registry, action, args);
+        trace::record(registry, action, args, &result, cached, started.elapsed());
+        timings::report(started.elapsed());
+        result
+    }
+}
+
+impl BitwardenCredential {
+    /// [`Credential::perform`], and whether the answer is one given before.
+    fn perform_cached(
+        &self,
+        registry: &RegistryInfo<'_>,
+        action: &Action<'_>,
+        args: &[&str],
+    ) -> (Result<CredentialResponse, Error>, bool) {
+        // Cargo may ask a long-lived provider process for several registries;
+        // don't redo the whole lookup for ones we've already answered.
+        let key = lookup_key(registry.index_url, args);
+        match action {
+            Action::Get(operation) => match self.lookups.borrow().get(&key) {
+                Some(Lookup::Found {
+                    token,
+                    cache,
+                    operation_independent,
+                    scope,
+                }) if is_cacheable(cache)
+                    && (*operation_independent || *scope == Scope::for_operation(operation)) =>
+                {
+                    let response = CredentialResponse::Get {
+                        token: token.clone(),
+                        cache: cache.clone(),
+                        operation_independent: *operation_independent,
+                    };
+                    return (Ok(response), true);
+                }
+                Some(Lookup::Missing { scope }) if *scope == Scope::for_operation(operation) => {
+                    return (Err(Error::NotFound), true)
+                }
+                // Expired, not to be cached at all, or for another scope.
+                Some(_) | None => {}
+            },
+            _ => {
+                self.lookups
+                    .borrow_mut()
+                    .retain(|(index_url, _), _| *index_url != key.0);
+            }
+        }
+
+        let result = self.perform_uncached(registry, action, args);
+        if let Action::Get(operation) = action {
+            let scope = Scope::for_operation(operation);
+            let lookup = match &result {
+                Ok(CredentialResponse::Get {
+                    token,
+                    cache,
+                    operation_independent,
+                }) => Some(Lookup::Found {
+                    token: token.clone(),
+                    cache: cache.clone(),
+                    operation_independent: *operation_independent,
+                    scope,
+                }),
+                Err(Error::NotFound) => Some(Lookup::Missing { scope }),
+                _ => None,
+            };
+            if let Some(lookup) = lookup {
+                self.lookups.borrow_mut().insert(key, lookup);
+            }
+        }
+        (result, false)
+    }
+
+    /// The vault for `args`, made once per process: finding the CLI and
+    /// asking for its version take a `bw` run each.
+    fn vault(&self, args: &[&str]) -> Result<Rc<BitwardenVault>, Error> {
+        let key = args.join("\0");
+        if let Some(op) = self.vaults.borrow().get(&key) {
+            op.begin_request();
+            return Ok(Rc::clone(op));
+        }
+        let op = Rc::new(BitwardenVault::new(args)?);
+        self.vaults.borrow_mut().insert(key, Rc::clone(&op));
+        Ok(op)
+    }
+
+    /// Sign in once per process, checking sessions older than
+    /// `--session-max-age` before reusing them.
+    ///
+    /// With `--persist-session` or `--session-file`, sessions are also kept
+    /// for later processes.
+    fn session(&self, op: &BitwardenVault, args: &[&str]) -> Result<Option<String>, Error> {
+        let key = session_key(args);
+        let store = session_store_for(op);
+        let cached = self.sessions.borrow().get(&key, op.options.session_max_age);
+        let expired = match cached {
+            Cached::Fresh(session) => return Ok(session),
+            Cached::Stale(session) => {
+                if op.status_with(&session)?.status == VaultState::Unlocked {
+                    self.sessions.borrow_mut().store(&key, session.clone());
+                    return Ok(session);
+                }
+                self.sessions.borrow_mut().clear();
+                if let Some(store) = &store {
+                    let _ = store.clear();
+                }
+                if session.is_none() {
+                    return Err("the BW_SESSION session has expired; \
+                         run `bw unlock` and export the new BW_SESSION"
+                        .into());
+                }
+                true
+            }
+            Cached::Missing => {
+                if op.options.persist_session && store.is_none() {
+                    eprintln!(
+                        "note: `--persist-session` isn't supported on this platform yet; \
+                         `--session-file <path>` keeps the session in a private file instead"
+                    );
+                }
+                if let (true, Some(store)) = (op.options.forget_session, &store) {
+                    if let Err(e) = store.clear() {
+                        eprintln!("note: failed to remove the stored Bitwarden session: {}", e);
+                    }
+                }
+                if let Some(session) = store.as_ref().and_then(|store| store.load(&key)) {
+                    redact::register(&session);
+                    let session = Some(session);
+                    if op.status_with(&session)?.status == VaultState::Unlocked {
+                        self.sessions.borrow_mut().store(&key, session.clone());
+                        return Ok(session);
+                    }
+                }
+                false
+            }
+        };
+
+        let session = op.signin().map_err(|e| -> Error {
+            if expired {
+                format!(
+                    "the Bitwarden session expired and could not be renewed: {}",
+                    e
+                )
+                .into()
+            } else {
+                e
+            }
+        })?;
+        self.keep_session(op, args, &session);
+        Ok(session)
+    }
+
+    /// Keep `session` for later requests with `args`, and with
+    /// `--persist-session` or `--session-file` for later processes.
+    fn keep_session(&self, op: &BitwardenVault, args: &[&str], session: &Option<String>) {
+        let key = session_key(args);
+        if let (Some(store), Some(session), None) =
+            (session_store_for(op), session, op.options.teardown)
+        {
+            if let Err(e) = store.save(&key, session) {
+                eprintln!("note: failed to store the Bitwarden session: {}", e);
+            }
+        }
+        self.sessions.borrow_mut().store(&key, session.clone());
+    }
+
+    /// Look up the token for `operation`.
+    fn get(
+        &self,
+        op: &BitwardenVault,
+        registry: &RegistryInfo<'_>,
+        operation: &Operation<'_>,
+        args: &[&str],
+    ) -> Result<CredentialResponse, Error> {
+        op.only_reading.set(true);
+        let session = self.session(op, args)?;
+        let scope = op.options.scope.map(|_| Scope::for_operation(operation));
+        let item = op
+            .read_then_sync(&session, || {
+                op.read_or_sync(&session, || {
+                    op.find_cached(&session, registry.index_url, scope)
+                })
+            })?
+            .ok_or(Error::NotFound)?;
+        verbose::log(|| format!("using item `{}` ({})", item.name, item.id));
+        op.read_item.replace(Some(item.id.clone()));
+        op.reprompt(&item)?;
+        let token = op.token_for(&item, operation).ok_or(Error::NotFound)?;
+        redact::register(&token);
+        check_fingerprint(&item, &token);
+        let mut token = stored_token(&item.name, &token)?;
+        let mut expires = token_expiry(&item)?;
+        let now = time::OffsetDateTime::now_utc();
+        let refreshed = if op.refresh_due(&item, expires, now) {
+            op.refresh(&session, registry, &item)
+        } else {
+            None
+        };
+        match refreshed {
+            Some(refreshed) => {
+                token = refreshed;
+                expires = op.options.expires;
+            }
+            None => {
+                if let Some(expires) = expires.filter(|expires| *expires <= now) {
+                    return Err(format!(
+                        "the token for {} expired on {}; run `cargo login` to store a new one",
+                        registry.name.unwrap_or(registry.index_url),
+                        expires
+                            .format(&time::format_description::well_known::Rfc3339)
+                            .unwrap_or_default()
+                    )
+                    .into());
+                }
+                self.warn_if_stale(op, registry, &item)?;
+            }
+        }
+        Ok(CredentialResponse::Get {
+            token: op.format_token(op.compose_token(&item, token)?),
+            cache: cache_until(op.cache_control(&session), expires),
+            // Scoped tokens differ per operation, so cargo needs to ask again.
+            operation_independent: op.options.scope.is_none(),
+        })
+    }
+
+    /// Remind to rotate a token older than `--warn-stale`, once per process,
+    /// or with `--max-age-strict` refuse it.
+    fn warn_if_stale(
+        &self,
+        op: &BitwardenVault,
+        registry: &RegistryInfo<'_>,
+        item: &ListItem,
+    ) -> Result<(), Error> {
+        let Some(days) = op.stale_days(item, time::OffsetDateTime::now_utc()) else {
+            return Ok(());
+        };
+        let name = registry.name.unwrap_or(registry.index_url);
+        if op.options.stale_strict {
+            return Err(format!(
+                "the token for {} was last rotated {} days ago, longer than `--max-age` allows; \
+                 run `cargo login` to rotate it",
+                name, days
+            )
+            .into());
+        }
+        if op.options.quiet || self.stale_warned.get() {
+            return Ok(());
+        }
+        eprintln!(
+            "warning: the token for {} was last rotated {} days ago; consider `cargo login`",
+            name, days
+        );
+        self.stale_warned.set(true);
+        Ok(())
+    }
+
+    /// Point out what made a lookup slow, once per process. Skipped with
+    /// `--quiet` and when nobody is watching stderr.
+    fn hint_if_slow(&self, op: &BitwardenVault) {
+        if op.options.quiet || self.slow_hint_shown.get() || !std::io::stderr().is_terminal() {
+            return;
+        }
+        if let Some(hint) = op.slow_hint() {
+            eprintln!("{}", hint);
+            self.slow_hint_shown.set(true);
+        }
+    }
+
+    /// Answer cargo from Secrets Manager, without touching the vault.
+    fn perform_bws(
+        &self,
+        op: &BitwardenVault,
+        registry: &RegistryInfo<'_>,
+        action: &Action<'_>,
+    ) -> Result<CredentialResponse, Error> {
+        let bws = bws::Bws::new(op)?;
+        match action {
+            Action::Get(operation) => match (bws.get(registry), &op.options.fallback_provider) {
+                (Err(Error::NotFound), Some(command)) => {
+                    fallback::get(command, registry, operation)
+                }
+                (result, _) => result,
+            },
+            Action::Login(options) => {
+                bws.store(registry, || {
+                    op.verify_token(registry, read_token(options, registry)?)
+                })?;
+                Ok(CredentialResponse::Login)
+            }
+            Action::Logout => {
+                bws.delete(registry)?;
+                Ok(CredentialResponse::Logout)
+            }
+            _ => Err(unsupported_action(action)),
+        }
+    }
+
+    fn perform_uncached(
+        &self,
+        registry: &RegistryInfo<'_>,
+        action: &Action<'_>,
+        args: &[&str],
+    ) -> Result<CredentialResponse, Error> {
+        // Like strings that aren't URLs, which have nothing to look up by.
+        let url = normalize_index_url(registry.index_url);
+        if url.host().is_none() && !url.is_local() {
+            return Err(Error::UrlNotSupported);
+        }
+        let args = registry_args(registry, args).inspect_err(options::explain)?;
+        let args: &[&str] = &args.iter().map(String::as_str).collect::<Vec<_>>();
+        let op = self.vault(args).inspect_err(options::explain)?;
+        op.setup()?;
+        op.registry_name.replace(registry.name.map(str::to_string));
+        let index_url = op.canonical_url(registry.index_url);
+        let registry = &RegistryInfo {
+            index_url: &index_url,
+            name: registry.name,
+            headers: registry.headers.clone(),
+        };
+        if op.options.read_only && matches!(action, Action::Login(_) | Action::Logout) {
+            return Err(format!(
+                "the credential provider for `{}` is configured with `--read-only`; \
+                 change its token in the vault itself, or drop `--read-only`",
+                registry.name.unwrap_or(registry.index_url)
+            )
+            .into());
+        }
+        if op.options.backend == backend::Kind::Bws {
+            let result = self.perform_bws(&op, registry, action);
+            op.audit(registry, action, &result);
+            return result;
+        }
+        // Syncing rewrites the data file of `bw`, which readers mustn't see.
+        let mode = match action {
+            // Refreshing a token writes it back.
+            Action::Get(_) if !op.options.auto_sync && op.options.refresh_command.is_none() => {
+                lock::Mode::Shared
+            }
+            _ => lock::Mode::Exclusive,
+        };
+        let _lock = op.lock(mode)?;
+        // Dropped before the lock, also when a step below fails.
+        let _teardown = op.options.teardown.map(|teardown| TeardownGuard {
+            credential: self,
+            op: &op,
+            args,
+            teardown,
+        });
+        op.capabilities()?;
+        op.verify_server()?;
+        let result = self.perform_cli(&op, registry, action, args);
+        op.audit(registry, action, &result);
+        // Later requests would find the vault locked with the old session.
+        if let Some(renewed) = op.renewed_session.take() {
+            self.keep_session(&op, args, &Some(renewed));
+        }
+        result
+    }
+
+    /// Answer cargo through the `bw` CLI, or `bw serve` or rbw.
+    fn perform_cli(
+        &self,
+        op: &BitwardenVault,
+        registry: &RegistryInfo<'_>,
+        action: &Action<'_>,
+        args: &[&str],
+    ) -> Result<CredentialResponse, Error> {
+        match action {
+            Action::Get(operation) => {
+                let result = self.get(op, registry, operation, args);
+                self.hint_if_slow(op);
+                match (result, &op.options.fallback_provider) {
+                    (Err(Error::NotFound), Some(command)) => {
+                        fallback::get(command, registry, operation)
+                    }
+                    (result, _) => result,
+                }
+            }
+            Action::Login(options) => {
+                let session = self.session(op, args)?;
+                op.begin_batch();
+                let result = op.store(&session, registry, || {
+                    op.verify_token(registry, read_token(options, registry)?)
+                });
+                // Sync whatever was changed, even if a later step failed.
+                let synced = op.finish_batch(&session);
+                item_cache::evict(&uri_key(registry.index_url));
+                result?;
+                synced?;
+                Ok(CredentialResponse::Login)
+            }
+            Action::Logout => {
+                let session = self.session(op, args)?;
+                op.begin_batch();
+                // Check if an item already exists.
+                let result = match op.find(&session, registry.index_url) {
+                    Ok(Some(item)) => op.logout(&session, &item, registry.index_url),
+                    Ok(None) => Err(Error::NotFound),
+                    Err(e) => Err(e),
+                };
+                let synced = op.finish_batch(&session);
+                item_cache::evict(&uri_key(registry.index_url));
+                result?;
+                synced?;
+                Ok(CredentialResponse::Logout)
+            }
+            // `Action::Unknown`, sent by cargo for actions newer than the
+            // `cargo_credential` version we use, and those added to it later.
+            _ => Err(unsupported_action(action)),
+        }
+    }
+}
+
+/// Run the provider: as cargo's credential provider when cargo passed
+/// `--cargo-plugin`, and otherwise the maintenance mode the arguments ask
+/// for, see the README. This is all the binary does.
+pub fn run() -> ExitCode {
+    redact::install_panic_hook();
+    cancel::install_signal_handlers();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // Cargo always passes `--cargo-plugin`; anything else is a person or a script.
+    if args.iter().any(|arg| arg == "--cargo-plugin") {
+        cancel::watch_protocol();
+        cargo_credential::main(BitwardenCredential::default());
+        serve::shutdown();
+        background::finish(background::MAX_WAIT);
+        return ExitCode::SUCCESS;
+    }
+
+    let code = maintenance::run(&args);
+    serve::shutdown();
+    code
+}
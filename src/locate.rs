@@ -0,0 +1,625 @@
+//! Finding the `bw` CLI.
+//!
+//! Apps started from the macOS Finder or Dock don't inherit the shell's
+//! `PATH`, so a Homebrew-installed `bw` is often missing from it when cargo
+//! runs from an editor. The usual install locations are searched as well.
+//!
+//! On Windows, `bw` may be an `.exe` from scoop or a chocolatey shim, a
+//! `.cmd` from npm, or a PowerShell script, so every extension of `PATHEXT`
+//! and `.ps1` are tried in each directory of `PATH`. The batch file npm
+//! installs is skipped for the script it starts with node, as `cmd.exe`
+//! mangles arguments, and breaks on spaces in the install path. In WSL, the Windows
+//! `bw.exe` on `PATH` through interop is tried after `bw`, or before it with
+//! `--prefer-windows-bw`. With `--allow-npx`, `npx --yes @bitwarden/cli` is
+//! the last resort.
+
+use crate::wsl;
+use cfg_if::cfg_if;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Start of the error message when the CLI can't be found.
+pub const NOT_FOUND: &str = "could not find the Bitwarden CLI";
+
+/// Environment variable naming the CLI, like `--bw-path`.
+pub const PATH_ENV: &str = "CARGO_CREDENTIAL_BITWARDEN_BW";
+
+/// Arguments of `npx` that run the Bitwarden CLI.
+const NPX_ARGS: &[&str] = &["--yes", "@bitwarden/cli"];
+
+/// Variables batch files of npm start the path of the script with, for
+/// the directory of the batch file.
+const SHIM_DIR_VARS: &[&str] = &["%dp0%", "%~dp0"];
+
+/// Arguments of PowerShell that run the script following them.
+const POWERSHELL_ARGS: &[&str] = &["-NoProfile", "-ExecutionPolicy", "Bypass", "-File"];
+
+/// How to run the CLI: `program`, with `args` before those of every command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Located {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl Located {
+    fn program(program: impl Into<String>) -> Located {
+        Located {
+            program: program.into(),
+            args: Vec::new(),
+        }
+    }
+
+    fn with_args(program: impl Into<String>, args: &[&str]) -> Located {
+        Located {
+            program: program.into(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+}
+
+/// The program that `program` with `args` really runs, and how many of
+/// `args` are only there to start it through `npx` or PowerShell, so that
+/// messages can name `bw` and its subcommand, or `node`.
+pub fn unwrap_launcher(program: &str, args: &[String]) -> (String, usize) {
+    let starts_with = |launcher: &[&str]| {
+        args.len() >= launcher.len() && args.iter().zip(launcher).all(|(arg, l)| arg == l)
+    };
+    let stem = Path::new(program)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match stem.as_str() {
+        "npx" if starts_with(NPX_ARGS) => ("bw".to_string(), NPX_ARGS.len()),
+        // The script follows the arguments of PowerShell.
+        "powershell" | "pwsh"
+            if args.len() > POWERSHELL_ARGS.len() && starts_with(POWERSHELL_ARGS) =>
+        {
+            (
+                args[POWERSHELL_ARGS.len()].clone(),
+                POWERSHELL_ARGS.len() + 1,
+            )
+        }
+        // The script npm installs `bw` with.
+        "node" if args.first().is_some_and(|arg| arg.ends_with(".js")) => (args[0].clone(), 1),
+        _ => (program.to_string(), 0),
+    }
+}
+
+/// Command to run `bw` with: `bw_path` if given, otherwise the one in
+/// [`PATH_ENV`], otherwise `bw` from `PATH`, otherwise the first `bw` in a
+/// well-known install directory, otherwise `npx` if `allow_npx`.
+/// `prefer_windows` tries `bw.exe` from `PATH` first in WSL.
+pub fn find_cmd(
+    bw_path: Option<&str>,
+    allow_npx: bool,
+    prefer_windows: bool,
+) -> Result<Located, String> {
+    if let Some(bw_path) = bw_path {
+        return check_path(bw_path, "--bw-path").map(|path| launch(&path));
+    }
+    if let Some(bw_path) = std::env::var_os(PATH_ENV).filter(|path| !path.is_empty()) {
+        return check_path(&bw_path.to_string_lossy(), PATH_ENV).map(|path| launch(&path));
+    }
+
+    let path: Vec<PathBuf> = std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).collect())
+        .unwrap_or_default();
+    if let Some(found) = find_on_path(&path, prefer_windows)? {
+        return Ok(found);
+    }
+
+    let dirs = install_dirs();
+    if let Some(found) = find_in(&dirs, &["bw"], |path| path.is_file()) {
+        return Ok(Located::program(found.to_string_lossy()));
+    }
+
+    if allow_npx {
+        let npx = if cfg!(windows) { "npx.cmd" } else { "npx" };
+        if command_exists(npx)? {
+            return Ok(Located::with_args(npx, NPX_ARGS));
+        }
+    }
+
+    let mut searched = path;
+    searched.extend(dirs);
+    Err(not_found_message(&searched, allow_npx))
+}
+
+/// How to run the file `path`: PowerShell scripts can't be started
+/// directly, and the batch files of npm are skipped for node.
+fn launch(path: &str) -> Located {
+    let has_extension = |wanted: &str| {
+        Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(wanted))
+    };
+    if has_extension("cmd") || has_extension("bat") {
+        if let Some(located) = npm_script(Path::new(path)) {
+            return located;
+        }
+    }
+    if !has_extension("ps1") {
+        return Located::program(path);
+    }
+    let mut located = Located::with_args("powershell", POWERSHELL_ARGS);
+    located.args.push(path.to_string());
+    located
+}
+
+/// The node script the npm batch file `shim` starts, with the `node.exe`
+/// next to it if there is one, like the batch file does. Batch files that
+/// don't name a script are run as they are; the standard library quotes
+/// their arguments for `cmd.exe` then.
+fn npm_script(shim: &Path) -> Option<Located> {
+    let dir = shim.parent()?;
+    let contents = std::fs::read_to_string(shim).ok()?;
+    let script = contents
+        .split('"')
+        .skip(1)
+        .step_by(2)
+        .filter(|quoted| quoted.to_lowercase().ends_with(".js"))
+        .find_map(|quoted| {
+            SHIM_DIR_VARS
+                .iter()
+                .find_map(|var| quoted.strip_prefix(var))
+        })
+        .map(|rest| dir.join(rest.trim_start_matches(['\\', '/'])))
+        .filter(|script| script.is_file())?;
+    let node = dir.join("node.exe");
+    let node = match node.is_file() {
+        true => node.to_string_lossy().into_owned(),
+        false => "node".to_string(),
+    };
+    let mut located = Located::program(node);
+    located.args.push(script.to_string_lossy().into_owned());
+    Some(located)
+}
+
+/// `path`, given through `source`, made absolute if it exists and can be
+/// run. A relative path is taken from the current directory rather than
+/// looked up on `PATH`.
+fn check_path(path: &str, source: &str) -> Result<String, String> {
+    let given = Path::new(path);
+    let absolute = match given.is_absolute() {
+        true => given.to_path_buf(),
+        false => std::env::current_dir()
+            .map(|dir| dir.join(given))
+            .map_err(|e| format!("failed to resolve `{}` from `{}`: {}", path, source, e))?,
+    };
+    let metadata = match absolute.metadata() {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(format!(
+                "{} at `{}`, given by `{}`: no such file\nhelp: pass the full path of the \
+                 `bw` executable, or drop `{}` to search `PATH`",
+                NOT_FOUND,
+                absolute.display(),
+                source,
+                source
+            ))
+        }
+        Err(e) => {
+            return Err(format!(
+                "{} at `{}`, given by `{}`: {}",
+                NOT_FOUND,
+                absolute.display(),
+                source,
+                e
+            ))
+        }
+    };
+    if !metadata.is_file() {
+        return Err(format!(
+            "{} at `{}`, given by `{}`: not a file\nhelp: pass the `bw` executable itself, \
+             not the directory it is in",
+            NOT_FOUND,
+            absolute.display(),
+            source
+        ));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(format!(
+                "the Bitwarden CLI at `{}`, given by `{}`, is not executable\nhelp: run \
+                 `chmod +x {}`",
+                absolute.display(),
+                source,
+                absolute.display()
+            ));
+        }
+    }
+    Ok(absolute.to_string_lossy().into_owned())
+}
+
+/// `bw` from the directories of `path`.
+fn find_on_path(path: &[PathBuf], prefer_windows: bool) -> Result<Option<Located>, String> {
+    cfg_if! {
+        if #[cfg(target_os = "windows")] {
+            let _ = prefer_windows;
+            let names = windows_names(std::env::var("PATHEXT").ok().as_deref());
+            let names: Vec<&str> = names.iter().map(String::as_str).collect();
+            Ok(find_in(path, &names, |path| path.is_file())
+                .map(|found| launch(&found.to_string_lossy())))
+        } else {
+            let _ = (path, prefer_windows);
+            for &name in unix_names(prefer_windows) {
+                if command_exists(name)? {
+                    return Ok(Some(Located::program(name)));
+                }
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Names of `bw` looked up on `PATH` elsewhere, in the order they are
+/// tried: in WSL also the Windows `bw.exe`.
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+fn unix_names(prefer_windows: bool) -> &'static [&'static str] {
+    match (wsl::detected(), prefer_windows) {
+        (false, _) => &["bw"],
+        (true, false) => &["bw", "bw.exe"],
+        (true, true) => &["bw.exe", "bw"],
+    }
+}
+
+/// File names of `bw` on Windows, in the order they are tried in each
+/// directory: every extension of `pathext`, like the shell does, then the
+/// PowerShell script.
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn windows_names(pathext: Option<&str>) -> Vec<String> {
+    let pathext = pathext
+        .filter(|pathext| !pathext.trim().is_empty())
+        .unwrap_or(".COM;.EXE;.BAT;.CMD");
+    let mut names: Vec<String> = pathext
+        .split(';')
+        .map(str::trim)
+        .filter(|ext| ext.starts_with('.') && ext.len() > 1)
+        .map(|ext| format!("bw{}", ext.to_lowercase()))
+        .collect();
+    for name in ["bw.exe", "bw.cmd", "bw.ps1"] {
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// Whether `command` can be started from `PATH`. Failures other than a
+/// missing command, like a `bw` without execute permission, are errors.
+fn command_exists(command: &str) -> Result<bool, String> {
+    let mut cmd = Command::new(command);
+    cmd.stdout(Stdio::null());
+    cmd.stderr(Stdio::null());
+    match cmd.spawn() {
+        // Only started to see that it exists.
+        Ok(mut child) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            Ok(true)
+        }
+        Err(e) => match e.kind() {
+            ErrorKind::NotFound => Ok(false),
+            _ => Err(format!(
+                "failed to run the Bitwarden CLI (`{}`): {}",
+                command, e
+            )),
+        },
+    }
+}
+
+/// The first of `names` in `dirs`, trying every name in a directory before
+/// the next one, using `exists` to probe the filesystem.
+fn find_in(dirs: &[PathBuf], names: &[&str], exists: impl Fn(&Path) -> bool) -> Option<PathBuf> {
+    dirs.iter()
+        .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+        .find(|path| exists(path))
+}
+
+/// Install directories that may be missing from `PATH`.
+fn install_dirs() -> Vec<PathBuf> {
+    cfg_if! {
+        if #[cfg(target_os = "macos")] {
+            homebrew_dirs(brew_prefix().as_deref())
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// The output of `brew --prefix`, only asked for custom prefixes if `brew`
+/// itself is on `PATH`.
+#[cfg(target_os = "macos")]
+fn brew_prefix() -> Option<String> {
+    let output = Command::new("brew")
+        .arg("--prefix")
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let prefix = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !prefix.is_empty()).then_some(prefix)
+}
+
+/// The bin directories of Homebrew on Apple silicon and Intel, then that of
+/// `prefix` if it is another one.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn homebrew_dirs(prefix: Option<&str>) -> Vec<PathBuf> {
+    let mut dirs = vec![
+        PathBuf::from("/opt/homebrew/bin"),
+        PathBuf::from("/usr/local/bin"),
+    ];
+    if let Some(bin) = prefix.map(|prefix| Path::new(prefix).join("bin")) {
+        if !dirs.contains(&bin) {
+            dirs.push(bin);
+        }
+    }
+    dirs
+}
+
+fn not_found_message(searched: &[PathBuf], allow_npx: bool) -> String {
+    cfg_if! {
+        if #[cfg(target_os = "macos")] {
+            const GUI_NOTE: &str = "\nnote: apps launched from the Finder or the Dock don't get \
+                                    your shell's PATH, so Homebrew's bin directory may be missing";
+        } else {
+            const GUI_NOTE: &str = "";
+        }
+    }
+    cfg_if! {
+        if #[cfg(target_os = "windows")] {
+            let names = windows_names(std::env::var("PATHEXT").ok().as_deref()).join("`, `");
+        } else {
+            let names = unix_names(false).join("`, `");
+        }
+    }
+
+    let searched: Vec<_> = searched
+        .iter()
+        .map(|dir| dir.display().to_string())
+        .collect();
+    let npx = match allow_npx {
+        true => ", then `npx --yes @bitwarden/cli`, but `npx` wasn't found either",
+        false => "",
+    };
+    format!(
+        "{} (`bw`); tried `--bw-path`, then `{}`, then `{}` in each of: {}{}{}\nhelp: install the \
+         Bitwarden CLI with `npm install -g @bitwarden/cli` or the native package from \
+         https://bitwarden.com/download/, or pass `--bw-path <path>` to the provider if it is \
+         installed elsewhere",
+        NOT_FOUND,
+        PATH_ENV,
+        names,
+        searched.join(", "),
+        npx,
+        GUI_NOTE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake;
+
+    /// An executable `bw` in a fresh directory for test `name`.
+    fn executable(name: &str) -> PathBuf {
+        let path = fake::temp_dir(name).join("bw");
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        path
+    }
+
+    /// `bw` found by probing only `existing`.
+    fn probe(dirs: &[PathBuf], existing: &[&str]) -> Option<PathBuf> {
+        find_in(dirs, &["bw"], |path| {
+            existing.iter().any(|existing| path == Path::new(existing))
+        })
+    }
+
+    #[test]
+    fn homebrew_prefixes() {
+        assert_eq!(
+            homebrew_dirs(None),
+            [
+                PathBuf::from("/opt/homebrew/bin"),
+                PathBuf::from("/usr/local/bin")
+            ]
+        );
+        assert_eq!(homebrew_dirs(Some("/usr/local")).len(), 2);
+        assert_eq!(
+            homebrew_dirs(Some("/Users/me/brew")).last(),
+            Some(&PathBuf::from("/Users/me/brew/bin"))
+        );
+    }
+
+    #[test]
+    fn homebrew_probes() {
+        let dirs = homebrew_dirs(Some("/Users/me/brew"));
+        assert_eq!(probe(&dirs, &[]), None);
+        assert_eq!(
+            probe(&dirs, &["/usr/local/bin/bw", "/Users/me/brew/bin/bw"]),
+            Some(PathBuf::from("/usr/local/bin/bw"))
+        );
+        assert_eq!(
+            probe(&dirs, &["/opt/homebrew/bin/bw", "/usr/local/bin/bw"]),
+            Some(PathBuf::from("/opt/homebrew/bin/bw"))
+        );
+        assert_eq!(
+            probe(&dirs, &["/Users/me/brew/bin/bw"]),
+            Some(PathBuf::from("/Users/me/brew/bin/bw"))
+        );
+        // Directories themselves aren't probed.
+        assert_eq!(probe(&dirs, &["/opt/homebrew/bin"]), None);
+    }
+
+    #[test]
+    fn not_found_lists_the_searched_directories() {
+        let message = not_found_message(&homebrew_dirs(None), false);
+        assert!(message.starts_with(NOT_FOUND), "{}", message);
+        assert!(
+            message.contains("/opt/homebrew/bin, /usr/local/bin"),
+            "{}",
+            message
+        );
+        assert!(message.contains("--bw-path <path>"), "{}", message);
+        assert!(!message.contains("npx"), "{}", message);
+        assert_eq!(
+            message.contains("Finder or the Dock"),
+            cfg!(target_os = "macos"),
+            "{}",
+            message
+        );
+        assert!(not_found_message(&[], true).contains("`npx` wasn't found either"));
+    }
+
+    #[test]
+    fn bw_paths() {
+        let bw = executable("bw-path with spaces");
+        let given = bw.to_str().unwrap();
+        assert_eq!(check_path(given, "--bw-path").unwrap(), given);
+        assert_eq!(launch(given), Located::program(given));
+
+        // Relative paths are taken from the current directory, not `PATH`.
+        let relative = fake::relative(&bw);
+        assert!(relative.is_relative());
+        let found = check_path(relative.to_str().unwrap(), "--bw-path").unwrap();
+        assert_eq!(
+            Path::new(&found).canonicalize().unwrap(),
+            bw.canonicalize().unwrap()
+        );
+        let e = check_path("bw", "--bw-path").unwrap_err();
+        assert!(e.contains("no such file"), "{}", e);
+
+        let dir = bw.parent().unwrap().to_str().unwrap();
+        let e = check_path(dir, PATH_ENV).unwrap_err();
+        assert!(
+            e.contains("given by `CARGO_CREDENTIAL_BITWARDEN_BW`: not a file"),
+            "{}",
+            e
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&bw, std::fs::Permissions::from_mode(0o644)).unwrap();
+            let e = check_path(given, "--bw-path").unwrap_err();
+            assert!(e.contains("is not executable"), "{}", e);
+            assert!(e.contains(&format!("chmod +x {}", given)), "{}", e);
+        }
+    }
+
+    #[test]
+    fn windows_names_follow_pathext() {
+        assert_eq!(
+            windows_names(None),
+            ["bw.com", "bw.exe", "bw.bat", "bw.cmd", "bw.ps1"]
+        );
+        assert_eq!(windows_names(Some(" ")), windows_names(None));
+        assert_eq!(
+            windows_names(Some(".CMD; .Exe;;EXE;.")),
+            ["bw.cmd", "bw.exe", "bw.ps1"]
+        );
+    }
+
+    #[test]
+    fn launchers() {
+        let npx: Vec<String> = ["--yes", "@bitwarden/cli", "status"]
+            .map(String::from)
+            .into();
+        assert_eq!(unwrap_launcher("npx.cmd", &npx), ("bw".to_string(), 2));
+        assert_eq!(unwrap_launcher("npx", &npx[2..]), ("npx".to_string(), 0));
+        let script = r"C:\Users\me\bw.ps1";
+        let mut powershell: Vec<String> = POWERSHELL_ARGS.iter().map(|a| a.to_string()).collect();
+        powershell.extend([script.to_string(), "status".to_string()]);
+        assert_eq!(
+            unwrap_launcher("powershell", &powershell),
+            (script.to_string(), 5)
+        );
+        assert_eq!(
+            launch(script),
+            Located {
+                program: "powershell".to_string(),
+                args: powershell[..5].to_vec(),
+            }
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_resolution_order() {
+        let empty = |dir: &Path, names: &[&str]| {
+            for name in names {
+                std::fs::write(dir.join(name), "").unwrap();
+            }
+        };
+        let first = fake::temp_dir("windows-order-first");
+        let second = fake::temp_dir("windows-order-second");
+        let dirs = [first.clone(), second.clone()];
+
+        assert_eq!(find_on_path(&dirs, false).unwrap(), None);
+        // scoop's `bw.exe` and chocolatey's shim before npm's batch file.
+        empty(&second, &["bw.cmd", "bw.exe"]);
+        let found = find_on_path(&dirs, false).unwrap().unwrap();
+        assert_eq!(Path::new(&found.program), second.join("bw.exe"));
+        // Every name in a directory before the next directory.
+        empty(&first, &["bw.ps1"]);
+        let found = find_on_path(&dirs, false).unwrap().unwrap();
+        assert_eq!(found.program, "powershell");
+        assert_eq!(
+            found.args.last().map(Path::new),
+            Some(&*first.join("bw.ps1"))
+        );
+        empty(&first, &["bw.cmd"]);
+        let found = find_on_path(&dirs, false).unwrap().unwrap();
+        assert_eq!(Path::new(&found.program), first.join("bw.cmd"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn npm_shims_run_their_script() {
+        // What npm installs for `@bitwarden/cli`.
+        const SHIM: &str = "@ECHO off\r\n\
+            GOTO start\r\n\
+            :find_dp0\r\n\
+            SET dp0=%~dp0\r\n\
+            EXIT /b\r\n\
+            :start\r\n\
+            SETLOCAL\r\n\
+            CALL :find_dp0\r\n\
+            IF EXIST \"%dp0%\\node.exe\" (\r\n\
+            \x20 SET \"_prog=%dp0%\\node.exe\"\r\n\
+            ) ELSE (\r\n\
+            \x20 SET \"_prog=node\"\r\n\
+            \x20 SET PATHEXT=%PATHEXT:;.JS;=;%\r\n\
+            )\r\n\
+            endLocal & goto #_undefined_# 2>NUL || title %COMSPEC% & \"%_prog%\"  \
+            \"%dp0%\\node_modules\\@bitwarden\\cli\\build\\bw.js\" %*\r\n";
+        let prefix = fake::temp_dir("npm prefix with spaces");
+        let shim = prefix.join("bw.cmd");
+        std::fs::write(&shim, SHIM).unwrap();
+        let shim = shim.to_str().unwrap();
+        // Without the script, the batch file is all there is.
+        assert_eq!(launch(shim), Located::program(shim));
+
+        let script = prefix.join(r"node_modules\@bitwarden\cli\build\bw.js");
+        std::fs::create_dir_all(script.parent().unwrap()).unwrap();
+        std::fs::write(&script, "").unwrap();
+        let located = launch(shim);
+        assert_eq!(located.program, "node");
+        assert_eq!(located.args, [script.to_string_lossy()]);
+
+        std::fs::write(prefix.join("node.exe"), "").unwrap();
+        let located = launch(shim);
+        assert_eq!(Path::new(&located.program), prefix.join("node.exe"));
+        assert_eq!(
+            unwrap_launcher(&located.program, &located.args),
+            (script.to_string_lossy().into_owned(), 1)
+        );
+    }
+}
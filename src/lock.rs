@@ -0,0 +1,129 @@
+//! An advisory lock shared by all provider processes of a user, so that two
+//! of them started together by cargo don't run `bw` at the same time: `bw`
+//! rewrites its data file on every sync, and a process reading it halfway
+//! fails to parse it.
+//!
+//! Lookups take the lock shared, changes to the vault exclusively. The lock
+//! belongs to an open file, so the operating system releases it when the
+//! [`Lock`] is dropped, on panics, or when the process dies.
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Shared,
+    Exclusive,
+}
+
+/// Held until dropped.
+pub struct Lock {
+    _file: File,
+}
+
+/// The lock file in `dir`.
+pub fn path(dir: &Path) -> PathBuf {
+    dir.join("bw.lock")
+}
+
+/// Take the lock at `path`, waiting for at most `timeout` while another
+/// process holds it. Without a lock file to open, as on a read-only home
+/// directory, this warns and goes on without the lock.
+pub fn acquire(path: &Path, mode: Mode, timeout: Duration) -> Result<Option<Lock>, String> {
+    let open = || {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+    };
+    let file = match open() {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!(
+                "warning: running without the Bitwarden lock, failed to open `{}`: {}",
+                path.display(),
+                e
+            );
+            return Ok(None);
+        }
+    };
+
+    let started = Instant::now();
+    loop {
+        match try_lock(&file, mode) {
+            Ok(true) => return Ok(Some(Lock { _file: file })),
+            Ok(false) if started.elapsed() < timeout => thread::sleep(Duration::from_millis(100)),
+            Ok(false) => {
+                return Err(format!(
+                    "another cargo-credential-bitwarden process is holding the Bitwarden lock \
+                     `{}`; gave up after {} seconds, pass a larger `--lock-timeout` to wait longer",
+                    path.display(),
+                    timeout.as_secs()
+                ))
+            }
+            Err(e) => return Err(format!("failed to lock `{}`: {}", path.display(), e)),
+        }
+    }
+}
+
+/// Lock `file` without waiting; `false` if another process holds it.
+#[cfg(unix)]
+fn try_lock(file: &File, mode: Mode) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    let operation = match mode {
+        Mode::Shared => libc::LOCK_SH,
+        Mode::Exclusive => libc::LOCK_EX,
+    };
+    if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let e = io::Error::last_os_error();
+    match e.kind() {
+        io::ErrorKind::WouldBlock => Ok(false),
+        _ => Err(e),
+    }
+}
+
+/// Lock `file` without waiting; `false` if another process holds it.
+#[cfg(windows)]
+fn try_lock(file: &File, mode: Mode) -> io::Result<bool> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::{ERROR_LOCK_VIOLATION, HANDLE};
+    use windows_sys::Win32::Storage::FileSystem::{
+        LockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
+    };
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    let mut flags = LOCKFILE_FAIL_IMMEDIATELY;
+    if mode == Mode::Exclusive {
+        flags |= LOCKFILE_EXCLUSIVE_LOCK;
+    }
+    let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+    let locked = unsafe {
+        LockFileEx(
+            file.as_raw_handle() as HANDLE,
+            flags,
+            0,
+            u32::MAX,
+            u32::MAX,
+            &mut overlapped,
+        )
+    };
+    if locked != 0 {
+        return Ok(true);
+    }
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        Some(code) if code == ERROR_LOCK_VIOLATION as i32 => Ok(false),
+        _ => Err(e),
+    }
+}
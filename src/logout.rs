@@ -0,0 +1,168 @@
+//! Removing the token of `cargo logout`, and the item with it when the item
+//! holds nothing else.
+
+use crate::backend::{self, Backend};
+use crate::scope::Scope;
+use crate::{
+    is_note, remove_field, stored_secret, uri_key, BitwardenVault, ItemRef, ListItem,
+    FINGERPRINT_FIELD, NOTE_URL_FIELD,
+};
+use cargo_credential::Error;
+use serde_json::Value;
+
+impl BitwardenVault {
+    /// Remove the token for `index_url` from `item`, deleting the item only
+    /// when it doesn't hold anything else worth keeping.
+    pub(crate) fn logout(
+        &self,
+        session: &Option<String>,
+        item: &ListItem,
+        index_url: &str,
+    ) -> Result<(), Error> {
+        let own_item = Scope::from_item_name(&item.name).is_some();
+        if let (Some(scope), false) = (self.options.scope, own_item) {
+            let others_left = [Scope::Read, Scope::Publish, Scope::All]
+                .iter()
+                .any(|&other| {
+                    other != scope && stored_secret(item, other.field_name().as_deref()).is_some()
+                });
+            if others_left {
+                let mut full = self.get_item(session, &item.id)?;
+                remove_secret(&mut full, scope.field_name().as_deref());
+                self.edit_removed(session, full)?;
+                eprintln!(
+                    "note: removed the `{}` token from item `{}`, which holds tokens of other scopes",
+                    scope, item.name
+                );
+                return Ok(());
+            }
+        }
+
+        if let (Some(field), false) = (&self.options.token_field, self.options.delete_item) {
+            if stored_secret(item, Some(field)).is_none() {
+                return Err(Error::NotFound);
+            }
+            // The rest of the item is the user's.
+            let mut full = self.get_item(session, &item.id)?;
+            remove_field(&mut full, field);
+            self.edit_removed(session, full)?;
+            eprintln!(
+                "note: removed field `{}` from item `{}`; pass `--delete-item` to delete the item instead",
+                field, item.name
+            );
+            return Ok(());
+        }
+        // The token stays for the other registries of a shared item.
+        let has_other_uris = item
+            .login
+            .uris
+            .iter()
+            .any(|uri| uri_key(&uri.uri) != uri_key(index_url));
+        if !self.options.delete_item && (self.options.attach_to.is_some() || has_other_uris) {
+            return self.detach(session, item, index_url);
+        }
+        if self.options.delete_item || self.is_managed(item) {
+            return self.delete(session, item);
+        }
+
+        let mut full = self.get_item(session, &item.id)?;
+        // Fields this provider adds itself don't count as other data.
+        let has_fields = full["fields"].as_array().is_some_and(|fields| {
+            fields
+                .iter()
+                .any(|field| field["name"] != FINGERPRINT_FIELD && field["name"] != NOTE_URL_FIELD)
+        });
+        if !has_fields {
+            return self.delete(session, item);
+        }
+
+        remove_secret(&mut full, None);
+        remove_field(&mut full, NOTE_URL_FIELD);
+        if let Some(uris) = full
+            .get_mut("login")
+            .and_then(|login| login.get_mut("uris"))
+            .and_then(Value::as_array_mut)
+        {
+            uris.retain(|uri| uri["uri"].as_str().map(uri_key) != Some(uri_key(index_url)));
+        }
+
+        self.edit_removed(session, full)?;
+        eprintln!(
+            "note: removed `{}` from item `{}`, which holds other data; pass `--delete-item` to delete it instead",
+            index_url, item.name
+        );
+        Ok(())
+    }
+
+    /// Remove `index_url` from the URIs of the shared `item`, whose token
+    /// stays for the others, or delete it with the last. Also undoes
+    /// `--attach-to`.
+    fn detach(
+        &self,
+        session: &Option<String>,
+        item: &ListItem,
+        index_url: &str,
+    ) -> Result<(), Error> {
+        let mut full = self.get_item(session, &item.id)?;
+        let Some(uris) = full["login"]["uris"].as_array_mut() else {
+            return self.delete(session, item);
+        };
+        uris.retain(|uri| uri["uri"].as_str().map(uri_key) != Some(uri_key(index_url)));
+        if uris.is_empty() {
+            return self.delete(session, item);
+        }
+        self.edit(session, &full)?;
+        eprintln!(
+            "note: removed `{}` from item `{}`, which keeps the token for its other URIs",
+            index_url, item.name
+        );
+        Ok(())
+    }
+
+    /// Store `item` once a token was removed from it. With `--purge` the
+    /// password history goes too, which may hold earlier tokens.
+    fn edit_removed(&self, session: &Option<String>, mut item: Value) -> Result<(), Error> {
+        if let (true, Some(object)) = (self.options.purge, item.as_object_mut()) {
+            object.insert("passwordHistory".into(), Value::Array(Vec::new()));
+        }
+        self.edit(session, &item)
+    }
+
+    /// Move `item` to the trash, or with `--purge` delete it for good.
+    fn delete(&self, session: &Option<String>, item: &ListItem) -> Result<(), Error> {
+        let target = ItemRef {
+            id: &item.id,
+            name: &item.name,
+            organization_id: item.organization_id.as_deref(),
+        };
+        if self.options.purge {
+            // Only the CLI can skip the trash.
+            backend::Cli {
+                vault: self,
+                session,
+            }
+            .delete_item(&target, true)?;
+        } else {
+            self.backend(session)?.delete_item(&target, false)?;
+            eprintln!(
+                "note: moved item `{}` to the Bitwarden trash, where it can be restored from; \
+                 pass `--purge` to delete it for good",
+                item.name
+            );
+        }
+        self.sync_after_write(session)
+    }
+}
+
+/// Remove the secret in `field`, or else the password, from a full item.
+fn remove_secret(item: &mut Value, field: Option<&str>) {
+    match field {
+        None if is_note(item) => item["notes"] = Value::Null,
+        None => {
+            if let Some(login) = item.get_mut("login").and_then(Value::as_object_mut) {
+                login.insert("password".into(), Value::Null);
+            }
+        }
+        Some(field_name) => remove_field(item, field_name),
+    }
+}
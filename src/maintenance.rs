@@ -0,0 +1,1713 @@
+//! Maintenance modes, used when the binary is run directly instead of by
+//! cargo.
+//!
+//! Unlike the credential protocol, these report failures through the process
+//! exit code so that scripts can branch on the kind of failure.
+
+use crate::backend::{self, Backend};
+use crate::scope::Scope;
+use crate::{
+    bw_error, cargo_config, fingerprint, fixture, last_rotated, locate, options, redact, timings,
+    tty, validate_token, BitwardenVault, ItemRef, ListItem, VaultState, SESSION_ENV,
+};
+use cargo_credential::{Error, RegistryInfo, Secret};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{ErrorKind, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
+
+/// Exit codes of the maintenance modes. These are part of the public
+/// interface and must stay stable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Success = 0,
+    Other = 1,
+    Usage = 2,
+    CliMissing = 3,
+    Locked = 4,
+    NotFound = 5,
+    Network = 6,
+    Permission = 7,
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> ExitCode {
+        ExitCode::from(exit as u8)
+    }
+}
+
+/// A failed maintenance mode.
+pub struct Failure {
+    exit: Exit,
+    message: String,
+}
+
+impl Failure {
+    pub fn new(exit: Exit, message: impl Into<String>) -> Failure {
+        Failure {
+            exit,
+            message: message.into(),
+        }
+    }
+
+    pub fn usage(message: impl Into<String>) -> Failure {
+        Failure::new(Exit::Usage, message)
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Failure {
+        let exit = classify(&e);
+        Failure::new(exit, e.to_string())
+    }
+}
+
+/// Map a provider error onto the exit code describing it best.
+fn classify(e: &Error) -> Exit {
+    if let Error::NotFound = e {
+        return Exit::NotFound;
+    }
+
+    match bw_error::classify_message(&e.to_string()) {
+        bw_error::Kind::Locked => Exit::Locked,
+        bw_error::Kind::Network => Exit::Network,
+        bw_error::Kind::Permission => Exit::Permission,
+        bw_error::Kind::NotFound => Exit::NotFound,
+        _ => Exit::Other,
+    }
+}
+
+/// Run the maintenance mode selected by `args`, which exclude the program
+/// name.
+///
+/// Modes may be spelled with or without leading dashes (`--check` or
+/// `check`).
+pub fn run(args: &[String]) -> ExitCode {
+    let started = Instant::now();
+    let result = match args.first().map(|mode| mode.trim_start_matches('-')) {
+        Some("check") => check(&args[1..]),
+        Some("import") => import(&args[1..]),
+        Some("export") => export(&args[1..]),
+        Some("login") => login(&args[1..]),
+        Some("status") => status(&args[1..]),
+        Some("list") => list(&args[1..]),
+        Some("rotate") => rotate(&args[1..]),
+        Some("prune") => prune(&args[1..]),
+        Some("capture-fixture") => capture_fixture(&args[1..]),
+        Some("diagnose") => diagnose(&args[1..]),
+        Some("setup") => setup(&args[1..]),
+        Some(mode) => Err(Failure::usage(format!("unknown mode `{}`", mode))),
+        None => Err(Failure::usage(
+            "this is a cargo credential provider, see the README for how to configure cargo",
+        )),
+    };
+    timings::report(started.elapsed());
+
+    match result {
+        Ok(()) => Exit::Success.into(),
+        Err(failure) => {
+            eprintln!("error: {}", failure.message);
+            failure.exit.into()
+        }
+    }
+}
+
+/// Arguments of a maintenance mode: its own options, and the provider
+/// options that are passed on to [`BitwardenVault::new`].
+struct ModeArgs<'a> {
+    flags: Vec<&'a str>,
+    values: HashMap<&'a str, Vec<&'a str>>,
+    provider: Vec<&'a str>,
+}
+
+impl<'a> ModeArgs<'a> {
+    /// Split off the mode's boolean `flags` and the options in `values` that
+    /// take an argument.
+    fn parse(args: &'a [String], flags: &[&str], values: &[&str]) -> Result<ModeArgs<'a>, Failure> {
+        let mut parsed = ModeArgs {
+            flags: Vec::new(),
+            values: HashMap::new(),
+            provider: Vec::new(),
+        };
+
+        let mut args = args.iter().map(String::as_str);
+        while let Some(arg) = args.next() {
+            if flags.contains(&arg) {
+                parsed.flags.push(arg);
+            } else if values.contains(&arg) {
+                let value = args
+                    .next()
+                    .ok_or_else(|| Failure::usage(format!("{} needs an arg", arg)))?;
+                parsed.values.entry(arg).or_default().push(value);
+            } else {
+                parsed.provider.push(arg);
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.contains(&name)
+    }
+
+    /// The last value given for option `name`.
+    fn value(&self, name: &str) -> Option<&'a str> {
+        self.values(name).last().copied()
+    }
+
+    /// All values given for option `name`, which may be repeated.
+    fn values(&self, name: &str) -> &[&'a str] {
+        self.values.get(name).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Construct the vault from provider options and find the CLI, telling a
+/// missing CLI apart from bad options.
+fn vault(args: &[&str]) -> Result<BitwardenVault, Failure> {
+    let op = BitwardenVault::new(args)
+        .inspect_err(options::explain)
+        .map_err(|e| Failure::usage(e.to_string()))?;
+    op.setup().map_err(|e| {
+        let message = e.to_string();
+        if message.contains(locate::NOT_FOUND) {
+            Failure::new(Exit::CliMissing, message)
+        } else {
+            Failure::usage(message)
+        }
+    })?;
+    op.capabilities()?;
+    op.verify_server()?;
+    Ok(op)
+}
+
+/// `--check [--index-url <url>] [options...]`: verify that the vault is
+/// usable, and optionally that it holds a token for a registry.
+fn check(args: &[String]) -> Result<(), Failure> {
+    let args = ModeArgs::parse(args, &[], &["--index-url"])?;
+    let op = vault(&args.provider)?;
+    if let Some(version) = op.version.get() {
+        println!("bw {}", version);
+    }
+
+    let status = op.status()?;
+    if status.status != VaultState::Unlocked {
+        return Err(Failure::new(
+            Exit::Locked,
+            "the Bitwarden vault is not unlocked; run `bw login` or `bw unlock` and export BW_SESSION",
+        ));
+    }
+
+    if let Some(index_url) = args.value("--index-url") {
+        let index_url = &op.canonical_url(index_url);
+        match op.search(&None, index_url)? {
+            Some(item) => println!(
+                "found item `{}` ({}) for `{}`",
+                item.name, item.id, index_url
+            ),
+            None => {
+                return Err(Failure::new(
+                    Exit::NotFound,
+                    format!("no Bitwarden item matches registry `{}`", index_url),
+                ))
+            }
+        }
+    }
+
+    println!("ok");
+    Ok(())
+}
+
+/// `--import [--credentials <path>] [--delete-source] [--force] [options...]`:
+/// move the tokens in cargo's `credentials.toml` into the vault.
+fn import(args: &[String]) -> Result<(), Failure> {
+    let args = ModeArgs::parse(args, &["--delete-source", "--force"], &["--credentials"])?;
+    let path = match args.value("--credentials") {
+        Some(path) => PathBuf::from(path),
+        None => cargo_config::credentials_path()
+            .ok_or_else(|| Failure::usage("cannot determine $CARGO_HOME, pass --credentials"))?,
+    };
+
+    let Some(mut doc) = cargo_config::read_toml(&path).map_err(Failure::usage)? else {
+        println!("`{}` doesn't exist, nothing to import", path.display());
+        return Ok(());
+    };
+    let tokens = cargo_config::stored_tokens(&doc);
+    if tokens.is_empty() {
+        println!("`{}` holds no tokens, nothing to import", path.display());
+        return Ok(());
+    }
+
+    let mut op = vault(&args.provider)?;
+    // Overwriting is confirmed below, once per token.
+    op.options.force = true;
+    let session = op.signin()?;
+
+    let mut imported = Vec::new();
+    let mut failed = 0;
+    for stored in &tokens {
+        redact::register(&stored.token);
+        let name = stored.registry.as_deref().unwrap_or("crates-io");
+        let Some(index_url) = cargo_config::registry_index(stored.registry.as_deref()) else {
+            eprintln!(
+                "warning: skipping `{}`: its index URL isn't in any cargo config",
+                name
+            );
+            continue;
+        };
+        let index_url = op.canonical_url(&index_url);
+        let registry = RegistryInfo {
+            index_url: &index_url,
+            name: Some(name),
+            headers: Vec::new(),
+        };
+        // For `--match-by-name`, like a request from cargo.
+        op.registry_name.replace(Some(name.to_string()));
+
+        let result = op.search(&session, &index_url).and_then(|existing| {
+            if let (Some(item), false) = (existing, args.flag("--force")) {
+                let question = format!(
+                    "Overwrite the token for `{}` stored in Bitwarden item `{}`?",
+                    name, item.name
+                );
+                match tty::confirm(&question) {
+                    Some(true) => {}
+                    Some(false) => return Ok(false),
+                    None => {
+                        return Err(format!(
+                            "item `{}` already holds a token, pass --force to overwrite it",
+                            item.name
+                        )
+                        .into())
+                    }
+                }
+            }
+            op.store(&session, &registry, || {
+                Ok(Secret::from(stored.token.clone()))
+            })?;
+            Ok(true)
+        });
+
+        match result {
+            Ok(true) => {
+                println!("imported `{}` ({})", name, index_url);
+                imported.push(stored.registry.clone());
+            }
+            Ok(false) => println!("skipped `{}`", name),
+            Err(e) => {
+                eprintln!("error: failed to import `{}`: {}", name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if args.flag("--delete-source") && !imported.is_empty() {
+        for registry in &imported {
+            cargo_config::remove_token(&mut doc, registry.as_deref());
+        }
+        std::fs::write(&path, doc.to_string()).map_err(|e| {
+            Failure::new(
+                Exit::Other,
+                format!("failed to write `{}`: {}", path.display(), e),
+            )
+        })?;
+        println!(
+            "removed {} imported token(s) from `{}`",
+            imported.len(),
+            path.display()
+        );
+    }
+
+    println!("imported {} of {} token(s)", imported.len(), tokens.len());
+    if failed > 0 {
+        return Err(Failure::new(
+            Exit::Other,
+            format!("{} token(s) could not be imported", failed),
+        ));
+    }
+    Ok(())
+}
+
+/// `--export [--out <path>] [--overwrite] [--force-print] [--registry-url <url>]
+/// [--registry-name <name>] [options...]`: write the tokens of the items this
+/// provider manages as a `credentials.toml`.
+fn export(args: &[String]) -> Result<(), Failure> {
+    let args = ModeArgs::parse(
+        args,
+        &["--overwrite", "--force-print"],
+        &["--out", "--registry-url", "--registry-name"],
+    )?;
+    let out = args.value("--out").map(PathBuf::from);
+    if out.is_none() && std::io::stdout().is_terminal() && !args.flag("--force-print") {
+        return Err(Failure::usage(
+            "refusing to print plaintext tokens to the terminal; pass --out <path> or --force-print",
+        ));
+    }
+
+    let op = vault(&args.provider)?;
+    op.only_reading.set(true);
+    let session = op.signin()?;
+
+    let mut doc = toml_edit::DocumentMut::new();
+    let mut exported = 0;
+    for item in op.managed_items(&session)? {
+        let Some(index_url) = item.login.uris.first().map(|uri| uri.uri.as_str()) else {
+            continue;
+        };
+        // Templates that don't spell out the registry leave only its host.
+        let host = crate::normalize_index_url(index_url)
+            .host()
+            .map(str::to_string);
+        let name = match (op.options.name_template.registry(&item.name), &host) {
+            (Some(name), _) => name,
+            (None, Some(host)) => host,
+            (None, None) => continue,
+        };
+        if args
+            .value("--registry-url")
+            .is_some_and(|url| crate::uri_key(url) != crate::uri_key(index_url))
+            || args.value("--registry-name").is_some_and(|n| n != name)
+        {
+            continue;
+        }
+
+        // Look the token up the same way cargo would get it.
+        let Some(found) = op.search(&session, index_url)? else {
+            continue;
+        };
+        op.reprompt(&found)?;
+        let Some(token) = crate::stored_secret(&found, op.options.token_field.as_deref()) else {
+            continue;
+        };
+        redact::register(&token);
+
+        let registry = if crate::uri_key(index_url) == crate::uri_key(cargo_config::CRATES_IO_INDEX)
+            || name == "crates-io"
+        {
+            None
+        } else {
+            Some(name)
+        };
+        cargo_config::set_token(&mut doc, registry, &token);
+        exported += 1;
+    }
+    if exported == 0 {
+        return Err(Failure::new(
+            Exit::NotFound,
+            "no Bitwarden items with registry tokens found",
+        ));
+    }
+
+    match out {
+        Some(out) => {
+            cargo_config::write_private(&out, &doc.to_string(), args.flag("--overwrite")).map_err(
+                |e| {
+                    if e.kind() == ErrorKind::AlreadyExists {
+                        Failure::usage(format!(
+                            "`{}` already exists, pass --overwrite to replace it",
+                            out.display()
+                        ))
+                    } else {
+                        Failure::new(
+                            Exit::Other,
+                            format!("failed to write `{}`: {}", out.display(), e),
+                        )
+                    }
+                },
+            )?;
+            println!("exported {} token(s) to `{}`", exported, out.display());
+        }
+        None => print!("{}", doc),
+    }
+    eprintln!(
+        "warning: the exported credentials contain {} plaintext token(s); keep them safe",
+        exported
+    );
+    Ok(())
+}
+
+/// `--login --registry-url <url> [--registry-name <name>] [--from-send <url>
+/// [--send-password-env <var>]] [options...]`: store a token without going
+/// through `cargo login`.
+///
+/// The token is taken from a Bitwarden Send, or asked for on the terminal.
+fn login(args: &[String]) -> Result<(), Failure> {
+    let args = ModeArgs::parse(
+        args,
+        &[],
+        &[
+            "--registry-url",
+            "--registry-name",
+            "--from-send",
+            "--send-password-env",
+        ],
+    )?;
+    let index_url = args
+        .value("--registry-url")
+        .ok_or_else(|| Failure::usage("--login needs --registry-url <url>"))?;
+    if args.value("--send-password-env").is_some() && args.value("--from-send").is_none() {
+        return Err(Failure::usage("--send-password-env requires --from-send"));
+    }
+
+    let op = vault(&args.provider)?;
+    let session = op.signin()?;
+    let index_url = &op.canonical_url(index_url);
+    let registry = RegistryInfo {
+        index_url,
+        name: args.value("--registry-name"),
+        headers: Vec::new(),
+    };
+
+    op.store(&session, &registry, || match args.value("--from-send") {
+        Some(url) => op.receive(url, args.value("--send-password-env")),
+        None => {
+            let token = tty::prompt_secret(&format!("Token for {}", index_url))
+                .ok_or("no terminal to read the token from; use --from-send")?;
+            let token = validate_token(&token)?;
+            redact::register(token);
+            Ok(Secret::from(token.to_string()))
+        }
+    })?;
+
+    println!("stored the token for `{}`", index_url);
+    Ok(())
+}
+
+/// A row of the `--status` output.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct RegistryStatus {
+    registry: String,
+    index_url: String,
+    item_name: Option<String>,
+    item_id: Option<String>,
+    /// See [`crate::fingerprint`].
+    fingerprint: Option<String>,
+    last_rotated: Option<String>,
+    /// More than one item holds the exact index URL.
+    duplicates: bool,
+    /// Items match the registry's host, but none holds the exact index URL.
+    missing_uri: bool,
+    /// The token is older than `--warn-stale` allows.
+    stale: bool,
+}
+
+impl RegistryStatus {
+    fn flags(&self) -> String {
+        let mut flags = Vec::new();
+        if self.duplicates {
+            flags.push("duplicates");
+        }
+        if self.missing_uri {
+            flags.push("missing-uri");
+        }
+        if self.stale {
+            flags.push("stale");
+        }
+        flags.join(",")
+    }
+}
+
+/// `--status [--cargo-config <path>]... [--json] [options...]`: show which
+/// configured registries have a token in the vault. Tokens are never shown.
+fn status(args: &[String]) -> Result<(), Failure> {
+    let args = ModeArgs::parse(args, &["--json"], &["--cargo-config"])?;
+    let config_paths = match args.values("--cargo-config") {
+        [] => cargo_config::config_paths(),
+        paths => paths.iter().map(PathBuf::from).collect(),
+    };
+
+    let registries = cargo_config::registries(&config_paths).map_err(Failure::usage)?;
+    let op = vault(&args.provider)?;
+    op.only_reading.set(true);
+    let session = op.signin()?;
+
+    let now = time::OffsetDateTime::now_utc();
+    let mut rows = Vec::new();
+    for (name, index_url) in registries {
+        let index_url = op.canonical_url(&index_url);
+        let matches = op.list_matches(&session, &index_url)?;
+        let exact: Vec<_> = matches
+            .iter()
+            .filter(|item| item.has_uri(&index_url))
+            .collect();
+        let item = exact.first();
+        rows.push(RegistryStatus {
+            item_name: item.map(|item| item.name.clone()),
+            item_id: item.map(|item| item.id.clone()),
+            fingerprint: item
+                .and_then(|item| crate::stored_secret(item, op.options.token_field.as_deref()))
+                .filter(|token| !token.is_empty())
+                .map(|token| fingerprint::fingerprint(&token)),
+            last_rotated: item.and_then(|item| last_rotated(item)),
+            stale: item.is_some_and(|item| op.stale_days(item, now).is_some()),
+            duplicates: exact.len() > 1,
+            missing_uri: exact.is_empty() && !matches.is_empty(),
+            registry: name,
+            index_url,
+        });
+    }
+
+    if args.flag("--json") {
+        let json = serde_json::to_string_pretty(&rows)
+            .map_err(|e| Failure::new(Exit::Other, format!("failed to serialize status: {}", e)))?;
+        println!("{}", json);
+    } else {
+        print_table(&rows);
+    }
+    Ok(())
+}
+
+fn print_table(rows: &[RegistryStatus]) {
+    let dash = || "-".to_string();
+    let mut table = vec![[
+        "REGISTRY".to_string(),
+        "ITEM".to_string(),
+        "ID".to_string(),
+        "FINGERPRINT".to_string(),
+        "LAST ROTATED".to_string(),
+        "FLAGS".to_string(),
+    ]];
+    for row in rows {
+        table.push([
+            row.registry.clone(),
+            row.item_name.clone().unwrap_or_else(dash),
+            row.item_id.clone().unwrap_or_else(dash),
+            row.fingerprint.clone().unwrap_or_else(dash),
+            row.last_rotated.clone().unwrap_or_else(dash),
+            row.flags(),
+        ]);
+    }
+
+    print_columns(&table);
+}
+
+/// Print `table` with its columns aligned.
+fn print_columns<const N: usize>(table: &[[String; N]]) {
+    let mut widths = [0; N];
+    for line in table {
+        for (width, cell) in widths.iter_mut().zip(line) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for line in table {
+        let cells: Vec<_> = line
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+/// A row of the `--list` output.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ListedItem {
+    index_url: Option<String>,
+    item_name: String,
+    item_id: String,
+    folder: Option<String>,
+    revision_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+}
+
+/// `--list [--cargo-config <path>]... [--json] [--show-tokens]
+/// [options...]`: show the items in the vault holding registry tokens,
+/// whether or not a cargo config names their registry.
+fn list(args: &[String]) -> Result<(), Failure> {
+    let args = ModeArgs::parse(args, &["--json", "--show-tokens"], &["--cargo-config"])?;
+    let show_tokens = args.flag("--show-tokens");
+    if show_tokens && !std::io::stdout().is_terminal() {
+        return Err(Failure::usage(
+            "refusing to print plaintext tokens when stdout isn't a terminal",
+        ));
+    }
+    let config_paths = match args.values("--cargo-config") {
+        [] => cargo_config::config_paths(),
+        paths => paths.iter().map(PathBuf::from).collect(),
+    };
+    let configured: Vec<String> = cargo_config::registries(&config_paths)
+        .map_err(Failure::usage)?
+        .into_iter()
+        .map(|(_, index_url)| crate::uri_key(&index_url))
+        .collect();
+    let is_index = |uri: &str| looks_like_index(uri, &configured);
+
+    let op = vault(&args.provider)?;
+    op.only_reading.set(true);
+    let session = op.signin()?;
+    let folders = op.folder_names(&session);
+
+    let mut rows: Vec<ListedItem> = op
+        .registry_items(&session, is_index)?
+        .into_iter()
+        .map(|item| {
+            if show_tokens {
+                op.reprompt(&item)?;
+            }
+            let uris = &item.login.uris;
+            Ok(ListedItem {
+                index_url: uris
+                    .iter()
+                    .find(|uri| is_index(&uri.uri))
+                    .or(uris.first())
+                    .map(|uri| uri.uri.clone()),
+                folder: item
+                    .folder_id
+                    .as_ref()
+                    .and_then(|id| folders.get(id))
+                    .cloned(),
+                revision_date: item.revision_date.clone(),
+                token: crate::stored_secret(&item, op.options.token_field.as_deref())
+                    .filter(|_| show_tokens),
+                item_name: item.name,
+                item_id: item.id,
+            })
+        })
+        .collect::<Result<_, Failure>>()?;
+    rows.sort_by(|a, b| (&a.index_url, &a.item_name).cmp(&(&b.index_url, &b.item_name)));
+
+    if args.flag("--json") {
+        let json = serde_json::to_string_pretty(&rows)
+            .map_err(|e| Failure::new(Exit::Other, format!("failed to serialize items: {}", e)))?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    let dash = || "-".to_string();
+    let mut table = vec![[
+        "REGISTRY".to_string(),
+        "ITEM".to_string(),
+        "ID".to_string(),
+        "FOLDER".to_string(),
+        "REVISED".to_string(),
+        if show_tokens { "TOKEN" } else { "" }.to_string(),
+    ]];
+    for row in rows {
+        table.push([
+            row.index_url.unwrap_or_else(dash),
+            row.item_name,
+            row.item_id,
+            row.folder.unwrap_or_else(dash),
+            row.revision_date.unwrap_or_else(dash),
+            row.token.unwrap_or_default(),
+        ]);
+    }
+    print_columns(&table);
+    Ok(())
+}
+
+/// Whether `uri` is the index URL of a registry: one of those in
+/// `configured`, as [`crate::uri_key`]s, or a sparse or crates.io one.
+fn looks_like_index(uri: &str, configured: &[String]) -> bool {
+    let key = crate::uri_key(uri);
+    uri.starts_with("sparse+")
+        || key == crate::uri_key(cargo_config::CRATES_IO_INDEX)
+        || configured.contains(&key)
+}
+
+/// What `--prune` does with an item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Prune {
+    /// Delete it for good, as another item holds the same token.
+    Delete,
+    /// Move it to the trash, from where it can be restored, as the token
+    /// it holds may still be wanted.
+    Trash,
+    /// Leave it, as it belongs to an organization.
+    Skip,
+}
+
+impl Prune {
+    fn as_str(self) -> &'static str {
+        match self {
+            Prune::Delete => "delete",
+            Prune::Trash => "trash",
+            Prune::Skip => "skip",
+        }
+    }
+}
+
+/// `--prune [--apply] [--include-org] [--cargo-config <path>]...
+/// [options...]`: find the items this provider manages that are
+/// duplicates, for registries no cargo config names, or without a token,
+/// and with `--apply` remove them.
+fn prune(args: &[String]) -> Result<(), Failure> {
+    let args = ModeArgs::parse(args, &["--apply", "--include-org"], &["--cargo-config"])?;
+    let config_paths = match args.values("--cargo-config") {
+        [] => cargo_config::config_paths(),
+        paths => paths.iter().map(PathBuf::from).collect(),
+    };
+    let configured: Vec<String> = cargo_config::registries(&config_paths)
+        .map_err(Failure::usage)?
+        .into_iter()
+        .map(|(_, index_url)| crate::uri_key(&index_url))
+        .collect();
+
+    let op = vault(&args.provider)?;
+    if op.options.backend.is_other_client() {
+        return Err(Failure::usage(format!(
+            "--prune is not supported with `--backend {}`",
+            op.options.backend.as_str()
+        )));
+    }
+    let session = op.signin()?;
+    let plan = prune_plan(&op, &session, &configured, args.flag("--include-org"))?;
+
+    if plan.is_empty() {
+        println!("nothing to prune");
+        return Ok(());
+    }
+    let mut table = vec![[
+        "ACTION".to_string(),
+        "ID".to_string(),
+        "ITEM".to_string(),
+        "REASON".to_string(),
+    ]];
+    for (prune, item, reason) in &plan {
+        table.push([
+            prune.as_str().to_string(),
+            item.id.clone(),
+            item.name.clone(),
+            reason.clone(),
+        ]);
+    }
+    print_columns(&table);
+    if !args.flag("--apply") {
+        eprintln!("note: nothing was changed; pass --apply to do this");
+        return Ok(());
+    }
+
+    let failed = apply_prune(&op, &session, &plan)?;
+    if failed > 0 {
+        return Err(Failure::new(
+            Exit::Other,
+            format!("{} item(s) could not be removed", failed),
+        ));
+    }
+    Ok(())
+}
+
+/// What `--prune` does with each item `op` manages, and why, given the
+/// [`crate::uri_key`]s of the registries in the cargo config.
+fn prune_plan(
+    op: &BitwardenVault,
+    session: &Option<String>,
+    configured: &[String],
+    include_org: bool,
+) -> Result<Vec<(Prune, ListItem, String)>, Error> {
+    let mut items = op.managed_items(session)?;
+    items.sort_by(|a, b| (crate::revised_at(b), &a.id).cmp(&(crate::revised_at(a), &b.id)));
+
+    // The tokens of an item, to tell exact duplicates apart.
+    let tokens = |item: &ListItem| {
+        [Scope::All, Scope::Read, Scope::Publish].map(|scope| match scope.field_name() {
+            Some(field) => item
+                .field(&field)
+                .filter(|t| !t.is_empty())
+                .map(str::to_string),
+            None => crate::stored_secret(item, op.options.token_field.as_deref()),
+        })
+    };
+
+    // The newest item for each URI, and scope when it has one of its own,
+    // with its tokens. An item is a duplicate when newer ones hold all its
+    // URIs, so that none is lost.
+    let mut newest = HashMap::new();
+    let mut plan: Vec<(Prune, ListItem, String)> = Vec::new();
+    for item in items {
+        let mut keys: Vec<String> = Vec::new();
+        for uri in &item.login.uris {
+            let key = crate::uri_key(&uri.uri);
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        if keys.is_empty() {
+            plan.push((Prune::Trash, item, "has no URI".to_string()));
+            continue;
+        }
+        let scope = Scope::from_item_name(&item.name);
+        let item_tokens = tokens(&item);
+
+        let newer: Option<Vec<_>> = keys
+            .iter()
+            .map(|key| Some((key, newest.get(&(key.clone(), scope))?)))
+            .collect();
+        if let Some(newer) = newer {
+            let (prune, reason) = if newer.iter().all(|(_, (_, t))| *t == item_tokens) {
+                (Prune::Delete, "duplicate, same token as")
+            } else {
+                (Prune::Trash, "duplicate, other token than")
+            };
+            let holders: Vec<String> = newer
+                .iter()
+                .map(|(key, (id, _))| format!("{} for {}", id, key))
+                .collect();
+            let reason = format!("{} {}", reason, holders.join(", "));
+            plan.push((prune, item, reason));
+            continue;
+        }
+        for key in &keys {
+            newest
+                .entry((key.clone(), scope))
+                .or_insert_with(|| (item.id.clone(), item_tokens.clone()));
+        }
+
+        let reason = if !keys.iter().any(|key| configured.contains(key)) {
+            match keys.as_slice() {
+                [key] => format!("{} is no registry in the cargo config", key),
+                keys => format!(
+                    "none of {} is a registry in the cargo config",
+                    keys.join(", ")
+                ),
+            }
+        } else if item_tokens.iter().all(Option::is_none) {
+            "holds no token".to_string()
+        } else {
+            continue;
+        };
+        plan.push((Prune::Trash, item, reason));
+    }
+    for (prune, item, reason) in &mut plan {
+        if item.organization_id.is_some() && !include_org {
+            *prune = Prune::Skip;
+            reason.push_str("; in an organization, pass --include-org");
+        }
+    }
+    Ok(plan)
+}
+
+/// Carry out `plan` of [`prune_plan`], and tell how many items could not be
+/// removed.
+fn apply_prune(
+    op: &BitwardenVault,
+    session: &Option<String>,
+    plan: &[(Prune, ListItem, String)],
+) -> Result<usize, Error> {
+    op.begin_batch();
+    let mut failed = 0;
+    for (prune, item, _) in plan {
+        let target = ItemRef {
+            id: &item.id,
+            name: &item.name,
+            organization_id: item.organization_id.as_deref(),
+        };
+        let result = match prune {
+            // Only the CLI can skip the trash.
+            Prune::Delete => backend::Cli { vault: op, session }.delete_item(&target, true),
+            Prune::Trash => op
+                .backend(session)
+                .and_then(|backend| backend.delete_item(&target, false)),
+            Prune::Skip => continue,
+        };
+        match result.and_then(|()| op.sync_after_write(session)) {
+            Ok(()) if *prune == Prune::Delete => println!("deleted {}", item.id),
+            Ok(()) => println!("moved {} to the trash", item.id),
+            Err(e) => {
+                eprintln!("error: failed to remove {}: {}", item.id, e);
+                failed += 1;
+            }
+        }
+    }
+    op.finish_batch(session)?;
+    Ok(failed)
+}
+
+/// `--rotate [--registry <name|url>]... [--from-file <path>] [options...]`:
+/// store new tokens for several registries, asking for each on the terminal
+/// or reading `registry=token` lines from a file.
+///
+/// `--rotate --index-url <url> [--create-missing] [options...]` replaces
+/// the token of one registry with the one piped to stdin, for scripts.
+fn rotate(args: &[String]) -> Result<(), Failure> {
+    let args = ModeArgs::parse(
+        args,
+        &["--create-missing"],
+        &["--registry", "--from-file", "--index-url"],
+    )?;
+    let configured =
+        cargo_config::registries(&cargo_config::config_paths()).map_err(Failure::usage)?;
+    if let Some(index_url) = args.value("--index-url") {
+        return rotate_one(&args, &configured, index_url);
+    }
+    if args.flag("--create-missing") {
+        return Err(Failure::usage("--create-missing needs --index-url"));
+    }
+
+    let selected = args
+        .values("--registry")
+        .iter()
+        .map(|registry| find_registry(&configured, registry))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // (name, index URL, token from the file)
+    let mut registries: Vec<(String, String, Option<String>)> = match args.value("--from-file") {
+        Some(path) => read_token_file(Path::new(path))?
+            .into_iter()
+            .map(|(registry, token)| {
+                let (name, index_url) = find_registry(&configured, &registry)?;
+                Ok((name, index_url, Some(token)))
+            })
+            .collect::<Result<_, Failure>>()?,
+        None if selected.is_empty() => configured
+            .into_iter()
+            .map(|(name, index_url)| (name, index_url, None))
+            .collect(),
+        None => selected
+            .iter()
+            .map(|(name, index_url)| (name.clone(), index_url.clone(), None))
+            .collect(),
+    };
+    if !selected.is_empty() {
+        registries.retain(|(_, index_url, _)| selected.iter().any(|(_, url)| url == index_url));
+    }
+
+    let mut op = vault(&args.provider)?;
+    // Rotating is replacing the tokens; there is nothing to confirm.
+    op.options.force = true;
+    let session = op.signin()?;
+    op.begin_batch();
+
+    let (mut rotated, mut skipped, mut failed) = (0, 0, 0);
+    for (name, index_url, token) in registries {
+        let token = match token {
+            Some(token) => token,
+            None => tty::prompt_secret(&format!(
+                "New token for `{}` ({}), empty to skip",
+                name, index_url
+            ))
+            .ok_or_else(|| {
+                Failure::usage("no terminal to read the tokens from; use --from-file")
+            })?,
+        };
+        if token.trim().is_empty() {
+            println!("skipped `{}`", name);
+            skipped += 1;
+            continue;
+        }
+
+        let index_url = op.canonical_url(&index_url);
+        let registry = RegistryInfo {
+            index_url: &index_url,
+            name: Some(&name),
+            headers: Vec::new(),
+        };
+        let result = validate_token(&token)
+            .map_err(Error::from)
+            .and_then(|token| {
+                redact::register(token);
+                op.store(&session, &registry, || Ok(Secret::from(token.to_string())))
+            });
+        match result {
+            Ok(()) => {
+                println!("rotated `{}`", name);
+                rotated += 1;
+            }
+            Err(e) => {
+                eprintln!("error: failed to rotate `{}`: {}", name, e);
+                failed += 1;
+            }
+        }
+    }
+    op.finish_batch(&session)?;
+
+    println!(
+        "rotated {}, skipped {}, failed {} registr{}",
+        rotated,
+        skipped,
+        failed,
+        if rotated + skipped + failed == 1 {
+            "y"
+        } else {
+            "ies"
+        }
+    );
+    if failed > 0 {
+        return Err(Failure::new(
+            Exit::Other,
+            format!("{} token(s) could not be rotated", failed),
+        ));
+    }
+    Ok(())
+}
+
+/// `--rotate --index-url <url>`: store the token from stdin in the item of
+/// the registry, like `cargo login` would, and print the id of the item.
+fn rotate_one(
+    args: &ModeArgs,
+    configured: &[(String, String)],
+    index_url: &str,
+) -> Result<(), Failure> {
+    if !args.values("--registry").is_empty() || args.value("--from-file").is_some() {
+        return Err(Failure::usage(
+            "--index-url can't be combined with --registry or --from-file",
+        ));
+    }
+    let token = match std::io::stdin().is_terminal() {
+        true => tty::prompt_secret(&format!("New token for `{}`", index_url))
+            .ok_or_else(|| Failure::usage("no terminal to read the token from"))?,
+        false => {
+            let mut token = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut token).map_err(|e| {
+                Failure::new(
+                    Exit::Other,
+                    format!("failed to read the token from stdin: {}", e),
+                )
+            })?;
+            token
+        }
+    };
+    redact::register(token.trim());
+    let token = validate_token(&token).map_err(Failure::usage)?;
+
+    // A registry cargo doesn't know is only named by its URL.
+    let (name, index_url) = find_registry(configured, index_url)?;
+
+    let mut op = vault(&args.provider)?;
+    op.options.force = true;
+    let session = op.signin()?;
+    let canonical = op.canonical_url(&index_url);
+    let registry = RegistryInfo {
+        index_url: &canonical,
+        name: Some(name.as_str()).filter(|name| *name != index_url),
+        headers: Vec::new(),
+    };
+    let index_url = &canonical;
+    // Several matching items are refused here, unless `--item-id` says which.
+    if op.find(&session, index_url)?.is_none() && !args.flag("--create-missing") {
+        return Err(Failure::new(
+            Exit::NotFound,
+            format!(
+                "no Bitwarden item matches registry `{}`; pass --create-missing to create one",
+                index_url
+            ),
+        ));
+    }
+    op.begin_batch();
+    let stored = op.store(&session, &registry, || Ok(Secret::from(token.to_string())));
+    let synced = op.finish_batch(&session);
+    stored?;
+    synced?;
+
+    let item = op
+        .find(&session, index_url)?
+        .ok_or_else(|| Failure::new(Exit::NotFound, "the stored item could not be found again"))?;
+    println!("{}", item.id);
+    Ok(())
+}
+
+/// Look up a registry given by name or index URL among the configured ones.
+///
+/// Index URLs that aren't configured are accepted as they are.
+fn find_registry(
+    configured: &[(String, String)],
+    registry: &str,
+) -> Result<(String, String), Failure> {
+    if let Some(found) = configured
+        .iter()
+        .find(|(name, url)| name == registry || url == registry)
+    {
+        return Ok(found.clone());
+    }
+    if registry.contains("://") {
+        return Ok((registry.to_string(), registry.to_string()));
+    }
+    cargo_config::registry_index(Some(registry))
+        .map(|url| (registry.to_string(), url))
+        .ok_or_else(|| Failure::usage(format!("unknown registry `{}`", registry)))
+}
+
+/// Read `registry=token` lines, refusing files others can read.
+fn read_token_file(path: &Path) -> Result<Vec<(String, String)>, Failure> {
+    let read_error =
+        |e: std::io::Error| Failure::usage(format!("failed to read `{}`: {}", path.display(), e));
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(path)
+            .map_err(read_error)?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            return Err(Failure::new(
+                Exit::Permission,
+                format!(
+                    "`{}` is accessible by other users (mode {:o}); run `chmod 600` on it",
+                    path.display(),
+                    mode & 0o777
+                ),
+            ));
+        }
+    }
+
+    let contents = std::fs::read_to_string(path).map_err(read_error)?;
+    let mut tokens = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (registry, token) = line.split_once('=').ok_or_else(|| {
+            Failure::usage(format!(
+                "`{}` line {}: expected `registry=token`",
+                path.display(),
+                number + 1
+            ))
+        })?;
+        redact::register(token.trim());
+        tokens.push((registry.trim().to_string(), token.trim().to_string()));
+    }
+    Ok(tokens)
+}
+
+/// `--capture-fixture [--out <dir>] [--index-url <url>] [--overwrite]
+/// [options...]`: save sanitized output of the `bw` commands the provider
+/// parses, for reproducing parsing problems with other CLI versions.
+fn capture_fixture(args: &[String]) -> Result<(), Failure> {
+    let args = ModeArgs::parse(args, &["--overwrite"], &["--out", "--index-url"])?;
+    let op = vault(&args.provider)?;
+    let session = op.signin()?;
+
+    let version = op
+        .version
+        .get()
+        .map_or_else(|| "unknown".to_string(), |version| version.to_string());
+    let dir = Path::new(args.value("--out").unwrap_or("fixtures")).join(format!("bw-{}", version));
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| Failure::usage(format!("failed to create `{}`: {}", dir.display(), e)))?;
+
+    let mut list = vec!["list", "items"];
+    if let Some(index_url) = args.value("--index-url") {
+        list.extend(["--url", index_url]);
+    }
+    let mut list_response = list.clone();
+    list_response.push("--response");
+    let mut list_trash = list.clone();
+    list_trash.push("--trash");
+    let captures: [(&str, &[&str]); 4] = [
+        ("status.json", &["status"]),
+        ("list-items.json", &list),
+        ("list-items-response.json", &list_response),
+        ("list-items-trash.json", &list_trash),
+    ];
+
+    let mut sanitizer = fixture::Sanitizer::default();
+    for (file, command) in captures {
+        let output = op.run_cmd(op.make_cmd(&session, command)?)?;
+        let path = dir.join(file);
+        cargo_config::write_private(
+            &path,
+            &sanitizer.sanitize_output(&output),
+            args.flag("--overwrite"),
+        )
+        .map_err(|e| {
+            if e.kind() == ErrorKind::AlreadyExists {
+                Failure::usage(format!(
+                    "`{}` already exists; pass --overwrite to replace it",
+                    path.display()
+                ))
+            } else {
+                Failure::new(
+                    Exit::Other,
+                    format!("failed to write `{}`: {}", path.display(), e),
+                )
+            }
+        })?;
+        println!("wrote `bw {}` to `{}`", command.join(" "), path.display());
+    }
+
+    println!("check the files for anything private before sharing them");
+    Ok(())
+}
+
+/// Outcome of the checks of `--diagnose`, printed as they are made.
+#[derive(Default)]
+struct Report {
+    /// Exit code of the first failed check.
+    failed: Option<Exit>,
+    failures: usize,
+}
+
+impl Report {
+    fn ok(&self, check: &str, detail: impl std::fmt::Display) {
+        println!("ok       {}: {}", check, detail);
+    }
+
+    fn warn(&self, check: &str, detail: impl std::fmt::Display) {
+        println!("warning  {}: {}", check, detail);
+    }
+
+    fn fail(&mut self, check: &str, exit: Exit, detail: impl std::fmt::Display) {
+        println!("FAILED   {}: {}", check, detail);
+        self.failed.get_or_insert(exit);
+        self.failures += 1;
+    }
+
+    fn finish(self) -> Result<(), Failure> {
+        match self.failed {
+            None => Ok(()),
+            Some(exit) => Err(Failure::new(
+                exit,
+                format!("{} check(s) failed", self.failures),
+            )),
+        }
+    }
+}
+
+/// `--diagnose [<index-url>] [options...]`: check, without signing in or
+/// changing anything, what stands between cargo and the token of a
+/// registry: the CLI, the vault state, `BW_SESSION`, the matching items
+/// and the last sync.
+fn diagnose(args: &[String]) -> Result<(), Failure> {
+    let (index_url, args) = match args.split_first() {
+        Some((url, rest)) if !url.starts_with('-') => (Some(url.as_str()), rest),
+        _ => (None, args),
+    };
+    let args = ModeArgs::parse(args, &[], &[])?;
+    let mut report = Report::default();
+
+    let op = BitwardenVault::new(&args.provider).map_err(|e| Failure::usage(e.to_string()))?;
+    let setup = match op.setup() {
+        Ok(setup) => setup,
+        Err(e) if e.to_string().contains(locate::NOT_FOUND) => {
+            report.fail("cli", Exit::CliMissing, e);
+            return report.finish();
+        }
+        Err(e) => return Err(Failure::usage(e.to_string())),
+    };
+    // Nothing here may sync or sign in.
+    op.defer_sync.set(true);
+    let mut program = vec![setup.cmd_name.as_str()];
+    program.extend(setup.cmd_args.iter().map(String::as_str));
+    let program = program.join(" ");
+    match op.capabilities() {
+        Ok(_) => match op.version.get() {
+            Some(version) => report.ok("cli", format!("`{}`, version {}", program, version)),
+            None => report.warn("cli", format!("`{}`, of unknown version", program)),
+        },
+        Err(e) => {
+            report.fail("cli", classify(&e), format!("`{}`: {}", program, e));
+            return report.finish();
+        }
+    }
+
+    let status = match op.status() {
+        Ok(status) => status,
+        Err(e) => {
+            report.fail("status", classify(&e), e);
+            return report.finish();
+        }
+    };
+    let account = format!(
+        "{} on {}",
+        status.user_email.as_deref().unwrap_or("no account"),
+        status
+            .server_url
+            .as_deref()
+            .unwrap_or(crate::DEFAULT_SERVER)
+    );
+    match status.status {
+        VaultState::Unauthenticated => report.fail(
+            "status",
+            Exit::Locked,
+            "not logged in; run `bw login`, or let `cargo login` sign in",
+        ),
+        VaultState::Locked => report.ok("status", format!("locked, {}", account)),
+        VaultState::Unlocked => report.ok("status", format!("unlocked, {}", account)),
+        VaultState::Unknown => report.warn("status", format!("unknown state, {}", account)),
+    }
+
+    let env_session = std::env::var(SESSION_ENV)
+        .ok()
+        .filter(|session| !session.trim().is_empty());
+    let session = match &env_session {
+        None if status.status == VaultState::Unlocked => {
+            report.ok(
+                "session",
+                "BW_SESSION is not set, but the vault is unlocked",
+            );
+            None
+        }
+        None => {
+            report.warn(
+                "session",
+                "BW_SESSION is not set, so lookups ask for the master password",
+            );
+            None
+        }
+        Some(session) => {
+            redact::register(session);
+            let session = Some(session.clone());
+            match op.status_with(&session) {
+                Ok(status) if status.status == VaultState::Unlocked => {
+                    report.ok("session", "BW_SESSION unlocks the vault");
+                    session
+                }
+                Ok(_) => {
+                    report.fail(
+                        "session",
+                        Exit::Locked,
+                        "BW_SESSION is set but doesn't unlock the vault; it may be from before \
+                         the last `bw lock` or `bw logout`, run `bw unlock --raw` for a new one",
+                    );
+                    None
+                }
+                Err(e) => {
+                    report.fail("session", classify(&e), e);
+                    None
+                }
+            }
+        }
+    };
+    let unlocked = session.is_some() || status.status == VaultState::Unlocked;
+
+    let index_url = index_url.map(|url| op.canonical_url(url));
+    match (index_url.as_deref(), unlocked) {
+        (None, _) => {}
+        (Some(_), false) => report.warn("items", "skipped, the vault is locked"),
+        (Some(index_url), true) => match op.list_matches(&session, index_url) {
+            Err(e) => report.fail("items", classify(&e), e),
+            Ok(items) => {
+                let exact: Vec<_> = items
+                    .iter()
+                    .filter(|item| item.has_uri(index_url))
+                    .collect();
+                let listed: String = items
+                    .iter()
+                    .map(|item| format!("\n           `{}` ({})", item.name, item.id))
+                    .collect();
+                let summary = format!(
+                    "{} item(s) share the host of `{}`, {} with exactly its URL{}",
+                    items.len(),
+                    index_url,
+                    exact.len(),
+                    listed
+                );
+                match op.search(&session, index_url) {
+                    Ok(Some(item)) => {
+                        report.ok("items", summary);
+                        report.ok("items", format!("uses `{}` ({})", item.name, item.id));
+                    }
+                    Ok(None) => report.fail("items", Exit::NotFound, summary),
+                    Err(e) => report.fail("items", classify(&e), format!("{}\n{}", summary, e)),
+                }
+            }
+        },
+    }
+
+    let now = time::OffsetDateTime::now_utc();
+    let last_sync = status.last_sync.as_deref().and_then(|date| {
+        time::OffsetDateTime::parse(date, &time::format_description::well_known::Rfc3339).ok()
+    });
+    match last_sync {
+        None => report.warn("sync", "the vault was never synced; run `bw sync`"),
+        Some(date) => {
+            let age = now - date;
+            let ago = format!("last synced {} minute(s) ago", age.whole_minutes());
+            let stale = age
+                > time::Duration::try_from(op.options.sync_interval).unwrap_or(time::Duration::MAX);
+            match (stale, op.options.auto_sync) {
+                (false, _) => report.ok("sync", ago),
+                (true, true) => report.ok("sync", format!("{}; `--sync` syncs when a token is missing", ago)),
+                (true, false) => report.warn(
+                    "sync",
+                    format!("{}; tokens added on other devices since are missing until `bw sync`, or pass `--sync`", ago),
+                ),
+            }
+        }
+    }
+
+    report.finish()
+}
+
+/// `--setup [--registry <name>] [--index-url <url>] [--global]
+/// [--cargo-config <path>] [--dry-run] [options...]`: configure cargo to use
+/// the provider with the given options, keeping the rest of the config.
+fn setup(args: &[String]) -> Result<(), Failure> {
+    const PROGRAM: &str = env!("CARGO_PKG_NAME");
+
+    let args = ModeArgs::parse(
+        args,
+        &["--global", "--dry-run"],
+        &["--registry", "--index-url", "--cargo-config"],
+    )?;
+    let registry = args.value("--registry");
+    let index_url = args.value("--index-url");
+    match (registry, index_url) {
+        (None, _) if !args.flag("--global") => {
+            return Err(Failure::usage(
+                "pass --registry <name>, or --global to use the provider for every registry",
+            ))
+        }
+        (None, Some(_)) => return Err(Failure::usage("--index-url needs --registry")),
+        (Some("crates-io"), Some(_)) => {
+            return Err(Failure::usage(
+                "the index URL of crates.io can't be changed",
+            ))
+        }
+        _ => {}
+    }
+    if let Some(name) = registry {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if name.is_empty() || !name.chars().all(valid) {
+            return Err(Failure::usage(format!(
+                "invalid registry name `{}`: use letters, digits, `-` and `_`",
+                name
+            )));
+        }
+    }
+    // Catch mistakes in the options before cargo runs into them; the CLI
+    // doesn't have to be installed yet.
+    if let Err(e) = BitwardenVault::new(&args.provider) {
+        return Err(Failure::usage(e.to_string()));
+    }
+    let global = match args.flag("--global") {
+        true => {
+            // Cargo splits the entries of the list on whitespace.
+            if let Some(arg) = args
+                .provider
+                .iter()
+                .find(|arg| arg.is_empty() || arg.contains(char::is_whitespace))
+            {
+                return Err(Failure::usage(format!(
+                    "`registry.global-credential-providers` can't hold the option `{}`; \
+                     put the options in an args file and pass `@<path>` instead",
+                    arg
+                )));
+            }
+            let mut entry = vec![PROGRAM];
+            entry.extend(&args.provider);
+            Some(entry.join(" "))
+        }
+        false => None,
+    };
+
+    let path = match args.value("--cargo-config") {
+        Some(path) => PathBuf::from(path),
+        None => cargo_config::home_config_path()
+            .ok_or_else(|| Failure::usage("cannot determine $CARGO_HOME, pass --cargo-config"))?,
+    };
+    let mut doc = cargo_config::read_toml(&path)
+        .map_err(Failure::usage)?
+        .unwrap_or_default();
+    let before = doc.to_string();
+
+    if let Some(name) = registry {
+        let mut provider = vec![PROGRAM];
+        provider.extend(&args.provider);
+        cargo_config::set_credential_provider(&mut doc, Some(name), &provider);
+        match index_url {
+            Some(index_url) => cargo_config::set_index(&mut doc, name, index_url),
+            None if name != "crates-io"
+                && doc
+                    .get("registries")
+                    .and_then(|registries| registries.get(name)?.get("index"))
+                    .is_none()
+                && cargo_config::registry_index(Some(name)).is_none() =>
+            {
+                return Err(Failure::usage(format!(
+                    "registry `{}` has no index URL in the cargo config; pass --index-url",
+                    name
+                )))
+            }
+            None => {}
+        }
+    }
+    if let Some(entry) = &global {
+        cargo_config::set_global_provider(&mut doc, PROGRAM, entry);
+    }
+
+    let after = doc.to_string();
+    if after == before {
+        println!("`{}` is already set up", path.display());
+        return Ok(());
+    }
+    println!("--- {}", path.display());
+    println!("+++ {}", path.display());
+    for line in line_diff(&before, &after) {
+        println!("{}", line);
+    }
+    if args.flag("--dry-run") {
+        println!("not written, as --dry-run was given");
+        return Ok(());
+    }
+
+    let write = || {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, &after)
+    };
+    write().map_err(|e| {
+        Failure::new(
+            Exit::Other,
+            format!("failed to write `{}`: {}", path.display(), e),
+        )
+    })?;
+    println!("updated `{}`", path.display());
+    Ok(())
+}
+
+/// The lines removed from `old` and added in `new`, as `-` and `+` lines
+/// in the order of the files, each after the header of the table it is in.
+fn line_diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // Lengths of the longest common subsequences of the rests of the files.
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = match old[i] == new[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut diff = Vec::new();
+    // The header of the table of the next change, until shown.
+    let mut header = None;
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            if old[i].trim_start().starts_with('[') {
+                header = Some(old[i]);
+            }
+            i += 1;
+            j += 1;
+            continue;
+        }
+        if let Some(header) = header.take() {
+            diff.push(format!(" {}", header));
+        }
+        if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            diff.push(format!("-{}", old[i]));
+            i += 1;
+        } else {
+            diff.push(format!("+{}", new[j]));
+            j += 1;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{self, FakeBw};
+    use serde_json::{json, Value};
+
+    const OLD: &str = "2024-01-01T00:00:00.000Z";
+    const NEW: &str = "2024-06-01T00:00:00.000Z";
+
+    /// The plan of `--prune` for `bw`, as action, id and reason.
+    fn plan(bw: &FakeBw, configured: &[&str]) -> Vec<(Prune, String, String)> {
+        let op = bw.vault(&[]);
+        let session = op.signin().unwrap();
+        let configured: Vec<String> = configured.iter().map(|url| crate::uri_key(url)).collect();
+        prune_plan(&op, &session, &configured, false)
+            .unwrap()
+            .into_iter()
+            .map(|(prune, item, reason)| (prune, item.id, reason))
+            .collect()
+    }
+
+    #[test]
+    fn duplicates_with_the_same_token_are_deleted() {
+        let index_url = "https://delete.prune.example.com/index/";
+        let name = "Cargo registry token for delete.prune.example.com";
+        let bw = FakeBw::with_items(vec![
+            fake::login("newest", name, index_url, "token-1", NEW),
+            fake::login("same", name, index_url, "token-1", OLD),
+            fake::login("other", name, index_url, "token-2", OLD),
+        ]);
+        let key = crate::uri_key(index_url);
+        assert_eq!(
+            plan(&bw, &[index_url]),
+            [
+                (
+                    Prune::Trash,
+                    "other".to_string(),
+                    format!("duplicate, other token than newest for {}", key)
+                ),
+                (
+                    Prune::Delete,
+                    "same".to_string(),
+                    format!("duplicate, same token as newest for {}", key)
+                ),
+            ]
+        );
+
+        let op = bw.vault(&[]);
+        let session = op.signin().unwrap();
+        let configured = [key];
+        let plan = prune_plan(&op, &session, &configured, false).unwrap();
+        assert_eq!(apply_prune(&op, &session, &plan).unwrap(), 0);
+        let deletes = bw.calls_of(&["delete", "item"]);
+        assert_eq!(deletes.len(), 2);
+        assert!(deletes
+            .iter()
+            .any(|call| call.is(&["delete", "item", "same"]) && call.has("--permanent")));
+        assert!(deletes
+            .iter()
+            .any(|call| call.is(&["delete", "item", "other"]) && !call.has("--permanent")));
+        let state = bw.state();
+        let ids = |items: &[Value]| -> Vec<String> {
+            items
+                .iter()
+                .map(|item| item["id"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(ids(&state.items), ["newest"]);
+        assert_eq!(ids(&state.trash), ["other"]);
+    }
+
+    #[test]
+    fn duplicates_are_found_by_every_uri() {
+        let first = "https://first.prune.example.com/index/";
+        let second = "https://second.prune.example.com/index/";
+        let both = |id: &str, revised: &str| {
+            let mut item = fake::login(
+                id,
+                "Cargo registry token for first.prune.example.com",
+                first,
+                "token-1",
+                revised,
+            );
+            item["login"]["uris"]
+                .as_array_mut()
+                .unwrap()
+                .push(json!({ "match": null, "uri": second }));
+            item
+        };
+        let bw = FakeBw::with_items(vec![
+            fake::login(
+                "first",
+                "Cargo registry token for first.prune.example.com",
+                first,
+                "token-1",
+                NEW,
+            ),
+            both("both", "2024-03-01T00:00:00.000Z"),
+            both("both-again", OLD),
+        ]);
+        // `both` is the only one for the second registry, so it stays.
+        let plan = plan(&bw, &[first, second]);
+        assert_eq!(plan.len(), 1, "{:?}", plan);
+        let (prune, id, reason) = &plan[0];
+        assert_eq!((*prune, id.as_str()), (Prune::Delete, "both-again"));
+        assert_eq!(
+            *reason,
+            format!(
+                "duplicate, same token as first for {}, both for {}",
+                crate::uri_key(first),
+                crate::uri_key(second)
+            )
+        );
+    }
+}
@@ -0,0 +1,322 @@
+//! Matching registry index URLs to the URIs of Bitwarden login items.
+//!
+//! Cargo spells the same registry in several ways: with or without the
+//! `sparse+` prefix, with a trailing slash or not, with an upper case host.
+//! Both sides are therefore normalized with [`normalize_index_url`] before
+//! they are compared with [`uri_matches`], which follows Bitwarden's URI
+//! match detection.
+//!
+//! ```
+//! use cargo_credential_bitwarden::matching::{normalize_index_url, uri_matches, MatchMode, Uri};
+//!
+//! let url = normalize_index_url("sparse+https://Index.Crates.io/");
+//! let uri = Uri::new("https://index.crates.io");
+//! assert!(uri_matches(&url, &uri, MatchMode::Exact));
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use url::Url;
+
+/// Prefixes cargo puts in front of the URL to tell the index protocol apart.
+const PROTOCOL_PREFIXES: &[&str] = &["sparse+", "registry+"];
+
+/// An index URL in a canonical form, from [`normalize_index_url`].
+///
+/// URLs are equal if their canonical forms are.
+#[derive(Debug, Clone)]
+pub struct NormalizedUrl {
+    normalized: String,
+    url: Option<Url>,
+}
+
+impl NormalizedUrl {
+    pub fn as_str(&self) -> &str {
+        &self.normalized
+    }
+
+    /// The host, lower case and in its ASCII (punycode) form, or `None` if
+    /// the URL couldn't be parsed.
+    pub fn host(&self) -> Option<&str> {
+        self.url.as_ref()?.host_str()
+    }
+
+    /// Whether the URL is of a registry on disk, like
+    /// `sparse+file:///home/me/registry/`, which has no host.
+    pub fn is_local(&self) -> bool {
+        self.url.as_ref().is_some_and(|url| url.scheme() == "file")
+    }
+
+    /// The port, also when it is the scheme's default.
+    pub fn port(&self) -> Option<u16> {
+        self.url.as_ref()?.port_or_known_default()
+    }
+}
+
+impl PartialEq for NormalizedUrl {
+    fn eq(&self, other: &NormalizedUrl) -> bool {
+        self.normalized == other.normalized
+    }
+}
+
+impl Eq for NormalizedUrl {}
+
+impl fmt::Display for NormalizedUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.normalized)
+    }
+}
+
+/// Bring `index_url` into a canonical form.
+///
+/// Surrounding whitespace, the `sparse+` or `registry+` prefix and trailing
+/// slashes are removed. Scheme and host are lower cased, internationalized
+/// host names converted to punycode, and default ports dropped. Strings that
+/// aren't URLs are only trimmed.
+///
+/// ```
+/// use cargo_credential_bitwarden::matching::normalize_index_url;
+///
+/// let url = normalize_index_url(" sparse+HTTPS://Example.com:443/index/ ");
+/// assert_eq!(url.as_str(), "https://example.com/index");
+/// assert_eq!(url.host(), Some("example.com"));
+///
+/// // The forms cargo passes for the same registry are all the same.
+/// for index_url in [
+///     "sparse+https://registry.example.com/index/",
+///     "https://registry.example.com/index",
+///     "https://REGISTRY.example.com/index/",
+/// ] {
+///     assert_eq!(normalize_index_url(index_url).as_str(), "https://registry.example.com/index");
+/// }
+/// let git = normalize_index_url("registry+https://github.com/rust-lang/crates.io-index");
+/// assert_eq!(git.as_str(), "https://github.com/rust-lang/crates.io-index");
+/// let ssh = normalize_index_url("ssh://git@Git.example.com/index.git");
+/// assert_eq!(ssh.as_str(), "ssh://git@git.example.com/index.git");
+/// let local = normalize_index_url("sparse+file:///home/me/registry/");
+/// assert_eq!(local.as_str(), "file:///home/me/registry");
+/// assert!(local.is_local() && local.host().is_none());
+/// ```
+pub fn normalize_index_url(index_url: &str) -> NormalizedUrl {
+    let mut raw = index_url.trim();
+    for prefix in PROTOCOL_PREFIXES {
+        if let Some(stripped) = raw.strip_prefix(prefix) {
+            raw = stripped;
+        }
+    }
+
+    let mut url = Url::parse(raw).ok();
+    // Hosts of schemes the URL standard doesn't know, like `ssh`, keep
+    // their case when parsed.
+    if let Some(url) = url.as_mut() {
+        if let Some(host) = url
+            .host_str()
+            .filter(|host| host.chars().any(char::is_uppercase))
+        {
+            let host = host.to_lowercase();
+            let _ = url.set_host(Some(&host));
+        }
+    }
+    let normalized = url.as_ref().map_or(raw, Url::as_str);
+    NormalizedUrl {
+        normalized: normalized.trim_end_matches('/').to_string(),
+        url,
+    }
+}
+
+/// A URI of a Bitwarden login item.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Uri {
+    /// Bitwarden's match detection for this URI, see [`MatchMode::from_bitwarden`].
+    pub r#match: Option<u32>,
+    pub uri: String,
+}
+
+impl Uri {
+    /// A URI using the default match detection.
+    pub fn new(uri: impl Into<String>) -> Uri {
+        Uri {
+            r#match: None,
+            uri: uri.into(),
+        }
+    }
+}
+
+/// How a URI is compared with a URL, like Bitwarden's "match detection".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Same base domain, e.g. `crates.io` for `index.crates.io`. The base
+    /// domain is taken to be the last two labels of the host.
+    Domain,
+    /// Same host and port.
+    Host,
+    /// The URL starts with the URI.
+    StartsWith,
+    /// Same scheme, host and port, and the URI's path is the URL's path or
+    /// one of its parent directories. Unlike [`MatchMode::StartsWith`],
+    /// `https://example.com/index` doesn't match `https://example.com/index2`.
+    Base,
+    /// The URL and the URI are the same.
+    Exact,
+    /// Bitwarden matches the URL against a regular expression. This isn't
+    /// supported, so such URIs never match.
+    RegularExpression,
+    Never,
+}
+
+impl MatchMode {
+    /// The mode for the `match` value Bitwarden stores with a URI.
+    pub fn from_bitwarden(value: u32) -> Option<MatchMode> {
+        match value {
+            0 => Some(MatchMode::Domain),
+            1 => Some(MatchMode::Host),
+            2 => Some(MatchMode::StartsWith),
+            3 => Some(MatchMode::Exact),
+            4 => Some(MatchMode::RegularExpression),
+            5 => Some(MatchMode::Never),
+            _ => None,
+        }
+    }
+
+    /// The `match` value Bitwarden stores for the mode, `None` for
+    /// [`MatchMode::Base`], which Bitwarden doesn't have.
+    pub fn to_bitwarden(self) -> Option<u32> {
+        match self {
+            MatchMode::Domain => Some(0),
+            MatchMode::Host => Some(1),
+            MatchMode::StartsWith => Some(2),
+            MatchMode::Exact => Some(3),
+            MatchMode::RegularExpression => Some(4),
+            MatchMode::Never => Some(5),
+            MatchMode::Base => None,
+        }
+    }
+
+    /// The mode for `--uri-match`.
+    pub fn parse(name: &str) -> Option<MatchMode> {
+        match name {
+            "exact" => Some(MatchMode::Exact),
+            "host" => Some(MatchMode::Host),
+            "base" => Some(MatchMode::Base),
+            "starts-with" => Some(MatchMode::StartsWith),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `uri` matches `url` when compared with `mode`.
+///
+/// ```
+/// use cargo_credential_bitwarden::matching::{normalize_index_url, uri_matches, MatchMode, Uri};
+///
+/// let url = normalize_index_url("https://my.registry.example/index/");
+/// let uri = Uri::new("https://my.registry.example");
+/// assert!(uri_matches(&url, &uri, MatchMode::Host));
+/// assert!(!uri_matches(&url, &uri, MatchMode::Exact));
+///
+/// // Ports, user info and IDN hosts are compared in normalized form.
+/// let url = normalize_index_url("https://user@Bücher.example:8443/git/index");
+/// assert!(uri_matches(&url, &Uri::new("https://xn--bcher-kva.example:8443/git"), MatchMode::Base));
+/// assert!(!uri_matches(&url, &Uri::new("https://bücher.example/git"), MatchMode::Base));
+/// assert!(!uri_matches(&url, &Uri::new("https://bücher.example:8443/gi"), MatchMode::Base));
+/// assert!(uri_matches(&url, &Uri::new("https://other@bücher.example:8443"), MatchMode::Host));
+///
+/// // Registries on disk have no host, so the modes going by it compare
+/// // them exactly.
+/// let url = normalize_index_url("sparse+file:///home/me/registry/");
+/// assert!(uri_matches(&url, &Uri::new("file:///home/me/registry"), MatchMode::Host));
+/// assert!(!uri_matches(&url, &Uri::new("file:///home/me/other"), MatchMode::Base));
+/// ```
+pub fn uri_matches(url: &NormalizedUrl, uri: &Uri, mode: MatchMode) -> bool {
+    let uri = normalize_index_url(&uri.uri);
+    let mode = match mode {
+        MatchMode::Domain | MatchMode::Host | MatchMode::Base if url.is_local() => MatchMode::Exact,
+        mode => mode,
+    };
+    match mode {
+        MatchMode::Domain => match (url.host(), uri.host()) {
+            (Some(a), Some(b)) => base_domain(a) == base_domain(b),
+            _ => false,
+        },
+        MatchMode::Host => {
+            url.host().is_some() && (url.host(), url.port()) == (uri.host(), uri.port())
+        }
+        MatchMode::StartsWith => url.as_str().starts_with(uri.as_str()),
+        MatchMode::Base => match (&url.url, &uri.url) {
+            (Some(a), Some(b)) => {
+                a.scheme() == b.scheme()
+                    && a.host_str().is_some()
+                    && (a.host_str(), a.port_or_known_default())
+                        == (b.host_str(), b.port_or_known_default())
+                    && is_path_prefix(b.path(), a.path())
+            }
+            _ => false,
+        },
+        MatchMode::Exact => url.as_str() == uri.as_str(),
+        MatchMode::RegularExpression | MatchMode::Never => false,
+    }
+}
+
+/// Whether `prefix` is `path` or one of its parent directories.
+fn is_path_prefix(prefix: &str, path: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    let path = path.trim_end_matches('/');
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+fn base_domain(host: &str) -> &str {
+    // IP addresses have no base domain to share.
+    if host.starts_with('[') || host.parse::<std::net::Ipv4Addr>().is_ok() {
+        return host;
+    }
+    match host.rmatch_indices('.').nth(1) {
+        Some((i, _)) => &host[i + 1..],
+        None => host,
+    }
+}
+
+/// Outcome of [`select`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selection<T> {
+    None,
+    One(T),
+    /// Several candidates match, so none can be picked safely.
+    Ambiguous(Vec<T>),
+}
+
+/// Pick the candidate holding the token for `url`: the one with a URI that
+/// matches it with `mode`.
+///
+/// ```
+/// use cargo_credential_bitwarden::matching::{normalize_index_url, select, MatchMode, Selection, Uri};
+///
+/// let items = vec![
+///     ("a", vec![Uri::new("https://a.example/index")]),
+///     ("b", vec![Uri::new("https://b.example/index")]),
+/// ];
+/// let url = normalize_index_url("sparse+https://b.example/index/");
+/// let selection = select(&url, items, |(_, uris)| uris, MatchMode::Exact);
+/// assert!(matches!(selection, Selection::One(("b", _))));
+/// ```
+pub fn select<T>(
+    url: &NormalizedUrl,
+    candidates: impl IntoIterator<Item = T>,
+    uris: impl Fn(&T) -> &[Uri],
+    mode: MatchMode,
+) -> Selection<T> {
+    let mut matches: Vec<T> = candidates
+        .into_iter()
+        .filter(|candidate| {
+            uris(candidate)
+                .iter()
+                .any(|uri| uri_matches(url, uri, mode))
+        })
+        .collect();
+    match matches.len() {
+        0 => Selection::None,
+        1 => Selection::One(matches.remove(0)),
+        _ => Selection::Ambiguous(matches),
+    }
+}
@@ -0,0 +1,201 @@
+//! Names of the items `cargo login` creates, from `--name-template`.
+//!
+//! The template also tells which items this provider created: those whose
+//! names start and end like the template's text around its placeholders.
+
+use crate::matching::normalize_index_url;
+
+/// Template of the names this provider always used.
+pub const DEFAULT: &str = "Cargo registry token for {registry}";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    /// The registry name, or the host without one.
+    Registry,
+    Host,
+    IndexUrl,
+}
+
+#[derive(Debug, Clone)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+impl NameTemplate {
+    pub fn parse(template: &str) -> Result<NameTemplate, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| format!("unclosed `{{` in `--name-template {}`", template))?;
+            parts.push(match &rest[start + 1..end] {
+                "registry" => Part::Registry,
+                "host" => Part::Host,
+                "index_url" => Part::IndexUrl,
+                other => {
+                    return Err(format!(
+                        "unknown placeholder `{{{}}}` in `--name-template`, expected \
+                         `{{registry}}`, `{{host}}` or `{{index_url}}`",
+                        other
+                    ))
+                }
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        if parts.iter().all(|part| matches!(part, Part::Text(_))) {
+            return Err(format!(
+                "`--name-template {}` has no placeholder, so every registry would share a name",
+                template
+            ));
+        }
+        Ok(NameTemplate { parts })
+    }
+
+    /// Name of the item for `index_url`. Without a registry name from
+    /// cargo, as for registries only known through
+    /// `global-credential-providers`, the host stands in for `{registry}`,
+    /// see [`site`].
+    pub fn render(&self, index_url: &str, registry_name: Option<&str>) -> String {
+        let host = site(index_url);
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Registry => match registry_name.filter(|name| !name.trim().is_empty()) {
+                    Some(name) => name.to_string(),
+                    None => host.clone(),
+                },
+                Part::Host => host.clone(),
+                Part::IndexUrl => index_url.to_string(),
+            })
+            .collect()
+    }
+
+    /// Text before the first placeholder.
+    fn prefix(&self) -> &str {
+        match self.parts.first() {
+            Some(Part::Text(text)) => text,
+            _ => "",
+        }
+    }
+
+    /// Text after the last placeholder.
+    fn suffix(&self) -> &str {
+        match self.parts.last() {
+            Some(Part::Text(text)) => text,
+            _ => "",
+        }
+    }
+
+    /// Whether `name` could have been made from this template.
+    pub fn matches(&self, name: &str) -> bool {
+        let (prefix, suffix) = (self.prefix(), self.suffix());
+        name.len() > prefix.len() + suffix.len()
+            && name.starts_with(prefix)
+            && name.ends_with(suffix)
+    }
+
+    /// The registry name in `name`, if the template has nothing but text
+    /// around `{registry}`.
+    pub fn registry<'a>(&self, name: &'a str) -> Option<&'a str> {
+        let placeholders: Vec<_> = self
+            .parts
+            .iter()
+            .filter(|part| !matches!(part, Part::Text(_)))
+            .collect();
+        if placeholders != [&Part::Registry] || !self.matches(name) {
+            return None;
+        }
+        Some(&name[self.prefix().len()..name.len() - self.suffix().len()])
+    }
+
+    /// Longest text of the template, to narrow down searches for the items
+    /// made from it.
+    pub fn search_term(&self) -> Option<&str> {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Text(text) => Some(text.trim()),
+                _ => None,
+            })
+            .filter(|text| !text.is_empty())
+            .max_by_key(|text| text.len())
+    }
+}
+
+/// The host of `index_url`, or for URLs without one, like
+/// `file:///srv/registry/index`, the last segment of its path that says
+/// more than `index`, or else the URL itself.
+fn site(index_url: &str) -> String {
+    let url = normalize_index_url(index_url);
+    if let Some(host) = url.host() {
+        return host.to_string();
+    }
+    let path = url
+        .as_str()
+        .split_once("://")
+        .map_or(url.as_str(), |(_, path)| path);
+    path.rsplit(['/', '\\'])
+        .map(|segment| segment.trim_end_matches(".git"))
+        .find(|segment| {
+            !segment.is_empty()
+                && !segment.starts_with('.')
+                && !segment.contains(':')
+                && !segment.eq_ignore_ascii_case("index")
+        })
+        .map_or_else(|| index_url.to_string(), str::to_string)
+}
+
+impl Default for NameTemplate {
+    fn default() -> NameTemplate {
+        NameTemplate::parse(DEFAULT).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_of_index_urls() {
+        let template = NameTemplate::default();
+        let name = |index_url: &str| template.render(index_url, None);
+        for (index_url, site) in [
+            ("https://example.com/index/", "example.com"),
+            ("sparse+https://example.com/index/", "example.com"),
+            ("https://example.com:8443/git/index", "example.com"),
+            ("file:///srv/registry/index", "registry"),
+            ("sparse+file:///home/me/registry/", "registry"),
+            ("file:///C:/registries/local/index.git", "local"),
+            // Not URLs, but still named after what they say.
+            ("registry.example.com/index", "registry.example.com"),
+            ("../registry", "registry"),
+            ("index", "index"),
+        ] {
+            assert_eq!(
+                name(index_url),
+                format!("Cargo registry token for {}", site),
+                "{}",
+                index_url
+            );
+        }
+
+        // The name cargo has for the registry comes first.
+        let named = template.render("file:///srv/registry/index", Some("local"));
+        assert_eq!(named, "Cargo registry token for local");
+        let template = NameTemplate::parse("{host} at {index_url}").unwrap();
+        assert_eq!(
+            template.render("sparse+file:///srv/registry/", Some("local")),
+            "registry at sparse+file:///srv/registry/"
+        );
+    }
+}
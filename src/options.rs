@@ -0,0 +1,1487 @@
+//! The options [`crate::BitwardenVault::new`] understands: which of them
+//! take a value, so that `--name=value` can be split, which one a mistyped
+//! option was meant to be, the environment variables standing for them, and
+//! the [`Options`] they come to.
+
+use crate::backend;
+use crate::matching::{normalize_index_url, MatchMode};
+use crate::name_template::NameTemplate;
+use crate::scope::Scope;
+use crate::{
+    args_file, cargo_config, config_file, timings, token_format, two_step, uri_key, ItemType,
+    Server, Teardown, TokenCache,
+};
+use cargo_credential::Error;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// What follows an option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Takes {
+    Nothing,
+    /// A value, given once.
+    Value(&'static str),
+    /// A value, given as often as wanted.
+    Values(&'static str),
+    /// Nothing, or a value given as `--name=value`.
+    Optional(&'static str),
+}
+
+use Takes::{Nothing, Optional, Value, Values};
+
+/// Every option, with its older names after the first.
+const OPTIONS: &[(&[&str], Takes)] = &[
+    (&["--config"], Value("<path>")),
+    (&["--email"], Value("<address>")),
+    (&["--switch-account"], Nothing),
+    (&["--apikey"], Nothing),
+    (&["--passwordenv"], Value("<var>")),
+    (&["--password-command"], Value("<command>")),
+    (&["--sso"], Nothing),
+    (&["--no-login"], Nothing),
+    (&["--offline"], Nothing),
+    (&["--method"], Value("<authenticator|email|yubikey>")),
+    (&["--code"], Value("<code|env:VAR>")),
+    (&["--sync"], Nothing),
+    (&["--sync-interval", "--sync-max-age"], Value("<seconds>")),
+    (&["--server"], Value("<url>")),
+    (&["--region"], Value("<us|eu>")),
+    (&["--force-server"], Nothing),
+    (&["--username"], Value("<name>")),
+    (&["--token-format"], Value("<template>")),
+    (&["--compose-token"], Value("<template>")),
+    (&["--split-token"], Value("<separator>")),
+    (&["--field"], Value("<name>")),
+    (&["--attach-to"], Value("<item>")),
+    (&["--item-id"], Value("<item>")),
+    (&["--append-uri"], Nothing),
+    (&["--delete-item"], Nothing),
+    (&["--purge"], Nothing),
+    (&["--no-create"], Nothing),
+    (&["--no-restore"], Nothing),
+    (&["--verify"], Nothing),
+    (&["--force"], Nothing),
+    (&["--item-type"], Value("<login|note>")),
+    (&["--no-notes"], Nothing),
+    (&["--name-template"], Value("<template>")),
+    (&["--organization-id"], Value("<id>")),
+    (&["--collection-id"], Values("<id>")),
+    (&["--collection"], Values("<name>")),
+    (&["--folder"], Value("<name|id>")),
+    (&["--no-create-folder"], Nothing),
+    (&["--migrate-to-org"], Nothing),
+    (&["--include-personal"], Nothing),
+    (&["--scoped"], Nothing),
+    (&["--scope"], Value("<read|publish|all>")),
+    (&["--keep-previous", "--keep-history"], Nothing),
+    (&["--session-max-age"], Value("<seconds>")),
+    (&["--env-session-cache"], Value("<seconds>")),
+    (&["--cache-ttl"], Value("<seconds>")),
+    (&["--cache"], Value("<session|never>")),
+    (&["--persist-session"], Nothing),
+    (&["--session-file"], Value("<path>")),
+    (&["--forget-session"], Nothing),
+    (&["--lock-after"], Nothing),
+    (&["--logout-after"], Nothing),
+    (&["--read-only"], Nothing),
+    (&["--ignore-reprompt"], Nothing),
+    (&["--bw-path"], Value("<path>")),
+    (&["--bw-arg"], Values("<arg>")),
+    (&["--appdata-dir"], Value("<path>")),
+    (&["--prefer-windows-bw"], Nothing),
+    (&["--allow-npx"], Nothing),
+    (&["--fingerprint"], Nothing),
+    (&["--expires"], Value("<days|date>")),
+    (&["--background-sync"], Nothing),
+    (&["--sync-strict"], Nothing),
+    (&["--check-reuse"], Nothing),
+    (&["--uri-match"], Value("<exact|host|base|starts-with>")),
+    (&["--create-uri-match"], Value("<exact|host|base>")),
+    (&["--alias"], Values("<from>=<to>")),
+    (&["--prefer-newest"], Nothing),
+    (&["--match-by-name"], Nothing),
+    (&["--fallback-provider", "--fallback"], Value("<command>")),
+    (&["--warn-stale", "--max-age"], Value("<days>")),
+    (&["--max-age-strict"], Nothing),
+    (&["--refresh-command"], Value("<command>")),
+    (&["--refresh-after"], Value("<days>")),
+    (&["--notify-command"], Value("<command>")),
+    (&["--audit-log"], Value("<path>")),
+    (&["--askpass"], Value("<program>")),
+    (&["--backend"], Value("<cli|serve|rbw|bws>")),
+    (&["--secret-id"], Value("<id>")),
+    (&["--project-id"], Value("<id>")),
+    (&["--timeout"], Value("<seconds>")),
+    (&["--retries"], Value("<n>")),
+    (&["--lock-timeout"], Value("<seconds>")),
+    (&["--slow-threshold"], Value("<seconds>")),
+    (&["--timings"], Optional("<text|json>")),
+    (&["--quiet"], Nothing),
+    (&["--verbose"], Nothing),
+];
+
+/// Index URLs that stand for others without `--alias`: the sparse index of
+/// crates.io is found under its git index URL, which earlier versions stored.
+const BUILTIN_ALIASES: &[(&str, &str)] = &[(
+    "sparse+https://index.crates.io/",
+    cargo_config::CRATES_IO_INDEX,
+)];
+
+/// How long cargo may keep a token read with a `BW_SESSION` from the
+/// environment.
+const DEFAULT_ENV_SESSION_CACHE: Duration = Duration::from_secs(60);
+
+/// Total time in `bw` after which a lookup hints at how to speed it up.
+const DEFAULT_SLOW_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// How recent a sync by any process makes syncing before a read pointless,
+/// by default.
+const DEFAULT_SYNC_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How long to wait for another process to release the lock, by default.
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often a `bw` command that failed transiently is retried, by default.
+const DEFAULT_RETRIES: u32 = 2;
+
+/// How long a `bw` command may run by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A `--bw-arg`, unless it would take over what the provider does itself:
+/// the session it passes in the environment, the output format it reads,
+/// or the command, which `--help` and `--version` replace.
+fn parse_bw_arg(arg: &str) -> Result<String, String> {
+    const OWN: &[&str] = &["--session", "--response", "--help", "-h", "--version", "-v"];
+    let name = arg.split_once('=').map_or(arg, |(name, _)| name);
+    if OWN.contains(&name) {
+        return Err(format!(
+            "`--bw-arg {}` would interfere with how the provider runs `bw`",
+            arg
+        ));
+    }
+    if arg.trim().is_empty() {
+        return Err("`--bw-arg` is empty".to_string());
+    }
+    Ok(arg.to_string())
+}
+
+/// `--expires` as a time: a number of days from `now`, or an RFC 3339 date.
+fn parse_expires(value: &str, now: time::OffsetDateTime) -> Result<time::OffsetDateTime, String> {
+    let invalid = || {
+        format!(
+            "invalid --expires `{}`, expected days or an RFC 3339 date",
+            value
+        )
+    };
+    let expires = match value.parse::<u32>() {
+        Ok(days) => now
+            .replace_nanosecond(0)
+            .ok()
+            .and_then(|now| now.checked_add(time::Duration::days(i64::from(days))))
+            .ok_or_else(invalid)?,
+        Err(_) => {
+            time::OffsetDateTime::parse(value, &time::format_description::well_known::Rfc3339)
+                .map_err(|_| invalid())?
+        }
+    };
+    if expires <= now {
+        return Err(format!("`--expires {}` is not in the future", value));
+    }
+    Ok(expires)
+}
+
+/// The `<from>=<to>` of `--alias`, with `from` as a [`uri_key`].
+fn parse_alias(alias: &str) -> Result<(String, String), Error> {
+    let Some((from, to)) = alias.split_once('=') else {
+        return Err(format!("`--alias {}` must be `<from>=<to>`", alias).into());
+    };
+    for url in [from, to] {
+        let normalized = normalize_index_url(url);
+        if normalized.host().is_none() && !normalized.is_local() {
+            return Err(format!("`--alias {}`: `{}` isn't a registry URL", alias, url).into());
+        }
+    }
+    if uri_key(from) == uri_key(to) {
+        return Err(format!("`--alias {}` names the same URL twice", alias).into());
+    }
+    Ok((uri_key(from), to.trim().to_string()))
+}
+
+/// `aliases` and the [`BUILTIN_ALIASES`] they don't replace or turn around.
+fn with_builtin_aliases(mut aliases: Vec<(String, String)>) -> Vec<(String, String)> {
+    for (from, to) in BUILTIN_ALIASES {
+        let from = uri_key(from);
+        if !aliases
+            .iter()
+            .any(|(alias, target)| *alias == from || uri_key(target) == from)
+        {
+            aliases.push((from, to.to_string()));
+        }
+    }
+    aliases
+}
+
+/// Start of the environment variables that stand for options, like
+/// `CARGO_CREDENTIAL_BITWARDEN_EMAIL` for `--email`.
+const ENV_PREFIX: &str = "CARGO_CREDENTIAL_BITWARDEN_";
+
+/// The first name of the option `name`, and what follows it.
+pub fn find(name: &str) -> Option<(&'static str, Takes)> {
+    OPTIONS
+        .iter()
+        .find(|(names, _)| names.contains(&name))
+        .map(|(names, takes)| (names[0], *takes))
+}
+
+/// Split `--name=value` in `args` into `--name` and `value`, and reject
+/// unknown options and options given twice with different values. `@file`
+/// arguments are left for [`crate::args_file::expand`], and may be
+/// overridden by the arguments after them.
+pub fn split(args: &[&str]) -> Result<Vec<String>, String> {
+    let mut split = Vec::new();
+    let mut given: Vec<(&str, String)> = Vec::new();
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value)),
+            _ => (arg, None),
+        };
+        if !name.starts_with("--") {
+            split.push(arg.to_string());
+            continue;
+        }
+        let (canonical, takes) = find(name).ok_or_else(|| unknown(name))?;
+        if let Optional(_) = takes {
+            // A value of its own would be taken for the next argument.
+            split.push(arg.to_string());
+            continue;
+        }
+        split.push(name.to_string());
+        let value = match (takes, inline) {
+            (Nothing, Some(_)) => return Err(format!("`{}` takes no value", name)),
+            (Nothing, None) => continue,
+            (_, Some(value)) => value,
+            (_, None) => match args.next() {
+                Some(&value) => value,
+                // Left for `BitwardenVault::new` to report.
+                None => continue,
+            },
+        };
+        if let Value(_) = takes {
+            if given
+                .iter()
+                .any(|(option, earlier)| *option == canonical && earlier != value)
+            {
+                return Err(format!(
+                    "`{}` is given twice, with different values",
+                    canonical
+                ));
+            }
+            given.push((canonical, value.to_string()));
+        }
+        split.push(value.to_string());
+    }
+    Ok(split)
+}
+
+/// The options set through their environment variables, as split by
+/// [`split`]. Flags take `1`, `true` or `yes` to turn them on, and `0`,
+/// `false` or `no` to leave them to the other arguments, as do options
+/// whose value is optional, which take anything else as it. Repeatable
+/// options get one value. `--config` is read by [`crate::config_file`].
+pub fn from_env() -> Result<Vec<String>, String> {
+    from_vars(|var| std::env::var_os(var))
+}
+
+/// [`from_env`] with the variables `var` looks up.
+fn from_vars(var: impl Fn(&str) -> Option<OsString>) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for (names, takes) in OPTIONS {
+        let option = names[0];
+        if option == "--config" {
+            continue;
+        }
+        let name = env_var(option);
+        let Some(value) = var(&name).filter(|value| !value.is_empty()) else {
+            continue;
+        };
+        let value = value
+            .into_string()
+            .map_err(|_| format!("`{}` isn't valid UTF-8", name))?;
+        match (takes, value.trim().to_ascii_lowercase().as_str()) {
+            (Nothing | Optional(_), "1" | "true" | "yes") => args.push(option.to_string()),
+            (Nothing | Optional(_), "0" | "false" | "no") => {}
+            (Nothing, _) => {
+                return Err(format!(
+                    "`{}` must be 1, true, yes, 0, false or no, not `{}`",
+                    name, value
+                ))
+            }
+            (Optional(_), _) => args.push(format!("{}={}", option, value)),
+            (Value(_) | Values(_), _) => args.extend([option.to_string(), value]),
+        }
+    }
+    Ok(args)
+}
+
+/// The environment variable standing for `option`.
+fn env_var(option: &str) -> String {
+    let name = option.trim_start_matches('-').replace('-', "_");
+    format!("{}{}", ENV_PREFIX, name.to_ascii_uppercase())
+}
+
+/// The options of `args` as split by [`split`], each with its value if it
+/// takes one, and the other arguments on their own.
+fn entries(args: &[String]) -> Vec<(Option<&'static str>, &[String])> {
+    let mut entries = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        let option = find(&args[i]);
+        let len = match option {
+            Some((_, Nothing | Optional(_))) | None => 1,
+            Some(_) => 2.min(args.len() - i),
+        };
+        entries.push((option.map(|(name, _)| name), &args[i..i + len]));
+        i += len;
+    }
+    entries
+}
+
+/// `later` after the options of `earlier` it doesn't give itself, both split
+/// by [`split`]. Options with values, also repeated ones, replace those of
+/// `earlier`; flags can only be added.
+pub fn layer(earlier: &[String], later: &[String]) -> Vec<String> {
+    let given: Vec<_> = entries(later)
+        .into_iter()
+        .filter_map(|(option, _)| option)
+        .collect();
+    let mut layered: Vec<String> = entries(earlier)
+        .into_iter()
+        .filter(|(option, args)| match option {
+            Some(option) => args.len() == 1 || !given.contains(option),
+            None => true,
+        })
+        .flat_map(|(_, args)| args.iter().cloned())
+        .collect();
+    layered.extend(later.iter().cloned());
+    layered
+}
+
+/// Start of the errors for unknown options, see [`explain`].
+const UNKNOWN: &str = "unknown option `";
+
+/// Error for the unknown option `name`, suggesting the closest known one.
+pub fn unknown(name: &str) -> String {
+    let closest = OPTIONS
+        .iter()
+        .flat_map(|(names, _)| names.iter())
+        .map(|option| (distance(name, option), option))
+        .min();
+    match closest {
+        Some((distance, option)) if distance <= (name.len() / 4).max(2) => {
+            format!("{}{}`; did you mean `{}`?", UNKNOWN, name, option)
+        }
+        _ => format!("{}{}`", UNKNOWN, name),
+    }
+}
+
+/// For the error `e` of reading the options, list the options there are on
+/// stderr when it is about an unknown one.
+pub fn explain(e: &impl std::fmt::Display) {
+    if e.to_string().contains(UNKNOWN) {
+        eprintln!("{}", usage());
+    }
+}
+
+/// The options, one per line.
+fn usage() -> String {
+    let mut usage = String::from("options of cargo-credential-bitwarden:");
+    for (names, takes) in OPTIONS {
+        usage.push_str("\n  ");
+        usage.push_str(&names.join(", "));
+        match takes {
+            Nothing => {}
+            Value(value) => usage.push_str(&format!(" {}", value)),
+            Values(value) => usage.push_str(&format!(" {}...", value)),
+            Optional(value) => usage.push_str(&format!("[={}]", value)),
+        }
+    }
+    usage
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// What the provider arguments come to, checked against each other but
+/// not against the system: [`Options::parse`] finds no CLI and runs nothing.
+pub struct Options {
+    pub email_address: Option<String>,
+    /// Username of the items `cargo login` creates or updates, from
+    /// `--username`.
+    pub username: Option<String>,
+    /// Hidden custom field holding the token instead of the password, from
+    /// `--field`.
+    pub token_field: Option<String>,
+    /// Names of the items `cargo login` creates, from `--name-template`.
+    pub name_template: NameTemplate,
+    /// Text around the tokens cargo gets, from `--token-format`.
+    pub token_format: Option<token_format::TokenFormat>,
+    /// Template of the tokens cargo gets from the username and password of
+    /// the item, from `--compose-token`.
+    pub compose_token: Option<token_format::Compose>,
+    /// Separator of the username and password in tokens from `cargo
+    /// login`, from `--split-token`.
+    pub split_token: Option<String>,
+    /// The CLI to run instead of the one found on `PATH`, from `--bw-path`.
+    pub bw_path: Option<String>,
+    pub allow_npx: bool,
+    pub prefer_windows_bw: bool,
+    /// Arguments after those of every command, from `--bw-arg`.
+    pub bw_args: Vec<String>,
+    /// Profile of its own for `bw`, from `--appdata-dir`.
+    pub appdata_dir: Option<PathBuf>,
+    pub auto_sync: bool,
+    /// Skip syncing before a read if any process synced this recently.
+    pub sync_interval: Duration,
+    pub server: Option<Server>,
+    pub force_server: bool,
+    pub attach_to: Option<String>,
+    /// Item given with `--item-id`, used instead of matching by URL.
+    pub item_id: Option<String>,
+    pub append_uri: bool,
+    pub delete_item: bool,
+    /// `--purge`: delete items for good instead of moving them to the trash.
+    pub purge: bool,
+    pub no_create: bool,
+    /// `--no-restore`: create a new item for `cargo login` even when one
+    /// for the registry is in the trash.
+    pub no_restore: bool,
+    /// Try the token of `cargo login` with the registry first, for `--verify`.
+    pub verify: bool,
+    /// `--force`: replace the token of an existing item without asking.
+    pub force: bool,
+    /// `--no-notes`: leave the notes of items alone, see [`crate::provenance`].
+    pub no_notes: bool,
+    pub item_type: ItemType,
+    pub organization_id: Option<String>,
+    pub collection_ids: Vec<String>,
+    pub collection_names: Vec<String>,
+    /// Folder given with `--folder`, by name or id.
+    pub folder: Option<String>,
+    /// Fail instead of creating a missing `--folder`, for `--no-create-folder`.
+    pub no_create_folder: bool,
+    pub migrate_to_org: bool,
+    pub include_personal: bool,
+    /// Scope tokens are stored under, only set in `--scoped` mode.
+    pub scope: Option<Scope>,
+    pub keep_previous: bool,
+    pub session_max_age: Option<Duration>,
+    pub persist_session: bool,
+    /// Plaintext file to keep the session in, from `--session-file`.
+    pub session_file: Option<PathBuf>,
+    /// Drop the kept session before signing in.
+    pub forget_session: bool,
+    /// Lock or log out of the vault after each request, if the provider
+    /// unlocked it.
+    pub teardown: Option<Teardown>,
+    /// Only answer `get`, and never run a command that changes the vault,
+    /// for `--read-only`.
+    pub read_only: bool,
+    /// Use the tokens of items that ask for the master password again
+    /// without asking, for `--ignore-reprompt`.
+    pub ignore_reprompt: bool,
+    /// When the tokens stored now expire, for `--expires`.
+    pub expires: Option<time::OffsetDateTime>,
+    pub fingerprint: bool,
+    pub check_reuse: bool,
+    /// How item URIs are compared with the index URL, from `--uri-match`.
+    pub uri_match: MatchMode,
+    /// Match detection of the URIs this provider adds, from
+    /// `--create-uri-match`.
+    pub create_uri_match: MatchMode,
+    /// Index URLs, as [`crate::uri_key`]s, and the ones they stand for, from
+    /// `--alias` and [`BUILTIN_ALIASES`].
+    pub aliases: Vec<(String, String)>,
+    /// Use the most recently updated of several matching items.
+    pub prefer_newest: bool,
+    /// Look for items named after the registry when none has its URL, for
+    /// `--match-by-name`.
+    pub match_by_name: bool,
+    pub quiet: bool,
+    /// Log to stderr, from `--verbose`, see [`crate::verbose`].
+    pub verbose: bool,
+    /// How the time spent in `bw` is reported, from `--timings`.
+    pub timings: Option<timings::Format>,
+    /// Program asked for the master password without a terminal, from
+    /// `--askpass`.
+    pub askpass: Option<String>,
+    pub env_session_cache: Duration,
+    pub cache: TokenCache,
+    pub slow_threshold: Duration,
+    /// How long a `bw` command may run before it is killed, for `--timeout`.
+    pub timeout: Option<Duration>,
+    /// `--retries`, see [`crate::BitwardenVault::run_cmd_retrying`].
+    pub retries: u32,
+    /// How long to wait for the lock of another process, see [`crate::lock`].
+    pub lock_timeout: Duration,
+    pub background_sync: bool,
+    pub sync_strict: bool,
+    /// Provider asked for tokens the vault has no item for.
+    pub fallback_provider: Option<String>,
+    /// Sign in with `BW_CLIENTID` and `BW_CLIENTSECRET` instead of a prompt.
+    pub apikey: bool,
+    /// Environment variable holding the master password, from
+    /// `--passwordenv`.
+    pub password_env: Option<String>,
+    /// Shell command printing the master password, from
+    /// `--password-command`.
+    pub password_command: Option<String>,
+    /// Sign in through the organization's single sign-on, from `--sso`.
+    pub sso: bool,
+    /// Log out of another account than `--email`, from `--switch-account`.
+    pub switch_account: bool,
+    /// Fail instead of signing in, from `--no-login` or `--offline`.
+    pub no_login: bool,
+    /// Never sync or sign in, for `--offline`.
+    pub offline: bool,
+    /// Two-step login method for `bw login`, from `--method`.
+    pub two_step_method: Option<two_step::Method>,
+    /// Two-step login code for `bw login`, from `--code`.
+    pub two_step_code: Option<String>,
+    /// Days after which a token counts as stale, for `--warn-stale`.
+    pub warn_stale: Option<u32>,
+    /// Refuse stale tokens instead of warning, for `--max-age-strict`.
+    pub stale_strict: bool,
+    /// Shell command printing a new token, for `--refresh-command`.
+    pub refresh_command: Option<String>,
+    /// Days after which a token is refreshed, for `--refresh-after`.
+    pub refresh_after: Option<u32>,
+    /// Shell command started whenever a token is read, for
+    /// `--notify-command`.
+    pub notify_command: Option<String>,
+    /// File every request is appended to, for `--audit-log`.
+    pub audit_log: Option<PathBuf>,
+    /// How items are read and changed, from `--backend`.
+    pub backend: backend::Kind,
+    /// Secrets Manager secret given with `--secret-id`.
+    pub secret_id: Option<String>,
+    /// Secrets Manager project new secrets are created in.
+    pub project_id: Option<String>,
+}
+
+impl Options {
+    /// Parse the provider arguments, like `--sync` or `--collection <name>`,
+    /// as they follow `cargo-credential-bitwarden` in cargo's configuration,
+    /// after the configuration file and the environment variables.
+    pub fn parse(args: &[&str]) -> Result<Options, Error> {
+        let split = split(args)?;
+        let env = from_env()?;
+        let below = match config_file::load(config_file::path_arg(&split))? {
+            Some(config) => layer(config.global(), &env),
+            None => env,
+        };
+        let split = layer(&below, &split);
+        let split: Vec<&str> = split.iter().map(String::as_str).collect();
+        let expanded = args_file::expand(&split)?;
+        let expanded: Vec<&str> = expanded.iter().map(String::as_str).collect();
+        let mut args = expanded.iter();
+        let mut email_address = None;
+        let mut username = None;
+        let mut token_field = None;
+        let mut name_template = NameTemplate::default();
+        let mut token_format = None;
+        let mut compose_token = None;
+        let mut split_token = None;
+        let mut auto_sync = false;
+        let mut sync_interval = DEFAULT_SYNC_INTERVAL;
+        let mut server = None;
+        let mut region = None;
+        let mut force_server = false;
+        let mut attach_to = None;
+        let mut item_id = None;
+        let mut append_uri = false;
+        let mut delete_item = false;
+        let mut no_create = false;
+        let mut no_restore = false;
+        let mut verify = false;
+        let mut no_notes = false;
+        let mut item_type = ItemType::Login;
+        let mut verbose = false;
+        let mut retries = DEFAULT_RETRIES;
+        let mut lock_timeout = DEFAULT_LOCK_TIMEOUT;
+        let mut purge = false;
+        let mut force = false;
+        let mut organization_id = None;
+        let mut collection_ids = Vec::new();
+        let mut bw_args = Vec::new();
+        let mut collection_names = Vec::new();
+        let mut folder = None;
+        let mut no_create_folder = false;
+        let mut migrate_to_org = false;
+        let mut include_personal = false;
+        let mut scoped = false;
+        let mut scope = None;
+        let mut keep_previous = false;
+        let mut session_max_age = None;
+        let mut persist_session = false;
+        let mut session_file = None;
+        let mut forget_session = false;
+        let mut teardown = None;
+        let mut read_only = false;
+        let mut ignore_reprompt = false;
+        let mut bw_path = None;
+        let mut appdata_dir = None;
+        let mut fingerprint = false;
+        let mut expires = None;
+        let mut check_reuse = false;
+        let mut prefer_newest = false;
+        let mut match_by_name = false;
+        let mut uri_match = MatchMode::Exact;
+        let mut create_uri_match = MatchMode::Exact;
+        let mut aliases = Vec::new();
+        let mut allow_npx = false;
+        let mut prefer_windows_bw = false;
+        let mut background_sync = false;
+        let mut sync_strict = false;
+        let mut fallback_provider = None;
+        let mut backend = backend::Kind::Cli;
+        let mut secret_id = None;
+        let mut project_id = None;
+        let mut askpass = None;
+        let mut warn_stale = None;
+        let mut stale_strict = false;
+        let mut refresh_command = None;
+        let mut notify_command = None;
+        let mut audit_log = None;
+        let mut refresh_after = None;
+        let mut apikey = false;
+        let mut password_env = None;
+        let mut password_command = None;
+        let mut sso = false;
+        let mut no_login = false;
+        let mut offline = false;
+        let mut switch_account = false;
+        let mut two_step_method = None;
+        let mut two_step_code = None;
+        let mut quiet = false;
+        let mut timings = None;
+        let mut env_session_cache = DEFAULT_ENV_SESSION_CACHE;
+        let mut cache_never = false;
+        let mut cache_ttl = None;
+        let mut slow_threshold = DEFAULT_SLOW_THRESHOLD;
+        let mut timeout = Some(DEFAULT_TIMEOUT);
+        while let Some(arg) = args.next() {
+            match *arg {
+                "--config" => {
+                    // Read above.
+                    args.next().ok_or("--config needs an arg")?;
+                }
+                "--email" => {
+                    email_address = Some(args.next().ok_or("--email needs an arg")?);
+                }
+                "--username" => {
+                    username = Some(args.next().ok_or("--username needs an arg")?);
+                }
+                "--name-template" => {
+                    let template = args.next().ok_or("--name-template needs an arg")?;
+                    name_template = NameTemplate::parse(template)?;
+                }
+                "--token-format" => {
+                    let template = args.next().ok_or("--token-format needs an arg")?;
+                    token_format = Some(token_format::TokenFormat::parse(template)?);
+                }
+                "--compose-token" => {
+                    let template = args.next().ok_or("--compose-token needs an arg")?;
+                    compose_token = Some(token_format::Compose::parse(template)?);
+                }
+                "--split-token" => {
+                    let separator = args.next().ok_or("--split-token needs an arg")?;
+                    if separator.is_empty() {
+                        return Err("`--split-token` needs a separator".into());
+                    }
+                    split_token = Some(separator.to_string());
+                }
+                "--field" => {
+                    let name = args.next().ok_or("--field needs an arg")?;
+                    if name.is_empty() {
+                        return Err("`--field` needs a field name".into());
+                    }
+                    token_field = Some(name.to_string());
+                }
+                "--sync" => {
+                    auto_sync = true;
+                }
+                // `--sync-max-age` is the older name.
+                option @ ("--sync-interval" | "--sync-max-age") => {
+                    let secs = args
+                        .next()
+                        .ok_or_else(|| format!("{} needs an arg", option))?;
+                    let secs = secs
+                        .parse()
+                        .map_err(|_| format!("invalid {} `{}`", option, secs))?;
+                    sync_interval = Duration::from_secs(secs);
+                }
+                "--server" => {
+                    server = Some(args.next().ok_or("--server needs an arg")?);
+                }
+                "--region" => {
+                    region = Some(args.next().ok_or("--region needs an arg")?);
+                }
+                "--force-server" => {
+                    force_server = true;
+                }
+                "--attach-to" => {
+                    attach_to = Some(args.next().ok_or("--attach-to needs an arg")?);
+                }
+                "--item-id" => {
+                    item_id = Some(args.next().ok_or("--item-id needs an arg")?);
+                }
+                "--append-uri" => {
+                    append_uri = true;
+                }
+                "--delete-item" => {
+                    delete_item = true;
+                }
+                "--purge" => {
+                    purge = true;
+                }
+                "--no-create" => {
+                    no_create = true;
+                }
+                "--no-restore" => {
+                    no_restore = true;
+                }
+                "--verify" => {
+                    verify = true;
+                }
+                "--no-notes" => {
+                    no_notes = true;
+                }
+                "--item-type" => {
+                    item_type = match *args.next().ok_or("--item-type needs an arg")? {
+                        "login" => ItemType::Login,
+                        "note" => ItemType::Note,
+                        other => {
+                            return Err(format!(
+                                "unknown --item-type `{}`, expected `login` or `note`",
+                                other
+                            )
+                            .into())
+                        }
+                    };
+                }
+                "--force" => {
+                    force = true;
+                }
+                "--organization-id" => {
+                    organization_id = Some(args.next().ok_or("--organization-id needs an arg")?);
+                }
+                "--collection-id" => {
+                    let id = args.next().ok_or("--collection-id needs an arg")?;
+                    collection_ids.push(id.to_string());
+                }
+                "--collection" => {
+                    let name = args.next().ok_or("--collection needs an arg")?;
+                    collection_names.push(name.to_string());
+                }
+                "--folder" => {
+                    folder = Some(args.next().ok_or("--folder needs an arg")?);
+                }
+                "--no-create-folder" => {
+                    no_create_folder = true;
+                }
+                "--migrate-to-org" => {
+                    migrate_to_org = true;
+                }
+                "--include-personal" => {
+                    include_personal = true;
+                }
+                "--scoped" => {
+                    scoped = true;
+                }
+                "--scope" => {
+                    let name = args.next().ok_or("--scope needs an arg")?;
+                    scope = Some(Scope::parse(name)?);
+                }
+                // `--keep-history` is another name.
+                "--keep-previous" | "--keep-history" => {
+                    keep_previous = true;
+                }
+                "--session-max-age" => {
+                    let secs = args.next().ok_or("--session-max-age needs an arg")?;
+                    let secs = secs
+                        .parse()
+                        .map_err(|_| format!("invalid --session-max-age `{}`", secs))?;
+                    session_max_age = Some(Duration::from_secs(secs));
+                }
+                "--persist-session" => {
+                    persist_session = true;
+                }
+                "--session-file" => {
+                    let path = args.next().ok_or("--session-file needs an arg")?;
+                    session_file = Some(std::path::PathBuf::from(path));
+                }
+                "--forget-session" => {
+                    forget_session = true;
+                }
+                "--lock-after" => {
+                    // `--logout-after` locks as well.
+                    teardown = teardown.or(Some(Teardown::Lock));
+                }
+                "--logout-after" => {
+                    teardown = Some(Teardown::Logout);
+                }
+                "--read-only" => {
+                    read_only = true;
+                }
+                "--ignore-reprompt" => {
+                    ignore_reprompt = true;
+                }
+                "--fingerprint" => {
+                    fingerprint = true;
+                }
+                "--expires" => {
+                    let value = args.next().ok_or("--expires needs an arg")?;
+                    expires = Some(parse_expires(value, time::OffsetDateTime::now_utc())?);
+                }
+                "--check-reuse" => {
+                    check_reuse = true;
+                }
+                "--match-by-name" => {
+                    match_by_name = true;
+                }
+                "--prefer-newest" => {
+                    prefer_newest = true;
+                }
+                "--uri-match" => {
+                    let name = args.next().ok_or("--uri-match needs an arg")?;
+                    uri_match = MatchMode::parse(name).ok_or_else(|| {
+                        format!(
+                            "unknown --uri-match `{}`, expected `exact`, `host`, `base` or \
+                             `starts-with`",
+                            name
+                        )
+                    })?;
+                }
+                "--create-uri-match" => {
+                    create_uri_match =
+                        match *args.next().ok_or("--create-uri-match needs an arg")? {
+                            "exact" => MatchMode::Exact,
+                            "host" => MatchMode::Host,
+                            // Bitwarden's "base domain".
+                            "base" => MatchMode::Domain,
+                            other => {
+                                return Err(format!(
+                            "unknown --create-uri-match `{}`, expected `exact`, `host` or `base`",
+                            other
+                        )
+                                .into())
+                            }
+                        };
+                }
+                "--alias" => {
+                    let alias = args.next().ok_or("--alias needs an arg")?;
+                    aliases.push(parse_alias(alias)?);
+                }
+                "--allow-npx" => {
+                    allow_npx = true;
+                }
+                "--prefer-windows-bw" => {
+                    prefer_windows_bw = true;
+                }
+                "--background-sync" => {
+                    background_sync = true;
+                }
+                "--sync-strict" => {
+                    sync_strict = true;
+                }
+                "--quiet" => {
+                    quiet = true;
+                }
+                "--verbose" => {
+                    verbose = true;
+                }
+                "--env-session-cache" => {
+                    let secs = args.next().ok_or("--env-session-cache needs an arg")?;
+                    let secs = secs
+                        .parse()
+                        .map_err(|_| format!("invalid --env-session-cache `{}`", secs))?;
+                    env_session_cache = Duration::from_secs(secs);
+                }
+                "--cache" => {
+                    cache_never = match *args.next().ok_or("--cache needs an arg")? {
+                        "session" => false,
+                        "never" => true,
+                        other => {
+                            return Err(format!(
+                                "unknown --cache `{}`, expected `session` or `never`",
+                                other
+                            )
+                            .into())
+                        }
+                    };
+                }
+                "--cache-ttl" => {
+                    let secs = args.next().ok_or("--cache-ttl needs an arg")?;
+                    let secs = secs
+                        .parse()
+                        .map_err(|_| format!("invalid --cache-ttl `{}`", secs))?;
+                    cache_ttl = (secs > 0).then(|| Duration::from_secs(secs));
+                }
+                "--slow-threshold" => {
+                    let secs = args.next().ok_or("--slow-threshold needs an arg")?;
+                    slow_threshold = secs
+                        .parse()
+                        .ok()
+                        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                        .ok_or_else(|| format!("invalid --slow-threshold `{}`", secs))?;
+                }
+                "--lock-timeout" => {
+                    let secs = args.next().ok_or("--lock-timeout needs an arg")?;
+                    let secs = secs
+                        .parse()
+                        .map_err(|_| format!("invalid --lock-timeout `{}`", secs))?;
+                    lock_timeout = Duration::from_secs(secs);
+                }
+                "--retries" => {
+                    let count = args.next().ok_or("--retries needs an arg")?;
+                    retries = count
+                        .parse()
+                        .map_err(|_| format!("invalid --retries `{}`", count))?;
+                }
+                "--timeout" => {
+                    let secs = args.next().ok_or("--timeout needs an arg")?;
+                    let secs = secs
+                        .parse()
+                        .map_err(|_| format!("invalid --timeout `{}`", secs))?;
+                    timeout = (secs > 0).then(|| Duration::from_secs(secs));
+                }
+                "--backend" => {
+                    let name = args.next().ok_or("--backend needs an arg")?;
+                    backend = backend::Kind::parse(name).ok_or_else(|| {
+                        format!(
+                            "unknown backend `{}`, expected `cli`, `serve`, `rbw` or `bws`",
+                            name
+                        )
+                    })?;
+                }
+                "--secret-id" => {
+                    secret_id = Some(args.next().ok_or("--secret-id needs an arg")?);
+                }
+                "--project-id" => {
+                    project_id = Some(args.next().ok_or("--project-id needs an arg")?);
+                }
+                // `--fallback` is the short name.
+                option @ ("--fallback-provider" | "--fallback") => {
+                    fallback_provider = Some(
+                        args.next()
+                            .ok_or_else(|| format!("{} needs an arg", option))?,
+                    );
+                }
+                "--apikey" => {
+                    apikey = true;
+                }
+                "--sso" => {
+                    sso = true;
+                }
+                "--no-login" => {
+                    no_login = true;
+                }
+                "--offline" => {
+                    offline = true;
+                }
+                "--switch-account" => {
+                    switch_account = true;
+                }
+                "--method" => {
+                    let name = args.next().ok_or("--method needs an arg")?;
+                    two_step_method = Some(two_step::Method::parse(name)?);
+                }
+                "--code" => {
+                    let code = args.next().ok_or("--code needs an arg")?;
+                    two_step_code = Some(two_step::read_code(code)?);
+                }
+                "--passwordenv" => {
+                    password_env = Some(args.next().ok_or("--passwordenv needs an arg")?);
+                }
+                "--password-command" => {
+                    let command = args.next().ok_or("--password-command needs an arg")?;
+                    if command.trim().is_empty() {
+                        return Err("`--password-command` is empty".into());
+                    }
+                    password_command = Some(command.to_string());
+                }
+                option @ ("--warn-stale" | "--max-age") => {
+                    let days = args
+                        .next()
+                        .ok_or_else(|| format!("{} needs an arg", option))?;
+                    warn_stale = Some(
+                        days.parse()
+                            .map_err(|_| format!("invalid {} `{}`", option, days))?,
+                    );
+                }
+                "--max-age-strict" => {
+                    stale_strict = true;
+                }
+                "--refresh-command" => {
+                    let command = args.next().ok_or("--refresh-command needs an arg")?;
+                    if command.trim().is_empty() {
+                        return Err("`--refresh-command` is empty".into());
+                    }
+                    refresh_command = Some(command.to_string());
+                }
+                "--notify-command" => {
+                    let command = args.next().ok_or("--notify-command needs an arg")?;
+                    if command.trim().is_empty() {
+                        return Err("`--notify-command` is empty".into());
+                    }
+                    notify_command = Some(command.to_string());
+                }
+                "--audit-log" => {
+                    let path = args.next().ok_or("--audit-log needs an arg")?;
+                    audit_log = Some(std::path::PathBuf::from(path));
+                }
+                "--refresh-after" => {
+                    let days = args.next().ok_or("--refresh-after needs an arg")?;
+                    refresh_after = Some(
+                        days.parse()
+                            .map_err(|_| format!("invalid --refresh-after `{}`", days))?,
+                    );
+                }
+                "--askpass" => {
+                    askpass = Some(args.next().ok_or("--askpass needs an arg")?);
+                }
+                "--bw-path" => {
+                    bw_path = Some(args.next().ok_or("--bw-path needs an arg")?);
+                }
+                "--bw-arg" => {
+                    let arg = args.next().ok_or("--bw-arg needs an arg")?;
+                    bw_args.push(parse_bw_arg(arg)?);
+                }
+                "--appdata-dir" => {
+                    appdata_dir = Some(args.next().ok_or("--appdata-dir needs an arg")?);
+                }
+                "--timings" => {
+                    timings = Some(timings::Format::Text);
+                }
+                s if s.starts_with("--timings=") => {
+                    let name = &s["--timings=".len()..];
+                    timings = Some(timings::Format::parse(name).ok_or_else(|| {
+                        format!("unknown --timings `{}`, expected `text` or `json`", name)
+                    })?);
+                }
+                s if s.starts_with('-') => {
+                    return Err(unknown(s).into());
+                }
+                _ => {
+                    return Err("too many arguments".into());
+                }
+            }
+        }
+
+        let server = match (server, region) {
+            (Some(_), Some(_)) => {
+                return Err("`--server` and `--region` cannot be used together".into());
+            }
+            (Some(url), None) => Some(Server::from_url(url)),
+            (None, Some(region)) => Some(Server::from_region(region)?),
+            (None, None) => None,
+        };
+
+        if !collection_names.is_empty() && organization_id.is_none() {
+            return Err("`--collection` requires `--organization-id`".into());
+        }
+
+        if attach_to.is_some() && item_id.is_some() {
+            return Err("`--attach-to` and `--item-id` cannot be used together".into());
+        }
+        if append_uri && item_id.is_none() {
+            return Err("`--append-uri` requires `--item-id`".into());
+        }
+
+        if stale_strict && warn_stale.is_none() {
+            return Err("`--max-age-strict` requires `--max-age`".into());
+        }
+        match (refresh_command.is_some(), refresh_after.is_some()) {
+            (true, false) => return Err("`--refresh-command` requires `--refresh-after`".into()),
+            (false, true) => return Err("`--refresh-after` requires `--refresh-command`".into()),
+            _ => {}
+        }
+        if refresh_command.is_some() && read_only {
+            return Err("`--refresh-command` and `--read-only` cannot be used together".into());
+        }
+        if refresh_command.is_some() && compose_token.is_some() {
+            // The command prints the whole token, not its password.
+            return Err("`--refresh-command` and `--compose-token` cannot be used together".into());
+        }
+        if split_token.is_some() && username.is_some() {
+            return Err("`--split-token` and `--username` cannot be used together".into());
+        }
+        if refresh_command.is_some() && scoped {
+            // Which of the tokens of the item would be replaced is unclear.
+            return Err("`--refresh-command` and `--scoped` cannot be used together".into());
+        }
+
+        if switch_account && email_address.is_none() {
+            return Err("`--switch-account` requires `--email`".into());
+        }
+        if sso && apikey {
+            return Err("`--sso` and `--apikey` cannot be used together".into());
+        }
+        if password_env.is_some() && password_command.is_some() {
+            return Err("`--passwordenv` and `--password-command` cannot be used together".into());
+        }
+        let sign_in = [
+            ("--apikey", apikey),
+            ("--sso", sso),
+            ("--passwordenv", password_env.is_some()),
+            ("--password-command", password_command.is_some()),
+            ("--switch-account", switch_account),
+        ];
+        if let (true, Some((name, _))) = (no_login, sign_in.iter().find(|(_, given)| *given)) {
+            return Err(format!("`--no-login` and `{}` cannot be used together", name).into());
+        }
+        if let (true, Some((name, _))) = (offline, sign_in.iter().find(|(_, given)| *given)) {
+            return Err(format!("`--offline` and `{}` cannot be used together", name).into());
+        }
+
+        if scope.is_some() && !scoped {
+            return Err("`--scope` requires `--scoped`".into());
+        }
+        let scope = scoped.then(|| scope.unwrap_or(Scope::All));
+        if scoped && token_field.is_some() {
+            return Err("`--field` and `--scoped` cannot be used together".into());
+        }
+
+        if item_type == ItemType::Note {
+            let login_only = [
+                ("--scoped", scoped),
+                ("--field", token_field.is_some()),
+                ("--attach-to", attach_to.is_some()),
+                ("--append-uri", append_uri),
+                ("--username", username.is_some()),
+                ("--compose-token", compose_token.is_some()),
+                ("--split-token", split_token.is_some()),
+            ];
+            if let Some((name, _)) = login_only.iter().find(|(_, given)| *given) {
+                return Err(
+                    format!("`--item-type note` and `{}` cannot be used together", name).into(),
+                );
+            }
+        }
+
+        backend.refuse(&[
+            ("--item-type", item_type == ItemType::Note),
+            ("--organization-id", organization_id.is_some()),
+            ("--collection-id", !collection_ids.is_empty()),
+            ("--collection", !collection_names.is_empty()),
+            ("--folder", folder.is_some()),
+            ("--no-create-folder", no_create_folder),
+            ("--scoped", scoped),
+            ("--fingerprint", fingerprint),
+            ("--expires", expires.is_some()),
+            ("--refresh-command", refresh_command.is_some()),
+            ("--bw-arg", !bw_args.is_empty()),
+            ("--compose-token", compose_token.is_some()),
+            ("--split-token", split_token.is_some()),
+            ("--apikey", apikey),
+            ("--passwordenv", password_env.is_some()),
+            ("--password-command", password_command.is_some()),
+            ("--sso", sso),
+            ("--switch-account", switch_account),
+            ("--appdata-dir", appdata_dir.is_some()),
+            ("--method", two_step_method.is_some()),
+            ("--code", two_step_code.is_some()),
+            ("--server", server.is_some()),
+            ("--session-max-age", session_max_age.is_some()),
+            ("--persist-session", persist_session),
+            ("--session-file", session_file.is_some()),
+            ("--forget-session", forget_session),
+            ("--lock-after", teardown == Some(Teardown::Lock)),
+            ("--logout-after", teardown == Some(Teardown::Logout)),
+            ("--field", token_field.is_some()),
+            ("--purge", purge),
+        ])?;
+        if backend == backend::Kind::Bws {
+            backend.refuse(&[
+                ("--attach-to", attach_to.is_some()),
+                ("--item-id", item_id.is_some()),
+                ("--prefer-newest", prefer_newest),
+                ("--username", username.is_some()),
+                // Secrets Manager has no local copy.
+                ("--offline", offline),
+            ])?;
+        } else if secret_id.is_some() || project_id.is_some() {
+            return Err("`--secret-id` and `--project-id` require `--backend bws`".into());
+        }
+
+        let username = match username {
+            Some(&"email") => Some(
+                email_address
+                    .ok_or("`--username email` requires `--email`")?
+                    .to_string(),
+            ),
+            username => username.map(|s| s.to_string()),
+        };
+        let cache = match (cache_never, cache_ttl) {
+            (true, Some(_)) => {
+                return Err("`--cache never` and `--cache-ttl` can't be combined".into())
+            }
+            (true, None) => TokenCache::Never,
+            (false, Some(ttl)) => TokenCache::Expires(ttl),
+            (false, None) => TokenCache::Session,
+        };
+
+        Ok(Options {
+            email_address: email_address.map(|s| s.to_string()),
+            username,
+            token_field,
+            name_template,
+            token_format,
+            compose_token,
+            split_token,
+            bw_path: bw_path.map(|s| s.to_string()),
+            allow_npx,
+            prefer_windows_bw,
+            bw_args,
+            appdata_dir: appdata_dir.map(PathBuf::from),
+            auto_sync: auto_sync && !offline,
+            sync_interval,
+            server,
+            force_server,
+            attach_to: attach_to.map(|s| s.to_string()),
+            item_id: item_id.map(|s| s.to_string()),
+            append_uri,
+            delete_item,
+            purge,
+            no_create,
+            no_restore,
+            verify,
+            force,
+            // The notes of a secure note are the token.
+            no_notes: no_notes || item_type == ItemType::Note,
+            item_type,
+            organization_id: organization_id.map(|s| s.to_string()),
+            collection_ids,
+            collection_names,
+            folder: folder.map(|s| s.to_string()),
+            no_create_folder,
+            migrate_to_org,
+            include_personal,
+            scope,
+            keep_previous,
+            session_max_age,
+            persist_session,
+            session_file,
+            forget_session,
+            teardown,
+            read_only,
+            ignore_reprompt,
+            expires,
+            fingerprint,
+            check_reuse,
+            uri_match,
+            create_uri_match,
+            aliases: with_builtin_aliases(aliases),
+            prefer_newest,
+            match_by_name,
+            quiet,
+            verbose,
+            timings,
+            askpass: askpass.map(|s| s.to_string()),
+            env_session_cache,
+            cache,
+            slow_threshold,
+            timeout,
+            retries,
+            lock_timeout,
+            background_sync,
+            sync_strict,
+            fallback_provider: fallback_provider.map(|s| s.to_string()),
+            apikey,
+            password_env: password_env.map(|s| s.to_string()),
+            password_command,
+            sso,
+            switch_account,
+            no_login: no_login || offline,
+            offline,
+            two_step_method,
+            two_step_code,
+            warn_stale,
+            stale_strict,
+            refresh_command,
+            refresh_after,
+            notify_command,
+            audit_log,
+            backend,
+            secret_id: secret_id.map(|s| s.to_string()),
+            project_id: project_id.map(|s| s.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn split_values() {
+        assert_eq!(
+            split(&["--email=me@example.com", "--sync", "--folder", "a=b"]).unwrap(),
+            strings(&["--email", "me@example.com", "--sync", "--folder", "a=b"])
+        );
+        // Optional values and argument files are left as they are.
+        assert_eq!(
+            split(&["--timings=json", "--timings", "@args.txt"]).unwrap(),
+            strings(&["--timings=json", "--timings", "@args.txt"])
+        );
+        // A value of `--name=value` may hold `=` itself.
+        assert_eq!(
+            split(&["--alias=a=b"]).unwrap(),
+            strings(&["--alias", "a=b"])
+        );
+        let e = split(&["--sync=yes"]).unwrap_err();
+        assert_eq!(e, "`--sync` takes no value");
+    }
+
+    #[test]
+    fn split_repeats() {
+        // The same value twice is harmless, as are repeatable options.
+        assert!(split(&["--email", "a@example.com", "--email=a@example.com"]).is_ok());
+        assert!(split(&["--collection-id", "a", "--collection-id", "b"]).is_ok());
+        for args in [
+            &["--email", "a@example.com", "--email=b@example.com"][..],
+            // An older name is the same option.
+            &["--sync-interval", "5", "--sync-max-age", "6"],
+        ] {
+            let e = split(args).unwrap_err();
+            assert!(e.contains("is given twice, with different values"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn layer_replaces_values() {
+        let earlier = strings(&["--email", "a", "--sync", "--collection-id", "x"]);
+        let later = strings(&["--email", "b", "--collection-id", "y", "--quiet"]);
+        assert_eq!(
+            layer(&earlier, &later),
+            strings(&["--sync", "--email", "b", "--collection-id", "y", "--quiet"])
+        );
+        assert_eq!(layer(&earlier, &[]), earlier);
+        assert_eq!(layer(&[], &later), later);
+    }
+
+    #[test]
+    fn env_vars() {
+        let vars = |vars: &'static [(&'static str, &'static str)]| {
+            from_vars(move |name| {
+                let name = name.strip_prefix(ENV_PREFIX)?;
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| OsString::from(value))
+            })
+        };
+        assert_eq!(
+            vars(&[
+                ("EMAIL", "me@example.com"),
+                ("SYNC", "Yes"),
+                ("QUIET", "0"),
+                ("TIMINGS", "json"),
+                ("CONFIG", "bitwarden.toml"),
+                ("VERBOSE", ""),
+            ])
+            .unwrap(),
+            strings(&["--email", "me@example.com", "--sync", "--timings=json"])
+        );
+        assert_eq!(
+            vars(&[("SYNC_INTERVAL", "10")]).unwrap(),
+            strings(&["--sync-interval", "10"])
+        );
+        let e = vars(&[("SYNC", "maybe")]).unwrap_err();
+        assert_eq!(
+            e,
+            "`CARGO_CREDENTIAL_BITWARDEN_SYNC` must be 1, true, yes, 0, false or no, not `maybe`"
+        );
+    }
+
+    #[test]
+    fn suggestions() {
+        for (name, suggestion) in [
+            ("--emial", Some("--email")),
+            ("--syn", Some("--sync")),
+            ("--bw-pth", Some("--bw-path")),
+            ("--session-max-ag", Some("--session-max-age")),
+            ("--frobnicate", None),
+            ("--collection-idz-and-more", None),
+        ] {
+            let expected = match suggestion {
+                Some(option) => format!("unknown option `{}`; did you mean `{}`?", name, option),
+                None => format!("unknown option `{}`", name),
+            };
+            assert_eq!(unknown(name), expected);
+        }
+    }
+
+    #[test]
+    fn distances() {
+        for (a, b, expected) in [
+            ("--email", "--email", 0),
+            ("--emial", "--email", 2),
+            ("--syn", "--sync", 1),
+            ("kitten", "sitting", 3),
+            ("", "abc", 3),
+            ("abc", "", 3),
+        ] {
+            assert_eq!(distance(a, b), expected, "{} and {}", a, b);
+        }
+    }
+
+    fn date(date: &str) -> time::OffsetDateTime {
+        time::OffsetDateTime::parse(date, &time::format_description::well_known::Rfc3339).unwrap()
+    }
+
+    #[test]
+    fn expires() {
+        let now = date("2024-06-01T12:00:00.5Z");
+        assert_eq!(
+            parse_expires("30", now).unwrap(),
+            date("2024-07-01T12:00:00Z")
+        );
+        assert_eq!(
+            parse_expires("2024-06-01T12:00:01Z", now).unwrap(),
+            date("2024-06-01T12:00:01Z")
+        );
+        for past in ["0", "2024-06-01T12:00:00.5Z", "2023-01-01T00:00:00+02:00"] {
+            let e = parse_expires(past, now).unwrap_err();
+            assert!(e.contains("is not in the future"), "{}: {}", past, e);
+        }
+        for invalid in [
+            "",
+            "-1",
+            "1.5",
+            "2024-13-01T00:00:00Z",
+            "2024-06-02",
+            "tomorrow",
+        ] {
+            let e = parse_expires(invalid, now).unwrap_err();
+            assert!(e.starts_with("invalid --expires"), "{}: {}", invalid, e);
+        }
+    }
+}
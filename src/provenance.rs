@@ -0,0 +1,74 @@
+//! Where a token came from, kept in the notes of the items `cargo login`
+//! writes, for `--no-notes` to turn off.
+//!
+//! The notes get a block between two marker lines. Logging in again
+//! replaces the block, and leaves whatever the user wrote around it alone.
+//! Items the user made themselves only get a block if they had one.
+
+/// First line of the block.
+const BEGIN: &str = "--- cargo-credential-bitwarden ---";
+/// Last line of the block.
+const END: &str = "--- end cargo-credential-bitwarden ---";
+
+/// The block describing a login for `index_url`, made now on this machine.
+pub fn block(index_url: &str, registry_name: Option<&str>) -> String {
+    let now = time::OffsetDateTime::now_utc();
+    let date = now
+        .replace_nanosecond(0)
+        .unwrap_or(now)
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_default();
+    let mut lines = vec![BEGIN.to_string()];
+    if let Some(name) = registry_name.filter(|name| !name.trim().is_empty()) {
+        lines.push(format!("registry: {}", name));
+    }
+    lines.push(format!("index: {}", index_url));
+    if let Some(host) = hostname() {
+        lines.push(format!("host: {}", host));
+    }
+    lines.push(format!("date: {}", date));
+    lines.push(format!("provider: {}", env!("CARGO_PKG_VERSION")));
+    lines.push(END.to_string());
+    lines.join("\n")
+}
+
+/// `notes` with its block replaced by `block`, or `block` appended when
+/// there is none yet.
+pub fn update(notes: Option<&str>, block: &str) -> String {
+    let notes = notes.unwrap_or_default();
+    if let Some(start) = notes.find(BEGIN) {
+        if let Some(len) = notes[start..].find(END) {
+            let end = start + len + END.len();
+            return format!("{}{}{}", &notes[..start], block, &notes[end..]);
+        }
+    }
+    match notes.trim_end() {
+        "" => block.to_string(),
+        notes => format!("{}\n\n{}", notes, block),
+    }
+}
+
+/// Whether `notes` hold a block.
+pub fn has_block(notes: Option<&str>) -> bool {
+    notes.is_some_and(|notes| notes.contains(BEGIN))
+}
+
+/// Name of this machine, if it has one.
+fn hostname() -> Option<String> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            let mut buffer = [0u8; 256];
+            let result = unsafe {
+                libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len())
+            };
+            if result != 0 {
+                return None;
+            }
+            let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+            let name = String::from_utf8_lossy(&buffer[..len]).into_owned();
+        } else {
+            let name = std::env::var("COMPUTERNAME").ok()?;
+        }
+    }
+    Some(name).filter(|name| !name.is_empty())
+}
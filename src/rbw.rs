@@ -0,0 +1,257 @@
+//! The `rbw` backend, for `--backend rbw`.
+//!
+//! [rbw](https://github.com/doy/rbw) is an unofficial Bitwarden client that
+//! keeps the unlocked vault in a background agent. Its entries are converted
+//! to the item JSON of the official CLI, so that matching them to registries
+//! works exactly like with `bw`.
+//!
+//! rbw has no sessions, organizations or collections, and `rbw edit` can
+//! only change the password and notes of an entry.
+
+use crate::backend::Backend;
+use crate::{BitwardenVault, CreateOutcome, ItemRef};
+use cargo_credential::Error;
+use serde_json::{json, Value};
+use std::process::{Output, Stdio};
+use url::Url;
+
+/// Command run when `--bw-path` isn't given.
+pub const CMD: &str = "rbw";
+
+/// Unlock the agent, which asks for the master password through pinentry.
+pub fn unlock(vault: &BitwardenVault) -> Result<(), Error> {
+    let mut check = vault.make_cmd(&None, &["unlocked"])?;
+    check
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    if vault.output(&mut check)?.status.success() {
+        return Ok(());
+    }
+
+    if vault.options.no_login {
+        return Err("the rbw agent is locked and `--no-login` was given; run `rbw unlock`".into());
+    }
+
+    let mut cmd = vault.make_cmd(&None, &["unlock"])?;
+    let status = crate::cancel::spawn(&mut cmd)
+        .and_then(|mut child| child.wait())
+        .map_err(|e| format!("failed to run `rbw unlock`: {}", e))?;
+    if !status.success() {
+        return Err(format!("`rbw unlock` failed: {}; see its output above", status).into());
+    }
+    Ok(())
+}
+
+/// The `rbw` CLI.
+pub struct Rbw<'a> {
+    pub vault: &'a BitwardenVault,
+}
+
+impl Rbw<'_> {
+    fn run(&self, args: &[&str], input: Option<&str>) -> Result<Output, Error> {
+        let mut cmd = self.vault.make_cmd(&None, args)?;
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        if input.is_none() {
+            cmd.stdin(Stdio::null());
+        }
+        let output = self
+            .vault
+            .output_with_input(&mut cmd, input.map(str::as_bytes))?;
+        if !output.status.success() {
+            return Err(crate::command_failed(&cmd, output.status, &output.stderr));
+        }
+        Ok(output)
+    }
+
+    /// What `rbw add` and `rbw edit` read from stdin: the password on the
+    /// first line, the notes on the following ones.
+    fn secrets(item: &Value) -> String {
+        let mut input = String::new();
+        input.push_str(item["login"]["password"].as_str().unwrap_or_default());
+        input.push('\n');
+        if let Some(notes) = item["notes"].as_str() {
+            input.push_str(notes);
+            input.push('\n');
+        }
+        input
+    }
+}
+
+/// Convert an entry printed by `rbw get --raw` to the item JSON of `bw`.
+fn to_item(entry: &Value) -> Value {
+    let data = &entry["data"];
+    // Only logins have a password; other entries are skipped by the callers.
+    let login = data.get("password").map(|password| {
+        let uris: Vec<Value> = data["uris"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|uri| json!({ "uri": uri["uri"], "match": Value::Null }))
+            .collect();
+        json!({
+            "username": data["username"],
+            "password": password,
+            "totp": data["totp"],
+            "uris": uris,
+        })
+    });
+    let fields: Vec<Value> = entry["fields"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|field| json!({ "name": field["name"], "value": field["value"], "type": 0 }))
+        .collect();
+    json!({
+        "id": entry["id"],
+        "type": if login.is_some() { 1 } else { 2 },
+        "name": entry["name"],
+        "organizationId": Value::Null,
+        "folderId": Value::Null,
+        "notes": entry["notes"],
+        "fields": fields,
+        "login": login,
+    })
+}
+
+/// What of `item` rbw can't store, compared to `current`.
+fn unsupported_change(current: &Value, item: &Value) -> Option<&'static str> {
+    let uris = |item: &Value| -> Vec<String> {
+        item["login"]["uris"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|uri| uri["uri"].as_str())
+            .map(str::to_string)
+            .collect()
+    };
+    let fields = |item: &Value| -> Vec<(Value, Value)> {
+        item["fields"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|field| (field["name"].clone(), field["value"].clone()))
+            .collect()
+    };
+    if current["name"] != item["name"] {
+        Some("its name")
+    } else if current["login"]["username"] != item["login"]["username"] {
+        Some("its username")
+    } else if uris(current) != uris(item) {
+        Some("its URIs")
+    } else if fields(current) != fields(item) {
+        Some("its custom fields")
+    } else {
+        None
+    }
+}
+
+/// Whether entry `item` is one `bw list items --url <url>` would return:
+/// one with a URI on the same host.
+fn same_host(item: &Value, url: &str) -> bool {
+    let Some(host) = Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+    else {
+        return false;
+    };
+    item["login"]["uris"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|uri| uri["uri"].as_str())
+        .filter_map(|uri| Url::parse(uri.trim_start_matches("sparse+")).ok())
+        .any(|uri| {
+            uri.host_str()
+                .is_some_and(|h| h.eq_ignore_ascii_case(&host))
+        })
+}
+
+impl Backend for Rbw<'_> {
+    /// rbw can't filter entries, so every one is fetched; the agent keeps
+    /// that fast.
+    fn list_items(&self, filters: &[(&str, &str)]) -> Result<Vec<Value>, Error> {
+        let output = self.run(&["list", "--fields", "id"], None)?;
+        let mut items = Vec::new();
+        for id in String::from_utf8_lossy(&output.stdout).lines() {
+            let item = self.get_item(id.trim())?;
+            let matches = filters.iter().all(|&(name, value)| match name {
+                "url" => same_host(&item, value),
+                "search" => item["name"]
+                    .as_str()
+                    .is_some_and(|name| name.to_lowercase().contains(&value.to_lowercase())),
+                _ => false,
+            });
+            if matches {
+                items.push(item);
+            }
+        }
+        Ok(items)
+    }
+
+    fn get_item(&self, id: &str) -> Result<Value, Error> {
+        let output = match self.run(&["get", "--raw", id], None) {
+            Ok(output) => output,
+            Err(e) if e.to_string().contains("no entry found") => return Err(Error::NotFound),
+            Err(e) => return Err(e),
+        };
+        let entry: Value = crate::json::parse_output(&String::from_utf8_lossy(&output.stdout))
+            .map_err(|e| format!("failed to deserialize JSON from `rbw get`: {}", e))?;
+        Ok(to_item(&entry))
+    }
+
+    fn create_item(&self, item: &Value) -> Result<CreateOutcome, Error> {
+        if item["fields"]
+            .as_array()
+            .is_some_and(|fields| !fields.is_empty())
+        {
+            return Err("rbw can't create entries with custom fields".into());
+        }
+        let name = item["name"].as_str().ok_or("new item has no name")?;
+        let uris: Vec<&str> = item["login"]["uris"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|uri| uri["uri"].as_str())
+            .collect();
+        let mut args = vec!["add"];
+        for uri in &uris {
+            args.extend(["--uri", uri]);
+        }
+        args.push(name);
+        if let Some(username) = item["login"]["username"].as_str() {
+            args.push(username);
+        }
+        self.run(&args, Some(&Rbw::secrets(item)))?;
+        Ok(CreateOutcome::Created(None))
+    }
+
+    fn edit_item(&self, target: &ItemRef<'_>, item: &Value) -> Result<(), Error> {
+        let current = self.get_item(target.id)?;
+        if let Some(change) = unsupported_change(&current, item) {
+            return Err(format!(
+                "rbw can only change the password and notes of an entry, not {} \
+                 (entry '{}', id {})",
+                change, target.name, target.id
+            )
+            .into());
+        }
+        self.run(&["edit", target.id], Some(&Rbw::secrets(item)))
+            .map(drop)
+    }
+
+    /// rbw has no trash to move entries to; `--purge` is refused for it.
+    fn delete_item(&self, target: &ItemRef<'_>, _permanent: bool) -> Result<(), Error> {
+        self.run(&["remove", target.id], None).map(drop)
+    }
+
+    /// Nothing is in the trash, as [`Self::list_items`] lists none for
+    /// `trash`.
+    fn restore_item(&self, target: &ItemRef<'_>) -> Result<(), Error> {
+        Err(format!("rbw has no trash to restore `{}` from", target.name).into())
+    }
+
+    fn sync(&self) -> Result<(), Error> {
+        self.run(&["sync"], None).map(drop)
+    }
+}
@@ -0,0 +1,107 @@
+//! Keeping secrets out of diagnostics.
+//!
+//! Tokens and session keys are registered here as soon as they are obtained,
+//! so that anything printed on the way out (panic messages in particular) can
+//! be scrubbed of them.
+
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+
+const REDACTED: &str = "<redacted>";
+
+/// Shorter values can't be real secrets, and replacing them would garble
+/// unrelated text.
+const MIN_SECRET_LEN: usize = 8;
+
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn secrets() -> MutexGuard<'static, Vec<String>> {
+    // A panic while holding the lock must not stop us from redacting.
+    SECRETS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Remember `secret` so that it gets redacted from now on.
+pub fn register(secret: &str) {
+    let secret = secret.trim();
+    if secret.len() < MIN_SECRET_LEN {
+        return;
+    }
+
+    let mut secrets = secrets();
+    if !secrets.iter().any(|s| s == secret) {
+        secrets.push(secret.to_string());
+        // Replace longer secrets first, in case one contains another.
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    }
+}
+
+/// Overwrite `secret` with zeros before freeing it, so that it doesn't
+/// linger in memory that is reused later.
+pub fn wipe(secret: impl Into<Vec<u8>>) {
+    let mut bytes = secret.into();
+    for byte in bytes.iter_mut() {
+        // SAFETY: `byte` is a valid, exclusive reference; the volatile write
+        // keeps the compiler from dropping the seemingly dead store.
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+}
+
+/// Replace every registered secret in `text`.
+pub fn redact(text: &str) -> String {
+    secrets().iter().fold(text.to_string(), |text, secret| {
+        text.replace(secret, REDACTED)
+    })
+}
+
+/// Print panics with registered secrets removed.
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        eprint!("{}", panic_report(info, &Backtrace::capture()));
+    }));
+}
+
+/// What the panic hook prints for the panic `info`.
+fn panic_report(info: &dyn fmt::Display, backtrace: &Backtrace) -> String {
+    let mut report = format!("{}\n", redact(&info.to_string()));
+    if backtrace.status() == BacktraceStatus::Captured {
+        report.push_str(&format!("{}\n", redact(&backtrace.to_string())));
+    }
+    report.push_str(&format!(
+        "note: cargo-credential-bitwarden crashed, please report this at {}/issues \
+         (known secrets have been redacted from the output above)\n",
+        env!("CARGO_PKG_REPOSITORY")
+    ));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_registered_secrets() {
+        register("  redact-test-secret  ");
+        register("redact-test-secret-but-longer");
+        register("short");
+        assert_eq!(
+            redact("a redact-test-secret-but-longer and a redact-test-secret, short"),
+            "a <redacted> and a <redacted>, short"
+        );
+    }
+
+    #[test]
+    fn panic_report_omits_secrets() {
+        register("cio-panic-test-token");
+        let message = std::panic::catch_unwind(|| {
+            let token = "cio-panic-test-token".to_string();
+            panic!("failed to store token {:?}", token);
+        })
+        .unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+        let report = panic_report(message, &Backtrace::disabled());
+        assert!(!report.contains("cio-panic-test-token"), "{}", report);
+        assert!(report.starts_with("failed to store token \"<redacted>\"\n"));
+        assert!(report.contains("known secrets have been redacted"));
+    }
+}
@@ -0,0 +1,48 @@
+//! Minting a new token when the stored one is old, for `--refresh-command`.
+//!
+//! The command is run by the shell with the registry in environment
+//! variables, and prints the new token. Its output is a secret: it is never
+//! logged, and wiped once the token is taken from it.
+
+use crate::{cancel, redact, shell_command, validate_token};
+use cargo_credential::RegistryInfo;
+use std::process::Stdio;
+
+/// Environment variable with the index URL of the registry.
+pub const INDEX_URL_ENV: &str = "CARGO_REGISTRY_INDEX_URL";
+
+/// Environment variable with the name of the registry, if cargo sent one.
+pub const NAME_ENV: &str = "CARGO_REGISTRY_NAME";
+
+/// A new token for `registry`, from `command`.
+pub fn mint(command: &str, registry: &RegistryInfo<'_>) -> Result<String, String> {
+    let mut cmd = shell_command(command);
+    cmd.env(INDEX_URL_ENV, registry.index_url);
+    match registry.name {
+        Some(name) => cmd.env(NAME_ENV, name),
+        None => cmd.env_remove(NAME_ENV),
+    };
+    // Its stderr is left alone, for the command to say what went wrong.
+    cmd.stdin(Stdio::null()).stdout(Stdio::piped());
+    let output = cancel::output(&mut cmd)
+        .map_err(|e| format!("failed to run `--refresh-command`: {}", e))?;
+    if !output.status.success() {
+        redact::wipe(output.stdout);
+        return Err(format!("`--refresh-command` failed: {}", output.status));
+    }
+    let printed = match String::from_utf8(output.stdout) {
+        Ok(printed) => printed,
+        Err(e) => {
+            redact::wipe(e.into_bytes());
+            return Err("`--refresh-command` printed a token that isn't UTF-8".to_string());
+        }
+    };
+    let token = validate_token(&printed)
+        .map(str::to_string)
+        .map_err(|e| format!("`--refresh-command` printed no usable token: {}", e));
+    redact::wipe(printed);
+    if let Ok(token) = &token {
+        redact::register(token);
+    }
+    token
+}
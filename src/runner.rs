@@ -0,0 +1,108 @@
+//! Running the commands of the Bitwarden CLI.
+//!
+//! Every `bw` command the provider waits for goes through a
+//! [`CommandRunner`], so that a program using [`crate::BitwardenVault`] can
+//! answer them itself, as a test would with canned output, instead of
+//! starting a real CLI through [`Process`].
+
+use crate::cancel;
+use std::io::{self, Write};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// Runs the commands [`crate::BitwardenVault`] builds.
+pub trait CommandRunner {
+    /// Run `cmd`, writing `input` to its stdin, and collect what it printed
+    /// to the streams piped on it. `Ok(None)` when it was still running
+    /// after `timeout`, and got killed.
+    ///
+    /// The program, arguments and environment of `cmd` tell which command
+    /// it is; the session key is in the `BW_SESSION` variable.
+    fn run(
+        &self,
+        cmd: &mut Command,
+        input: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Output>, String>;
+
+    /// Start `cmd` without waiting for it, for work that goes on after
+    /// cargo got its answer, like the `bw sync` of `--background-sync`.
+    fn start(&self, cmd: &mut Command) -> Result<Pending, String>;
+}
+
+/// Waits for a command [`CommandRunner::start`] started, and collects what
+/// it printed.
+pub type Pending = Box<dyn FnOnce() -> io::Result<Output> + Send>;
+
+/// Starts the commands as processes, the runner of the provider itself.
+///
+/// The processes are killed when cargo goes away, and on `--timeout`.
+pub struct Process;
+
+impl CommandRunner for Process {
+    fn run(
+        &self,
+        cmd: &mut Command,
+        input: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Output>, String> {
+        if input.is_some() {
+            cmd.stdin(Stdio::piped());
+        }
+        let mut child = cancel::spawn(cmd).map_err(|e| format!("failed to spawn `bw`: {}", e))?;
+        // Written from a thread of its own while the output is read, as `bw`
+        // may fill the pipe to us before it has read all of the input.
+        let writer = match (input, child.stdin.take()) {
+            (Some(input), Some(mut stdin)) => {
+                let input = input.to_vec();
+                // Dropping `stdin` at the end closes it, for `bw` to see the end.
+                Some(thread::spawn(move || stdin.write_all(&input)))
+            }
+            _ => None,
+        };
+        let output = match timeout {
+            Some(timeout) => child.wait_with_output_timeout(timeout),
+            None => child.wait_with_output().map(Some),
+        };
+        let written = writer.map_or(Ok(()), |writer| {
+            writer
+                .join()
+                .unwrap_or_else(|_| Err(io::ErrorKind::Other.into()))
+        });
+        let output = output.map_err(|e| format!("failed to wait for `bw`: {}", e))?;
+        match (written, &output) {
+            // A command that exits early reports why it failed itself.
+            (Err(e), Some(output)) if output.status.success() => {
+                Err(format!("failed to write to stdin: {}", e))
+            }
+            _ => Ok(output),
+        }
+    }
+
+    fn start(&self, cmd: &mut Command) -> Result<Pending, String> {
+        let child = cancel::spawn_background(cmd).map_err(|e| e.to_string())?;
+        Ok(Box::new(move || child.wait_with_output()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn large_input_and_output_at_once() {
+        // `cat` writes to its stdout while we still write to its stdin.
+        let input: Vec<u8> = (0..512 * 1024).map(|i| (i % 251) as u8).collect();
+        let mut cmd = Command::new("cat");
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let output = Process
+            .run(&mut cmd, Some(&input), Some(Duration::from_secs(60)))
+            .unwrap()
+            .expect("`cat` timed out");
+        assert!(output.status.success());
+        assert_eq!(output.stdout.len(), input.len());
+        assert!(output.stdout == input);
+    }
+}
@@ -0,0 +1,83 @@
+//! Token scopes of the `--scoped` mode.
+//!
+//! Each scope's token is kept separately in the item: the `all` token in the
+//! login password, the others in hidden custom fields. A scope can also have
+//! an item of its own, recognised by a name ending in `(read)` or
+//! `(publish)`, whose password is the token.
+
+use cargo_credential::Operation;
+use std::fmt;
+
+/// Operations a stored token is meant for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Read,
+    Publish,
+    All,
+}
+
+impl Scope {
+    pub fn parse(s: &str) -> Result<Scope, String> {
+        match s {
+            "read" => Ok(Scope::Read),
+            "publish" => Ok(Scope::Publish),
+            "all" => Ok(Scope::All),
+            _ => Err(format!(
+                "unknown scope `{}`, expected `read`, `publish` or `all`",
+                s
+            )),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Publish => "publish",
+            Scope::All => "all",
+        }
+    }
+
+    /// Name of the custom field holding this scope's token, or `None` for
+    /// the `all` scope, which uses the password.
+    pub fn field_name(self) -> Option<String> {
+        match self {
+            Scope::All => None,
+            scope => Some(format!("cargo-token-{}", scope.as_str())),
+        }
+    }
+
+    /// Scope of an item of its own, from the suffix of the item's `name`.
+    pub fn from_item_name(name: &str) -> Option<Scope> {
+        let name = name.trim_end().to_lowercase();
+        [Scope::Read, Scope::Publish]
+            .into_iter()
+            .find(|scope| name.ends_with(&format!("({})", scope.as_str())))
+    }
+
+    /// Narrowest scope needed for `operation`.
+    pub fn for_operation(operation: &Operation<'_>) -> Scope {
+        match operation {
+            Operation::Read => Scope::Read,
+            Operation::Publish { .. }
+            | Operation::Yank { .. }
+            | Operation::Unyank { .. }
+            | Operation::Owners { .. } => Scope::Publish,
+            _ => Scope::All,
+        }
+    }
+
+    /// Scopes whose tokens can be used for this scope, narrowest first.
+    pub fn covering(self) -> &'static [Scope] {
+        match self {
+            Scope::Read => &[Scope::Read, Scope::All],
+            Scope::Publish => &[Scope::Publish, Scope::All],
+            Scope::All => &[Scope::All],
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,481 @@
+//! The `bw serve` backend, for `--backend serve`.
+//!
+//! `bw serve` keeps the unlocked vault in memory and answers requests on a
+//! local REST API, which saves the second or two every CLI invocation takes
+//! to start and decrypt the vault. One server is started per process and
+//! session, and stopped by [`shutdown`] or when the provider is terminated.
+//!
+//! The API has no authentication: while it runs, other processes of the
+//! same machine can read the vault through it. It only listens on the
+//! loopback interface. `bw serve` can't listen on a port chosen by the
+//! system, so another process could take the port picked for it first and
+//! be sent the session's requests; the listener is therefore checked to be
+//! the server's before it is used.
+
+use crate::backend::Backend;
+use crate::cancel::{self, Guarded};
+use crate::{bw_error, BitwardenVault, CreateOutcome, ItemRef};
+use cargo_credential::Error;
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// How long `bw serve` may take to start listening.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a single request may take; syncing a large vault is the slowest.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+struct Running {
+    session: Option<String>,
+    port: u16,
+    /// Kills the server when dropped.
+    _child: Guarded,
+}
+
+static RUNNING: Mutex<Option<Running>> = Mutex::new(None);
+
+/// The REST API of the `bw serve` for `session`, started if necessary.
+pub struct Serve {
+    port: u16,
+    /// Refuse requests that change items, for `--read-only`.
+    read_only: bool,
+}
+
+/// Connect to the server for `session`, starting it on first use.
+pub fn connect(vault: &BitwardenVault, session: &Option<String>) -> Result<Serve, Error> {
+    let mut running = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(server) = running.as_ref().filter(|server| server.session == *session) {
+        return Ok(Serve {
+            port: server.port,
+            read_only: vault.options.read_only,
+        });
+    }
+    // A different session needs a server of its own.
+    *running = None;
+
+    // `bw serve` doesn't report the port it got for `--port 0`, so pick a
+    // free port ourselves.
+    let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .and_then(|listener| listener.local_addr())
+        .map_err(|e| format!("failed to find a free port for `bw serve`: {}", e))?
+        .port();
+    let port_arg = port.to_string();
+    let mut cmd = vault.make_cmd(
+        session,
+        &["serve", "--hostname", "127.0.0.1", "--port", &port_arg],
+    )?;
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    let mut child = cancel::spawn_background(&mut cmd)
+        .map_err(|e| format!("failed to run `bw serve`: {}", e))?;
+
+    let started = Instant::now();
+    while TcpStream::connect((Ipv4Addr::LOCALHOST, port)).is_err()
+        || !is_listening(&mut child, port)?
+    {
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(format!("`bw serve` exited before it was ready: {}", status).into());
+        }
+        if started.elapsed() > STARTUP_TIMEOUT {
+            return Err(format!(
+                "`bw serve` didn't start listening on port {} within {} seconds",
+                port,
+                STARTUP_TIMEOUT.as_secs()
+            )
+            .into());
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    *running = Some(Running {
+        session: session.clone(),
+        port,
+        _child: child,
+    });
+    Ok(Serve {
+        port,
+        read_only: vault.options.read_only,
+    })
+}
+
+/// Whether the process listening on `port` is the server `child`, rather
+/// than an earlier process that took the port it was meant to have. `false`
+/// while it's too soon to tell.
+fn is_listening(child: &mut Guarded, port: u16) -> Result<bool, Error> {
+    let Some(listeners) = listeners(port) else {
+        static WARNED: std::sync::Once = std::sync::Once::new();
+        WARNED.call_once(|| {
+            eprintln!(
+                "warning: can't check which process listens on port {} on this platform; \
+                 if it isn't `bw serve`, that process gets the requests of the session",
+                port
+            );
+        });
+        return Ok(true);
+    };
+    let ours = processes_under(child.id());
+    if listeners.iter().any(|pid| ours.contains(pid)) {
+        return Ok(true);
+    }
+    // The server hasn't bound yet, or failed to because the port is taken.
+    if let Ok(Some(_)) | Err(_) = child.try_wait() {
+        return Err(format!(
+            "another process took port {} before `bw serve` could listen on it; \
+             refusing to send it the session",
+            port
+        )
+        .into());
+    }
+    Ok(false)
+}
+
+/// `pid` and the processes below it, which `bw` may start the server in.
+fn processes_under(pid: u32) -> Vec<u32> {
+    let parents = process_parents();
+    let mut found = vec![pid];
+    let mut i = 0;
+    while let Some(&parent) = found.get(i) {
+        found.extend(
+            parents
+                .iter()
+                .filter(|&&(_, ppid)| ppid == parent)
+                .map(|&(pid, _)| pid),
+        );
+        i += 1;
+    }
+    found
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        /// The processes with a socket listening on `port` of the loopback
+        /// interface.
+        fn listeners(port: u16) -> Option<Vec<u32>> {
+            use std::fs;
+
+            let table = fs::read_to_string("/proc/net/tcp").ok()?;
+            // Addresses are hexadecimal in host byte order, states too; `0A`
+            // is listening.
+            let inodes: Vec<String> = table
+                .lines()
+                .skip(1)
+                .filter_map(|line| {
+                    let fields: Vec<&str> = line.split_whitespace().collect();
+                    let (address, listening) = fields.get(1)?.split_once(':')?;
+                    let loopback = matches!(address, "0100007F" | "7F000001" | "00000000");
+                    if !loopback
+                        || u16::from_str_radix(listening, 16).ok()? != port
+                        || *fields.get(3)? != "0A"
+                    {
+                        return None;
+                    }
+                    Some(format!("socket:[{}]", fields.get(9)?))
+                })
+                .collect();
+
+            let mut pids = Vec::new();
+            for entry in fs::read_dir("/proc").ok()?.flatten() {
+                let Some(pid) = entry.file_name().to_str().and_then(|pid| pid.parse().ok()) else {
+                    continue;
+                };
+                // Processes of other users can't be looked into, and aren't ours.
+                let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+                    continue;
+                };
+                let listens = fds.flatten().any(|fd| {
+                    fs::read_link(fd.path())
+                        .is_ok_and(|link| inodes.iter().any(|inode| link.as_os_str() == inode.as_str()))
+                });
+                if listens {
+                    pids.push(pid);
+                }
+            }
+            Some(pids)
+        }
+
+        /// Every process with its parent.
+        fn process_parents() -> Vec<(u32, u32)> {
+            let Ok(entries) = std::fs::read_dir("/proc") else {
+                return Vec::new();
+            };
+            entries
+                .flatten()
+                .filter_map(|entry| {
+                    let pid = entry.file_name().to_str()?.parse().ok()?;
+                    let stat = std::fs::read_to_string(entry.path().join("stat")).ok()?;
+                    // After the command name, which may hold spaces and parentheses.
+                    let rest = &stat[stat.rfind(')')? + 1..];
+                    let ppid = rest.split_whitespace().nth(1)?.parse().ok()?;
+                    Some((pid, ppid))
+                })
+                .collect()
+        }
+    } else if #[cfg(unix)] {
+        fn listeners(port: u16) -> Option<Vec<u32>> {
+            let output = std::process::Command::new("lsof")
+                .args(["-nP", "-t", "-sTCP:LISTEN"])
+                .arg(format!("-iTCP@127.0.0.1:{}", port))
+                .stderr(Stdio::null())
+                .output()
+                .ok()?;
+            // Exits with 1 when nothing listens.
+            let pids = String::from_utf8_lossy(&output.stdout);
+            Some(pids.lines().filter_map(|pid| pid.trim().parse().ok()).collect())
+        }
+
+        fn process_parents() -> Vec<(u32, u32)> {
+            let Ok(output) = std::process::Command::new("ps")
+                .args(["-A", "-o", "pid=", "-o", "ppid="])
+                .stderr(Stdio::null())
+                .output()
+            else {
+                return Vec::new();
+            };
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| {
+                    let mut fields = line.split_whitespace();
+                    Some((fields.next()?.parse().ok()?, fields.next()?.parse().ok()?))
+                })
+                .collect()
+        }
+    } else {
+        fn listeners(_port: u16) -> Option<Vec<u32>> {
+            None
+        }
+
+        fn process_parents() -> Vec<(u32, u32)> {
+            Vec::new()
+        }
+    }
+}
+
+/// Stop the server, if one was started.
+pub fn shutdown() {
+    RUNNING.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+/// Envelope of every `bw serve` response.
+#[derive(Deserialize)]
+struct Envelope {
+    success: bool,
+    message: Option<String>,
+    data: Option<Value>,
+}
+
+/// A failed request.
+struct Failure {
+    status: u16,
+    message: String,
+}
+
+impl Failure {
+    fn into_error(self, action: &str) -> Error {
+        format!("`bw serve` failed to {}: {}", action, self.message).into()
+    }
+}
+
+impl Serve {
+    fn url(&self, path: &str) -> Url {
+        let mut url = Url::parse("http://127.0.0.1/").unwrap();
+        let _ = url.set_port(Some(self.port));
+        url.set_path(path);
+        url
+    }
+
+    fn request(&self, method: &str, url: &Url, body: Option<&Value>) -> Result<Value, Failure> {
+        let failure = |message: String| Failure { status: 0, message };
+        if self.read_only && method != "GET" && url.path() != "/sync" {
+            return Err(failure(format!(
+                "refusing `{} {}`: the provider is configured with `--read-only`",
+                method,
+                url.path()
+            )));
+        }
+        let body = match body {
+            Some(body) => serde_json::to_vec(body).map_err(|e| failure(e.to_string()))?,
+            None => Vec::new(),
+        };
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, self.port))
+            .map_err(|e| failure(format!("failed to connect: {}", e)))?;
+        let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+        let head = format!(
+            "{} {} HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nConnection: close\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+            method,
+            path,
+            self.port,
+            body.len()
+        );
+        let mut response = Vec::new();
+        stream
+            .write_all(head.as_bytes())
+            .and_then(|()| stream.write_all(&body))
+            .and_then(|()| stream.read_to_end(&mut response))
+            .map_err(|e| failure(format!("request failed: {}", e)))?;
+
+        let (status, body) =
+            parse_response(&response).ok_or_else(|| failure("malformed response".to_string()))?;
+        match serde_json::from_slice::<Envelope>(&body) {
+            Ok(Envelope {
+                success: true,
+                data,
+                ..
+            }) => Ok(data.unwrap_or(Value::Null)),
+            Ok(Envelope { message, .. }) => Err(Failure {
+                status,
+                message: message.unwrap_or_else(|| format!("HTTP status {}", status)),
+            }),
+            Err(_) => Err(Failure {
+                status,
+                message: format!(
+                    "HTTP status {}: {}",
+                    status,
+                    String::from_utf8_lossy(&body).trim()
+                ),
+            }),
+        }
+    }
+}
+
+/// Split an HTTP/1.1 response into its status code and body.
+fn parse_response(response: &[u8]) -> Option<(u16, Vec<u8>)> {
+    let end = response.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&response[..end]).ok()?;
+    let body = &response[end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+    let chunked = lines.any(|line| {
+        let (name, value) = line.split_once(':').unwrap_or_default();
+        name.eq_ignore_ascii_case("transfer-encoding") && value.trim() == "chunked"
+    });
+    if !chunked {
+        return Some((status, body.to_vec()));
+    }
+
+    let mut decoded = Vec::new();
+    let mut rest = body;
+    loop {
+        let line_end = rest.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&rest[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            return Some((status, decoded));
+        }
+        decoded.extend_from_slice(rest.get(..size)?);
+        rest = rest.get(size + 2..)?;
+    }
+}
+
+impl Backend for Serve {
+    fn list_items(&self, filters: &[(&str, &str)]) -> Result<Vec<Value>, Error> {
+        let mut url = self.url("/list/object/items");
+        for (name, value) in filters {
+            url.query_pairs_mut().append_pair(name, value);
+        }
+        let data = self
+            .request("GET", &url, None)
+            .map_err(|e| e.into_error("list items"))?;
+        serde_json::from_value(data["data"].clone())
+            .map_err(|e| format!("failed to deserialize JSON from Bitwarden list: {}", e).into())
+    }
+
+    fn get_item(&self, id: &str) -> Result<Value, Error> {
+        let url = self.url(&format!("/object/item/{}", id));
+        match self.request("GET", &url, None) {
+            Ok(item) => Ok(item),
+            Err(Failure { status: 404, .. }) => Err(Error::NotFound),
+            Err(e) if bw_error::classify_message(&e.message) == bw_error::Kind::NotFound => {
+                Err(Error::NotFound)
+            }
+            Err(e) => Err(e.into_error("get the item")),
+        }
+    }
+
+    fn create_item(&self, item: &Value) -> Result<CreateOutcome, Error> {
+        match self.request("POST", &self.url("/object/item"), Some(item)) {
+            Ok(created) => Ok(CreateOutcome::Created(Some(created))),
+            Err(e) => {
+                crate::create_failed(bw_error::classify_message(&e.message), item, &e.message)
+            }
+        }
+    }
+
+    fn edit_item(&self, target: &ItemRef<'_>, item: &Value) -> Result<(), Error> {
+        let url = self.url(&format!("/object/item/{}", target.id));
+        self.request("PUT", &url, Some(item))
+            .map(drop)
+            .map_err(|e| write_error(target, e, "edit the item"))
+    }
+
+    /// The API only moves items to the trash, so permanent deletes go
+    /// through the CLI, see [`BitwardenVault::delete`].
+    fn delete_item(&self, target: &ItemRef<'_>, permanent: bool) -> Result<(), Error> {
+        if permanent {
+            return Err("`bw serve` can't delete items permanently".into());
+        }
+        let url = self.url(&format!("/object/item/{}", target.id));
+        self.request("DELETE", &url, None)
+            .map(drop)
+            .map_err(|e| write_error(target, e, "delete the item"))
+    }
+
+    fn restore_item(&self, target: &ItemRef<'_>) -> Result<(), Error> {
+        let url = self.url(&format!("/restore/item/{}", target.id));
+        self.request("POST", &url, None)
+            .map(drop)
+            .map_err(|e| write_error(target, e, "restore the item"))
+    }
+
+    fn sync(&self) -> Result<(), Error> {
+        self.request("POST", &self.url("/sync"), None)
+            .map(drop)
+            .map_err(|e| e.into_error("sync"))
+    }
+}
+
+fn write_error(target: &ItemRef<'_>, e: Failure, action: &str) -> Error {
+    if bw_error::classify_message(&e.message) == bw_error::Kind::Permission {
+        return crate::read_only_error(target);
+    }
+    e.into_error(action)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn listener_of_another_process() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let listeners = listeners(port).unwrap();
+        assert_eq!(listeners, [std::process::id()]);
+        assert!(processes_under(std::process::id()).contains(&std::process::id()));
+
+        let mut cmd = Command::new("sleep");
+        cmd.arg("30");
+        let mut child = cancel::spawn(&mut cmd).unwrap();
+        assert!(!is_listening(&mut child, port).unwrap());
+        child.kill().unwrap();
+        child.wait().unwrap();
+        let e = is_listening(&mut child, port).unwrap_err().to_string();
+        assert!(e.contains(&format!("took port {}", port)), "{}", e);
+    }
+}
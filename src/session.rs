@@ -0,0 +1,120 @@
+//! Reusing the Bitwarden session for the lifetime of the provider process.
+//!
+//! Cargo may keep the provider running for hours, by which time the server
+//! may have expired the session. Sessions older than `--session-max-age` are
+//! therefore checked again before they are used.
+
+use std::time::{Duration, Instant};
+
+/// Source of the current time, replaceable to test expiry.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// State of a cached session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cached {
+    Missing,
+    /// Young enough to be used as is.
+    Fresh(Option<String>),
+    /// Older than the maximum age; check it before use.
+    Stale(Option<String>),
+}
+
+struct Entry {
+    /// Provider arguments the session was obtained with.
+    key: String,
+    /// `None` when the session comes from `BW_SESSION`.
+    session: Option<String>,
+    checked_at: Instant,
+}
+
+/// The most recent session, together with when it was last known to work.
+#[derive(Default)]
+pub struct SessionCache<C = SystemClock> {
+    clock: C,
+    entry: Option<Entry>,
+}
+
+impl<C: Clock> SessionCache<C> {
+    /// The session obtained with provider arguments `key`. Without a
+    /// `max_age` sessions never go stale.
+    pub fn get(&self, key: &str, max_age: Option<Duration>) -> Cached {
+        let Some(entry) = self.entry.as_ref().filter(|entry| entry.key == key) else {
+            return Cached::Missing;
+        };
+        let age = self.clock.now().saturating_duration_since(entry.checked_at);
+        match max_age {
+            Some(max_age) if age > max_age => Cached::Stale(entry.session.clone()),
+            _ => Cached::Fresh(entry.session.clone()),
+        }
+    }
+
+    /// Remember a session that is known to work right now.
+    pub fn store(&mut self, key: &str, session: Option<String>) {
+        self.entry = Some(Entry {
+            key: key.to_string(),
+            session,
+            checked_at: self.clock.now(),
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.entry = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Clone)]
+    struct FakeClock(Rc<Cell<Instant>>);
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn sessions_go_stale_past_the_max_age() {
+        let clock = FakeClock(Rc::new(Cell::new(Instant::now())));
+        let advance = |by: Duration| clock.0.set(clock.0.get() + by);
+        let mut cache = SessionCache {
+            clock: clock.clone(),
+            entry: None,
+        };
+        let max_age = Some(Duration::from_secs(600));
+        let session = Some("s3ss10n".to_string());
+
+        assert_eq!(cache.get("args", max_age), Cached::Missing);
+        cache.store("args", session.clone());
+        assert_eq!(cache.get("other args", max_age), Cached::Missing);
+
+        advance(Duration::from_secs(599));
+        assert_eq!(cache.get("args", max_age), Cached::Fresh(session.clone()));
+        advance(Duration::from_secs(1));
+        assert_eq!(cache.get("args", max_age), Cached::Fresh(session.clone()));
+        advance(Duration::from_millis(1));
+        assert_eq!(cache.get("args", max_age), Cached::Stale(session.clone()));
+        assert_eq!(cache.get("args", None), Cached::Fresh(session.clone()));
+
+        // Checking it again starts over.
+        cache.store("args", session.clone());
+        assert_eq!(cache.get("args", max_age), Cached::Fresh(session));
+        cache.clear();
+        assert_eq!(cache.get("args", max_age), Cached::Missing);
+    }
+}
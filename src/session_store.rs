@@ -0,0 +1,233 @@
+//! Keeping the Bitwarden session between provider processes, for
+//! `--persist-session` and `--session-file`.
+//!
+//! `--persist-session` only stores sessions protected by the platform.
+//! `--session-file` is the explicit opt-in to a plaintext file that only the
+//! current user may read, for platforms without such protection.
+
+use cfg_if::cfg_if;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A place to keep the session key between processes.
+pub trait SessionStore {
+    /// The stored session, if it was obtained with provider arguments `key`.
+    fn load(&self, key: &str) -> Option<String>;
+
+    fn save(&self, key: &str, session: &str) -> io::Result<()>;
+
+    fn clear(&self) -> io::Result<()>;
+}
+
+/// The protected store available on this platform.
+pub fn default_store() -> Option<Box<dyn SessionStore>> {
+    cfg_if! {
+        if #[cfg(windows)] {
+            let dir = crate::state::state_dir()?;
+            Some(Box::new(dpapi::DpapiStore::new(&dir)))
+        } else {
+            None
+        }
+    }
+}
+
+/// The session in a file of its own, readable only by the current user.
+pub fn file_store(path: &Path) -> Box<dyn SessionStore> {
+    Box::new(FileStore {
+        path: path.to_path_buf(),
+    })
+}
+
+struct FileStore {
+    path: PathBuf,
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, key: &str) -> Option<String> {
+        let contents = std::fs::read_to_string(&self.path).ok()?;
+        let session = decode(&contents, key);
+        if session.is_none() {
+            // Corrupt, or for other arguments: start over.
+            let _ = self.clear();
+        }
+        session
+    }
+
+    fn save(&self, key: &str, session: &str) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        crate::cargo_config::write_private(&self.path, &encode(key, session), true)
+    }
+
+    fn clear(&self) -> io::Result<()> {
+        remove(&self.path)
+    }
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Serialized form of a stored session: the provider arguments, then the
+/// session key.
+fn encode(key: &str, session: &str) -> String {
+    format!("{}\n{}", key.replace('\0', " "), session)
+}
+
+fn decode(contents: &str, key: &str) -> Option<String> {
+    let (stored_key, session) = contents.split_once('\n')?;
+    let session = session.trim_end();
+    (stored_key == key.replace('\0', " ") && !session.is_empty()).then(|| session.to_string())
+}
+
+#[cfg(windows)]
+mod dpapi {
+    //! Sessions encrypted with DPAPI for the current user, in
+    //! `%LOCALAPPDATA%\cargo-credential-bitwarden\`.
+
+    use super::{decode, encode, remove, SessionStore};
+    use std::path::{Path, PathBuf};
+    use std::{fs, io, ptr, slice};
+    use windows_sys::Win32::Foundation::LocalFree;
+    use windows_sys::Win32::Security::Cryptography::{
+        CryptProtectData, CryptUnprotectData, CRYPTPROTECT_UI_FORBIDDEN, CRYPT_INTEGER_BLOB,
+    };
+
+    pub struct DpapiStore {
+        path: PathBuf,
+    }
+
+    impl DpapiStore {
+        pub fn new(dir: &Path) -> DpapiStore {
+            DpapiStore {
+                path: dir.join("session.dpapi"),
+            }
+        }
+    }
+
+    impl SessionStore for DpapiStore {
+        fn load(&self, key: &str) -> Option<String> {
+            let blob = fs::read(&self.path).ok()?;
+            let session = unprotect(&blob)
+                .ok()
+                .and_then(|data| String::from_utf8(data).ok())
+                .and_then(|contents| decode(&contents, key));
+            if session.is_none() {
+                // Corrupt, from another user, or for other arguments: start over.
+                let _ = self.clear();
+            }
+            session
+        }
+
+        fn save(&self, key: &str, session: &str) -> io::Result<()> {
+            let blob = protect(encode(key, session).as_bytes())?;
+            crate::state::write_atomic(&self.path, &blob)
+        }
+
+        fn clear(&self) -> io::Result<()> {
+            remove(&self.path)
+        }
+    }
+
+    fn protect(data: &[u8]) -> io::Result<Vec<u8>> {
+        crypt(data, |input, output| unsafe {
+            CryptProtectData(
+                input,
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+                CRYPTPROTECT_UI_FORBIDDEN,
+                output,
+            )
+        })
+    }
+
+    fn unprotect(data: &[u8]) -> io::Result<Vec<u8>> {
+        crypt(data, |input, output| unsafe {
+            CryptUnprotectData(
+                input,
+                ptr::null_mut(),
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+                CRYPTPROTECT_UI_FORBIDDEN,
+                output,
+            )
+        })
+    }
+
+    /// Run a DPAPI function on `data`, and take ownership of its output.
+    fn crypt(
+        data: &[u8],
+        f: impl FnOnce(*const CRYPT_INTEGER_BLOB, *mut CRYPT_INTEGER_BLOB) -> i32,
+    ) -> io::Result<Vec<u8>> {
+        let input = CRYPT_INTEGER_BLOB {
+            cbData: u32::try_from(data.len()).map_err(io::Error::other)?,
+            pbData: data.as_ptr() as *mut u8,
+        };
+        let mut output = CRYPT_INTEGER_BLOB {
+            cbData: 0,
+            pbData: ptr::null_mut(),
+        };
+        if f(&input, &mut output) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: on success DPAPI returns a buffer of `cbData` bytes, which
+        // we must release with `LocalFree`.
+        let result =
+            unsafe { slice::from_raw_parts(output.pbData, output.cbData as usize) }.to_vec();
+        unsafe { LocalFree(output.pbData as _) };
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake;
+
+    /// Save, load with the same and other arguments, and clear.
+    fn round_trip(store: &dyn SessionStore) {
+        store.save("--registry\0a", "s3ss10n").unwrap();
+        assert_eq!(store.load("--registry\0a").as_deref(), Some("s3ss10n"));
+        assert_eq!(store.load("--registry\0b"), None);
+        // Loading for other arguments started over.
+        assert_eq!(store.load("--registry\0a"), None);
+
+        store.save("", "again").unwrap();
+        store.clear().unwrap();
+        assert_eq!(store.load(""), None);
+        store.clear().unwrap();
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let dir = fake::temp_dir("session-file");
+        round_trip(&*file_store(&dir.join("nested").join("session")));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn dpapi_round_trip() {
+        let dir = fake::temp_dir("session-dpapi");
+        let store = dpapi::DpapiStore::new(&dir);
+        round_trip(&store);
+
+        store.save("key", "s3ss10n").unwrap();
+        let blob = std::fs::read(dir.join("session.dpapi")).unwrap();
+        assert!(!blob.windows(7).any(|w| w == b"s3ss10n"));
+
+        // Undecryptable blobs are discarded.
+        std::fs::write(dir.join("session.dpapi"), b"not dpapi").unwrap();
+        assert_eq!(store.load("key"), None);
+        assert!(!dir.join("session.dpapi").exists());
+        store.save("key", "new").unwrap();
+        assert_eq!(store.load("key").as_deref(), Some("new"));
+    }
+}
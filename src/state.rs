@@ -0,0 +1,214 @@
+//! Small pieces of state shared between credential-process invocations.
+
+use cfg_if::cfg_if;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Directory used for state that should survive between invocations.
+///
+/// Returns `None` when no suitable base directory can be determined, in which
+/// case callers should fall back to their stateless behavior.
+pub fn state_dir() -> Option<PathBuf> {
+    state_dir_with(&|name| std::env::var_os(name))
+}
+
+/// [`state_dir`] for the environment variables `var` gives.
+pub fn state_dir_with(var: &dyn Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let env_path = |name: &str| var(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+
+    cfg_if! {
+        if #[cfg(target_os = "windows")] {
+            let base = env_path("LOCALAPPDATA")?;
+        } else if #[cfg(target_os = "macos")] {
+            let base = env_path("HOME")?.join("Library").join("Application Support");
+        } else {
+            let base = env_path("XDG_STATE_HOME")
+                .or_else(|| env_path("HOME").map(|home| home.join(".local").join("state")))?;
+        }
+    }
+
+    Some(base.join("cargo-credential-bitwarden"))
+}
+
+/// Directory for what only saves work, which may be deleted at any time.
+///
+/// Returns `None` like [`state_dir`].
+pub fn cache_dir() -> Option<PathBuf> {
+    fn env_path(name: &str) -> Option<PathBuf> {
+        std::env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    }
+
+    cfg_if! {
+        if #[cfg(target_os = "windows")] {
+            let base = env_path("LOCALAPPDATA")?;
+        } else if #[cfg(target_os = "macos")] {
+            let base = env_path("HOME")?.join("Library").join("Caches");
+        } else {
+            let base = env_path("XDG_CACHE_HOME")
+                .or_else(|| env_path("HOME").map(|home| home.join(".cache")))?;
+        }
+    }
+
+    Some(base.join("cargo-credential-bitwarden"))
+}
+
+/// Timestamp of the last successful `bw sync`, stored in a file so that
+/// several provider processes started close together don't all sync.
+pub struct SyncStamp {
+    path: PathBuf,
+}
+
+impl SyncStamp {
+    pub fn new(dir: &Path) -> SyncStamp {
+        SyncStamp {
+            path: dir.join("last-sync"),
+        }
+    }
+
+    /// Whether the last recorded sync happened less than `max_age` ago.
+    ///
+    /// A missing or unreadable file, or a timestamp in the future (clock
+    /// skew), counts as stale so that we err on the side of syncing.
+    pub fn is_fresh(&self, max_age: Duration) -> bool {
+        self.is_fresh_at(max_age, SystemTime::now())
+    }
+
+    /// Like [`Self::is_fresh`], at the time `now`.
+    pub fn is_fresh_at(&self, max_age: Duration, now: SystemTime) -> bool {
+        let Ok(contents) = fs::read_to_string(&self.path) else {
+            return false;
+        };
+        let Ok(secs) = contents.trim().parse::<u64>() else {
+            return false;
+        };
+        let Ok(now) = now.duration_since(UNIX_EPOCH) else {
+            return false;
+        };
+
+        match now.checked_sub(Duration::from_secs(secs)) {
+            Some(age) => age < max_age,
+            None => false,
+        }
+    }
+
+    /// Record that a sync just finished.
+    pub fn touch(&self) -> io::Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(io::Error::other)?;
+
+        write_atomic(&self.path, now.as_secs().to_string().as_bytes())
+    }
+}
+
+/// Replace the file at `path` atomically, so that concurrent readers never
+/// see it partially written.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Unique per process and per call, so concurrent writers never share
+    // a temporary file.
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let tmp = path.with_extension(format!(
+        "tmp.{}.{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn fresh_after_touch() {
+        let dir = fake::temp_dir("sync-stamp-touch");
+        let stamp = SyncStamp::new(&dir);
+        assert!(!stamp.is_fresh(MINUTE));
+        stamp.touch().unwrap();
+        assert!(stamp.is_fresh(MINUTE));
+        assert!(!stamp.is_fresh(Duration::ZERO));
+    }
+
+    #[test]
+    fn stale_when_old_corrupt_or_in_the_future() {
+        let dir = fake::temp_dir("sync-stamp-stale");
+        let stamp = SyncStamp::new(&dir);
+        for contents in [
+            (now() - 120).to_string(),
+            (now() + 3600).to_string(),
+            "not a number".to_string(),
+            String::new(),
+        ] {
+            fs::write(dir.join("last-sync"), &contents).unwrap();
+            assert!(!stamp.is_fresh(MINUTE), "{:?}", contents);
+        }
+        fs::write(dir.join("last-sync"), format!("{}\n", now() - 10)).unwrap();
+        assert!(stamp.is_fresh(MINUTE));
+    }
+
+    #[test]
+    fn fresh_within_the_interval() {
+        let dir = fake::temp_dir("sync-stamp-interval");
+        let stamp = SyncStamp::new(&dir);
+        let synced = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        fs::write(dir.join("last-sync"), "1700000000").unwrap();
+        let second = Duration::from_secs(1);
+        assert!(stamp.is_fresh_at(MINUTE, synced));
+        assert!(stamp.is_fresh_at(MINUTE, synced + MINUTE - second));
+        assert!(!stamp.is_fresh_at(MINUTE, synced + MINUTE));
+        assert!(!stamp.is_fresh_at(MINUTE, synced + MINUTE + second));
+        assert!(!stamp.is_fresh_at(MINUTE, synced - second));
+    }
+
+    #[test]
+    fn concurrent_touches() {
+        let dir = fake::temp_dir("sync-stamp-concurrent");
+        SyncStamp::new(&dir).touch().unwrap();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let dir = dir.clone();
+                std::thread::spawn(move || {
+                    let stamp = SyncStamp::new(&dir);
+                    for _ in 0..50 {
+                        stamp.touch().unwrap();
+                        // Readers never see a partly written stamp.
+                        assert!(stamp.is_fresh(MINUTE));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let files: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1, "temporary files were left behind");
+    }
+}
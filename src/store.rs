@@ -0,0 +1,1127 @@
+//! Storing the token of `cargo login`: in the item that has one for the
+//! registry, one restored from the trash, one given with `--attach-to` or
+//! `--item-id`, or a new one.
+
+use crate::backend;
+use crate::matching::{self, normalize_index_url, MatchMode, Selection, Uri};
+use crate::scope::Scope;
+use crate::{
+    bw_error, command_failed, fingerprint, full_secret, is_note, json, keep_previous,
+    list_collections, provenance, read_only_error, remove_field, revised_at, session_of, set_field,
+    stored_secret, tty, uri_key, BatchSync, BitwardenVault, Collection, CreateOutcome, Field,
+    Folder, ItemRef, ItemType, ListItem, ListItemCreateRequest, LoginItem, EXPIRES_FIELD,
+    FINGERPRINT_FIELD, MAX_URIS, NOTE_URL_FIELD, SESSION_ENV,
+};
+use cargo_credential::{Error, RegistryInfo, Secret};
+use serde_json::Value;
+use std::process::{Command, Output, Stdio};
+
+impl BitwardenVault {
+    /// Replace the token of `item`. The item is patched as it is stored,
+    /// so that notes, custom fields, TOTP secrets and anything else this
+    /// provider doesn't know about are kept.
+    pub(crate) fn modify(
+        &self,
+        session: &Option<String>,
+        item: &ListItem,
+        token: Secret<&str>,
+        registry: &RegistryInfo<'_>,
+    ) -> Result<(), Error> {
+        let mut full = self.get_item(session, &item.id)?;
+        set_secret(&mut full, self.options.token_field.as_deref(), token)?;
+        self.record_expiry(&mut full);
+        self.set_username(&mut full);
+        self.record_provenance(item, &mut full, registry);
+        self.add_uri_if_by_name(item, &mut full, registry.index_url);
+        // Only keep the names of items we created in line with the registry;
+        // the user may have named the others. Without a registry name
+        // there's nothing better than the current name.
+        let has_name = registry.name.is_some_and(|name| !name.trim().is_empty());
+        if has_name && self.is_managed(item) {
+            full["name"] = self
+                .options
+                .name_template
+                .render(registry.index_url, registry.name)
+                .into();
+        }
+        self.edit(session, &full)
+    }
+
+    /// Record in a full item when its new token expires, from `--expires`.
+    /// Without it an earlier expiry is dropped, as it was that of the
+    /// token replaced.
+    fn record_expiry(&self, item: &mut Value) {
+        match self.options.expires.and_then(|expires| {
+            expires
+                .format(&time::format_description::well_known::Rfc3339)
+                .ok()
+        }) {
+            Some(expires) => set_field(item, EXPIRES_FIELD, &expires, 0), // text
+            None => remove_field(item, EXPIRES_FIELD),
+        }
+    }
+
+    /// The custom field recording when a new item's token expires.
+    fn expiry_field(&self) -> Option<Field> {
+        Some(Field {
+            name: EXPIRES_FIELD.to_string(),
+            value: Some(
+                self.options
+                    .expires?
+                    .format(&time::format_description::well_known::Rfc3339)
+                    .ok()?,
+            ),
+            r#type: 0, // text
+            linked_id: None,
+        })
+    }
+
+    /// Set the username of a full item to `--username` or the one split
+    /// off the token, if any; otherwise the item keeps the one it has.
+    fn set_username(&self, item: &mut Value) {
+        let Some(username) = self.new_username() else {
+            return;
+        };
+        if let Some(login) = item.get_mut("login").and_then(Value::as_object_mut) {
+            login.insert("username".into(), username.into());
+        }
+    }
+
+    /// Username for the item the token is stored on.
+    fn new_username(&self) -> Option<String> {
+        let split = self.split_username.borrow().clone();
+        split.or_else(|| self.options.username.clone())
+    }
+
+    /// Update the provenance block in the notes of `full`, the full `item`,
+    /// unless `--no-notes` is given. The notes of items the user made are
+    /// theirs, so they are only updated if they have a block already.
+    fn record_provenance(&self, item: &ListItem, full: &mut Value, registry: &RegistryInfo<'_>) {
+        if self.options.no_notes
+            || !(self.is_managed(item) || provenance::has_block(full["notes"].as_str()))
+        {
+            return;
+        }
+        let block = provenance::block(registry.index_url, registry.name);
+        let notes = provenance::update(full["notes"].as_str(), &block);
+        if let Some(full) = full.as_object_mut() {
+            full.insert("notes".into(), notes.into());
+        }
+    }
+
+    /// Replace the stored item with `item`, matched by its id.
+    pub(crate) fn edit(&self, session: &Option<String>, item: &Value) -> Result<(), Error> {
+        let mut item = item.clone();
+        dedup_uris(&mut item);
+        if self.options.fingerprint {
+            update_fingerprint(&mut item, self.options.token_field.as_deref());
+        }
+        let item = &item;
+
+        let target = ItemRef {
+            id: item["id"].as_str().ok_or("Bitwarden item has no id")?,
+            name: item["name"].as_str().unwrap_or("<unnamed>"),
+            organization_id: item["organizationId"].as_str(),
+        };
+
+        self.backend(session)?.edit_item(&target, item)?;
+        self.sync_after_write(session)
+    }
+
+    /// Run a command that modifies `target`, explaining permission problems.
+    ///
+    /// `input` is written to its stdin.
+    pub(crate) fn run_write_cmd(
+        &self,
+        mut cmd: Command,
+        target: &ItemRef<'_>,
+        input: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        loop {
+            let Output {
+                status,
+                stdout,
+                stderr,
+            } = self.output_with_input(&mut cmd, input)?;
+
+            if status.success() {
+                return Ok(());
+            }
+
+            let stdout = String::from_utf8_lossy(&stdout);
+            let stderr = String::from_utf8_lossy(&stderr);
+            let signals = bw_error::Signals {
+                version: self.version.get(),
+                stdout: &stdout,
+                stderr: &stderr,
+                ..Default::default()
+            };
+            if bw_error::classify(&signals) == bw_error::Kind::Permission {
+                return Err(read_only_error(target));
+            }
+            let kind = bw_error::classify(&signals);
+            if self.renewed_after_write(&session_of(&cmd), kind)? {
+                if let Some(renewed) = &*self.renewed_session.borrow() {
+                    cmd.env(SESSION_ENV, renewed);
+                }
+                continue;
+            }
+
+            return Err(command_failed(&cmd, status, stderr.as_bytes()));
+        }
+    }
+
+    /// Fetch the full JSON of item `id`.
+    ///
+    /// Fails with [`Error::NotFound`] if there is no such item.
+    pub(crate) fn get_item(&self, session: &Option<String>, id: &str) -> Result<Value, Error> {
+        self.backend(session)?.get_item(id)
+    }
+
+    /// Find the item given with `--attach-to`, by id or by exact name.
+    ///
+    /// The item is returned as raw JSON so that fields this provider doesn't
+    /// know about survive the edit.
+    pub(crate) fn resolve_item(
+        &self,
+        session: &Option<String>,
+        name_or_id: &str,
+    ) -> Result<Value, Error> {
+        if is_item_id(name_or_id) {
+            return self.get_item(session, name_or_id);
+        }
+
+        let items = self
+            .backend(session)?
+            .list_items(&[("search", name_or_id)])?;
+        let mut items: Vec<Value> = items
+            .into_iter()
+            .filter(|item| item["name"].as_str() == Some(name_or_id))
+            .collect();
+
+        match items.len() {
+            0 => Err(format!("no Bitwarden item named `{}` found", name_or_id).into()),
+            1 => Ok(items.remove(0)),
+            _ => {
+                let candidates = items
+                    .iter()
+                    .map(|item| format!("\n  {}", item["id"].as_str().unwrap_or("<no id>")))
+                    .collect::<String>();
+                Err(format!(
+                    "multiple Bitwarden items are named `{}`, pass one of these ids instead:{}",
+                    name_or_id, candidates
+                )
+                .into())
+            }
+        }
+    }
+
+    /// Store the token on an existing item, and with `append_uri` add the
+    /// registry to its URIs.
+    fn attach(
+        &self,
+        session: &Option<String>,
+        item: &Value,
+        index_url: &str,
+        token: Secret<&str>,
+        append_uri: bool,
+    ) -> Result<(), Error> {
+        let mut item = item.clone();
+        let id = item["id"].as_str().unwrap_or("<no id>").to_string();
+        set_secret(
+            &mut item,
+            self.secret_field(self.options.scope.unwrap_or(Scope::All))
+                .as_deref(),
+            token,
+        )?;
+        self.record_expiry(&mut item);
+        // Attached items keep their username, but for one split off the
+        // token, which the token is no good without.
+        if self.options.split_token.is_some() {
+            self.set_username(&mut item);
+        }
+        let login = item
+            .get_mut("login")
+            .and_then(Value::as_object_mut)
+            .ok_or_else(|| format!("Bitwarden item `{}` is not a login item", id))?;
+        if !append_uri {
+            return self.edit(session, &item);
+        }
+
+        self.add_uri(login, index_url);
+        self.edit(session, &item)
+    }
+
+    /// Add `index_url` to the URIs of `login`, unless it has it already.
+    fn add_uri(&self, login: &mut serde_json::Map<String, Value>, index_url: &str) {
+        let uris = login
+            .entry("uris")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if !uris.is_array() {
+            *uris = Value::Array(Vec::new());
+        }
+        let uris = uris.as_array_mut().unwrap();
+        if !uris
+            .iter()
+            .any(|uri| uri["uri"].as_str().map(uri_key) == Some(uri_key(index_url)))
+        {
+            uris.push(serde_json::json!({
+                "uri": uri_key(index_url),
+                "match": self.uri_match_for(index_url).to_bitwarden(),
+            }));
+        }
+    }
+
+    /// Match detection of the URI this provider adds for `index_url`:
+    /// `--create-uri-match`, but exact for registries on disk, which have
+    /// no host to match by.
+    fn uri_match_for(&self, index_url: &str) -> MatchMode {
+        match normalize_index_url(index_url).is_local() {
+            true => MatchMode::Exact,
+            false => self.options.create_uri_match,
+        }
+    }
+
+    /// Give an item found with `--match-by-name` the URL of `index_url`,
+    /// so that later lookups find it by its URIs.
+    fn add_uri_if_by_name(&self, item: &ListItem, full: &mut Value, index_url: &str) {
+        // rbw can't change the URIs of an entry.
+        if !item.by_name || self.options.backend.is_other_client() {
+            return;
+        }
+        if let Some(login) = full.get_mut("login").and_then(Value::as_object_mut) {
+            self.add_uri(login, index_url);
+            eprintln!(
+                "note: added `{}` to the URIs of item `{}`, which was found by its name",
+                uri_key(index_url),
+                item.name
+            );
+        }
+    }
+
+    fn create(
+        &self,
+        session: &Option<String>,
+        index_url: &str,
+        token: Secret<&str>,
+        name: &Option<&str>,
+    ) -> Result<(), Error> {
+        let notes = (!self.options.no_notes).then(|| provenance::block(index_url, *name));
+        let name = self.options.name_template.render(index_url, *name);
+        if self.options.item_type == ItemType::Note {
+            return self.create_note(session, index_url, token, name);
+        }
+        // With `--field` the password is left for the user.
+        let (password, mut fields) = match &self.options.token_field {
+            Some(field) => (
+                String::new(),
+                vec![Field {
+                    name: field.clone(),
+                    value: Some(token.expose().to_string()),
+                    r#type: 1, // hidden
+                    linked_id: None,
+                }],
+            ),
+            None => (token.expose().to_string(), Vec::new()),
+        };
+        fields.extend(self.expiry_field());
+        let request = ListItemCreateRequest {
+            name,
+            r#type: 1, // login type
+            secure_note: None,
+            login: Some(LoginItem {
+                password,
+                username: self.new_username(),
+                // Normalized, so that later lookups match however cargo
+                // spells the URL.
+                uris: Vec::from(&[Uri {
+                    uri: uri_key(index_url),
+                    r#match: self.uri_match_for(index_url).to_bitwarden(),
+                }]),
+            }),
+            organization_id: None,
+            collection_ids: Vec::new(),
+            folder_id: self.folder_id(session)?,
+            fields,
+            notes,
+        };
+        self.create_request(session, request)
+    }
+
+    /// Create a secure note holding `token`, for `--item-type note`.
+    fn create_note(
+        &self,
+        session: &Option<String>,
+        index_url: &str,
+        token: Secret<&str>,
+        name: String,
+    ) -> Result<(), Error> {
+        let request = ListItemCreateRequest {
+            name,
+            r#type: 2, // secure note type
+            login: None,
+            secure_note: Some(serde_json::json!({ "type": 0 })), // generic note
+            organization_id: None,
+            collection_ids: Vec::new(),
+            folder_id: self.folder_id(session)?,
+            fields: [Field {
+                name: NOTE_URL_FIELD.to_string(),
+                value: Some(uri_key(index_url)),
+                r#type: 0, // text
+                linked_id: None,
+            }]
+            .into_iter()
+            .chain(self.expiry_field())
+            .collect(),
+            notes: Some(token.expose().to_string()),
+        };
+        self.create_request(session, request)
+    }
+
+    /// Create the item of `request`, in the organization's collections if
+    /// given or required.
+    fn create_request(
+        &self,
+        session: &Option<String>,
+        mut request: ListItemCreateRequest,
+    ) -> Result<(), Error> {
+        // Shared tokens go straight into the organization's collections.
+        let shared =
+            !self.options.collection_ids.is_empty() || !self.options.collection_names.is_empty();
+        if let (Some(organization_id), true) = (&self.options.organization_id, shared) {
+            request.organization_id = Some(organization_id.clone());
+            request.collection_ids = self.collection_ids(session)?;
+        }
+
+        let created = match self.create_item(session, &request)? {
+            CreateOutcome::Created(created) => created,
+            CreateOutcome::PersonalOwnershipDisabled if request.organization_id.is_some() => {
+                return Err("bw refused to create the item in the organization".into())
+            }
+            CreateOutcome::PersonalOwnershipDisabled => {
+                let Some(organization_id) = &self.options.organization_id else {
+                    return Err("an organization policy forbids creating items in your individual vault; \
+                         pass `--organization-id` (and `--collection-id`) to create the token in an organization"
+                        .into());
+                };
+
+                request.organization_id = Some(organization_id.clone());
+                request.collection_ids = self.collection_ids(session)?;
+                match self.create_item(session, &request)? {
+                    CreateOutcome::Created(created) => created,
+                    CreateOutcome::PersonalOwnershipDisabled => {
+                        return Err("bw refused to create the item in the organization".into())
+                    }
+                }
+            }
+        };
+
+        // Older CLIs create the item in the individual vault regardless, and
+        // only `bw share` moves it into the organization.
+        if let (Some(organization_id), Some(created)) = (&request.organization_id, &created) {
+            if created["organizationId"].is_null() {
+                if let Some(id) = created["id"].as_str() {
+                    let item = ItemRef {
+                        id,
+                        name: &request.name,
+                        organization_id: Some(organization_id),
+                    };
+                    self.share(session, &item)?;
+                }
+            }
+        }
+
+        self.sync_after_write(session)
+    }
+
+    /// Ids of the collections given with `--collection-id` and `--collection`.
+    ///
+    /// Collection names are resolved once per process.
+    fn collection_ids(&self, session: &Option<String>) -> Result<Vec<String>, Error> {
+        if let Some(ids) = self.resolved_collection_ids.get() {
+            return Ok(ids.clone());
+        }
+
+        let mut ids = self.options.collection_ids.clone();
+        if let (Some(organization_id), false) = (
+            &self.options.organization_id,
+            self.options.collection_names.is_empty(),
+        ) {
+            let cmd = self.make_cmd(
+                session,
+                &[
+                    "list",
+                    "org-collections",
+                    "--organizationid",
+                    organization_id,
+                ],
+            )?;
+            let buffer = self.run_cmd(cmd)?;
+            let collections: Vec<Collection> = json::parse_output(&buffer).map_err(|e| {
+                format!(
+                    "failed to deserialize JSON from Bitwarden collections: {}",
+                    e
+                )
+            })?;
+
+            for name in &self.options.collection_names {
+                ids.push(resolve_collection(&collections, name)?.id.clone());
+            }
+        }
+
+        Ok(self.resolved_collection_ids.get_or_init(|| ids).clone())
+    }
+
+    /// Id of the folder given with `--folder` for new items, creating the
+    /// folder unless `--no-create-folder` is given.
+    fn folder_id(&self, session: &Option<String>) -> Result<Option<String>, Error> {
+        let Some(folder) = &self.options.folder else {
+            return Ok(None);
+        };
+        if let Some(id) = self.find_folder(session)? {
+            return Ok(Some(id));
+        }
+        if self.options.no_create_folder || is_object_id(folder) {
+            return Err(self.missing_folder());
+        }
+        let id = self.create_folder(session, folder)?;
+        Ok(Some(self.resolved_folder_id.get_or_init(|| id).clone()))
+    }
+
+    /// Id of the folder given with `--folder`, resolved once per process;
+    /// `None` if there is no such folder.
+    pub(crate) fn find_folder(&self, session: &Option<String>) -> Result<Option<String>, Error> {
+        let Some(folder) = &self.options.folder else {
+            return Ok(None);
+        };
+        if let Some(id) = self.resolved_folder_id.get() {
+            return Ok(Some(id.clone()));
+        }
+
+        let cmd = self.make_cmd(session, &["list", "folders"])?;
+        let buffer = self.run_cmd(cmd)?;
+        let folders: Vec<Folder> = json::parse_output(&buffer)
+            .map_err(|e| format!("failed to deserialize JSON from Bitwarden folders: {}", e))?;
+        let id = folders
+            .into_iter()
+            .filter_map(|f| Some((f.id?, f.name)))
+            .find(|(id, name)| id == folder || name == folder)
+            .map(|(id, _)| id);
+        Ok(id.map(|id| self.resolved_folder_id.get_or_init(|| id).clone()))
+    }
+
+    /// Create the folder `name`, encoded like items are. Another login may
+    /// create it at the same time, which is just as good.
+    fn create_folder(&self, session: &Option<String>, name: &str) -> Result<String, Error> {
+        let data = serde_json::to_vec(&serde_json::json!({ "name": name }))
+            .map_err(|e| format!("failed to serialize new folder: {}", e))?;
+        let encoded = self.encode(session, &data)?;
+        let mut cmd = self.make_cmd(session, &["create", "folder"])?;
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+        let output = self.output_with_input(&mut cmd, Some(encoded.as_bytes()))?;
+        if !output.status.success() {
+            if let Some(id) = self.find_folder(session)? {
+                return Ok(id);
+            }
+            return Err(command_failed(&cmd, output.status, &output.stderr));
+        }
+        let created: Folder = json::parse_output(&String::from_utf8_lossy(&output.stdout))
+            .map_err(|e| format!("failed to deserialize JSON from Bitwarden folder: {}", e))?;
+        let id = created
+            .id
+            .ok_or("`bw create folder` printed no folder id")?;
+        eprintln!("note: created Bitwarden folder `{}`", name);
+        self.sync_after_write(session)?;
+        Ok(id)
+    }
+
+    pub(crate) fn missing_folder(&self) -> Error {
+        format!(
+            "no Bitwarden folder named `{}` found; create it with `bw create folder` \
+             or in the Bitwarden app",
+            self.options.folder.as_deref().unwrap_or_default()
+        )
+        .into()
+    }
+
+    /// Store a token for `registry`, updating the matching item if there is
+    /// one. `read_token` is only called once we know where the token goes.
+    pub(crate) fn store(
+        &self,
+        session: &Option<String>,
+        registry: &RegistryInfo<'_>,
+        read_token: impl FnOnce() -> Result<Secret<String>, Error>,
+    ) -> Result<(), Error> {
+        let mut digest = None;
+        self.store_token(session, registry, || {
+            let token = self.split_token(self.unformat_token(read_token()?))?;
+            if self.options.check_reuse {
+                // Only keep a hash around for the comparison.
+                digest = Some(fingerprint::digest(token.as_deref().expose()));
+            }
+            Ok(token)
+        })?;
+
+        if let Some(digest) = digest {
+            self.warn_reused(session, registry.index_url, &digest);
+        }
+        Ok(())
+    }
+
+    /// Warn when another registry's item holds the token that was just
+    /// stored for `index_url`, which has the SHA-256 `digest`.
+    fn warn_reused(&self, session: &Option<String>, index_url: &str, digest: &[u8; 32]) {
+        match self.reused_by(session, index_url, digest) {
+            Ok(reused) if reused.is_empty() => {}
+            Ok(reused) => eprintln!(
+                "warning: the token stored for `{}` is also stored for {}; \
+                 check that the right token was pasted",
+                index_url,
+                reused
+                    .iter()
+                    .map(|registry| format!("`{}`", registry))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Err(e) => eprintln!("warning: could not check for reused tokens: {}", e),
+        }
+    }
+
+    /// The registries other than `index_url` whose items hold a token with
+    /// the SHA-256 `digest`.
+    pub(crate) fn reused_by(
+        &self,
+        session: &Option<String>,
+        index_url: &str,
+        digest: &[u8; 32],
+    ) -> Result<Vec<String>, Error> {
+        Ok(self
+            .managed_items(session)?
+            .iter()
+            .filter(|item| !item.has_uri(index_url))
+            .filter(|item| {
+                [Scope::All, Scope::Read, Scope::Publish]
+                    .iter()
+                    .any(|&scope| {
+                        stored_secret(item, self.secret_field(scope).as_deref())
+                            .is_some_and(|secret| fingerprint::digest(&secret) == *digest)
+                    })
+            })
+            .map(|item| {
+                self.options
+                    .name_template
+                    .registry(&item.name)
+                    .unwrap_or(&item.name)
+                    .to_string()
+            })
+            .collect())
+    }
+
+    fn store_token(
+        &self,
+        session: &Option<String>,
+        registry: &RegistryInfo<'_>,
+        read_token: impl FnOnce() -> Result<Secret<String>, Error>,
+    ) -> Result<(), Error> {
+        // Use the explicitly chosen item, or check if an item already exists.
+        // A missing item is worth more than cargo's "not found" here.
+        let resolve = |name_or_id: &str| {
+            self.resolve_item(session, name_or_id).map_err(|e| match e {
+                Error::NotFound => {
+                    format!("no Bitwarden item with id `{}` found", name_or_id).into()
+                }
+                e => e,
+            })
+        };
+        if let Some(attach_to) = &self.options.attach_to {
+            let item = resolve(attach_to)?;
+            let token = read_token()?;
+            self.attach(session, &item, registry.index_url, token.as_deref(), true)
+        } else if let Some(item_id) = &self.options.item_id {
+            let item = resolve(item_id)?;
+            let token = read_token()?;
+            let append_uri = self.options.append_uri;
+            self.attach(
+                session,
+                &item,
+                registry.index_url,
+                token.as_deref(),
+                append_uri,
+            )
+        } else if let Some(item) = self.search(session, registry.index_url)? {
+            self.confirm_overwrite(&item.name, item.revision_date.as_deref(), registry)?;
+            let token = read_token()?;
+            // The password of an item of its own is its scope's token.
+            let scope = match Scope::from_item_name(&item.name) {
+                Some(_) => Scope::All,
+                None => self.options.scope.unwrap_or(Scope::All),
+            };
+            if self.options.keep_previous || scope != Scope::All {
+                let mut full = self.get_item(session, &item.id)?;
+                if self.options.keep_previous {
+                    keep_previous(&mut full, self.secret_field(scope).as_deref());
+                }
+                set_secret(
+                    &mut full,
+                    self.secret_field(scope).as_deref(),
+                    token.as_deref(),
+                )?;
+                self.record_expiry(&mut full);
+                self.set_username(&mut full);
+                self.record_provenance(&item, &mut full, registry);
+                self.add_uri_if_by_name(&item, &mut full, registry.index_url);
+                self.edit(session, &full)?;
+            } else {
+                self.modify(session, &item, token.as_deref(), registry)?;
+            }
+            self.migrate_to_org(session, &item);
+            Ok(())
+        } else if let Some(item) = self.trashed(session, registry.index_url)? {
+            let token = read_token()?;
+            let target = ItemRef {
+                id: &item.id,
+                name: &item.name,
+                organization_id: item.organization_id.as_deref(),
+            };
+            self.backend(session)?.restore_item(&target)?;
+            eprintln!(
+                "note: restored `{}` for `{}` from the Bitwarden trash, storing the token \
+                 there; pass `--no-restore` to create a new item instead",
+                item.name, registry.index_url
+            );
+            self.modify(session, &item, token.as_deref(), registry)
+        } else if self.options.no_create {
+            Err(format!(
+                "no Bitwarden item matches registry `{}` and `--no-create` forbids creating one; \
+                 the item is expected to be provisioned in the vault already",
+                registry.index_url
+            )
+            .into())
+        } else if let Some(scope @ (Scope::Read | Scope::Publish)) = self.options.scope {
+            // Create the item without an `all` token, then add the scoped one.
+            let token = read_token()?;
+            self.create(
+                session,
+                registry.index_url,
+                Secret::from(""),
+                &registry.name,
+            )?;
+            let item = self
+                .search(session, registry.index_url)?
+                .ok_or("the created Bitwarden item could not be found")?;
+            let mut full = self.get_item(session, &item.id)?;
+            set_secret(&mut full, scope.field_name().as_deref(), token.as_deref())?;
+            self.edit(session, &full)
+        } else {
+            let token = read_token()?;
+            if let Some(item) = self.created_meanwhile(session, registry.index_url)? {
+                eprintln!(
+                    "note: `{}` was created for `{}` while the token was read, probably by \
+                     another `cargo login`; storing the token there",
+                    item.name, registry.index_url
+                );
+                return self.modify(session, &item, token.as_deref(), registry);
+            }
+            self.create(
+                session,
+                registry.index_url,
+                token.as_deref(),
+                &registry.name,
+            )?;
+            self.settle_duplicates(session, registry.index_url)
+        }
+    }
+
+    /// The item for `index_url` in the trash, which `cargo logout` put there,
+    /// to restore instead of creating another one, unless `--no-restore`
+    /// is given. The most recently changed of several wins. With a scope
+    /// of its own the token goes elsewhere than the item's password, so
+    /// a new item is created then.
+    fn trashed(
+        &self,
+        session: &Option<String>,
+        index_url: &str,
+    ) -> Result<Option<ListItem>, Error> {
+        if self.options.no_restore || !matches!(self.options.scope, None | Some(Scope::All)) {
+            return Ok(None);
+        }
+        let items = self.list_matches_in(session, index_url, true)?;
+        let url = normalize_index_url(index_url);
+        Ok(
+            match matching::select(&url, items, |item| &item.login.uris, self.options.uri_match) {
+                Selection::One(item) => Some(item),
+                Selection::None => None,
+                Selection::Ambiguous(items) => items.into_iter().max_by_key(revised_at),
+            },
+        )
+    }
+
+    /// The item for `index_url` that another `cargo login` may have created
+    /// since it was searched for, as the server has it now.
+    fn created_meanwhile(
+        &self,
+        session: &Option<String>,
+        index_url: &str,
+    ) -> Result<Option<ListItem>, Error> {
+        self.sync(session)?;
+        self.search(session, index_url)
+    }
+
+    /// Settle the items for `index_url` that a `cargo login` elsewhere
+    /// created at the same time as this one. Those with the same token are
+    /// moved to the trash but for the one with the lowest id, so that every
+    /// machine keeps the same one; those with different tokens are kept and
+    /// pointed out.
+    fn settle_duplicates(&self, session: &Option<String>, index_url: &str) -> Result<(), Error> {
+        // In a batch the sync after the write is still to come; this one
+        // takes its place, or leaves it to report the failure.
+        if self.batch.get().is_some() {
+            if self.sync(session).is_err() {
+                return Ok(());
+            }
+            self.batch.set(Some(BatchSync::Synced));
+        }
+        let url = normalize_index_url(index_url);
+        let items = self.list_matches(session, index_url)?;
+        let Selection::Ambiguous(mut items) =
+            matching::select(&url, items, |item| &item.login.uris, self.options.uri_match)
+        else {
+            return Ok(());
+        };
+        items.retain(|item| self.is_managed(item));
+        if items.len() < 2 {
+            return Ok(());
+        }
+        items.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let field = self.options.token_field.as_deref();
+        let kept = stored_secret(&items[0], field);
+        if items[1..]
+            .iter()
+            .any(|item| stored_secret(item, field) != kept)
+        {
+            let ids: Vec<&str> = items.iter().map(|item| item.id.as_str()).collect();
+            eprintln!(
+                "warning: {} Bitwarden logins with different tokens now match registry `{}`, \
+                 probably from another `cargo login` at the same time: {}; delete the wrong \
+                 ones with `bw delete item <id>`",
+                ids.len(),
+                index_url,
+                ids.join(", ")
+            );
+            return Ok(());
+        }
+        for item in &items[1..] {
+            let target = ItemRef {
+                id: &item.id,
+                name: &item.name,
+                organization_id: item.organization_id.as_deref(),
+            };
+            self.backend(session)?.delete_item(&target, false)?;
+            eprintln!(
+                "note: another `cargo login` stored the same token for `{}` at the same time; \
+                 moved its duplicate item {} to the Bitwarden trash, keeping {}",
+                index_url, item.id, items[0].id
+            );
+        }
+        self.sync_after_write(session)
+    }
+
+    /// Make sure replacing the token in the existing item `name`, last
+    /// changed at `revised`, is meant: with `--force`, or else by asking on
+    /// the terminal.
+    pub(crate) fn confirm_overwrite(
+        &self,
+        name: &str,
+        revised: Option<&str>,
+        registry: &RegistryInfo<'_>,
+    ) -> Result<(), Error> {
+        if self.options.force {
+            eprintln!("note: token already exists for `{}`", registry.index_url);
+            return Ok(());
+        }
+        let revised = match revised {
+            Some(date) => format!(" (last changed {})", date),
+            None => String::new(),
+        };
+        eprintln!(
+            "note: `{}` already holds a token for `{}`{}",
+            name, registry.index_url, revised
+        );
+        match tty::confirm("Replace it?") {
+            Some(true) => Ok(()),
+            Some(false) => Err(format!("kept the token in `{}`", name).into()),
+            None => Err(format!(
+                "a token for `{}` already exists in `{}`; pass `--force` to replace it \
+                 without being asked",
+                registry.index_url, name
+            )
+            .into()),
+        }
+    }
+
+    /// Offer to move a personal item into the configured organization.
+    ///
+    /// Runs after the token was already updated, so failures are reported but
+    /// don't fail the login.
+    fn migrate_to_org(&self, session: &Option<String>, item: &ListItem) {
+        let Some(organization_id) = &self.options.organization_id else {
+            return;
+        };
+        if item.organization_id.is_some() {
+            return;
+        }
+
+        let question = format!(
+            "Move item `{}` into organization {}?",
+            item.name, organization_id
+        );
+        if !self.options.migrate_to_org && tty::confirm(&question) != Some(true) {
+            eprintln!(
+                "note: item `{}` stays in your individual vault; pass `--migrate-to-org` to move it",
+                item.name
+            );
+            return;
+        }
+
+        let target = ItemRef {
+            id: &item.id,
+            name: &item.name,
+            organization_id: Some(organization_id),
+        };
+        let result = self
+            .share(session, &target)
+            .and_then(|()| self.sync_after_write(session));
+
+        match result {
+            Ok(()) => eprintln!(
+                "note: moved item `{}` into organization {}",
+                item.name, organization_id
+            ),
+            Err(e) => eprintln!(
+                "warning: the token was updated, but moving item `{}` into organization {} failed: {}",
+                item.name, organization_id, e
+            ),
+        }
+    }
+
+    /// Move `item` into its organization and the configured collections.
+    fn share(&self, session: &Option<String>, item: &ItemRef<'_>) -> Result<(), Error> {
+        let organization_id = item
+            .organization_id
+            .ok_or("no organization to move the item into")?;
+        let ids = self.collection_ids(session)?;
+        let data = serde_json::to_vec(&ids)
+            .map_err(|e| format!("failed to serialize collection ids: {}", e))?;
+        let encoded = self.encode(session, &data)?;
+        let cmd = self.make_cmd(session, &["share", item.id, organization_id, &encoded])?;
+        self.run_write_cmd(cmd, item, None)
+    }
+
+    /// The item shape the CLI expects, so that new items get whatever fields
+    /// the server requires. Fetched once per process.
+    fn item_template(&self, session: &Option<String>) -> Option<&Value> {
+        self.item_template
+            .get_or_init(|| {
+                let get = |name| {
+                    let mut cmd = self.make_cmd(session, &["get", "template", name]).ok()?;
+                    cmd.stdin(Stdio::null())
+                        .stdout(Stdio::piped())
+                        .stderr(Stdio::null());
+                    let output = self.output(&mut cmd).ok()?;
+                    if !output.status.success() {
+                        return None;
+                    }
+                    let mut template: Value =
+                        json::parse_output(&String::from_utf8_lossy(&output.stdout)).ok()?;
+                    json::blank(&mut template);
+                    Some(template)
+                };
+
+                let mut item = get("item")?;
+                item.as_object_mut()?
+                    .insert("login".into(), get("item.login")?);
+                Some(item)
+            })
+            .as_ref()
+    }
+
+    fn create_item(
+        &self,
+        session: &Option<String>,
+        request: &ListItemCreateRequest,
+    ) -> Result<CreateOutcome, Error> {
+        let template = match self.options.backend {
+            backend::Kind::Rbw => None,
+            _ => self.item_template(session),
+        };
+        let mut item = match template {
+            Some(template) => template.clone(),
+            None => Value::Object(Default::default()),
+        };
+        let request = serde_json::to_value(request)
+            .map_err(|e| format!("failed to serialize new item: {}", e))?;
+        json::merge(&mut item, &request);
+        // The template's blank login would make a note a login again.
+        if let (Some(object), true) = (item.as_object_mut(), request["login"].is_null()) {
+            object.insert("login".into(), Value::Null);
+        }
+        if self.options.fingerprint {
+            update_fingerprint(&mut item, self.options.token_field.as_deref());
+        }
+
+        match self.backend(session)?.create_item(&item) {
+            Err(e)
+                if self
+                    .renewed_after_write(session, bw_error::classify_message(&e.to_string()))? =>
+            {
+                // Look before creating it again, in case it was created after all.
+                if let Ok(created) =
+                    self.resolve_item(session, request["name"].as_str().unwrap_or_default())
+                {
+                    return Ok(CreateOutcome::Created(Some(created)));
+                }
+                self.backend(session)?.create_item(&item)
+            }
+            outcome => outcome,
+        }
+    }
+}
+
+/// Whether `s` looks like the id of a Bitwarden object, a UUID, rather
+/// than a name.
+fn is_object_id(s: &str) -> bool {
+    let groups: Vec<_> = s.split('-').map(str::len).collect();
+    groups == [8, 4, 4, 4, 12] && s.chars().all(|c| c == '-' || c.is_ascii_hexdigit())
+}
+
+/// Find the collection called `name`.
+///
+/// Nested collections are written as `Parent/Child`, the way Bitwarden names
+/// them. A name without a parent also matches a nested collection as long as
+/// that is unambiguous.
+fn resolve_collection<'a>(
+    collections: &'a [Collection],
+    name: &str,
+) -> Result<&'a Collection, Error> {
+    if let Some(collection) = collections.iter().find(|c| c.name == name) {
+        return Ok(collection);
+    }
+
+    let nested: Vec<_> = collections
+        .iter()
+        .filter(|c| c.name.rsplit('/').next() == Some(name))
+        .collect();
+    if let [collection] = nested[..] {
+        return Ok(collection);
+    }
+
+    if nested.len() > 1 {
+        return Err(format!(
+            "collection name `{}` is ambiguous, use the full path:{}",
+            name,
+            list_collections(&nested)
+        )
+        .into());
+    }
+
+    let lowercase = name.to_lowercase();
+    let near: Vec<_> = collections
+        .iter()
+        .filter(|c| c.name.to_lowercase().contains(&lowercase))
+        .collect();
+    if near.is_empty() {
+        Err(format!("no collection named `{}` found in the organization", name).into())
+    } else {
+        Err(format!(
+            "no collection named `{}` found, did you mean:{}",
+            name,
+            list_collections(&near)
+        )
+        .into())
+    }
+}
+
+/// Whether `s` looks like a Bitwarden item id (a UUID) rather than a name.
+fn is_item_id(s: &str) -> bool {
+    s.len() == 36
+        && s.chars().enumerate().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// Remove repeated URIs from a full item, keeping the first occurrence (and
+/// its match mode) of each, and warn about items with more than
+/// [`MAX_URIS`] left.
+fn dedup_uris(item: &mut Value) {
+    let name = item["name"].as_str().unwrap_or("<unnamed>").to_string();
+    // Not through `item["login"]`, which would give a note a login.
+    let Some(uris) = item
+        .get_mut("login")
+        .and_then(|login| login.get_mut("uris"))
+        .and_then(Value::as_array_mut)
+    else {
+        return;
+    };
+
+    let mut seen = Vec::new();
+    uris.retain(|uri| {
+        let Some(key) = uri["uri"].as_str().map(uri_key) else {
+            return true;
+        };
+        if seen.contains(&key) {
+            return false;
+        }
+        seen.push(key);
+        true
+    });
+
+    if uris.len() > MAX_URIS {
+        eprintln!(
+            "warning: item `{}` has {} URIs; consider removing the ones no longer in use",
+            name,
+            uris.len()
+        );
+    }
+}
+
+/// Store `token` in a full item, in the hidden custom field `field` or
+/// else the password.
+fn set_secret(item: &mut Value, field: Option<&str>, token: Secret<&str>) -> Result<(), Error> {
+    let id = item["id"].as_str().unwrap_or("<no id>").to_string();
+    let Some(field_name) = field else {
+        if is_note(item) {
+            item["notes"] = token.expose().into();
+            return Ok(());
+        }
+        let login = item
+            .get_mut("login")
+            .and_then(Value::as_object_mut)
+            .ok_or_else(|| format!("Bitwarden item `{}` is not a login item", id))?;
+        login.insert("password".into(), token.expose().into());
+        return Ok(());
+    };
+
+    if !item.is_object() {
+        return Err(format!("Bitwarden item `{}` is not an object", id).into());
+    }
+    set_field(item, field_name, token.expose(), 1); // hidden
+    Ok(())
+}
+
+/// Record the fingerprint of the item's token, kept in `field` or else the
+/// password, for `--fingerprint`.
+fn update_fingerprint(item: &mut Value, field: Option<&str>) {
+    match full_secret(item, field).filter(|token| !token.is_empty()) {
+        Some(token) => {
+            let fingerprint = fingerprint::fingerprint(&token);
+            set_field(item, FINGERPRINT_FIELD, &fingerprint, 0); // text
+        }
+        None => remove_field(item, FINGERPRINT_FIELD),
+    }
+}
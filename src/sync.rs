@@ -0,0 +1,166 @@
+//! When the local copy of the vault is synced with the server: around
+//! reads and writes, in the background, and once for a batch of them.
+
+use crate::state::SyncStamp;
+use crate::{background, state, verbose, BatchSync, BitwardenVault};
+use cargo_credential::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+impl BitwardenVault {
+    pub(crate) fn sync(&self, session: &Option<String>) -> Result<(), Error> {
+        if !self.options.auto_sync {
+            return Ok(());
+        }
+
+        self.backend(session)?.sync()?;
+
+        if let Some(stamp) = self.sync_stamp() {
+            // Failing to record the sync only means the next process syncs again.
+            let _ = stamp.touch();
+        }
+        Ok(())
+    }
+
+    /// Sync after a change to the vault. The change is already stored
+    /// locally by then, so unless `--sync-strict` is given a failed sync
+    /// only warns: failing would make people retry and create duplicates.
+    ///
+    /// In a batch, the sync is deferred to [`Self::finish_batch`].
+    pub(crate) fn sync_after_write(&self, session: &Option<String>) -> Result<(), Error> {
+        if self.batch.get().is_some() {
+            self.batch.set(Some(BatchSync::Pending));
+            return Ok(());
+        }
+        match self.sync(session) {
+            Err(e) if !self.options.sync_strict => {
+                eprintln!(
+                    "warning: the change is saved in the local vault, but `bw sync` failed: {}\n\
+                     warning: other devices won't see it until `bw sync` succeeds",
+                    e
+                );
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Sync before a read, unless some process synced within
+    /// `--sync-interval`, or this batch already did.
+    pub(crate) fn sync_if_stale(&self, session: &Option<String>) -> Result<(), Error> {
+        if self.defer_sync.get() || self.synced_recently() {
+            return Ok(());
+        }
+        match self.batch.get() {
+            Some(BatchSync::NotSynced) => self.batch.set(Some(BatchSync::Synced)),
+            // Changes of this batch are in the local vault already.
+            Some(BatchSync::Synced | BatchSync::Pending) => return Ok(()),
+            None => {}
+        }
+
+        self.sync_before_read(session)
+    }
+
+    /// Sync before reading. For requests that only read, a failed sync
+    /// warns, once per process, and the local vault is read as it is: it
+    /// still has the token when offline. Changes need the vault up to date.
+    fn sync_before_read(&self, session: &Option<String>) -> Result<(), Error> {
+        static WARNED: AtomicBool = AtomicBool::new(false);
+        match self.sync(session) {
+            Err(e) if self.only_reading.get() => {
+                if !WARNED.swap(true, Ordering::Relaxed) {
+                    eprintln!("warning: {}; using cached vault data", e);
+                }
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn synced_recently(&self) -> bool {
+        match self.sync_stamp() {
+            Some(stamp) if !self.options.sync_interval.is_zero() => {
+                stamp.is_fresh(self.options.sync_interval)
+            }
+            _ => false,
+        }
+    }
+
+    /// With `--background-sync`, read from the local vault and sync after
+    /// `read` is done, for the next invocation's benefit.
+    pub(crate) fn read_then_sync<T>(
+        &self,
+        session: &Option<String>,
+        read: impl FnOnce() -> T,
+    ) -> T {
+        if !(self.options.auto_sync && self.options.background_sync) || self.synced_recently() {
+            return read();
+        }
+
+        self.defer_sync.set(true);
+        let result = read();
+        self.defer_sync.set(false);
+        if let Ok(cmd) = self.make_cmd(session, &["sync"]) {
+            background::sync(&*self.runner, cmd, self.sync_stamp());
+        }
+        result
+    }
+
+    /// With `--sync`, look in the local vault first, and only sync and
+    /// `read` again when it found nothing: a token added on another device
+    /// is what the sync is for, and most lookups find theirs without it.
+    pub(crate) fn read_or_sync<T>(
+        &self,
+        session: &Option<String>,
+        read: impl Fn() -> Result<Option<T>, Error>,
+    ) -> Result<Option<T>, Error> {
+        if !self.options.auto_sync || self.defer_sync.get() {
+            return read();
+        }
+        self.defer_sync.set(true);
+        let found = match read() {
+            Ok(None) | Err(Error::NotFound) if !self.synced_recently() => {
+                verbose::log(|| "nothing found in the local vault, syncing".to_string());
+                self.sync_before_read(session).and_then(|()| read())
+            }
+            found => found,
+        };
+        self.defer_sync.set(false);
+        found
+    }
+
+    /// Start a batch of changes, like one `cargo login` or updating several
+    /// registries, deferring syncs until [`Self::finish_batch`].
+    pub(crate) fn begin_batch(&self) {
+        self.batch.set(Some(BatchSync::NotSynced));
+    }
+
+    /// Forget what an earlier request on this vault left behind: its
+    /// timings, that it unlocked the vault again, and that it only read.
+    pub(crate) fn begin_request(&self) {
+        self.timings.borrow_mut().clear();
+        self.session_renewed.set(false);
+        self.only_reading.set(false);
+    }
+
+    /// Run the sync deferred since [`Self::begin_batch`], if any.
+    pub(crate) fn finish_batch(&self, session: &Option<String>) -> Result<(), Error> {
+        let pending = self.batch.take() == Some(BatchSync::Pending);
+        if pending {
+            self.sync_after_write(session)?;
+        }
+        Ok(())
+    }
+
+    /// When the profile `bw` uses was last synced. A profile of its own
+    /// from `--appdata-dir` keeps the stamp with it.
+    fn sync_stamp(&self) -> Option<SyncStamp> {
+        match self
+            .setup()
+            .ok()
+            .and_then(|setup| setup.appdata_dir.as_ref())
+        {
+            Some(dir) if self.options.appdata_dir.is_some() => Some(SyncStamp::new(dir)),
+            _ => state::state_dir().map(|dir| SyncStamp::new(&dir)),
+        }
+    }
+}
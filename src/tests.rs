@@ -0,0 +1,2574 @@
+//! Tests of the vault and the provider against [`FakeBw`].
+//!
+//! The tests run in parallel and share the caches of the test process, so
+//! each uses registries of its own.
+
+use crate::fake::{self, FakeBw};
+use crate::*;
+use serde_json::json;
+
+const OLD: &str = "2024-01-01T00:00:00.000Z";
+
+fn registry(index_url: &str) -> RegistryInfo<'_> {
+    RegistryInfo {
+        index_url,
+        name: None,
+        headers: Vec::new(),
+    }
+}
+
+/// The vault for the provider arguments `args`, or why they are wrong.
+fn parse(args: &[&str]) -> Result<BitwardenVault, Error> {
+    let bw_path = fake::bw_path().to_str().unwrap();
+    BitwardenVault::new(&[args, &["--bw-path", bw_path]].concat())
+}
+
+fn find(vault: &BitwardenVault, index_url: &str) -> Result<Option<String>, Error> {
+    let session = vault.connect()?;
+    let found = vault.find_token(&session, index_url)?;
+    Ok(found.map(|found| found.token.expose()))
+}
+
+fn save(vault: &BitwardenVault, index_url: &str, token: &str) -> Result<(), Error> {
+    let session = vault.connect()?;
+    vault.save_token(
+        &session,
+        &registry(index_url),
+        Secret::from(token.to_string()),
+    )
+}
+
+/// A provider that answers for `index_url` with `bw`, as if it had made
+/// the vault for `args` itself, and the arguments to hand it.
+fn provider(bw: &FakeBw, index_url: &str, args: &[&str]) -> (BitwardenCredential, Vec<String>) {
+    let bw_path = fake::bw_path().to_str().unwrap();
+    let full: Vec<String> = [args, &["--bw-path", bw_path]]
+        .concat()
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    let full_args: Vec<&str> = full.iter().map(String::as_str).collect();
+    let key = registry_args(&registry(index_url), &full_args)
+        .unwrap()
+        .join("\0");
+    let credential = BitwardenCredential::default();
+    credential
+        .vaults
+        .borrow_mut()
+        .insert(key, Rc::new(bw.vault(args)));
+    (credential, full)
+}
+
+#[test]
+fn search_matches_exactly() {
+    let bw = FakeBw::with_items(vec![fake::login(
+        "a",
+        "Cargo registry token for exact.example.com",
+        "https://exact.example.com/index",
+        "token-a",
+        OLD,
+    )]);
+    let vault = bw.vault(&[]);
+    assert_eq!(
+        find(&vault, "sparse+https://exact.example.com/index/").unwrap(),
+        Some("token-a".to_string())
+    );
+    // The same host, but another registry.
+    assert_eq!(
+        find(&vault, "sparse+https://exact.example.com/other/").unwrap(),
+        None
+    );
+}
+
+#[test]
+fn notes_hold_tokens_with_item_type_note() {
+    let index_url = "sparse+https://notes.example.com/index/";
+    let url = "https://notes.example.com/index";
+    let note = |id: &str, field: &str, value: &str| {
+        let mut note = fake::login(id, id, url, "", OLD);
+        note["type"] = 2.into();
+        note["login"] = Value::Null;
+        note["secureNote"] = json!({ "type": 0 });
+        note["notes"] = format!("token-{}", id).into();
+        note["fields"] = json!([{ "name": field, "value": value, "type": 0 }]);
+        note
+    };
+    let bw = FakeBw::with_items(vec![
+        // A login for the URL, and notes that aren't for it.
+        fake::login("login", "the login", url, "token-login", OLD),
+        note("other-field", "url", url),
+        note(
+            "other-url",
+            NOTE_URL_FIELD,
+            "https://other.example.com/index",
+        ),
+    ]);
+    let args = ["--item-type", "note", "--force"];
+    assert_eq!(find(&bw.vault(&args), index_url).unwrap(), None);
+
+    save(&bw.vault(&args), index_url, "token-note").unwrap();
+    let created = bw.state().items.last().unwrap().clone();
+    assert_eq!(created["type"], 2);
+    assert_eq!(created["login"], Value::Null);
+    assert_eq!(created["notes"], "token-note");
+    assert_eq!(created["fields"][0]["name"], NOTE_URL_FIELD);
+    assert_eq!(created["fields"][0]["value"], uri_key(index_url));
+    let id = created["id"].as_str().unwrap().to_string();
+
+    assert_eq!(
+        find(&bw.vault(&args), index_url).unwrap().as_deref(),
+        Some("token-note")
+    );
+    // Logins are still found without the option.
+    assert_eq!(
+        find(&bw.vault(&[]), index_url).unwrap().as_deref(),
+        Some("token-login")
+    );
+
+    save(&bw.vault(&args), index_url, "token-note-2").unwrap();
+    assert_eq!(bw.state().items.len(), 4);
+    assert_eq!(bw.item(&id).unwrap()["notes"], "token-note-2");
+
+    let vault = bw.vault(&args);
+    let session = vault.connect().unwrap();
+    vault.delete_token(&session, index_url).unwrap();
+    assert!(bw.state().trash.iter().any(|item| item["id"] == id));
+    assert_eq!(
+        bw.item("login").unwrap()["login"]["password"],
+        "token-login"
+    );
+}
+
+#[test]
+fn search_normalizes_both_sides() {
+    let bw = FakeBw::with_items(vec![fake::login(
+        "a",
+        "Cargo registry token for spelling.example.com",
+        "https://Spelling.Example.com/index/",
+        "token-a",
+        OLD,
+    )]);
+    let vault = bw.vault(&[]);
+    for index_url in [
+        "sparse+https://spelling.example.com/index/",
+        "https://spelling.example.com/index",
+        "registry+https://SPELLING.example.com:443/index",
+    ] {
+        assert_eq!(
+            find(&vault, index_url).unwrap(),
+            Some("token-a".to_string()),
+            "{}",
+            index_url
+        );
+    }
+}
+
+#[test]
+fn search_skips_other_item_types() {
+    let url = "https://mixed.example.com/index";
+    let login = fake::login("login", "the login", url, "token-login", OLD);
+    let mut without_login = fake::login("null", "login: null", url, "token-null", OLD);
+    without_login["login"] = Value::Null;
+    let mut unreadable = fake::login("unreadable", "unreadable", url, "token-unreadable", OLD);
+    unreadable["login"]["uris"] = "not a list".into();
+    let mut without_uris = fake::login("no-uris", "no uris", url, "token-no-uris", OLD);
+    without_uris["login"]
+        .as_object_mut()
+        .unwrap()
+        .remove("uris");
+    let mut note = fake::login("note", "a note", url, "token-note", OLD);
+    note["type"] = 2.into();
+    note["login"] = Value::Null;
+    note["secureNote"] = json!({ "type": 0 });
+    note["notes"] = "token-note".into();
+    let mut card = fake::login("card", "a card", url, "", OLD);
+    card["type"] = 3.into();
+    card.as_object_mut().unwrap().remove("login");
+    card["card"] = json!({ "cardholderName": "Me", "number": "4111111111111111" });
+    let mut identity = fake::login("identity", "an identity", url, "", OLD);
+    identity["type"] = 4.into();
+    identity.as_object_mut().unwrap().remove("login");
+    identity["identity"] = json!({ "firstName": "Me" });
+    let items = vec![
+        card,
+        without_login,
+        identity,
+        note,
+        login,
+        unreadable,
+        without_uris,
+    ];
+    // Like `bw list items --url`, which also returns items without the URL.
+    let bw = FakeBw::new();
+    let envelope = json!({ "success": true, "data": { "object": "list", "data": items.clone() } });
+    bw.print(&["list", "items", "--url"], &envelope.to_string());
+    let vault = bw.vault(&[]);
+    assert_eq!(
+        find(&vault, "sparse+https://mixed.example.com/index/").unwrap(),
+        Some("token-login".to_string())
+    );
+    // Only the login without URIs is left with it, matching nothing.
+    let logins: Vec<_> = vault.login_items(items).map(|item| item.id).collect();
+    assert_eq!(logins, ["login", "no-uris"]);
+}
+
+#[test]
+fn search_without_match() {
+    let bw = FakeBw::with_items(vec![fake::login(
+        "a",
+        "Cargo registry token for elsewhere.example.com",
+        "https://elsewhere.example.com/index",
+        "token-a",
+        OLD,
+    )]);
+    let vault = bw.vault(&[]);
+    assert_eq!(
+        find(&vault, "sparse+https://nomatch.example.com/index/").unwrap(),
+        None
+    );
+    let search = &bw.calls_of(&["list", "items"])[0];
+    assert_eq!(
+        search.option("--url"),
+        Some("https://nomatch.example.com/index")
+    );
+}
+
+#[test]
+fn too_many_matches() {
+    let uri = "https://twice.example.com/index";
+    let bw = FakeBw::with_items(vec![
+        fake::login("a", "first", uri, "token-a", OLD),
+        fake::login("b", "second", uri, "token-b", OLD),
+    ]);
+    let vault = bw.vault(&[]);
+    let e = find(&vault, "sparse+https://twice.example.com/index/")
+        .unwrap_err()
+        .to_string();
+    assert!(e.starts_with("2 Bitwarden logins match registry"), "{}", e);
+    assert!(e.contains("\n  a  `first`"), "{}", e);
+    assert!(e.contains("\n  b  `second`"), "{}", e);
+    assert!(e.contains("--prefer-newest"), "{}", e);
+
+    let vault = bw.vault(&["--prefer-newest"]);
+    bw.state().items[1]["revisionDate"] = "2024-06-01T00:00:00.000Z".into();
+    assert_eq!(
+        find(&vault, "sparse+https://twice.example.com/index/").unwrap(),
+        Some("token-b".to_string())
+    );
+}
+
+#[test]
+fn create_request() {
+    let bw = FakeBw::new();
+    let vault = bw.vault(&["--username", "me"]);
+    save(
+        &vault,
+        "sparse+https://create.example.com/index/",
+        "token-new",
+    )
+    .unwrap();
+
+    let creates = bw.calls_of(&["create", "item"]);
+    assert_eq!(creates.len(), 1);
+    let mut request = creates[0].payload().unwrap();
+    let notes = request["notes"].take();
+    let notes = notes.as_str().unwrap();
+    assert!(notes.contains("index: sparse+https://create.example.com/index/"));
+    assert_eq!(
+        request,
+        json!({
+            "organizationId": null,
+            "collectionIds": null,
+            "folderId": null,
+            "type": 1,
+            "name": "Cargo registry token for create.example.com",
+            "notes": null,
+            "favorite": false,
+            "fields": [],
+            "login": {
+                "uris": [{ "uri": "https://create.example.com/index", "match": 3 }],
+                "username": "me",
+                "password": "token-new",
+                "totp": null,
+            },
+            "secureNote": null,
+            "card": null,
+            "identity": null,
+            "reprompt": 0,
+        })
+    );
+    assert_eq!(bw.state().items.len(), 1);
+    assert_eq!(bw.state().items[0]["login"]["password"], "token-new");
+}
+
+#[test]
+fn modify_keeps_other_fields() {
+    let mut item = fake::login(
+        "a",
+        "my registry",
+        "https://modify.example.com/index",
+        "token-old",
+        OLD,
+    );
+    item["notes"] = "recovery codes: 1234".into();
+    item["favorite"] = true.into();
+    item["folderId"] = "folder".into();
+    item["fields"] = json!([{ "name": "team", "value": "infra", "type": 0, "linkedId": null }]);
+    item["login"]["totp"] = "JBSWY3DPEHPK3PXP".into();
+    item["login"]["fido2Credentials"] = json!([{ "credentialId": "c" }]);
+    item["passwordHistory"] = json!([{ "password": "older", "lastUsedDate": OLD }]);
+    item["unknownField"] = json!({ "nested": [1, 2] });
+    let bw = FakeBw::with_items(vec![item.clone()]);
+    let vault = bw.vault(&["--force"]);
+    save(
+        &vault,
+        "sparse+https://modify.example.com/index/",
+        "token-new",
+    )
+    .unwrap();
+
+    assert!(bw.calls_of(&["create"]).is_empty());
+    let stored = bw.item("a").unwrap();
+    assert_eq!(stored["login"]["password"], "token-new");
+    // Made by hand, so its notes are left alone.
+    assert_eq!(stored["notes"], "recovery codes: 1234");
+    for key in [
+        "name",
+        "favorite",
+        "folderId",
+        "fields",
+        "passwordHistory",
+        "unknownField",
+    ] {
+        assert_eq!(stored[key], item[key], "{}", key);
+    }
+    for key in ["totp", "uris", "fido2Credentials"] {
+        assert_eq!(stored["login"][key], item["login"][key], "{}", key);
+    }
+}
+
+#[test]
+fn provenance_of_managed_items() {
+    let mut managed = fake::login(
+        "managed",
+        "Cargo registry token for provenance.example.com",
+        "https://provenance.example.com/index",
+        "token-old",
+        OLD,
+    );
+    managed["notes"] = "mine".into();
+    let mut marked = fake::login(
+        "marked",
+        "my other registry",
+        "https://marked.provenance.example.com/index",
+        "token-old",
+        OLD,
+    );
+    marked["notes"] = "--- cargo-credential-bitwarden ---\nindex: old\n\
+         --- end cargo-credential-bitwarden ---\n\nmine"
+        .into();
+    let bw = FakeBw::with_items(vec![managed, marked]);
+    let vault = bw.vault(&["--force"]);
+    let notes = |id: &str| bw.item(id).unwrap()["notes"].as_str().unwrap().to_string();
+
+    for token in ["token-1", "token-2"] {
+        save(
+            &vault,
+            "sparse+https://provenance.example.com/index/",
+            token,
+        )
+        .unwrap();
+        let notes = notes("managed");
+        assert!(
+            notes.starts_with("mine\n\n--- cargo-credential-bitwarden ---\n"),
+            "{}",
+            notes
+        );
+        assert_eq!(
+            notes.matches("--- cargo-credential-bitwarden ---").count(),
+            1
+        );
+        assert!(notes.contains("index: sparse+https://provenance.example.com/index/"));
+        assert!(
+            notes.ends_with("--- end cargo-credential-bitwarden ---"),
+            "{}",
+            notes
+        );
+    }
+
+    // Made by hand, but with a block of an earlier login.
+    save(
+        &vault,
+        "sparse+https://marked.provenance.example.com/index/",
+        "token-1",
+    )
+    .unwrap();
+    let notes = notes("marked");
+    assert!(notes.contains("index: sparse+https://marked.provenance.example.com/index/"));
+    assert!(!notes.contains("index: old"));
+    assert!(
+        notes.ends_with("--- end cargo-credential-bitwarden ---\n\nmine"),
+        "{}",
+        notes
+    );
+}
+
+#[test]
+fn edits_keep_unknown_fields() {
+    let mut item = fake::login(
+        "a",
+        "Cargo registry token for unknown.example.com",
+        "https://unknown.example.com/index",
+        "token-old",
+        OLD,
+    );
+    item["reprompt"] = 0.into();
+    item["key"] = "2.aGVsbG8=|d29ybGQ=".into();
+    item["attachments"] = json!([{ "id": "att", "fileName": "recovery.txt", "size": "12" }]);
+    item["futureField"] = json!({ "nested": { "deeper": [true, null, 1.5] } });
+    item["login"]["passwordRevisionDate"] = OLD.into();
+    item["login"]["futureLoginField"] = json!(["x"]);
+    item["login"]["uris"][0]["uriChecksum"] = "c2hhMjU2".into();
+    item["fields"] =
+        json!([{ "name": "team", "value": "infra", "type": 0, "linkedId": null, "future": 1 }]);
+    let bw = FakeBw::with_items(vec![item.clone()]);
+    let vault = bw.vault(&["--force", "--no-notes"]);
+    save(
+        &vault,
+        "sparse+https://unknown.example.com/index/",
+        "token-new",
+    )
+    .unwrap();
+
+    let edit = &bw.calls_of(&["edit", "item", "a"])[0];
+    let mut sent = edit.payload().unwrap();
+    assert_eq!(sent["login"]["password"], "token-new");
+    sent["login"]["password"] = item["login"]["password"].clone();
+    assert_eq!(sent, item);
+}
+
+#[test]
+fn edits_drop_repeated_uris() {
+    let mut item = fake::login(
+        "a",
+        "Cargo registry token for dedup.example.com",
+        "https://dedup.example.com/index",
+        "token-old",
+        OLD,
+    );
+    item["login"]["uris"] = json!([
+        { "uri": "https://dedup.example.com/index", "match": 3 },
+        { "uri": "https://mirror.dedup.example.com/", "match": null },
+        { "uri": "https://DEDUP.example.com/index/", "match": 0 },
+        { "uri": "https://dedup.example.com/index", "match": null },
+        { "uri": "https://mirror.dedup.example.com", "match": 3 },
+    ]);
+    let bw = FakeBw::with_items(vec![item]);
+    let vault = bw.vault(&["--force"]);
+    save(
+        &vault,
+        "sparse+https://dedup.example.com/index/",
+        "token-new",
+    )
+    .unwrap();
+
+    let stored = bw.item("a").unwrap();
+    assert_eq!(
+        stored["login"]["uris"],
+        json!([
+            { "uri": "https://dedup.example.com/index", "match": 3 },
+            { "uri": "https://mirror.dedup.example.com/", "match": null },
+        ])
+    );
+}
+
+#[test]
+fn encode_round_trip() {
+    let bw = FakeBw::new();
+    let vault = bw.vault(&[]);
+    let data = "{\"name\":\"ünïcödé\",\"password\":\"a+b/c=\"}".as_bytes();
+    let encoded = vault.encode(&None, data).unwrap();
+    assert_eq!(encoded, fake::encode(data));
+    assert_eq!(fake::decode(&encoded).unwrap(), data);
+    let encode = &bw.calls_of(&["encode"])[0];
+    assert_eq!(encode.input.as_deref(), Some(data));
+}
+
+/// The directories of `tests/fixtures`, one per CLI version.
+fn fixture_dirs() -> Vec<std::path::PathBuf> {
+    let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut dirs: Vec<_> = std::fs::read_dir(root)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+    assert!(!dirs.is_empty());
+    dirs
+}
+
+fn read_fixture(dir: &std::path::Path, file: &str) -> String {
+    std::fs::read_to_string(dir.join(file)).unwrap()
+}
+
+/// A fake vault holding the items of the fixture in `dir`, which prints
+/// the captured `list items --response`, on registries of its own.
+fn fixture_bw(dir: &std::path::Path) -> (FakeBw, String) {
+    let items: Vec<Value> = serde_json::from_str(&read_fixture(dir, "list-items.json")).unwrap();
+    let trash: Vec<Value> =
+        serde_json::from_str(&read_fixture(dir, "list-items-trash.json")).unwrap();
+    let bw = FakeBw::with_items(items);
+    bw.state().trash = trash;
+    let version = dir.file_name().unwrap().to_string_lossy().into_owned();
+    (bw, version)
+}
+
+#[test]
+fn fixtures_parse() {
+    for dir in fixture_dirs() {
+        let expected: Value = serde_json::from_str(&read_fixture(&dir, "expected.json")).unwrap();
+        let name = dir.display().to_string();
+
+        let status: Status = json::parse_output(&read_fixture(&dir, "status.json")).unwrap();
+        assert_eq!(
+            serde_json::to_value(format!("{:?}", status.status).to_lowercase()).unwrap(),
+            expected["status"],
+            "{}",
+            name
+        );
+
+        let items: Vec<Value> = json::parse_output(&read_fixture(&dir, "list-items.json")).unwrap();
+        assert_eq!(items.len() as u64, expected["items"], "{}", name);
+        let (bw, _) = fixture_bw(&dir);
+        let vault = bw.vault(&[]);
+        assert_eq!(
+            vault.login_items(items).count() as u64,
+            expected["logins"],
+            "{}",
+            name
+        );
+
+        // The lookups read the captured envelope, whatever they ask for.
+        bw.print(
+            &["list", "items", "--url"],
+            &read_fixture(&dir, "list-items-response.json"),
+        );
+        for (index_url, token) in expected["tokens"].as_object().unwrap() {
+            let found = find(&vault, index_url).unwrap();
+            assert_eq!(found.as_deref(), token.as_str(), "{} {}", name, index_url);
+        }
+
+        let (bw, _) = fixture_bw(&dir);
+        let vault = bw.vault(&["--item-type", "note"]);
+        for (index_url, token) in expected["notes"].as_object().unwrap() {
+            let found = find(&vault, index_url).unwrap();
+            assert_eq!(found.as_deref(), token.as_str(), "{} {}", name, index_url);
+        }
+    }
+}
+
+#[test]
+fn fixtures_with_banners() {
+    let mut checked = 0;
+    for dir in fixture_dirs() {
+        let Ok(output) = std::fs::read_to_string(dir.join("list-items-response-banner.txt")) else {
+            continue;
+        };
+        let expected: Value = serde_json::from_str(&read_fixture(&dir, "expected.json")).unwrap();
+        let (bw, version) = fixture_bw(&dir);
+        bw.print(&["list", "items", "--url"], &output);
+        let vault = bw.vault(&[]);
+        for (index_url, token) in expected["tokens"].as_object().unwrap() {
+            let found = find(&vault, index_url).unwrap();
+            assert_eq!(
+                found.as_deref(),
+                token.as_str(),
+                "{} {}",
+                version,
+                index_url
+            );
+        }
+        checked += 1;
+    }
+    assert!(checked > 0);
+}
+
+#[test]
+fn fixtures_restore_from_trash() {
+    for dir in fixture_dirs() {
+        let expected: Value = serde_json::from_str(&read_fixture(&dir, "expected.json")).unwrap();
+        for (index_url, token) in expected["trash"].as_object().unwrap() {
+            let (bw, version) = fixture_bw(&dir);
+            let trashed = bw.state().trash[0].clone();
+            assert_eq!(trashed["login"]["password"], *token);
+
+            let vault = bw.vault(&["--no-notes"]);
+            save(&vault, index_url, "token-new").unwrap();
+            assert!(bw.calls_of(&["create"]).is_empty(), "{}", version);
+            let restored = bw.item(trashed["id"].as_str().unwrap()).unwrap();
+            assert_eq!(restored["login"]["password"], "token-new", "{}", version);
+            assert!(bw.state().trash.is_empty(), "{}", version);
+        }
+    }
+}
+
+/// Storing a new token in a captured item changes its password, and
+/// sends everything else back byte for byte.
+#[test]
+fn fixtures_round_trip_edits() {
+    for dir in fixture_dirs() {
+        let expected: Value = serde_json::from_str(&read_fixture(&dir, "expected.json")).unwrap();
+        for (index_url, token) in expected["tokens"].as_object().unwrap() {
+            if token.is_null() {
+                continue;
+            }
+            let (bw, version) = fixture_bw(&dir);
+            let original = bw
+                .state()
+                .items
+                .iter()
+                .find(|item| item["login"]["password"] == *token)
+                .cloned()
+                .unwrap();
+            let vault = bw.vault(&["--force", "--no-notes"]);
+            save(&vault, index_url, "token-new").unwrap();
+
+            let edits = bw.calls_of(&["edit", "item"]);
+            assert_eq!(edits.len(), 1, "{} {}", version, index_url);
+            let mut sent = edits[0].payload().unwrap();
+            assert_eq!(sent["login"]["password"], "token-new");
+            sent["login"]["password"] = token.clone();
+            assert_eq!(
+                serde_json::to_string_pretty(&sent).unwrap(),
+                serde_json::to_string_pretty(&original).unwrap(),
+                "{} {}",
+                version,
+                index_url
+            );
+        }
+    }
+}
+
+/// What `--capture-fixture` writes still reads as the vault it came from.
+#[test]
+fn sanitized_output_parses_alike() {
+    let mut note = fake::login("n", "Cargo registry token for notes", "", "", OLD);
+    note["type"] = 2.into();
+    note["login"] = Value::Null;
+    note["notes"] = "token-note".into();
+    note["fields"] = json!([{
+        "name": NOTE_URL_FIELD,
+        "value": "https://sanitize-note.example.com/index",
+        "type": 0,
+        "linkedId": null,
+    }]);
+    let bw = FakeBw::with_items(vec![
+        fake::login(
+            "a",
+            "Cargo registry token for sanitize.example.com",
+            "https://sanitize.example.com/index",
+            "token-a",
+            OLD,
+        ),
+        fake::login(
+            "b",
+            "Cargo registry token for sanitize.example.com (publish)",
+            "https://sanitize.example.com/index",
+            "token-b",
+            OLD,
+        ),
+        note,
+    ]);
+    let vault = bw.vault(&[]);
+    let mut sanitizer = fixture::Sanitizer::default();
+    let raw = vault
+        .run_cmd(vault.make_cmd(&None, &["list", "items"]).unwrap())
+        .unwrap();
+    let sanitized = sanitizer.sanitize_output(&raw);
+    assert!(!sanitized.contains("sanitize"), "{}", sanitized);
+    assert!(!sanitized.contains("token-"), "{}", sanitized);
+
+    let raw: Vec<Value> = serde_json::from_str(&raw).unwrap();
+    let sanitized: Vec<Value> = serde_json::from_str(&sanitized).unwrap();
+    let shape = |items: &[Value]| -> Vec<(Value, Value, Value)> {
+        items
+            .iter()
+            .map(|item| {
+                (
+                    item["type"].clone(),
+                    item["revisionDate"].clone(),
+                    item["fields"][0]["name"].clone(),
+                )
+            })
+            .collect()
+    };
+    assert_eq!(shape(&raw), shape(&sanitized));
+
+    // Both logins have the same URL, still, and the note its own host.
+    let logins: Vec<ListItem> = vault.login_items(sanitized.clone()).collect();
+    assert_eq!(logins.len(), 2);
+    assert_eq!(logins[0].login.uris[0].uri, logins[1].login.uris[0].uri);
+    assert_eq!(logins[1].name, format!("{} (publish)", logins[0].name));
+    let url = sanitized[2]["fields"][0]["value"].as_str().unwrap();
+    assert!(url.starts_with("https://host-") && url.ends_with("/index"));
+
+    let bw = FakeBw::with_items(sanitized.clone());
+    let vault = bw.vault(&["--item-type", "note"]);
+    assert_eq!(
+        find(&vault, url).unwrap().as_deref(),
+        sanitized[2]["notes"].as_str()
+    );
+}
+
+/// A lookup that finds nothing syncs, unless another process synced less
+/// than `--sync-max-age` ago.
+#[test]
+fn sync_is_shared_between_processes() {
+    let bw = FakeBw::new();
+    let dir = fake::temp_dir("sync-shared");
+    let dir = dir.to_str().unwrap();
+    let args = ["--sync", "--sync-max-age", "600", "--appdata-dir", dir];
+    let url = "sparse+https://synced.example.com/index/";
+    for _ in 0..3 {
+        let vault = bw.vault(&args);
+        assert_eq!(find(&vault, url).unwrap(), None);
+    }
+    assert_eq!(bw.calls_of(&["sync"]).len(), 1);
+
+    // A broken stamp only means another sync.
+    std::fs::write(std::path::Path::new(dir).join("last-sync"), "garbage").unwrap();
+    let vault = bw.vault(&args);
+    assert_eq!(find(&vault, url).unwrap(), None);
+    assert_eq!(bw.calls_of(&["sync"]).len(), 2);
+}
+
+#[test]
+fn syncs_once_the_interval_passed() {
+    let bw = FakeBw::new();
+    let dir = fake::temp_dir("sync-interval");
+    let dir_arg = dir.to_str().unwrap();
+    let args = ["--sync", "--sync-interval", "600", "--appdata-dir", dir_arg];
+    let url = "sparse+https://interval.example.com/index/";
+    let synced_ago = |secs: u64| {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        std::fs::write(dir.join("last-sync"), (now - secs).to_string()).unwrap();
+    };
+
+    synced_ago(570);
+    assert_eq!(find(&bw.vault(&args), url).unwrap(), None);
+    assert!(bw.calls_of(&["sync"]).is_empty());
+    synced_ago(630);
+    assert_eq!(find(&bw.vault(&args), url).unwrap(), None);
+    assert_eq!(bw.calls_of(&["sync"]).len(), 1);
+}
+
+#[test]
+fn failed_syncs_after_writes_are_fatal_only_when_strict() {
+    let bw = FakeBw::with_items(vec![
+        fake::login(
+            "lenient",
+            "Cargo registry token for lenient.example.com",
+            "https://lenient.example.com/index",
+            "token-old",
+            OLD,
+        ),
+        fake::login(
+            "strict",
+            "Cargo registry token for strict.example.com",
+            "https://strict.example.com/index",
+            "token-old",
+            OLD,
+        ),
+    ]);
+    let dir = fake::temp_dir("sync-strict");
+    let dir_arg = dir.to_str().unwrap();
+    // Synced just now, so only the sync after the write runs.
+    let args = [
+        "--sync",
+        "--sync-interval",
+        "600",
+        "--appdata-dir",
+        dir_arg,
+        "--force",
+    ];
+    state::SyncStamp::new(&dir).touch().unwrap();
+    bw.fail(&["sync"], "fetch failed", usize::MAX);
+
+    let url = "sparse+https://lenient.example.com/index/";
+    save(&bw.vault(&args), url, "token-lenient").unwrap();
+    assert_eq!(
+        bw.item("lenient").unwrap()["login"]["password"],
+        "token-lenient"
+    );
+    assert!(!bw.calls_of(&["sync"]).is_empty());
+
+    let url = "sparse+https://strict.example.com/index/";
+    let strict = [&args[..], &["--sync-strict"]].concat();
+    let e = save(&bw.vault(&strict), url, "token-strict")
+        .unwrap_err()
+        .to_string();
+    assert!(e.contains("fetch failed"), "{}", e);
+    // Saved locally all the same.
+    assert_eq!(
+        bw.item("strict").unwrap()["login"]["password"],
+        "token-strict"
+    );
+}
+
+#[test]
+fn syncs_only_when_nothing_is_found() {
+    let dir = fake::temp_dir("sync-on-miss");
+    let dir_arg = dir.to_str().unwrap();
+    let args = ["--sync", "--sync-interval", "0", "--appdata-dir", dir_arg];
+    let login = |host: &str| {
+        fake::login(
+            host,
+            &format!("Cargo registry token for {}", host),
+            &format!("https://{}/index", host),
+            &format!("token-{}", host),
+            OLD,
+        )
+    };
+    let bw = FakeBw::with_items(vec![login("local.example.com")]);
+    bw.state().remote.push(login("remote.example.com"));
+    let syncs = || bw.calls_of(&["sync"]).len();
+    let searches = || bw.calls_of(&["list", "items"]).len();
+
+    let found = find(&bw.vault(&args), "sparse+https://local.example.com/index/").unwrap();
+    assert_eq!(found.as_deref(), Some("token-local.example.com"));
+    assert_eq!((syncs(), searches()), (0, 1));
+
+    // Added on another device.
+    let found = find(&bw.vault(&args), "sparse+https://remote.example.com/index/").unwrap();
+    assert_eq!(found.as_deref(), Some("token-remote.example.com"));
+    assert_eq!((syncs(), searches()), (1, 3));
+
+    let found = find(
+        &bw.vault(&args),
+        "sparse+https://nowhere.example.com/index/",
+    )
+    .unwrap();
+    assert_eq!(found, None);
+    assert_eq!((syncs(), searches()), (2, 5));
+}
+
+#[test]
+fn failed_syncs_only_fail_writes() {
+    let host = "offline.sync.example.com";
+    let index_url = format!("sparse+https://{}/index/", host);
+    let bw = FakeBw::with_items(vec![fake::login(
+        host,
+        &format!("Cargo registry token for {}", host),
+        &format!("https://{}/index", host),
+        "token-offline",
+        OLD,
+    )]);
+    bw.fail(&["sync"], "Failed to fetch: network is unreachable", 10);
+    let args = ["--sync", "--sync-interval", "0", "--force"];
+    let (credential, args) = provider(&bw, &index_url, &args);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let get = |index_url: &str| {
+        credential.perform(&registry(index_url), &Action::Get(Operation::Read), &args)
+    };
+
+    match get(&index_url) {
+        Ok(CredentialResponse::Get { token, .. }) => assert_eq!(token.expose(), "token-offline"),
+        other => panic!("expected a token, got {:?}", other),
+    }
+    // A miss syncs, and reads the local vault as it is when that fails.
+    assert!(matches!(
+        get("sparse+https://missing.sync.example.com/index/"),
+        Err(Error::NotFound)
+    ));
+    assert!(!bw.calls_of(&["sync"]).is_empty());
+
+    // A change on the same vault needs the sync to succeed.
+    let login = Action::Login(LoginOptions {
+        token: Some(Secret::from("token-new")),
+        login_url: None,
+    });
+    let e = credential
+        .perform(&registry(&index_url), &login, &args)
+        .unwrap_err()
+        .to_string();
+    assert!(e.contains("network is unreachable"), "{}", e);
+    assert_eq!(bw.item(host).unwrap()["login"]["password"], "token-offline");
+}
+
+#[test]
+fn regions() {
+    let eu = Server::from_region("EU").unwrap();
+    assert_eq!(
+        eu.config_args(),
+        [
+            "config",
+            "server",
+            "https://vault.bitwarden.eu",
+            "--api",
+            "https://api.bitwarden.eu",
+            "--identity",
+            "https://identity.bitwarden.eu",
+        ]
+    );
+    let us = Server::from_region("us").unwrap();
+    assert_eq!(us.url, "https://vault.bitwarden.com");
+    assert_eq!(us.api.as_deref(), Some("https://api.bitwarden.com"));
+    assert_eq!(
+        us.identity.as_deref(),
+        Some("https://identity.bitwarden.com")
+    );
+    let e = Server::from_region("mars").unwrap_err().to_string();
+    assert_eq!(e, "unknown region `mars`, expected `us` or `eu`");
+
+    assert_eq!(parse(&["--region", "eu"]).unwrap().options.server, Some(eu));
+    let e = parse(&["--region", "eu", "--server", "https://vault.example.com"])
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(e.contains("cannot be used together"), "{}", e);
+}
+
+#[test]
+fn region_is_verified_like_a_server() {
+    let bw = FakeBw::new();
+    let e = bw
+        .vault(&["--region", "eu"])
+        .connect()
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(
+        e.contains("configured for server `https://vault.bitwarden.com`"),
+        "{}",
+        e
+    );
+    assert!(
+        e.contains("bw config server https://vault.bitwarden.eu --api https://api.bitwarden.eu"),
+        "{}",
+        e
+    );
+
+    bw.state().status = "unauthenticated".into();
+    let vault = bw.vault(&["--region", "eu", "--force-server"]);
+    vault.verify_server().unwrap();
+    let config = &bw.calls_of(&["config", "server"])[0];
+    assert_eq!(
+        config.option("--identity"),
+        Some("https://identity.bitwarden.eu")
+    );
+    assert_eq!(
+        bw.state().server.as_deref(),
+        Some("https://vault.bitwarden.eu")
+    );
+    bw.vault(&["--region", "eu"]).verify_server().unwrap();
+}
+
+#[test]
+fn flags_follow_the_version() {
+    let bw = FakeBw::new();
+    bw.state().version = "1.15.0".into();
+    let vault = bw.vault(&[]);
+    vault.connect().unwrap();
+    let cmd = vault.make_cmd(&None, &["status"]).unwrap();
+    let args: Vec<_> = cmd.get_args().map(|arg| arg.to_str().unwrap()).collect();
+    assert_eq!(args, ["--nointeraction", "status"]);
+
+    let bw = FakeBw::new();
+    bw.state().version = "1.9.0".into();
+    let e = bw.vault(&[]).connect().err().unwrap().to_string();
+    assert!(e.contains("`bw` 1.9.0 is too old"), "{}", e);
+    assert!(bw.calls_of(&["status"]).is_empty());
+
+    // Without a version to go by, the flags `bw --help` lists.
+    let bw = FakeBw::new();
+    bw.state().version = "dev".into();
+    bw.print(&["--help"], "Options:\n  --raw\n  --response\n");
+    let vault = bw.vault(&[]);
+    vault.connect().unwrap();
+    let cmd = vault.make_cmd(&None, &["status"]).unwrap();
+    assert_eq!(cmd.get_args().count(), 1);
+    assert!(!vault.capabilities().unwrap().supports(Flag::NoInteraction));
+
+    bw.print(&["--help"], "Options:\n  --response\n");
+    let e = bw.vault(&[]).connect().err().unwrap().to_string();
+    assert!(e.contains("lacks the `--raw` flag"), "{}", e);
+}
+
+#[test]
+fn actions_are_dispatched() {
+    let index_url = "sparse+https://dispatch.example.com/index/";
+    let bw = FakeBw::new();
+    let (provider, args) = provider(&bw, index_url, &[]);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let registry = registry(index_url);
+    let get = Action::Get(Operation::Read);
+    let token = |result: Result<CredentialResponse, Error>| match result {
+        Ok(CredentialResponse::Get { token, .. }) => token.expose(),
+        other => panic!("expected a token, got {:?}", other),
+    };
+
+    assert!(matches!(
+        provider.perform(&registry, &get, &args),
+        Err(Error::NotFound)
+    ));
+    let login = Action::Login(LoginOptions {
+        token: Some(Secret::from("token-dispatch")),
+        login_url: None,
+    });
+    assert!(matches!(
+        provider.perform(&registry, &login, &args),
+        Ok(CredentialResponse::Login)
+    ));
+    assert_eq!(bw.state().items.len(), 1);
+    assert_eq!(
+        token(provider.perform(&registry, &get, &args)),
+        "token-dispatch"
+    );
+
+    assert!(matches!(
+        provider.perform(&registry, &Action::Logout, &args),
+        Ok(CredentialResponse::Logout)
+    ));
+    assert!(bw.state().items.is_empty());
+    assert_eq!(bw.state().trash.len(), 1);
+    assert!(matches!(
+        provider.perform(&registry, &get, &args),
+        Err(Error::NotFound)
+    ));
+    assert!(matches!(
+        provider.perform(&registry, &Action::Logout, &args),
+        Err(Error::NotFound)
+    ));
+
+    let calls = bw.calls().len();
+    assert!(matches!(
+        provider.perform(&registry, &Action::Unknown, &args),
+        Err(Error::OperationNotSupported)
+    ));
+    assert_eq!(bw.calls().len(), calls);
+    let note = unsupported_note(&Action::Unknown);
+    assert!(
+        note.contains("the `<unknown>` credential action (Unknown)"),
+        "{}",
+        note
+    );
+    assert!(note.contains(env!("CARGO_PKG_REPOSITORY")), "{}", note);
+}
+
+#[test]
+fn registries_without_a_name_are_named_by_host() {
+    let index_url = "sparse+https://nameless.example.com/index/";
+    let bw = FakeBw::new();
+    let (credential, args) = provider(
+        &bw,
+        index_url,
+        &["--name-template", "{registry} token", "--force"],
+    );
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let registry = registry(index_url);
+    assert_eq!(registry.name, None);
+    let get = Action::Get(Operation::Read);
+    let login = |token: &'static str| {
+        Action::Login(LoginOptions {
+            token: Some(Secret::from(token)),
+            login_url: None,
+        })
+    };
+
+    credential
+        .perform(&registry, &login("token-1"), &args)
+        .unwrap();
+    let items = bw.state().items.clone();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["name"], "nameless.example.com token");
+    // No registry field, rather than an empty one.
+    assert_eq!(items[0]["fields"], json!([]));
+    match credential.perform(&registry, &get, &args) {
+        Ok(CredentialResponse::Get { token, .. }) => assert_eq!(token.expose(), "token-1"),
+        other => panic!("expected a token, got {:?}", other),
+    }
+
+    // Logging in again keeps the name, even one the user gave it.
+    bw.state().items[0]["name"] = "my registry".into();
+    credential
+        .perform(&registry, &login("token-2"), &args)
+        .unwrap();
+    assert_eq!(bw.state().items.len(), 1);
+    assert_eq!(bw.state().items[0]["name"], "my registry");
+    assert_eq!(bw.state().items[0]["login"]["password"], "token-2");
+
+    assert!(matches!(
+        credential.perform(&registry, &Action::Logout, &args),
+        Ok(CredentialResponse::Logout)
+    ));
+    assert!(bw.state().items.is_empty());
+}
+
+#[test]
+fn teardown_follows_sessions_of_the_provider() {
+    let index_url = "sparse+https://teardown.example.com/index/";
+    let item = fake::login(
+        "teardown",
+        "Cargo registry token for teardown.example.com",
+        "https://teardown.example.com/index",
+        "token-teardown",
+        OLD,
+    );
+    let shell = match cfg!(windows) {
+        true => ["cmd", "/C"],
+        false => ["sh", "-c"],
+    };
+    // Calls of `bw <command>` after reading the token through `bw`.
+    let teardown = |bw: &FakeBw, after: &str, command: &str| {
+        let args = ["--password-command", "pw", after];
+        let (credential, full) = provider(bw, index_url, &args);
+        let full: Vec<&str> = full.iter().map(String::as_str).collect();
+        match credential.perform(&registry(index_url), &Action::Get(Operation::Read), &full) {
+            Ok(CredentialResponse::Get { token, .. }) => {
+                assert_eq!(token.expose(), "token-teardown")
+            }
+            other => panic!("expected a token, got {:?}", other),
+        }
+        bw.calls_of(&[command])
+    };
+
+    // The provider unlocked the vault, so it locks it again.
+    let bw = FakeBw::with_items(vec![item.clone()]);
+    bw.state().status = "locked".into();
+    bw.program(&[shell[0], shell[1], "pw"], "hunter2");
+    let locks = teardown(&bw, "--lock-after", "lock");
+    assert_eq!(bw.calls_of(&["unlock"]).len(), 1);
+    assert_eq!(locks.len(), 1);
+    assert_eq!(locks[0].session.as_deref(), Some("fake-session-key"));
+    assert_eq!(bw.state().status, "locked");
+
+    let bw = FakeBw::with_items(vec![item.clone()]);
+    bw.state().status = "locked".into();
+    bw.program(&[shell[0], shell[1], "pw"], "hunter2");
+    assert_eq!(teardown(&bw, "--logout-after", "logout").len(), 1);
+    assert_eq!(bw.state().status, "unauthenticated");
+
+    // Whoever unlocked it before leaves it unlocked.
+    let bw = FakeBw::with_items(vec![item.clone()]);
+    assert!(teardown(&bw, "--lock-after", "lock").is_empty());
+    assert!(teardown(&bw, "--logout-after", "logout").is_empty());
+    assert!(bw.calls_of(&["unlock"]).is_empty());
+
+    // Also through `BW_SESSION`.
+    let bw = FakeBw::with_items(vec![item]);
+    bw.state().env_session = Some("env-session".into());
+    assert!(teardown(&bw, "--lock-after", "lock").is_empty());
+    assert!(teardown(&bw, "--logout-after", "logout").is_empty());
+    assert!(bw.calls_of(&["unlock"]).is_empty());
+    assert_eq!(bw.state().status, "unlocked");
+}
+
+#[test]
+fn token_formats_round_trip() {
+    let index_url = "sparse+https://bearer.example.com/index/";
+    let bw = FakeBw::new();
+    let args = ["--token-format", "Bearer {token}", "--force"];
+    let (credential, args) = provider(&bw, index_url, &args);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let registry = registry(index_url);
+    let login = |token: &'static str| {
+        Action::Login(LoginOptions {
+            token: Some(Secret::from(token)),
+            login_url: None,
+        })
+    };
+    let get = || match credential.perform(&registry, &Action::Get(Operation::Read), &args) {
+        Ok(CredentialResponse::Get { token, .. }) => token.expose(),
+        other => panic!("expected a token, got {:?}", other),
+    };
+
+    credential
+        .perform(&registry, &login("Bearer abc"), &args)
+        .unwrap();
+    assert_eq!(bw.state().items[0]["login"]["password"], "abc");
+    assert_eq!(get(), "Bearer abc");
+
+    // The bare token is stored the same.
+    credential.perform(&registry, &login("def"), &args).unwrap();
+    assert_eq!(bw.state().items.len(), 1);
+    assert_eq!(bw.state().items[0]["login"]["password"], "def");
+    assert_eq!(get(), "Bearer def");
+}
+
+#[test]
+fn lookups_are_cached_per_registry() {
+    let first = "sparse+https://first.cached.example.com/index/";
+    let second = "sparse+https://second.cached.example.com/index/";
+    let bw = FakeBw::with_items(vec![
+        fake::login(
+            "first",
+            "Cargo registry token for first.cached.example.com",
+            "https://first.cached.example.com/index",
+            "token-first",
+            OLD,
+        ),
+        fake::login(
+            "second",
+            "Cargo registry token for second.cached.example.com",
+            "https://second.cached.example.com/index",
+            "token-second",
+            OLD,
+        ),
+    ]);
+    let (provider, args) = provider(&bw, first, &["--force"]);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let get = Action::Get(Operation::Read);
+    let token = |index_url: &str| match provider.perform(&registry(index_url), &get, &args) {
+        Ok(CredentialResponse::Get { token, .. }) => token.expose(),
+        other => panic!("expected a token, got {:?}", other),
+    };
+    // Both registries use the same vault, as they have the same arguments.
+    let lookups = || bw.calls_of(&["list", "items"]).len() + bw.calls_of(&["get", "item"]).len();
+
+    assert_eq!(token(first), "token-first");
+    assert_eq!(token(second), "token-second");
+    let calls = bw.calls().len();
+    let looked_up = lookups();
+    assert_eq!(looked_up, 2);
+    assert_eq!(token(first), "token-first");
+    assert_eq!(token(second), "token-second");
+    assert_eq!(bw.calls().len(), calls);
+
+    // A Login forgets what was found for its registry only.
+    let login = Action::Login(LoginOptions {
+        token: Some(Secret::from("token-first-new")),
+        login_url: None,
+    });
+    provider.perform(&registry(first), &login, &args).unwrap();
+    let after_login = lookups();
+    assert_eq!(token(second), "token-second");
+    assert_eq!(lookups(), after_login);
+    assert_eq!(token(first), "token-first-new");
+    assert_eq!(lookups(), after_login + 1);
+    assert_eq!(token(first), "token-first-new");
+    assert_eq!(lookups(), after_login + 1);
+
+    // And so does a Logout.
+    provider
+        .perform(&registry(second), &Action::Logout, &args)
+        .unwrap();
+    let calls = bw.calls().len();
+    assert_eq!(token(first), "token-first-new");
+    assert_eq!(bw.calls().len(), calls);
+    assert!(matches!(
+        provider.perform(&registry(second), &get, &args),
+        Err(Error::NotFound)
+    ));
+    assert!(bw.calls().len() > calls);
+    let calls = bw.calls().len();
+    assert!(matches!(
+        provider.perform(&registry(second), &get, &args),
+        Err(Error::NotFound)
+    ));
+    assert_eq!(bw.calls().len(), calls);
+}
+
+#[test]
+fn spellings_of_a_registry_share_its_lookup() {
+    let bw = FakeBw::with_items(vec![fake::login(
+        "a",
+        "Cargo registry token for spelled.example.com",
+        "https://spelled.example.com/index",
+        "token-a",
+        OLD,
+    )]);
+    let index_url = "sparse+https://spelled.example.com/index/";
+    let (provider, args) = provider(&bw, index_url, &[]);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let get = Action::Get(Operation::Read);
+    provider.perform(&registry(index_url), &get, &args).unwrap();
+    let calls = bw.calls().len();
+    for index_url in [
+        "https://spelled.example.com/index",
+        "sparse+https://SPELLED.example.com:443/index",
+    ] {
+        provider.perform(&registry(index_url), &get, &args).unwrap();
+        assert_eq!(bw.calls().len(), calls, "{}", index_url);
+    }
+}
+
+#[test]
+fn args_files_are_overridden_by_later_arguments() {
+    let path = fake::temp_dir("args-file-overrides").join("args.txt");
+    std::fs::write(&path, "--uri-match host\n--no-notes\n").unwrap();
+    let file = format!("@{}", path.display());
+
+    let vault = parse(&[&file]).unwrap();
+    assert_eq!(vault.options.uri_match, MatchMode::Host);
+    assert!(vault.options.no_notes);
+    let vault = parse(&[&file, "--uri-match", "base"]).unwrap();
+    assert_eq!(vault.options.uri_match, MatchMode::Base);
+    let vault = parse(&["--uri-match", "base", &file]).unwrap();
+    assert_eq!(vault.options.uri_match, MatchMode::Host);
+}
+
+#[test]
+fn session_state_is_per_vault() {
+    let locked = FakeBw::new();
+    locked.state().status = "locked".into();
+    let unlocked = FakeBw::new();
+    let (a, b) = (locked.vault(&[]), unlocked.vault(&[]));
+    for _ in 0..2 {
+        assert_eq!(a.session_state(), VaultState::Locked);
+        assert_eq!(b.session_state(), VaultState::Unlocked);
+    }
+    assert_eq!(locked.calls_of(&["status"]).len(), 1);
+    assert_eq!(unlocked.calls_of(&["status"]).len(), 1);
+
+    // A vault made later checks again.
+    locked.state().status = "unlocked".into();
+    assert_eq!(locked.vault(&[]).session_state(), VaultState::Unlocked);
+}
+
+#[test]
+fn read_only_never_changes_the_vault() {
+    let item = |host: &str| {
+        fake::login(
+            host,
+            &format!("Cargo registry token for {}", host),
+            &format!("https://{}/index", host),
+            &format!("token-{}", host),
+            OLD,
+        )
+    };
+    let kept = "kept.read-only.example.com";
+    let trashed = "trashed.read-only.example.com";
+    let bw = FakeBw::with_items(vec![item(kept)]);
+    bw.state().trash.push(item(trashed));
+    let mutating = || {
+        bw.calls()
+            .into_iter()
+            .filter(|call| {
+                ["create", "edit", "delete", "restore", "sync"]
+                    .iter()
+                    .any(|word| call.is(&[word]))
+            })
+            .map(|call| call.args)
+            .collect::<Vec<_>>()
+    };
+
+    let index_url = format!("sparse+https://{}/index/", kept);
+    let (credential, args) = provider(&bw, &index_url, &["--read-only", "--force"]);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let registry = registry(&index_url);
+    match credential.perform(&registry, &Action::Get(Operation::Read), &args) {
+        Ok(CredentialResponse::Get { token, .. }) => {
+            assert_eq!(token.expose(), format!("token-{}", kept))
+        }
+        other => panic!("expected a token, got {:?}", other),
+    }
+    let login = Action::Login(LoginOptions {
+        token: Some(Secret::from("token-new")),
+        login_url: None,
+    });
+    for action in [login, Action::Logout] {
+        let e = credential
+            .perform(&registry, &action, &args)
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("configured with `--read-only`"), "{}", e);
+    }
+
+    // Below the provider, the vault refuses to run the commands itself.
+    let vault = bw.vault(&["--read-only", "--force"]);
+    for host in [kept, trashed, "new.read-only.example.com"] {
+        let index_url = format!("sparse+https://{}/index/", host);
+        let e = save(&vault, &index_url, "token-new")
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("refusing to run `bw "), "{}: {}", host, e);
+    }
+    let e = logout(&vault, &format!("sparse+https://{}/index/", kept))
+        .unwrap_err()
+        .to_string();
+    assert!(e.contains("refusing to run `bw delete"), "{}", e);
+    assert!(find(
+        &vault,
+        "sparse+https://missing.read-only.example.com/index/"
+    )
+    .unwrap()
+    .is_none());
+
+    assert_eq!(mutating(), Vec::<Vec<String>>::new());
+    assert_eq!(
+        bw.item(kept).unwrap()["login"]["password"],
+        format!("token-{}", kept)
+    );
+    assert_eq!(bw.state().trash.len(), 1);
+}
+
+fn logout(vault: &BitwardenVault, index_url: &str) -> Result<(), Error> {
+    let session = vault.connect()?;
+    vault.delete_token(&session, index_url)
+}
+
+#[test]
+fn keep_previous_grows_and_caps_the_history() {
+    let host = "history.keep.example.com";
+    let index_url = format!("sparse+https://{}/index/", host);
+    let bw = FakeBw::with_items(vec![fake::login(
+        host,
+        "my registry",
+        &format!("https://{}/index", host),
+        "token-0",
+        OLD,
+    )]);
+    let vault = bw.vault(&["--force", "--keep-previous"]);
+    let history = || -> Vec<String> {
+        bw.item(host).unwrap()["passwordHistory"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                assert!(entry["lastUsedDate"].is_string());
+                entry["password"].as_str().unwrap().to_string()
+            })
+            .collect()
+    };
+
+    for n in 1..=MAX_HISTORY {
+        save(&vault, &index_url, &format!("token-{}", n)).unwrap();
+        let expected: Vec<String> = (0..n).rev().map(|n| format!("token-{}", n)).collect();
+        assert_eq!(history(), expected);
+    }
+    // Past the cap the oldest entries fall off.
+    for n in MAX_HISTORY + 1..MAX_HISTORY + 3 {
+        save(&vault, &index_url, &format!("token-{}", n)).unwrap();
+        let expected: Vec<String> = (n - MAX_HISTORY..n)
+            .rev()
+            .map(|n| format!("token-{}", n))
+            .collect();
+        assert_eq!(history(), expected);
+    }
+    assert_eq!(
+        bw.item(host).unwrap()["login"]["password"],
+        format!("token-{}", MAX_HISTORY + 2)
+    );
+
+    // A token in a field is recorded under its name.
+    let vault = bw.vault(&["--force", "--keep-previous", "--field", "cargo-token"]);
+    save(&vault, &index_url, "field-1").unwrap();
+    save(&vault, &index_url, "field-2").unwrap();
+    assert_eq!(history()[0], "cargo-token: field-1");
+    assert_eq!(history().len(), MAX_HISTORY);
+}
+
+#[test]
+fn logout_trashes_or_purges() {
+    let item = |host: &str| {
+        let mut item = fake::login(
+            host,
+            &format!("Cargo registry token for {}", host),
+            &format!("https://{}/index", host),
+            "token-old",
+            OLD,
+        );
+        item["passwordHistory"] = json!([{ "password": "token-older", "lastUsedDate": OLD }]);
+        item
+    };
+    let trashed = "trashed.logout.example.com";
+    let purged = "purged.logout.example.com";
+    let bw = FakeBw::with_items(vec![item(trashed), item(purged)]);
+
+    logout(
+        &bw.vault(&[]),
+        &format!("sparse+https://{}/index/", trashed),
+    )
+    .unwrap();
+    let deletes = bw.calls_of(&["delete"]);
+    assert_eq!(deletes.len(), 1);
+    assert_eq!(deletes[0].args, ["delete", "item", trashed]);
+    assert_eq!(bw.state().trash.len(), 1);
+
+    logout(
+        &bw.vault(&["--purge"]),
+        &format!("sparse+https://{}/index/", purged),
+    )
+    .unwrap();
+    let deletes = bw.calls_of(&["delete"]);
+    assert_eq!(deletes.len(), 2);
+    assert_eq!(deletes[1].args, ["delete", "item", purged, "--permanent"]);
+    assert_eq!(bw.state().trash.len(), 1);
+    assert!(bw.item(purged).is_none());
+}
+
+#[test]
+fn purge_clears_the_password_history() {
+    let field = |token: &str| json!([{ "name": "cargo-token", "value": token, "type": 1, "linkedId": null }]);
+    let item = |host: &str| {
+        let mut item = fake::login(
+            host,
+            "my registry",
+            &format!("https://{}/index", host),
+            "",
+            OLD,
+        );
+        item["fields"] = field("token-old");
+        item["passwordHistory"] = json!([{ "password": "token-older", "lastUsedDate": OLD }]);
+        item
+    };
+    let kept = "kept.history.example.com";
+    let cleared = "cleared.history.example.com";
+    let bw = FakeBw::with_items(vec![item(kept), item(cleared)]);
+
+    let vault = bw.vault(&["--field", "cargo-token"]);
+    logout(&vault, &format!("sparse+https://{}/index/", kept)).unwrap();
+    let stored = bw.item(kept).unwrap();
+    assert_eq!(stored["fields"], json!([]));
+    assert_eq!(stored["passwordHistory"][0]["password"], "token-older");
+
+    let vault = bw.vault(&["--field", "cargo-token", "--purge"]);
+    logout(&vault, &format!("sparse+https://{}/index/", cleared)).unwrap();
+    let stored = bw.item(cleared).unwrap();
+    assert_eq!(stored["fields"], json!([]));
+    assert_eq!(stored["passwordHistory"], json!([]));
+    assert!(bw.calls_of(&["delete"]).is_empty());
+}
+
+#[test]
+fn network_failures_are_retried() {
+    let bw = FakeBw::with_items(vec![fake::login(
+        "a",
+        "Cargo registry token for retried.example.com",
+        "https://retried.example.com/index",
+        "token-a",
+        OLD,
+    )]);
+    let index_url = "sparse+https://retried.example.com/index/";
+    let offline = "request to https://api.bitwarden.com failed, reason: getaddrinfo ENOTFOUND";
+    let searches = || bw.calls_of(&["list", "items"]).len();
+
+    bw.fail(&["list", "items"], offline, 2);
+    let vault = bw.vault(&["--retries", "2"]);
+    assert_eq!(
+        find(&vault, index_url).unwrap(),
+        Some("token-a".to_string())
+    );
+    assert_eq!(searches(), 3);
+
+    bw.fail(&["list", "items"], offline, 5);
+    let vault = bw.vault(&["--retries", "2"]);
+    let e = find(&vault, index_url).unwrap_err().to_string();
+    assert!(e.contains("ENOTFOUND"), "{}", e);
+    assert!(e.contains("(gave up after 3 attempts)"), "{}", e);
+    assert_eq!(searches(), 6);
+    bw.fail(&["list", "items"], offline, 0);
+}
+
+#[test]
+fn other_failures_are_not_retried() {
+    let bw = FakeBw::new();
+    let index_url = "sparse+https://not-retried.example.com/index/";
+    let searches = || bw.calls_of(&["list", "items"]).len();
+
+    bw.fail(&["list", "items"], "Vault is locked.", 1);
+    let vault = bw.vault(&["--retries", "3"]);
+    let e = find(&vault, index_url).unwrap_err().to_string();
+    assert!(!e.contains("attempts"), "{}", e);
+    assert_eq!(searches(), 1);
+
+    bw.fail(
+        &["list", "items"],
+        "You do not have permission to view this.",
+        1,
+    );
+    let vault = bw.vault(&["--retries", "3"]);
+    assert!(find(&vault, index_url).is_err());
+    assert_eq!(searches(), 2);
+
+    // Except for `bw sync`, which fails for any reason a network may cause.
+    bw.fail(&["sync"], "Unexpected token < in JSON at position 0", 1);
+    let vault = bw.vault(&["--retries", "1", "--sync"]);
+    assert_eq!(find(&vault, index_url).unwrap(), None);
+    assert_eq!(bw.calls_of(&["sync"]).len(), 2);
+}
+
+#[test]
+fn commands_past_the_timeout_are_killed() {
+    let bw = FakeBw::new();
+    let index_url = "sparse+https://timed-out.example.com/index/";
+    bw.delay(&["list", "items"], Duration::from_secs(3600));
+
+    let vault = bw.vault(&["--timeout", "5", "--retries", "3"]);
+    let e = find(&vault, index_url).unwrap_err().to_string();
+    assert!(
+        e.contains("`bw list items` timed out after 5 seconds"),
+        "{}",
+        e
+    );
+    assert!(e.contains("`--timeout 0`"), "{}", e);
+    // Like a network that stopped answering.
+    assert!(e.contains("(gave up after 4 attempts)"), "{}", e);
+    assert_eq!(bw.calls_of(&["list", "items"]).len(), 4);
+
+    bw.delay(&["list", "items"], Duration::from_millis(10));
+    let vault = bw.vault(&["--timeout", "5", "--retries", "3"]);
+    assert_eq!(find(&vault, index_url).unwrap(), None);
+    assert_eq!(bw.calls_of(&["list", "items"]).len(), 5);
+}
+
+#[test]
+fn slow_lookups_get_a_hint() {
+    let bw = FakeBw::new();
+    let index_url = "sparse+https://slow.example.com/index/";
+    bw.delay(&["list", "items"], Duration::from_millis(30));
+
+    let vault = bw.vault(&["--slow-threshold", "0.02"]);
+    assert_eq!(find(&vault, index_url).unwrap(), None);
+    let hint = vault.slow_hint().unwrap();
+    assert!(hint.contains("most of it in `bw list`"), "{}", hint);
+    assert!(hint.contains("consider `--item-id`"), "{}", hint);
+
+    let vault = bw.vault(&["--slow-threshold", "10"]);
+    assert_eq!(find(&vault, index_url).unwrap(), None);
+    assert_eq!(vault.slow_hint(), None);
+}
+
+#[test]
+fn tokens_are_cached_by_where_the_session_came_from() {
+    let bw = FakeBw::new();
+    let own = Some("own-session".to_string());
+    // Seconds cargo may keep the token for, or whether it may at all.
+    let cached =
+        |args: &[&str], session: &Option<String>| match bw.vault(args).cache_control(session) {
+            CacheControl::Expires { expiration } => {
+                let left = expiration - time::OffsetDateTime::now_utc();
+                Ok(left.as_seconds_f64().round() as i64)
+            }
+            CacheControl::Session => Err(true),
+            CacheControl::Never => Err(false),
+            _ => panic!("unknown cache control"),
+        };
+
+    // Sessions the provider signed in for live as long as it.
+    assert_eq!(cached(&[], &own), Err(true));
+    assert_eq!(cached(&["--env-session-cache", "0"], &own), Err(true));
+    assert_eq!(cached(&["--cache-ttl", "300"], &own), Ok(300));
+    assert_eq!(cached(&["--cache", "never"], &own), Err(false));
+
+    // Those of `BW_SESSION` may be locked at any time.
+    assert_eq!(cached(&[], &None), Ok(60));
+    assert_eq!(cached(&["--env-session-cache", "10"], &None), Ok(10));
+    assert_eq!(cached(&["--env-session-cache", "0"], &None), Err(false));
+    assert_eq!(cached(&["--cache-ttl", "30"], &None), Ok(30));
+    assert_eq!(cached(&["--cache-ttl", "300"], &None), Ok(60));
+    assert_eq!(cached(&["--cache", "never"], &None), Err(false));
+}
+
+#[test]
+fn expired_tokens_are_refused() {
+    let url = "https://expired.example.com/index";
+    let index_url = "sparse+https://expired.example.com/index/";
+    let mut item = fake::login("expired", "expired", url, "token-expired", OLD);
+    item["fields"] = json!([
+        { "name": EXPIRES_FIELD, "value": "2024-01-02T00:00:00Z", "type": 0 },
+    ]);
+    let bw = FakeBw::with_items(vec![item]);
+    let (credential, args) = provider(&bw, index_url, &[]);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let get = Action::Get(Operation::Read);
+
+    let e = credential
+        .perform(&registry(index_url), &get, &args)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        e.contains("expired on 2024-01-02T00:00:00Z; run `cargo login`"),
+        "{}",
+        e
+    );
+
+    // Until then, cargo keeps it for no longer.
+    let later = time::OffsetDateTime::now_utc() + time::Duration::seconds(30);
+    let later = later.replace_nanosecond(0).unwrap();
+    let expires = later
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap();
+    bw.state().items[0]["fields"][0]["value"] = expires.into();
+    match credential.perform(&registry(index_url), &get, &args) {
+        Ok(CredentialResponse::Get {
+            cache: CacheControl::Expires { expiration },
+            ..
+        }) => assert_eq!(expiration, later),
+        other => panic!("expected a token until {}, got {:?}", later, other),
+    }
+}
+
+#[test]
+fn expiry_edges() {
+    let now = time::OffsetDateTime::now_utc();
+    assert_eq!(expiry(now, Duration::ZERO), Some(now));
+    assert_eq!(
+        expiry(now, Duration::from_millis(1500)),
+        Some(now + time::Duration::milliseconds(1500))
+    );
+    // Past what a date can hold.
+    assert_eq!(expiry(now, Duration::from_secs(u64::MAX)), None);
+    assert_eq!(
+        expiry(now, Duration::from_secs(400 * 365 * 86400 * 100)),
+        None
+    );
+
+    let soon = now + time::Duration::minutes(1);
+    let hour = CacheControl::Expires {
+        expiration: now + time::Duration::hours(1),
+    };
+    let until_soon = CacheControl::Expires { expiration: soon };
+    assert_eq!(cache_until(hour.clone(), Some(soon)), until_soon);
+    assert_eq!(cache_until(CacheControl::Session, Some(soon)), until_soon);
+    assert_eq!(
+        cache_until(CacheControl::Never, Some(soon)),
+        CacheControl::Never
+    );
+    assert_eq!(cache_until(hour.clone(), None), hour);
+}
+
+#[test]
+fn usernames_of_new_items() {
+    let bw = FakeBw::new();
+    let username = |host: &str, args: &[&str]| {
+        let index_url = format!("sparse+https://{}/index/", host);
+        save(&bw.vault(args), &index_url, "token").unwrap();
+        let items = bw.state().items.clone();
+        let item = items
+            .iter()
+            .find(|item| item["login"]["uris"][0]["uri"] == format!("https://{}/index", host));
+        item.unwrap()["login"]["username"].clone()
+    };
+
+    assert_eq!(username("no-username.example.com", &[]), Value::Null);
+    assert_eq!(
+        username("literal-username.example.com", &["--username", "ci-bot"]),
+        "ci-bot"
+    );
+    assert_eq!(
+        username(
+            "email-username.example.com",
+            &["--username", "email", "--email", "me@example.com"]
+        ),
+        "me@example.com"
+    );
+    let e = parse(&["--username", "email"]).err().unwrap().to_string();
+    assert!(e.contains("`--username email` requires `--email`"), "{}", e);
+}
+
+#[test]
+fn no_create_needs_an_item_to_exist() {
+    let item = |host: &str| {
+        fake::login(
+            host,
+            &format!("Cargo registry token for {}", host),
+            &format!("https://{}/index", host),
+            "token-old",
+            OLD,
+        )
+    };
+    let existing = "existing.no-create.example.com";
+    let trashed = "trashed.no-create.example.com";
+    let missing = "missing.no-create.example.com";
+    let bw = FakeBw::with_items(vec![item(existing)]);
+    bw.state().trash.push(item(trashed));
+    let vault = bw.vault(&["--no-create", "--force"]);
+    let index_url = |host: &str| format!("sparse+https://{}/index/", host);
+
+    let e = save(&vault, &index_url(missing), "token-new")
+        .unwrap_err()
+        .to_string();
+    assert!(e.contains("`--no-create` forbids creating one"), "{}", e);
+    assert!(e.contains(missing), "{}", e);
+    assert!(bw.calls_of(&["create"]).is_empty());
+
+    // Items that are there, even in the trash, still get the token.
+    save(&vault, &index_url(existing), "token-existing").unwrap();
+    save(&vault, &index_url(trashed), "token-trashed").unwrap();
+    assert!(bw.calls_of(&["create"]).is_empty());
+    assert_eq!(bw.state().items.len(), 2);
+    for (host, token) in [(existing, "token-existing"), (trashed, "token-trashed")] {
+        assert_eq!(bw.item(host).unwrap()["login"]["password"], token);
+    }
+}
+
+#[test]
+fn no_create_is_set_per_registry_in_the_config_file() {
+    let path = fake::temp_dir("no-create-config").join("config.toml");
+    std::fs::write(&path, "[registries.provisioned]\nno-create = true\n").unwrap();
+    let config = ["--config", path.to_str().unwrap()];
+    let bw = FakeBw::new();
+    let save_new = |name: &'static str, host: &str| {
+        let index_url = format!("sparse+https://{}/index/", host);
+        let registry = RegistryInfo {
+            name: Some(name),
+            ..registry(&index_url)
+        };
+        let args = registry_args(&registry, &config).unwrap();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let vault = bw.vault(&args);
+        let session = vault.connect().unwrap();
+        vault.save_token(&session, &registry, Secret::from("token-new".to_string()))
+    };
+
+    let e = save_new("provisioned", "provisioned.no-create.example.com")
+        .unwrap_err()
+        .to_string();
+    assert!(e.contains("`--no-create`"), "{}", e);
+    save_new("other", "other.no-create.example.com").unwrap();
+    assert_eq!(bw.calls_of(&["create"]).len(), 1);
+}
+
+#[test]
+fn tokens_are_validated() {
+    let long = "a".repeat(64 * 1024);
+    for (token, expected) in [
+        ("cio1234567890", Ok("cio1234567890")),
+        (
+            "Bearer eyJhbGciOi.J9.eyJzdWIiOi",
+            Ok("Bearer eyJhbGciOi.J9.eyJzdWIiOi"),
+        ),
+        ("  token\n", Ok("token")),
+        ("token\r\n", Ok("token")),
+        ("\ttoken \u{a0}", Ok("token")),
+        // Nothing is cut off long tokens, like JWTs.
+        (&long, Ok(&long[..])),
+        ("", Err("the token is empty")),
+        (" \r\n\t", Err("the token is empty")),
+        ("to\nken", Err("the token contains the character '\\n'")),
+        ("to\tken", Err("the token contains the character '\\t'")),
+        (
+            "to\u{a0}ken",
+            Err("the token contains the character '\\u{a0}'"),
+        ),
+        (
+            "to\u{1b}[0mken",
+            Err("the token contains the character '\\u{1b}'"),
+        ),
+        ("token\0", Err("the token contains the character '\\0'")),
+    ] {
+        let expected = expected.map_err(str::to_string);
+        assert_eq!(validate_token(token), expected, "{:?}", token);
+    }
+}
+
+#[test]
+fn large_payloads_are_encoded_whole() {
+    let bw = FakeBw::new();
+    let vault = bw.vault(&[]);
+    // Larger than the buffer of a pipe.
+    let payload: Vec<u8> = (0..100 * 1024).map(|i| (i % 251) as u8).collect();
+    let encoded = vault.encode(&None, &payload).unwrap();
+    assert_eq!(encoded, fake::encode(&payload));
+    assert_eq!(fake::decode(&encoded).unwrap(), payload);
+    let encodes = bw.calls_of(&["encode"]);
+    assert_eq!(encodes[0].input.as_deref(), Some(&payload[..]));
+}
+
+#[test]
+fn the_cli_is_found_on_first_use() {
+    let missing = fake::temp_dir("no-cli").join("bw");
+    let missing = missing.to_str().unwrap();
+    let vault = BitwardenVault::new(&["--bw-path", missing]).unwrap();
+    let Err(e) = vault.connect() else {
+        panic!("connected without a CLI");
+    };
+    let e = e.to_string();
+    assert!(e.contains(locate::NOT_FOUND), "{}", e);
+    assert!(e.contains(missing), "{}", e);
+}
+
+#[test]
+fn file_registries_round_trip() {
+    let index_url = "sparse+file:///home/me/round-trip/registry/";
+    let bw = FakeBw::new();
+    let (provider, args) = provider(&bw, index_url, &["--create-uri-match", "host"]);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let registry = registry(index_url);
+    let get = Action::Get(Operation::Read);
+    let login = Action::Login(LoginOptions {
+        token: Some(Secret::from("token-file")),
+        login_url: None,
+    });
+
+    assert!(matches!(
+        provider.perform(&registry, &login, &args),
+        Ok(CredentialResponse::Login)
+    ));
+    let item = bw.state().items[0].clone();
+    assert_eq!(item["name"], "Cargo registry token for registry");
+    // A path has no host to match by.
+    assert_eq!(
+        item["login"]["uris"],
+        json!([{ "uri": "file:///home/me/round-trip/registry", "match": 3 }])
+    );
+
+    match provider.perform(&registry, &get, &args) {
+        Ok(CredentialResponse::Get { token, .. }) => assert_eq!(token.expose(), "token-file"),
+        other => panic!("expected a token, got {:?}", other),
+    }
+    let vault = bw.vault(&[]);
+    assert_eq!(
+        find(&vault, "file:///home/me/round-trip/registry").unwrap(),
+        Some("token-file".to_string())
+    );
+    assert_eq!(
+        find(&vault, "sparse+file:///home/me/registry/").unwrap(),
+        None
+    );
+
+    assert!(matches!(
+        provider.perform(&registry, &Action::Logout, &args),
+        Ok(CredentialResponse::Logout)
+    ));
+    assert!(bw.state().items.is_empty());
+    assert!(matches!(
+        provider.perform(&registry, &get, &args),
+        Err(Error::NotFound)
+    ));
+}
+
+#[test]
+fn tokens_of_username_and_password() {
+    let index_url = "sparse+https://compose.example.com/index/";
+    let bw = FakeBw::new();
+    let login = |args: &[&str], token: &str| {
+        let (provider, args) = provider(&bw, index_url, args);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let login = Action::Login(LoginOptions {
+            token: Some(Secret::from(token)),
+            login_url: None,
+        });
+        provider.perform(&registry(index_url), &login, &args)
+    };
+    let get = |args: &[&str]| {
+        let (provider, args) = provider(&bw, index_url, args);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let get = Action::Get(Operation::Read);
+        match provider.perform(&registry(index_url), &get, &args) {
+            Ok(CredentialResponse::Get { token, .. }) => Ok(token.expose()),
+            Ok(other) => panic!("expected a token, got {:?}", other),
+            Err(e) => Err(e.to_string()),
+        }
+    };
+
+    login(&["--split-token", ":"], "me:api-key").unwrap();
+    let item = bw.state().items[0].clone();
+    assert_eq!(item["login"]["username"], "me");
+    assert_eq!(item["login"]["password"], "api-key");
+    let compose = ["--compose-token", "{username}:{password}"];
+    assert_eq!(get(&compose).unwrap(), "me:api-key");
+    assert_eq!(get(&[]).unwrap(), "api-key");
+    assert_eq!(redact::redact("sent me:api-key"), "sent <redacted>");
+
+    // Logging in again replaces both parts.
+    login(&["--split-token", ":", "--force"], "you:other-key").unwrap();
+    assert_eq!(get(&compose).unwrap(), "you:other-key");
+    let e = login(&["--split-token", ":", "--force"], "no-separator").unwrap_err();
+    assert!(e.to_string().contains("`--split-token` expects"), "{}", e);
+    assert_eq!(get(&compose).unwrap(), "you:other-key");
+
+    // The template says what the item must have.
+    bw.state().items[0]["login"]["username"] = Value::Null;
+    let e = get(&compose).unwrap_err();
+    assert!(e.contains("has no username"), "{}", e);
+    assert!(!e.contains("other-key"), "{}", e);
+    assert_eq!(
+        get(&["--compose-token", "Basic {password}"]).unwrap(),
+        "Basic other-key"
+    );
+}
+
+#[test]
+fn logins_restore_trashed_items() {
+    let index_url = "sparse+https://restore.example.com/index/";
+    let url = "https://restore.example.com/index";
+    let bw = FakeBw::new();
+    let trashed = fake::login(
+        "old",
+        "Cargo registry token for restore",
+        url,
+        "token-old",
+        OLD,
+    );
+    bw.state().trash.push(trashed);
+
+    save(&bw.vault(&["--force"]), index_url, "token-new").unwrap();
+    let state = bw.state();
+    assert!(state.trash.is_empty());
+    assert_eq!(state.items.len(), 1);
+    assert_eq!(state.items[0]["id"], "old");
+    assert_eq!(state.items[0]["login"]["password"], "token-new");
+    drop(state);
+    let restored = bw
+        .calls()
+        .iter()
+        .position(|call| call.is(&["restore", "item", "old"]));
+    let edited = bw
+        .calls()
+        .iter()
+        .position(|call| call.is(&["edit", "item", "old"]));
+    assert!(restored.unwrap() < edited.unwrap());
+    assert!(bw.calls_of(&["create", "item"]).is_empty());
+
+    // Unless asked not to.
+    let bw = FakeBw::new();
+    let trashed = fake::login(
+        "old",
+        "Cargo registry token for restore",
+        url,
+        "token-old",
+        OLD,
+    );
+    bw.state().trash.push(trashed);
+    save(&bw.vault(&["--no-restore"]), index_url, "token-new").unwrap();
+    let state = bw.state();
+    assert_eq!(state.trash.len(), 1);
+    assert_eq!(state.items.len(), 1);
+    assert_ne!(state.items[0]["id"], "old");
+    drop(state);
+    assert!(bw.calls_of(&["restore", "item"]).is_empty());
+}
+
+#[test]
+fn logins_prefer_live_items_to_trashed_ones() {
+    let index_url = "sparse+https://live-and-trashed.example.com/index/";
+    let url = "https://live-and-trashed.example.com/index";
+    let name = "Cargo registry token for live-and-trashed";
+    let bw = FakeBw::with_items(vec![fake::login("live", name, url, "token-live", OLD)]);
+    // Changed more recently, which would win among trashed items.
+    let trashed = fake::login("old", name, url, "token-old", "2025-01-01T00:00:00.000Z");
+    bw.state().trash.push(trashed);
+
+    let vault = bw.vault(&["--force"]);
+    assert_eq!(
+        find(&vault, index_url).unwrap(),
+        Some("token-live".to_string())
+    );
+    save(&vault, index_url, "token-new").unwrap();
+    let state = bw.state();
+    assert_eq!(state.items.len(), 1);
+    assert_eq!(state.items[0]["id"], "live");
+    assert_eq!(state.items[0]["login"]["password"], "token-new");
+    assert_eq!(state.trash.len(), 1);
+    assert_eq!(state.trash[0]["login"]["password"], "token-old");
+    drop(state);
+    assert!(bw.calls_of(&["restore", "item"]).is_empty());
+    assert!(bw.calls_of(&["create", "item"]).is_empty());
+}
+
+/// A vault signing in with the askpass helper's answers, and the status
+/// it signs in from.
+fn askpass_vault(bw: &FakeBw, password: &str) -> (BitwardenVault, Status) {
+    let prompt = "Bitwarden master password for me@example.com: ";
+    bw.program(&["askpass", prompt], password);
+    let vault = bw.vault(&["--askpass", "askpass", "--email", "me@example.com"]);
+    let status = vault.status().unwrap();
+    (vault, status)
+}
+
+#[test]
+fn askpass_signs_in() {
+    let bw = FakeBw::new();
+    bw.state().status = "locked".into();
+    let (vault, status) = askpass_vault(&bw, "hunter2");
+    assert_eq!(
+        vault.signin_askpass(&status).unwrap().as_deref(),
+        Some("fake-session-key")
+    );
+    let unlocks = bw.calls_of(&["unlock"]);
+    assert_eq!(unlocks.len(), 1);
+    assert!(!unlocks[0].has("hunter2"));
+
+    let bw = FakeBw::new();
+    bw.state().status = "unauthenticated".into();
+    let (vault, status) = askpass_vault(&bw, "hunter2");
+    vault.signin_askpass(&status).unwrap();
+    let logins = bw.calls_of(&["login", "me@example.com"]);
+    assert_eq!(logins.len(), 1);
+    assert!(!logins[0].has("--code"));
+}
+
+#[test]
+fn askpass_reports_a_wrong_password() {
+    let bw = FakeBw::new();
+    bw.state().status = "locked".into();
+    let (vault, status) = askpass_vault(&bw, "wrong");
+    let e = vault.signin_askpass(&status).unwrap_err().to_string();
+    assert!(e.contains("Invalid master password."), "{}", e);
+    assert!(!e.contains("wrong"), "{}", e);
+    assert_eq!(bw.calls_of(&["unlock"]).len(), 1);
+
+    // Cancelling the helper is no answer at all.
+    let bw = FakeBw::new();
+    bw.state().status = "locked".into();
+    let vault = bw.vault(&["--askpass", "askpass"]);
+    let e = vault.signin_askpass(&status).unwrap_err().to_string();
+    assert!(e.contains("gave no answer"), "{}", e);
+    assert!(bw.calls_of(&["unlock"]).is_empty());
+}
+
+#[test]
+fn askpass_answers_two_step_challenges() {
+    let bw = FakeBw::new();
+    bw.state().status = "unauthenticated".into();
+    bw.fail(&["login"], "Code is required.", 1);
+    bw.program(&["askpass", "Bitwarden two-step login code: "], "123456");
+    let (vault, status) = askpass_vault(&bw, "hunter2");
+    assert!(vault.signin_askpass(&status).unwrap().is_some());
+
+    let logins = bw.calls_of(&["login", "me@example.com"]);
+    assert_eq!(logins.len(), 2);
+    assert!(!logins[0].has("--code"));
+    assert_eq!(logins[1].option("--code"), Some("123456"));
+    assert_eq!(logins[1].option("--method"), Some("0"));
+}
+
+#[test]
+fn unrelated_failures_ask_for_no_code() {
+    let bw = FakeBw::new();
+    bw.state().status = "unauthenticated".into();
+    bw.fail(&["login"], "Login failed with error code 500.", 1);
+    bw.program(&["askpass", "Bitwarden two-step login code: "], "123456");
+    let (vault, status) = askpass_vault(&bw, "hunter2");
+    let e = vault.signin_askpass(&status).unwrap_err().to_string();
+    assert!(e.contains("Login failed with error code 500."), "{}", e);
+    assert_eq!(bw.calls_of(&["login"]).len(), 1);
+    assert!(!bw
+        .calls()
+        .iter()
+        .any(|call| call.is(&["Bitwarden two-step login code: "])));
+}
+
+#[test]
+fn background_syncs_finish_after_the_answer() {
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    let index_url = "sparse+https://background.example.com/index/";
+    let url = "https://background.example.com/index";
+    let dir = fake::temp_dir("background-sync");
+    let dir_arg = dir.to_str().unwrap();
+    let args = ["--sync", "--background-sync", "--appdata-dir", dir_arg];
+    let stamp = state::SyncStamp::new(&dir);
+    let get = Action::Get(Operation::Read);
+    let item = fake::login("bg", "Cargo registry token for bg", url, "token-bg", OLD);
+
+    // A failed sync only warns, and leaves the stamp alone.
+    let bw = FakeBw::with_items(vec![item.clone()]);
+    bw.fail(&["sync"], "fetch failed", 1);
+    let (credential, full) = provider(&bw, index_url, &args);
+    let full: Vec<&str> = full.iter().map(String::as_str).collect();
+    assert!(matches!(
+        credential.perform(&registry(index_url), &get, &full),
+        Ok(CredentialResponse::Get { .. })
+    ));
+    background::finish(background::MAX_WAIT);
+    assert_eq!(bw.calls_of(&["sync"]).len(), 1);
+    assert!(!stamp.is_fresh(Duration::from_secs(60)));
+
+    // The answer comes before the sync is done, which records it.
+    let bw = FakeBw::with_items(vec![item]);
+    bw.state().background_delay = Duration::from_millis(300);
+    let finished = Arc::clone(&bw.state().background_finished);
+    let (credential, full) = provider(&bw, index_url, &args);
+    let full: Vec<&str> = full.iter().map(String::as_str).collect();
+    match credential.perform(&registry(index_url), &get, &full) {
+        Ok(CredentialResponse::Get { token, .. }) => assert_eq!(token.expose(), "token-bg"),
+        other => panic!("expected a token, got {:?}", other),
+    }
+    assert_eq!(bw.calls_of(&["sync"]).len(), 1);
+    assert_eq!(finished.load(Ordering::SeqCst), 0);
+    background::finish(background::MAX_WAIT);
+    assert_eq!(finished.load(Ordering::SeqCst), 1);
+    assert!(stamp.is_fresh(Duration::from_secs(60)));
+}
+
+#[test]
+fn remembered_items_are_fetched_by_id() {
+    let index_url = "sparse+https://remembered.example.com/index/";
+    let url = "https://remembered.example.com/index";
+    let name = "Cargo registry token for remembered";
+    let key = uri_key(index_url);
+    item_cache::evict(&key);
+    let bw = FakeBw::with_items(vec![fake::login("first", name, url, "token-1", OLD)]);
+    let vault = bw.vault(&[]);
+    let session = vault.connect().unwrap();
+    let lookup = || {
+        let before = bw.calls().len();
+        let item = vault.find_cached(&session.0, index_url, None).unwrap();
+        let calls: Vec<Vec<String>> = bw.calls()[before..]
+            .iter()
+            .map(|call| call.args.clone())
+            .collect();
+        (item.map(|item| item.id), calls)
+    };
+
+    // Found by a search, then by its id alone.
+    let (id, calls) = lookup();
+    assert_eq!(id.as_deref(), Some("first"));
+    assert!(calls.iter().any(|args| args.starts_with(&["list".into()])));
+    assert_eq!(item_cache::get(&key).as_deref(), Some("first"));
+    let (id, calls) = lookup();
+    assert_eq!(id.as_deref(), Some("first"));
+    assert_eq!(calls, [["get", "item", "first"]]);
+
+    // A deleted item is searched for again.
+    let second = fake::login("second", name, url, "token-2", OLD);
+    bw.state().items = vec![second];
+    let (id, calls) = lookup();
+    assert_eq!(id.as_deref(), Some("second"));
+    assert!(calls.iter().any(|args| args.starts_with(&["list".into()])));
+    assert_eq!(item_cache::get(&key).as_deref(), Some("second"));
+
+    // As is one that no longer has the registry's URL.
+    bw.state().items[0]["login"]["uris"][0]["uri"] = "https://elsewhere.example.com/".into();
+    let third = fake::login("third", name, url, "token-3", OLD);
+    bw.state().items.push(third);
+    let (id, calls) = lookup();
+    assert_eq!(id.as_deref(), Some("third"));
+    assert_eq!(calls[0], ["get", "item", "second"]);
+    assert_eq!(item_cache::get(&key).as_deref(), Some("third"));
+
+    // Logging out forgets it.
+    let (credential, args) = provider(&bw, index_url, &[]);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    assert!(matches!(
+        credential.perform(&registry(index_url), &Action::Logout, &args),
+        Ok(CredentialResponse::Logout)
+    ));
+    assert_eq!(item_cache::get(&key), None);
+}
+
+#[test]
+fn logout_removes_only_the_token_from_items_of_the_user() {
+    let index_url = "sparse+https://surgical.example.com/index/";
+    let url = "https://surgical.example.com/index";
+    let item = || {
+        let mut item = fake::login("web", "surgical web login", url, "token-old", OLD);
+        item["login"]["username"] = "me".into();
+        item["fields"] = json!([{ "name": "account", "value": "me", "type": 0, "linkedId": null }]);
+        item
+    };
+
+    // The item holds more than the token, so only the token goes.
+    let bw = FakeBw::with_items(vec![item()]);
+    logout(&bw.vault(&[]), index_url).unwrap();
+    assert!(bw.calls_of(&["delete"]).is_empty());
+    let kept = bw.item("web").unwrap();
+    assert!(kept["deletedDate"].is_null());
+    assert_eq!(kept["login"]["password"], Value::Null);
+    assert_eq!(kept["login"]["username"], "me");
+    assert_eq!(kept["login"]["uris"], json!([]));
+    assert_eq!(kept["fields"][0]["name"], "account");
+
+    // With `--field`, only that field.
+    let bw = FakeBw::with_items(vec![item()]);
+    bw.state().items[0]["fields"].as_array_mut().unwrap().push(
+        json!({ "name": "cargo-token", "value": "token-field", "type": 1, "linkedId": null }),
+    );
+    logout(&bw.vault(&["--field", "cargo-token"]), index_url).unwrap();
+    let kept = bw.item("web").unwrap();
+    assert_eq!(
+        kept["fields"],
+        json!([{ "name": "account", "value": "me", "type": 0, "linkedId": null }])
+    );
+    assert_eq!(kept["login"]["password"], "token-old");
+    assert_eq!(kept["login"]["uris"][0]["uri"], url);
+
+    // Unless asked to delete the whole item.
+    let bw = FakeBw::with_items(vec![item()]);
+    logout(&bw.vault(&["--delete-item"]), index_url).unwrap();
+    assert_eq!(bw.calls_of(&["delete", "item", "web"]).len(), 1);
+    assert!(bw.state().items.is_empty());
+}
+
+#[test]
+fn logout_deletes_items_of_the_provider() {
+    let index_url = "sparse+https://whole.example.com/index/";
+    let url = "https://whole.example.com/index";
+    let mut item = fake::login("own", "Cargo registry token for whole", url, "token", OLD);
+    item["login"]["username"] = "me".into();
+    item["fields"] = json!([{ "name": "account", "value": "me", "type": 0, "linkedId": null }]);
+    let bw = FakeBw::with_items(vec![item]);
+    logout(&bw.vault(&[]), index_url).unwrap();
+    assert_eq!(bw.calls_of(&["delete", "item", "own"]).len(), 1);
+    assert!(bw.calls_of(&["edit"]).is_empty());
+    assert!(bw.state().items.is_empty());
+    assert_eq!(bw.state().trash.len(), 1);
+}
+
+#[test]
+fn logout_prunes_the_uris_of_shared_items() {
+    let primary = "https://primary.prune.example.com/index";
+    let mirror = "https://mirror.prune.example.com/index";
+    let shared = || {
+        let mut item = fake::login(
+            "shared",
+            "Cargo registry token for prune",
+            primary,
+            "token-shared",
+            OLD,
+        );
+        item["login"]["uris"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({ "uri": mirror, "match": null }));
+        item
+    };
+    let uris = |bw: &FakeBw| -> Vec<String> {
+        bw.item("shared").unwrap()["login"]["uris"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|uri| uri["uri"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    // A single URI: the item goes.
+    let bw = FakeBw::with_items(vec![fake::login(
+        "single",
+        "Cargo registry token for single",
+        "https://single.prune.example.com/index",
+        "token-single",
+        OLD,
+    )]);
+    logout(
+        &bw.vault(&[]),
+        "sparse+https://single.prune.example.com/index/",
+    )
+    .unwrap();
+    assert_eq!(bw.calls_of(&["delete", "item", "single"]).len(), 1);
+    assert!(bw.calls_of(&["edit"]).is_empty());
+    assert_eq!(bw.state().trash.len(), 1);
+
+    // Several: only the registry's URI goes, and the token stays.
+    let bw = FakeBw::with_items(vec![shared()]);
+    logout(
+        &bw.vault(&[]),
+        "sparse+https://mirror.prune.example.com/index/",
+    )
+    .unwrap();
+    assert!(bw.calls_of(&["delete"]).is_empty());
+    assert_eq!(bw.calls_of(&["edit", "item", "shared"]).len(), 1);
+    assert_eq!(uris(&bw), [primary]);
+    assert_eq!(
+        bw.item("shared").unwrap()["login"]["password"],
+        "token-shared"
+    );
+    assert_eq!(
+        find(
+            &bw.vault(&[]),
+            "sparse+https://primary.prune.example.com/index/"
+        )
+        .unwrap()
+        .as_deref(),
+        Some("token-shared")
+    );
+
+    // Pruning the last one leaves nothing, so the item goes after all.
+    logout(
+        &bw.vault(&[]),
+        "sparse+https://primary.prune.example.com/index/",
+    )
+    .unwrap();
+    assert_eq!(bw.calls_of(&["delete", "item", "shared"]).len(), 1);
+    assert!(bw.state().items.is_empty());
+    assert_eq!(bw.state().trash.len(), 1);
+
+    // `--delete-item` deletes a shared item right away.
+    let bw = FakeBw::with_items(vec![shared()]);
+    logout(
+        &bw.vault(&["--delete-item"]),
+        "sparse+https://mirror.prune.example.com/index/",
+    )
+    .unwrap();
+    assert_eq!(bw.calls_of(&["delete", "item", "shared"]).len(), 1);
+    assert!(bw.calls_of(&["edit"]).is_empty());
+}
+
+#[test]
+fn check_reuse_finds_tokens_of_other_registries() {
+    let index_url = |host: &str| format!("sparse+https://{}/index/", host);
+    let prod = index_url("prod.reuse.example.com");
+    let staging = index_url("staging.reuse.example.com");
+    let bw = FakeBw::with_items(vec![fake::login(
+        "mine",
+        "my own login",
+        "https://mine.reuse.example.com/index",
+        "token-shared",
+        OLD,
+    )]);
+    let searches = || {
+        bw.calls_of(&["list", "items"])
+            .iter()
+            .filter(|call| call.option("--search").is_some())
+            .count()
+    };
+
+    // Skipped unless asked for.
+    save(&bw.vault(&[]), &prod, "token-shared").unwrap();
+    assert_eq!(searches(), 0);
+
+    let vault = bw.vault(&["--check-reuse", "--force"]);
+    save(&vault, &staging, "token-shared").unwrap();
+    assert_eq!(searches(), 1);
+    assert_eq!(
+        find(&vault, &staging).unwrap().as_deref(),
+        Some("token-shared")
+    );
+
+    // Items of the user don't count, nor does the registry's own.
+    let session = vault.connect().unwrap();
+    let reused = |index_url: &str, token: &str| {
+        vault
+            .reused_by(&session.0, index_url, &fingerprint::digest(token))
+            .unwrap()
+    };
+    assert_eq!(reused(&staging, "token-shared"), ["prod.reuse.example.com"]);
+    assert_eq!(reused(&prod, "token-shared"), ["staging.reuse.example.com"]);
+    assert!(reused(&staging, "token-other").is_empty());
+}
+
+/// The message of the failure captured from `bw` 2024.6.0 as `case`.
+fn fixture_message(case: &str) -> String {
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/bw-2024.6.0/errors.json"
+    );
+    let fixtures: Vec<Value> =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    let fixture = fixtures
+        .iter()
+        .find(|fixture| fixture["case"] == case)
+        .unwrap_or_else(|| panic!("no fixture {}", case));
+    let stdout: Value = serde_json::from_str(fixture["stdout"].as_str().unwrap()).unwrap();
+    stdout["message"].as_str().unwrap().to_string()
+}
+
+#[test]
+fn personal_ownership_policies_create_organization_items() {
+    let policy = fixture_message("personal-ownership");
+    let index_url = |host: &str| format!("sparse+https://{}/index/", host);
+
+    // Retried in the organization.
+    let host = "retried.ownership.example.com";
+    let bw = FakeBw::new();
+    bw.fail(&["create", "item"], &policy, 1);
+    let args = ["--organization-id", "org-1"];
+    save(&bw.vault(&args), &index_url(host), "token-org").unwrap();
+    let creates = bw.calls_of(&["create", "item"]);
+    assert_eq!(creates.len(), 2);
+    assert_eq!(creates[0].payload().unwrap()["organizationId"], Value::Null);
+    assert_eq!(creates[1].payload().unwrap()["organizationId"], "org-1");
+    let items = bw.state().items.clone();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["organizationId"], "org-1");
+    assert_eq!(items[0]["login"]["password"], "token-org");
+
+    // With collections the item goes there right away.
+    let host = "collection.ownership.example.com";
+    let bw = FakeBw::new();
+    bw.fail(&["create", "item"], &policy, 1);
+    let args = ["--organization-id", "org-1", "--collection-id", "col-1"];
+    let e = save(&bw.vault(&args), &index_url(host), "token-org")
+        .unwrap_err()
+        .to_string();
+    assert!(
+        e.contains("refused to create the item in the organization"),
+        "{}",
+        e
+    );
+    let creates = bw.calls_of(&["create", "item"]);
+    assert_eq!(creates.len(), 1);
+    assert_eq!(
+        creates[0].payload().unwrap()["collectionIds"],
+        json!(["col-1"])
+    );
+
+    // Without an organization there is nowhere to put it.
+    let host = "personal.ownership.example.com";
+    let bw = FakeBw::new();
+    bw.fail(&["create", "item"], &policy, 1);
+    let e = save(&bw.vault(&[]), &index_url(host), "token-org")
+        .unwrap_err()
+        .to_string();
+    assert!(e.contains("an organization policy forbids"), "{}", e);
+    assert!(e.contains("--organization-id"), "{}", e);
+    assert_eq!(bw.calls_of(&["create", "item"]).len(), 1);
+    assert!(bw.state().items.is_empty());
+}
+
+/// The `--organizationid` of every `bw list items` of a `cargo login` for
+/// a new registry with `args`, and the organization of the created item.
+fn organization_of_login(host: &str, args: &[&str]) -> (Vec<Option<String>>, Value) {
+    let bw = FakeBw::new();
+    save(
+        &bw.vault(args),
+        &format!("sparse+https://{}/index/", host),
+        "token-org",
+    )
+    .unwrap();
+    let searched = bw
+        .calls_of(&["list", "items"])
+        .iter()
+        .map(|call| call.option("--organizationid").map(str::to_string))
+        .collect();
+    let created = bw.calls_of(&["create", "item"])[0].payload().unwrap();
+    (searched, created["organizationId"].clone())
+}
+
+#[test]
+fn searches_without_an_organization() {
+    let (searched, created) = organization_of_login("personal.org.example.com", &[]);
+    assert!(!searched.is_empty());
+    assert!(searched.iter().all(Option::is_none), "{:?}", searched);
+    assert_eq!(created, Value::Null);
+}
+
+#[test]
+fn searches_in_the_organization() {
+    let args = ["--organization-id", "org-1", "--collection-id", "col-1"];
+    let (searched, created) = organization_of_login("shared.org.example.com", &args);
+    assert!(!searched.is_empty());
+    assert!(
+        searched.iter().all(|id| id.as_deref() == Some("org-1")),
+        "{:?}",
+        searched
+    );
+    assert_eq!(created, "org-1");
+}
+
+#[test]
+fn searches_include_personal_items() {
+    let args = ["--organization-id", "org-1", "--include-personal"];
+    let (searched, _) = organization_of_login("mixed.org.example.com", &args);
+    assert!(!searched.is_empty());
+    assert!(searched.iter().all(Option::is_none), "{:?}", searched);
+}
+
+#[cfg(windows)]
+#[test]
+fn batch_files_get_their_arguments_intact() {
+    let dir = fake::temp_dir("bw install with spaces");
+    let bw = dir.join("bw.cmd");
+    // Writes the arguments it got next to itself.
+    std::fs::write(&bw, "@echo off\r\n> \"%~dp0args.txt\" echo(%*\r\n").unwrap();
+    let vault = BitwardenVault::new(&["--bw-path", bw.to_str().unwrap()]).unwrap();
+
+    let payload = "100% ^caret^ and spaces %PATH%";
+    let mut cmd = vault.make_cmd(&None, &["create", "item", payload]).unwrap();
+    let output = vault.output(&mut cmd).unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let args = std::fs::read_to_string(dir.join("args.txt")).unwrap();
+    assert!(
+        args.trim_end()
+            .ends_with(&format!("create item \"{}\"", payload)),
+        "{}",
+        args
+    );
+}
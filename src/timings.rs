@@ -0,0 +1,113 @@
+//! How long each command the provider ran took, for `--timings`.
+//!
+//! Every command that runs through [`crate::BitwardenVault`] is recorded,
+//! and after each request a summary goes to stderr: one line, or with
+//! `--timings=json` one JSON object, e.g. to compare CI runs.
+
+use serde_json::json;
+use std::process::ExitStatus;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How the summary is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+struct Timing {
+    command: String,
+    elapsed: Duration,
+    /// `None` when the command timed out.
+    status: Option<ExitStatus>,
+}
+
+static FORMAT: Mutex<Option<Format>> = Mutex::new(None);
+static RECORDED: Mutex<Vec<Timing>> = Mutex::new(Vec::new());
+
+/// Record commands from now on, and print them in `format`.
+pub fn init(format: Option<Format>) {
+    if let Some(format) = format {
+        *FORMAT.lock().unwrap_or_else(|e| e.into_inner()) = Some(format);
+    }
+}
+
+fn format() -> Option<Format> {
+    *FORMAT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Record that `command`, like `bw list items`, ran for `elapsed` and
+/// exited with `status`.
+pub fn record(command: impl FnOnce() -> String, elapsed: Duration, status: Option<ExitStatus>) {
+    if format().is_none() {
+        return;
+    }
+    RECORDED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(Timing {
+            command: command(),
+            elapsed,
+            status,
+        });
+}
+
+/// Print the commands recorded since the last summary, and the `total`
+/// time of the request.
+pub fn report(total: Duration) {
+    let Some(format) = format() else {
+        return;
+    };
+    let timings = std::mem::take(&mut *RECORDED.lock().unwrap_or_else(|e| e.into_inner()));
+    match format {
+        Format::Text => {
+            let mut parts: Vec<String> = timings
+                .iter()
+                .map(|timing| {
+                    let outcome = match timing.status {
+                        Some(status) if status.success() => String::new(),
+                        Some(status) => format!(" ({})", status),
+                        None => " (timed out)".to_string(),
+                    };
+                    format!(
+                        "{} {:.1}s{}",
+                        timing.command,
+                        timing.elapsed.as_secs_f64(),
+                        outcome
+                    )
+                })
+                .collect();
+            parts.push(format!("total {:.1}s", total.as_secs_f64()));
+            eprintln!("timings: {}", parts.join(", "));
+        }
+        Format::Json => {
+            let commands: Vec<_> = timings
+                .iter()
+                .map(|timing| {
+                    json!({
+                        "command": timing.command,
+                        "elapsed-ms": timing.elapsed.as_millis() as u64,
+                        "exit-code": timing.status.and_then(|status| status.code()),
+                        "timed-out": timing.status.is_none(),
+                    })
+                })
+                .collect();
+            let summary = json!({
+                "commands": commands,
+                "total-ms": total.as_millis() as u64,
+            });
+            eprintln!("{}", summary);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! The form tokens are handed to cargo in, from `--token-format`, and
+//! tokens made of a username and a password, from `--compose-token` and
+//! `--split-token`.
+//!
+//! The vault keeps the bare token, so that other tools can use it too;
+//! cargo gets it with the text around `{token}` in the template, e.g. an
+//! authorization scheme, and `cargo login` takes that text off again.
+
+use cargo_credential::Secret;
+
+const PLACEHOLDER: &str = "{token}";
+
+#[derive(Debug, Clone)]
+pub struct TokenFormat {
+    prefix: String,
+    suffix: String,
+}
+
+impl TokenFormat {
+    pub fn parse(template: &str) -> Result<TokenFormat, String> {
+        let Some((prefix, suffix)) = template.split_once(PLACEHOLDER) else {
+            return Err(format!(
+                "`--token-format {}` has no `{}` placeholder",
+                template, PLACEHOLDER
+            ));
+        };
+        if suffix.contains(PLACEHOLDER) {
+            return Err(format!(
+                "`--token-format {}` has more than one `{}` placeholder",
+                template, PLACEHOLDER
+            ));
+        }
+        if template.chars().any(char::is_control) {
+            return Err("`--token-format` can't contain control characters".to_string());
+        }
+        Ok(TokenFormat {
+            prefix: prefix.to_string(),
+            suffix: suffix.to_string(),
+        })
+    }
+
+    /// The stored `token` as cargo gets it.
+    pub fn apply(&self, token: Secret<String>) -> Secret<String> {
+        token.map(|token| format!("{}{}{}", self.prefix, token, self.suffix))
+    }
+
+    /// The token to store for `token` as given to `cargo login`: without
+    /// the text of the template, if it has it, so that pasting the token
+    /// either way stores the same. The prefix is compared ignoring ASCII
+    /// case, like authorization schemes are.
+    pub fn strip(&self, token: Secret<String>) -> Secret<String> {
+        token.map(|token| {
+            let has_prefix = token.len() >= self.prefix.len()
+                && token.is_char_boundary(self.prefix.len())
+                && token[..self.prefix.len()].eq_ignore_ascii_case(&self.prefix);
+            let rest = match has_prefix {
+                true => &token[self.prefix.len()..],
+                false => &token,
+            };
+            let bare = rest.strip_suffix(self.suffix.as_str()).unwrap_or(rest);
+            let bare = bare.trim().to_string();
+            match bare.is_empty() {
+                true => token,
+                false => bare,
+            }
+        })
+    }
+}
+
+const USERNAME: &str = "{username}";
+const PASSWORD: &str = "{password}";
+
+/// A token made of the username and password of an item, from
+/// `--compose-token`, for registries that take e.g. `user:apikey`.
+#[derive(Debug, Clone)]
+pub struct Compose {
+    template: String,
+}
+
+impl Compose {
+    pub fn parse(template: &str) -> Result<Compose, String> {
+        if template.matches(PASSWORD).count() != 1 {
+            return Err(format!(
+                "`--compose-token {}` needs exactly one `{}` placeholder",
+                template, PASSWORD
+            ));
+        }
+        if template.matches(USERNAME).count() > 1 {
+            return Err(format!(
+                "`--compose-token {}` has more than one `{}` placeholder",
+                template, USERNAME
+            ));
+        }
+        if template.chars().any(char::is_control) {
+            return Err("`--compose-token` can't contain control characters".to_string());
+        }
+        Ok(Compose {
+            template: template.to_string(),
+        })
+    }
+
+    /// Whether the template takes the username.
+    pub fn needs_username(&self) -> bool {
+        self.template.contains(USERNAME)
+    }
+
+    /// The token for `password`, and `username` if the template takes it.
+    pub fn apply(&self, username: &str, password: Secret<String>) -> Secret<String> {
+        // Split at the password, so that a username spelling `{password}`
+        // isn't replaced too.
+        let (before, after) = self.template.split_once(PASSWORD).unwrap_or_default();
+        password.map(|password| {
+            format!(
+                "{}{}{}",
+                before.replace(USERNAME, username),
+                password,
+                after.replace(USERNAME, username)
+            )
+        })
+    }
+}
+
+/// The username and password of `token` from `cargo login`, split at the
+/// first `separator`, for `--split-token`.
+pub fn split(token: Secret<String>, separator: &str) -> Result<(String, Secret<String>), String> {
+    let expected = || format!("`--split-token` expects `<username>{}<token>`", separator);
+    let token = token.expose();
+    let Some((username, password)) = token.split_once(separator) else {
+        return Err(format!(
+            "the token has no `{}` to split it at; {}",
+            separator,
+            expected()
+        ));
+    };
+    if username.trim().is_empty() || password.trim().is_empty() {
+        return Err(format!("the token has an empty part; {}", expected()));
+    }
+    Ok((
+        username.trim().to_string(),
+        Secret::from(password.trim().to_string()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_and_strip() {
+        let format = TokenFormat::parse("Bearer {token}").unwrap();
+        let token = |token: &str| Secret::from(token.to_string());
+        assert_eq!(format.apply(token("abc")).expose(), "Bearer abc");
+        for given in ["Bearer abc", "bearer abc", "BEARER  abc\n", "abc"] {
+            assert_eq!(format.strip(token(given)).expose(), "abc", "{:?}", given);
+        }
+        // Nothing but the prefix is a token of its own.
+        assert_eq!(format.strip(token("Bearer ")).expose(), "Bearer ");
+
+        let format = TokenFormat::parse("<{token}>").unwrap();
+        assert_eq!(format.apply(token("abc")).expose(), "<abc>");
+        assert_eq!(format.strip(token("<abc>")).expose(), "abc");
+
+        for (template, error) in [
+            ("Bearer", "has no `{token}` placeholder"),
+            ("{token}{token}", "more than one `{token}` placeholder"),
+            ("Bearer\t{token}", "can't contain control characters"),
+        ] {
+            let e = TokenFormat::parse(template).unwrap_err();
+            assert!(e.contains(error), "{}: {}", template, e);
+        }
+    }
+
+    #[test]
+    fn compose_and_split() {
+        let compose = Compose::parse("{username}:{password}").unwrap();
+        assert!(compose.needs_username());
+        let token = compose.apply("me", Secret::from("key".to_string()));
+        assert_eq!(token.expose(), "me:key");
+        // Only the template's placeholders are replaced.
+        let token = compose.apply("{password}", Secret::from("{username}".to_string()));
+        assert_eq!(token.expose(), "{password}:{username}");
+
+        let compose = Compose::parse("Bearer {password}").unwrap();
+        assert!(!compose.needs_username());
+        let token = compose.apply("", Secret::from("key".to_string()));
+        assert_eq!(token.expose(), "Bearer key");
+
+        for template in [
+            "{username}",
+            "{password}{password}",
+            "{username}{username}:{password}",
+        ] {
+            assert!(Compose::parse(template).is_err(), "{}", template);
+        }
+
+        let split_at = |token: &str, separator| {
+            split(Secret::from(token.to_string()), separator)
+                .map(|(username, password)| (username, password.expose()))
+        };
+        assert_eq!(
+            split_at("me:key", ":").unwrap(),
+            ("me".to_string(), "key".to_string())
+        );
+        // At the first separator, as API keys may contain it.
+        assert_eq!(
+            split_at(" me :key:more\n", ":").unwrap(),
+            ("me".to_string(), "key:more".to_string())
+        );
+        assert_eq!(
+            split_at("me::key", "::").unwrap(),
+            ("me".to_string(), "key".to_string())
+        );
+        let e = split_at("secret-key", ":").unwrap_err();
+        assert!(e.contains("no `:` to split it at"), "{}", e);
+        assert!(!e.contains("secret-key"), "{}", e);
+        for token in [":key", "me:", " : "] {
+            let e = split_at(token, ":").unwrap_err();
+            assert!(e.contains("empty part"), "{}", e);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! A trace of the credential protocol, for the
+//! `CARGO_CREDENTIAL_BITWARDEN_TRACE` environment variable.
+//!
+//! Every request cargo makes is appended to the file the variable names as
+//! a JSON line: what cargo asked for, the provider's arguments and how it
+//! answered. Tokens are never written, and everything else goes through
+//! [`crate::redact`]. Failing to write the trace never fails the request.
+
+use cargo_credential::{Action, CredentialResponse, Error, RegistryInfo};
+use serde_json::{json, Value};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Environment variable naming the file to append the trace to.
+pub const ENV: &str = "CARGO_CREDENTIAL_BITWARDEN_TRACE";
+
+fn path() -> Option<&'static Path> {
+    static PATH: OnceLock<Option<PathBuf>> = OnceLock::new();
+    PATH.get_or_init(|| {
+        std::env::var_os(ENV)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    })
+    .as_deref()
+}
+
+/// Append the request `action` for `registry` with the provider arguments
+/// `args`, answered with `result` after `elapsed`, from the answers of
+/// earlier requests if `cached`.
+pub fn record(
+    registry: &RegistryInfo<'_>,
+    action: &Action<'_>,
+    args: &[&str],
+    result: &Result<CredentialResponse, Error>,
+    cached: bool,
+    elapsed: Duration,
+) {
+    let Some(path) = path() else {
+        return;
+    };
+    let line = json!({
+        "time": time::OffsetDateTime::now_utc()
+            .format(&time::format_description::well_known::Rfc3339)
+            .ok(),
+        "pid": std::process::id(),
+        "action": action_json(action),
+        "registry-name": registry.name,
+        "index-url": registry.index_url,
+        "args": args.iter().map(|arg| crate::redact::redact(arg)).collect::<Vec<_>>(),
+        "response": response_json(result),
+        "cached": cached,
+        "elapsed-ms": elapsed.as_millis() as u64,
+    });
+    if let Err(e) = append(path, &line.to_string()) {
+        crate::verbose::log(|| format!("failed to write the trace to `{}`: {}", path.display(), e));
+    }
+}
+
+/// `action` without the token `cargo login` passes.
+pub fn action_json(action: &Action<'_>) -> Value {
+    match action {
+        Action::Get(operation) => with_kind("get", serde_json::to_value(operation)),
+        Action::Login(options) => json!({
+            "kind": "login",
+            "token-given": options.token.is_some(),
+        }),
+        Action::Logout => json!({ "kind": "logout" }),
+        _ => json!({ "kind": "unknown" }),
+    }
+}
+
+/// `result` without the token of a `get`.
+pub fn response_json(result: &Result<CredentialResponse, Error>) -> Value {
+    match result {
+        Ok(CredentialResponse::Get {
+            cache,
+            operation_independent,
+            ..
+        }) => {
+            let mut response = with_kind("get", serde_json::to_value(cache));
+            response["operation-independent"] = json!(operation_independent);
+            response
+        }
+        Ok(CredentialResponse::Login) => json!({ "kind": "login" }),
+        Ok(CredentialResponse::Logout) => json!({ "kind": "logout" }),
+        Ok(_) => json!({ "kind": "unknown" }),
+        Err(e) => {
+            let kind = match e {
+                Error::NotFound => "not-found",
+                Error::UrlNotSupported => "url-not-supported",
+                Error::OperationNotSupported => "operation-not-supported",
+                _ => "other",
+            };
+            json!({
+                "kind": "error",
+                "error": kind,
+                "message": crate::redact::redact(&e.to_string()),
+            })
+        }
+    }
+}
+
+/// The fields of `value`, which cargo tags with one of its own, and `kind`.
+fn with_kind(kind: &str, value: serde_json::Result<Value>) -> Value {
+    let mut value = match value {
+        Ok(value @ Value::Object(_)) => value,
+        _ => json!({}),
+    };
+    value["kind"] = json!(kind);
+    value
+}
+
+/// Append `line` to the file at `path`, which only the current user may
+/// read when it is created.
+pub fn append(path: &Path, line: &str) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(format!("{}\n", line).as_bytes())
+}
@@ -0,0 +1,186 @@
+//! Prompting the user on the controlling terminal.
+//!
+//! Cargo owns the provider's stdin and stdout for the credential protocol, so
+//! prompts always go through the terminal device directly.
+//!
+//! Editors and GUI git clients run cargo without a terminal. Then prompts go
+//! to an askpass helper instead, like `SSH_ASKPASS`: a program run with the
+//! prompt as its argument, which prints the answer to stdout.
+
+use cfg_if::cfg_if;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Output, Stdio};
+use std::sync::OnceLock;
+
+/// Environment variable naming the askpass helper, like `--askpass`.
+pub const ASKPASS_ENV: &str = "CARGO_CREDENTIAL_ASKPASS";
+
+static ASKPASS: OnceLock<String> = OnceLock::new();
+
+cfg_if! {
+    if #[cfg(windows)] {
+        const IN_DEVICE: &str = "CONIN$";
+        const OUT_DEVICE: &str = "CONOUT$";
+    } else {
+        const IN_DEVICE: &str = "/dev/tty";
+        const OUT_DEVICE: &str = "/dev/tty";
+    }
+}
+
+/// Open the terminal for reading and writing, if there is one.
+fn open() -> Option<(BufReader<File>, File)> {
+    let input = File::open(IN_DEVICE).ok()?;
+    let output = OpenOptions::new().write(true).open(OUT_DEVICE).ok()?;
+    Some((BufReader::new(input), output))
+}
+
+/// Use `program` as the askpass helper, instead of [`ASKPASS_ENV`]. Only
+/// the first call has an effect.
+pub fn set_askpass(program: &str) {
+    let _ = ASKPASS.set(program.to_string());
+}
+
+fn askpass_program() -> Option<String> {
+    ASKPASS.get().cloned().or_else(|| {
+        std::env::var(ASKPASS_ENV)
+            .ok()
+            .filter(|program| !program.is_empty())
+    })
+}
+
+/// Whether there is a terminal to prompt on.
+pub fn is_available() -> bool {
+    open().is_some()
+}
+
+/// The terminal as standard input and error of a child process, so that
+/// it prompts there rather than on the protocol pipes.
+pub fn child_stdio() -> Option<(Stdio, Stdio)> {
+    let (input, output) = open()?;
+    Some((input.into_inner().into(), output.into()))
+}
+
+/// Whether prompts can be answered, on the terminal or by an askpass helper.
+pub fn can_prompt() -> bool {
+    is_available() || askpass_program().is_some()
+}
+
+/// The askpass helper asked `prompt`, if there is one, to run like
+/// [`askpass`] does and read the answer from with [`askpass_answer`].
+pub fn askpass_command(prompt: &str) -> Option<Command> {
+    let program = askpass_program()?;
+    let mut cmd = Command::new(program);
+    cmd.arg(prompt)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    Some(cmd)
+}
+
+/// The answer the askpass helper printed, or `None` when it failed, e.g.
+/// because the user cancelled.
+pub fn askpass_answer(output: Output) -> Option<String> {
+    if !output.status.success() {
+        return None;
+    }
+    let answer = String::from_utf8(output.stdout).ok()?;
+    Some(answer.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Ask the askpass helper, which prints the answer. Returns `None` when
+/// there is none, or it failed.
+fn askpass(prompt: &str) -> Option<String> {
+    let mut cmd = askpass_command(prompt)?;
+    let output = crate::cancel::output(&mut cmd)
+        .map_err(|e| {
+            eprintln!(
+                "warning: failed to run askpass helper `{}`: {}",
+                cmd.get_program().to_string_lossy(),
+                e
+            )
+        })
+        .ok()?;
+    askpass_answer(output)
+}
+
+/// Ask a yes/no question, defaulting to no.
+///
+/// Returns `None` when there is no terminal or askpass helper to ask.
+pub fn confirm(question: &str) -> Option<bool> {
+    let Some((mut input, mut output)) = open() else {
+        let answer = askpass(&format!("{} [y/N]", question))?;
+        return Some(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"));
+    };
+    write!(output, "{} [y/N] ", question).ok()?;
+    output.flush().ok()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer).ok()?;
+    Some(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Ask for a secret without echoing it.
+///
+/// Returns `None` when there is no terminal or askpass helper to ask.
+pub fn prompt_secret(question: &str) -> Option<String> {
+    let Some((mut input, mut output)) = open() else {
+        return askpass(&format!("{}: ", question));
+    };
+    write!(output, "{}: ", question).ok()?;
+    output.flush().ok()?;
+
+    let mut answer = String::new();
+    let read = without_echo(&mut input, |input| input.read_line(&mut answer));
+    writeln!(output).ok()?;
+    read.ok()?;
+    Some(answer.trim_end_matches(['\r', '\n']).to_string())
+}
+
+cfg_if! {
+    if #[cfg(windows)] {
+        /// Run `f` with echo turned off on the console `input`.
+        fn without_echo<T>(
+            input: &mut BufReader<File>,
+            f: impl FnOnce(&mut BufReader<File>) -> T,
+        ) -> T {
+            use std::os::windows::io::AsRawHandle;
+            use windows_sys::Win32::System::Console::{
+                GetConsoleMode, SetConsoleMode, ENABLE_ECHO_INPUT,
+            };
+
+            let handle = input.get_ref().as_raw_handle() as _;
+            let mut mode = 0;
+            // SAFETY: `handle` is an open console handle owned by `input`.
+            if unsafe { GetConsoleMode(handle, &mut mode) } == 0 {
+                return f(input);
+            }
+            unsafe { SetConsoleMode(handle, mode & !ENABLE_ECHO_INPUT) };
+            let result = f(input);
+            unsafe { SetConsoleMode(handle, mode) };
+            result
+        }
+    } else {
+        /// Run `f` with echo turned off on the terminal `input`.
+        fn without_echo<T>(
+            input: &mut BufReader<File>,
+            f: impl FnOnce(&mut BufReader<File>) -> T,
+        ) -> T {
+            use std::os::unix::io::AsRawFd;
+
+            let fd = input.get_ref().as_raw_fd();
+            // SAFETY: `fd` is an open terminal owned by `input`, and `termios`
+            // is plain data filled in by `tcgetattr`.
+            let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+            if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+                return f(input);
+            }
+            let original = termios;
+            termios.c_lflag &= !libc::ECHO;
+            unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) };
+            let result = f(input);
+            unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+            result
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Two-step login for `bw login`, from `--method` and `--code`.
+
+use crate::redact;
+
+/// A two-step login method of `bw login --method`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Authenticator,
+    Email,
+    Yubikey,
+}
+
+impl Method {
+    const ALL: [Method; 3] = [Method::Authenticator, Method::Email, Method::Yubikey];
+
+    pub fn parse(name: &str) -> Result<Method, String> {
+        Method::ALL
+            .into_iter()
+            .find(|method| method.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                let names: Vec<_> = Method::ALL.iter().map(|method| method.name()).collect();
+                format!(
+                    "invalid --method `{}`, expected one of: {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Method::Authenticator => "authenticator",
+            Method::Email => "email",
+            Method::Yubikey => "yubikey",
+        }
+    }
+
+    /// The number `bw` takes for the method.
+    pub fn as_arg(self) -> &'static str {
+        match self {
+            Method::Authenticator => "0",
+            Method::Email => "1",
+            Method::Yubikey => "3",
+        }
+    }
+}
+
+/// The value of `--code`: the code itself, or with `env:VAR` the contents
+/// of the environment variable `VAR`, so that the code stays out of cargo's
+/// configuration.
+pub fn read_code(arg: &str) -> Result<String, String> {
+    let code = match arg.strip_prefix("env:") {
+        Some(var) => std::env::var(var)
+            .ok()
+            .filter(|code| !code.trim().is_empty())
+            .ok_or_else(|| format!("`--code env:{}`: the variable is not set", var))?,
+        None => arg.to_string(),
+    };
+    redact::register(&code);
+    Ok(code.trim().to_string())
+}
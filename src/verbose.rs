@@ -0,0 +1,88 @@
+//! Diagnostics on stderr, for `--verbose` or the
+//! `CARGO_CREDENTIAL_BITWARDEN_LOG` environment variable.
+//!
+//! Every line goes through [`crate::redact`], and commands are printed with
+//! the values of options that take secrets left out, so that turning this on
+//! never puts a token or session key in a log.
+
+use std::ffi::OsStr;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Environment variable that turns logging on, like `--verbose`, when set
+/// to anything but `0` or nothing.
+pub const ENV: &str = "CARGO_CREDENTIAL_BITWARDEN_LOG";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Options whose value is a secret.
+const SECRET_OPTIONS: &[&str] = &["--session", "--code", "--value", "--password"];
+
+/// Environment variables whose value is a secret.
+const SECRET_ENVS: &[&str] = &[
+    "BW_SESSION",
+    "BW_CLIENTSECRET",
+    "BWS_ACCESS_TOKEN",
+    crate::PASSWORD_ENV,
+    crate::SEND_PASSWORD_ENV,
+];
+
+const REDACTED: &str = "<redacted>";
+
+/// Turn logging on with `flag`, or when the environment asks for it.
+pub fn init(flag: bool) {
+    let from_env = std::env::var_os(ENV).is_some_and(|value| !value.is_empty() && value != "0");
+    if flag || from_env {
+        ENABLED.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Print `message`, redacted, if logging is on.
+pub fn log(message: impl FnOnce() -> String) {
+    if enabled() {
+        eprintln!("debug: {}", crate::redact::redact(&message()));
+    }
+}
+
+/// `cmd` as it would be typed, without the secrets in its options or
+/// environment.
+pub fn command(cmd: &Command) -> String {
+    let mut words = vec![cmd.get_program().to_string_lossy().into_owned()];
+    let mut hide_next = false;
+    for arg in cmd.get_args().map(OsStr::to_string_lossy) {
+        if hide_next {
+            words.push(REDACTED.to_string());
+            hide_next = false;
+            continue;
+        }
+        match arg.split_once('=') {
+            Some((option, _)) if SECRET_OPTIONS.contains(&option) => {
+                words.push(format!("{}={}", option, REDACTED));
+            }
+            _ => {
+                hide_next = SECRET_OPTIONS.contains(&arg.as_ref());
+                words.push(arg.into_owned());
+            }
+        }
+    }
+    let envs: Vec<String> = cmd
+        .get_envs()
+        .filter_map(|(name, value)| {
+            let name = name.to_string_lossy();
+            let value = match value {
+                Some(_) if SECRET_ENVS.contains(&name.as_ref()) => REDACTED.into(),
+                Some(value) => value.to_string_lossy(),
+                None => return None,
+            };
+            Some(format!("{}={}", name, value))
+        })
+        .collect();
+    match envs.is_empty() {
+        true => words.join(" "),
+        false => format!("{} {}", envs.join(" "), words.join(" ")),
+    }
+}
@@ -0,0 +1,127 @@
+//! Trying a token with its registry before `cargo login` stores it, for
+//! `--verify`.
+//!
+//! The API of the registry is found in the `config.json` of its sparse
+//! index. Only a registry that rejects the token stops the login: when it
+//! can't be asked, the token is stored with a warning.
+
+use crate::cargo_config::CRATES_IO_INDEX;
+use cargo_credential::Error;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// How long each request to the registry may take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The sparse index of crates.io, which has the same `config.json` as its
+/// git index.
+const CRATES_IO_SPARSE: &str = "https://index.crates.io/";
+
+#[derive(Deserialize)]
+struct IndexConfig {
+    api: Option<String>,
+}
+
+/// Why the registry couldn't be asked.
+enum Failure {
+    /// The registry answered with this HTTP status.
+    Status(u16, String),
+    Other(String),
+}
+
+/// Check `token`, as cargo sends it, with the registry of `index_url`.
+pub fn check(index_url: &str, token: &str) -> Result<(), Error> {
+    let index = match index_url.strip_prefix("sparse+") {
+        Some(index) if index.starts_with("file:") => {
+            eprintln!(
+                "warning: can't verify the token with `{}`, which is on disk; storing it anyway",
+                index_url
+            );
+            return Ok(());
+        }
+        Some(index) => index.to_string(),
+        None if index_url.trim_end_matches('/') == CRATES_IO_INDEX => CRATES_IO_SPARSE.to_string(),
+        None => {
+            eprintln!(
+                "warning: can't verify the token with `{}`, only with sparse registries; storing it anyway",
+                index_url
+            );
+            return Ok(());
+        }
+    };
+    match registry_api(&index, token).and_then(|api| try_token(&api, token)) {
+        Ok(()) => Ok(()),
+        Err(Failure::Status(status @ (401 | 403), url)) => Err(format!(
+            "the registry rejected the token with HTTP {} for `GET {}`, so it wasn't stored; \
+             check that it is a token for `{}`, or drop `--verify`",
+            status, url, index_url
+        )
+        .into()),
+        Err(Failure::Status(status, url)) => {
+            eprintln!(
+                "warning: couldn't verify the token: HTTP {} for `GET {}`; storing it anyway",
+                status, url
+            );
+            Ok(())
+        }
+        Err(Failure::Other(reason)) => {
+            eprintln!(
+                "warning: couldn't verify the token: {}; storing it anyway",
+                reason
+            );
+            Ok(())
+        }
+    }
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(TIMEOUT)
+        // crates.io asks clients to say who they are.
+        .user_agent(concat!(
+            "cargo-credential-bitwarden/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .try_proxy_from_env(true)
+        .build()
+}
+
+/// Base URL of the web API, from the `config.json` of `index`. Registries
+/// that require authentication for the index get the token too.
+fn registry_api(index: &str, token: &str) -> Result<String, Failure> {
+    let url = format!("{}/config.json", index.trim_end_matches('/'));
+    let response = match agent().get(&url).call() {
+        Err(ureq::Error::Status(401, _)) => agent()
+            .get(&url)
+            .set("Authorization", token)
+            .call()
+            .map_err(|e| failed(e, &url))?,
+        response => response.map_err(|e| failed(e, &url))?,
+    };
+    let config: IndexConfig = response
+        .into_string()
+        .map_err(|e| e.to_string())
+        .and_then(|body| serde_json::from_str(&body).map_err(|e| e.to_string()))
+        .map_err(|e| Failure::Other(format!("invalid `{}`: {}", url, e)))?;
+    config
+        .api
+        .ok_or_else(|| Failure::Other(format!("`{}` names no API", url)))
+}
+
+/// Make an authenticated request to the API at `api`.
+fn try_token(api: &str, token: &str) -> Result<(), Failure> {
+    let url = format!("{}/api/v1/crates?q=&per_page=1", api.trim_end_matches('/'));
+    agent()
+        .get(&url)
+        .set("Authorization", token)
+        .call()
+        .map(drop)
+        .map_err(|e| failed(e, &url))
+}
+
+fn failed(e: ureq::Error, url: &str) -> Failure {
+    match e {
+        ureq::Error::Status(status, _) => Failure::Status(status, url.to_string()),
+        ureq::Error::Transport(e) => Failure::Other(e.to_string()),
+    }
+}
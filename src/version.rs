@@ -0,0 +1,200 @@
+//! Detection of the `bw` CLI version and the global flags it understands.
+
+use std::fmt;
+
+/// Version reported by `bw --version`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Version {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse output like `2024.6.0`, ignoring surrounding noise and any
+    /// pre-release or build suffix (`2024.6.0-beta.1`).
+    pub fn parse(output: &str) -> Option<Version> {
+        let word = output
+            .split_whitespace()
+            .find(|word| word.starts_with(|c: char| c.is_ascii_digit()))?;
+        let core = word.split(['-', '+']).next()?;
+
+        let mut parts = core.split('.').map(|part| part.parse::<u32>());
+        let major = parts.next()?.ok()?;
+        let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+        let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+        Some(Version::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Oldest CLI the provider runs with. Older ones lack `--nointeraction`,
+/// and wait for answers on the terminal where cargo gives them none.
+pub const MINIMUM: Version = Version::new(1, 11, 0);
+
+/// Global `bw` flags whose availability differs between CLI versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    Raw,
+    Response,
+    NoInteraction,
+    CleanExit,
+}
+
+impl Flag {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Flag::Raw => "--raw",
+            Flag::Response => "--response",
+            Flag::NoInteraction => "--nointeraction",
+            Flag::CleanExit => "--cleanexit",
+        }
+    }
+}
+
+/// First CLI version that supports each flag.
+const FLAG_VERSIONS: &[(Flag, Version)] = &[
+    (Flag::Raw, Version::new(1, 0, 0)),
+    (Flag::Response, Version::new(1, 0, 0)),
+    (Flag::NoInteraction, Version::new(1, 11, 0)),
+    (Flag::CleanExit, Version::new(1, 18, 0)),
+];
+
+/// Flags supported by the detected `bw` CLI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub raw: bool,
+    pub response: bool,
+    pub nointeraction: bool,
+    pub cleanexit: bool,
+}
+
+impl Capabilities {
+    /// Assume everything is supported, used when detection isn't possible.
+    pub const ALL: Capabilities = Capabilities {
+        raw: true,
+        response: true,
+        nointeraction: true,
+        cleanexit: true,
+    };
+
+    pub fn for_version(version: Version) -> Capabilities {
+        Capabilities::from_fn(|flag| {
+            FLAG_VERSIONS
+                .iter()
+                .any(|&(f, since)| f == flag && version >= since)
+        })
+    }
+
+    /// Fallback for unparseable versions: look for the flags in `bw --help`.
+    pub fn from_help(help: &str) -> Capabilities {
+        Capabilities::from_fn(|flag| {
+            help.split_whitespace()
+                .any(|word| word.trim_end_matches(',') == flag.as_str())
+        })
+    }
+
+    pub fn supports(&self, flag: Flag) -> bool {
+        match flag {
+            Flag::Raw => self.raw,
+            Flag::Response => self.response,
+            Flag::NoInteraction => self.nointeraction,
+            Flag::CleanExit => self.cleanexit,
+        }
+    }
+
+    fn from_fn(f: impl Fn(Flag) -> bool) -> Capabilities {
+        Capabilities {
+            raw: f(Flag::Raw),
+            response: f(Flag::Response),
+            nointeraction: f(Flag::NoInteraction),
+            cleanexit: f(Flag::CleanExit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let cases = [
+            ("2024.6.0\n", Some(Version::new(2024, 6, 0))),
+            ("1.22.1", Some(Version::new(1, 22, 1))),
+            ("2024.6.0-beta.1", Some(Version::new(2024, 6, 0))),
+            ("2023.12.1+build.7", Some(Version::new(2023, 12, 1))),
+            ("2024.2", Some(Version::new(2024, 2, 0))),
+            (
+                "A new version is available\n2024.1.3\n",
+                Some(Version::new(2024, 1, 3)),
+            ),
+            ("", None),
+            ("unknown", None),
+            ("1.x.0", None),
+        ];
+        for (output, version) in cases {
+            assert_eq!(Version::parse(output), version, "{:?}", output);
+        }
+    }
+
+    #[test]
+    fn capabilities_by_version() {
+        let cases = [
+            ((1, 0, 0), [true, true, false, false]),
+            ((1, 10, 9), [true, true, false, false]),
+            ((1, 11, 0), [true, true, true, false]),
+            ((1, 17, 9), [true, true, true, false]),
+            ((1, 18, 0), [true, true, true, true]),
+            ((2024, 6, 0), [true, true, true, true]),
+        ];
+        let flags = [
+            Flag::Raw,
+            Flag::Response,
+            Flag::NoInteraction,
+            Flag::CleanExit,
+        ];
+        for ((major, minor, patch), supported) in cases {
+            let capabilities = Capabilities::for_version(Version::new(major, minor, patch));
+            for (flag, supported) in flags.into_iter().zip(supported) {
+                assert_eq!(
+                    capabilities.supports(flag),
+                    supported,
+                    "{}.{}.{} {:?}",
+                    major,
+                    minor,
+                    patch,
+                    flag
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn capabilities_from_help() {
+        let help = "Options:\n  --raw          Return raw output\n  --response,    JSON\n  \
+                    --nointeractionx  not quite\n";
+        assert_eq!(
+            Capabilities::from_help(help),
+            Capabilities {
+                raw: true,
+                response: true,
+                nointeraction: false,
+                cleanexit: false,
+            }
+        );
+    }
+}
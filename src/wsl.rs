@@ -0,0 +1,52 @@
+//! Running the Windows `bw.exe` from WSL.
+//!
+//! Inside WSL, the Bitwarden CLI of the Windows side is on `PATH` through
+//! interop as `bw.exe`. Windows programs only see the environment variables
+//! listed in `WSLENV`, so the session key and the other variables the
+//! provider sets are listed there for them, and they end their lines with
+//! `\r\n`, which [`crate::normalize_newlines`] turns back into `\n`.
+
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// Environment variable listing the variables shared with Windows programs.
+const ENV: &str = "WSLENV";
+
+/// Whether this process runs in WSL.
+pub fn detected() -> bool {
+    static DETECTED: OnceLock<bool> = OnceLock::new();
+    *DETECTED.get_or_init(|| {
+        cfg!(target_os = "linux")
+            && (std::env::var_os("WSL_DISTRO_NAME").is_some()
+                || std::fs::read_to_string("/proc/version")
+                    .is_ok_and(|version| version.to_lowercase().contains("microsoft")))
+    })
+}
+
+/// Whether `program` is a Windows executable started through interop.
+pub fn is_windows_program(program: &str) -> bool {
+    detected() && program.to_lowercase().ends_with(".exe")
+}
+
+/// Share the variables `names` with the Windows program of `cmd`, keeping
+/// those `WSLENV` already shares. Names ending in `/p` are paths, which WSL
+/// translates.
+pub fn share_env(cmd: &mut Command, names: &[&str]) {
+    let mut shared = std::env::var_os(ENV).unwrap_or_default();
+    let present: Vec<String> = shared
+        .to_string_lossy()
+        .split(':')
+        .map(|entry| entry.split('/').next().unwrap_or_default().to_string())
+        .collect();
+    for name in names {
+        let bare = name.split('/').next().unwrap_or_default();
+        if present.iter().any(|entry| entry == bare) {
+            continue;
+        }
+        if !shared.is_empty() {
+            shared.push(":");
+        }
+        shared.push(name);
+    }
+    cmd.env(ENV, shared);
+}
@@ -0,0 +1,122 @@
+//! Cargo going away mid-request, with a shell script standing in for a
+//! `bw` that never answers.
+#![cfg(unix)]
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the provider and `bw` may take to go away.
+const BOUND: Duration = Duration::from_secs(10);
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "cargo-credential-bitwarden-cancel-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A `bw` in `dir` whose `list items` writes its process id to `bw.pid`
+/// and then hangs.
+fn hanging_bw(dir: &Path) {
+    let script = format!(
+        r#"#!/bin/sh
+case " $* " in
+  *" --version "*) echo 2024.6.0 ;;
+  *" status "*) echo '{{"serverUrl":null,"lastSync":null,"userEmail":"me@example.com","userId":"u1","status":"unlocked"}}' ;;
+  *" list items "*) echo $$ > '{}/bw.pid'; exec sleep 600 ;;
+  *) echo "unexpected: $*" >&2; exit 1 ;;
+esac
+"#,
+        dir.display()
+    );
+    let path = dir.join("bw");
+    fs::write(&path, script).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// Whether process `pid` still runs, not counting a zombie left for
+/// whoever adopted it.
+fn is_running(pid: u32) -> bool {
+    let output = Command::new("ps")
+        .args(["-o", "stat=", "-p", &pid.to_string()])
+        .output()
+        .unwrap();
+    let stat = String::from_utf8_lossy(&output.stdout);
+    let stat = stat.trim();
+    !stat.is_empty() && !stat.starts_with('Z')
+}
+
+/// Wait up to [`BOUND`] for `done`.
+fn wait_for(mut done: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + BOUND;
+    while Instant::now() < deadline {
+        if done() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[test]
+fn closing_stdin_stops_bw() {
+    let dir = test_dir("closed-stdin");
+    hanging_bw(&dir);
+    let mut provider = Command::new(env!("CARGO_BIN_EXE_cargo-credential-bitwarden"))
+        .arg("--cargo-plugin")
+        .env_clear()
+        .env("PATH", format!("{}:/usr/bin:/bin", dir.display()))
+        .env("HOME", &dir)
+        .env("XDG_STATE_HOME", dir.join("state"))
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let mut stdin = provider.stdin.take().unwrap();
+    let request = r#"{"v":1,"registry":{"index-url":"sparse+https://cancel.example.com/index/"},"kind":"get","operation":"read","args":[]}"#;
+    writeln!(stdin, "{}", request).unwrap();
+    let pid_file = dir.join("bw.pid");
+    let started = wait_for(|| fs::read_to_string(&pid_file).is_ok_and(|pid| pid.ends_with('\n')));
+    if !started {
+        kill(&mut provider);
+        panic!("the provider never ran `bw list items`");
+    }
+    let bw: u32 = fs::read_to_string(&pid_file)
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    assert!(is_running(bw));
+
+    drop(stdin);
+    let exited = wait_for(|| provider.try_wait().unwrap().is_some());
+    if !exited {
+        kill(&mut provider);
+    }
+    let bw_exited = wait_for(|| !is_running(bw));
+    if !bw_exited {
+        let _ = Command::new("kill").arg(bw.to_string()).status();
+    }
+    assert!(
+        exited,
+        "the provider kept running after its stdin was closed"
+    );
+    assert!(bw_exited, "`bw` kept running after the provider exited");
+}
@@ -0,0 +1,130 @@
+//! The exit codes of the maintenance modes, which scripts branch on, with a
+//! shell script standing in for `bw`.
+#![cfg(unix)]
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A fresh directory for test `name`, which becomes the whole `PATH`.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "cargo-credential-bitwarden-exit-codes-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A `bw` in `dir` reporting the vault as `status`, and answering
+/// `list items` with the `--response` envelope `list`.
+fn fake_bw(dir: &Path, status: &str, list: &str) {
+    let script = format!(
+        r#"#!/bin/sh
+case " $* " in
+  *" --version "*) echo 2024.6.0 ;;
+  *" status "*) echo '{{"serverUrl":null,"lastSync":null,"userEmail":"me@example.com","userId":"u1","status":"{}"}}' ;;
+  *" list items "*) echo '{}'; case '{}' in *'"success":false'*) exit 1 ;; esac ;;
+  *) echo "unexpected: $*" >&2; exit 1 ;;
+esac
+"#,
+        status, list, list
+    );
+    let path = dir.join("bw");
+    fs::write(&path, script).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// Exit code of the provider run with `args`, with only `dir` on `PATH`.
+fn run(dir: &Path, args: &[&str]) -> i32 {
+    Command::new(env!("CARGO_BIN_EXE_cargo-credential-bitwarden"))
+        .args(args)
+        .env_clear()
+        .env("PATH", dir)
+        .env("HOME", dir)
+        .env("XDG_STATE_HOME", dir.join("state"))
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap()
+        .code()
+        .unwrap()
+}
+
+const EMPTY: &str = r#"{"success":true,"data":{"object":"list","data":[]}}"#;
+
+#[test]
+fn success() {
+    let dir = test_dir("success");
+    fake_bw(&dir, "unlocked", EMPTY);
+    assert_eq!(run(&dir, &["--check"]), 0);
+}
+
+#[test]
+fn usage() {
+    let dir = test_dir("usage");
+    fake_bw(&dir, "unlocked", EMPTY);
+    assert_eq!(run(&dir, &["--no-such-mode"]), 2);
+    assert_eq!(run(&dir, &["--check", "--index-url"]), 2);
+    assert_eq!(run(&dir, &["--check", "--uri-match", "sometimes"]), 2);
+}
+
+#[test]
+fn cli_missing() {
+    let dir = test_dir("cli-missing");
+    assert_eq!(run(&dir, &["--check"]), 3);
+}
+
+#[test]
+fn locked() {
+    let dir = test_dir("locked");
+    fake_bw(&dir, "locked", EMPTY);
+    assert_eq!(run(&dir, &["--check"]), 4);
+    fake_bw(&dir, "unauthenticated", EMPTY);
+    assert_eq!(run(&dir, &["--check"]), 4);
+}
+
+#[test]
+fn not_found() {
+    let dir = test_dir("not-found");
+    fake_bw(&dir, "unlocked", EMPTY);
+    let args = [
+        "--check",
+        "--index-url",
+        "https://registry.example.com/index/",
+    ];
+    assert_eq!(run(&dir, &args), 5);
+}
+
+#[test]
+fn network() {
+    let dir = test_dir("network");
+    let failed = r#"{"success":false,"message":"getaddrinfo ENOTFOUND api.bitwarden.com"}"#;
+    fake_bw(&dir, "unlocked", failed);
+    let args = [
+        "--check",
+        "--index-url",
+        "https://registry.example.com/index/",
+        "--retries",
+        "0",
+    ];
+    assert_eq!(run(&dir, &args), 6);
+}
+
+#[test]
+fn permission() {
+    let dir = test_dir("permission");
+    let failed = r#"{"success":false,"message":"You do not have permission to view this."}"#;
+    fake_bw(&dir, "unlocked", failed);
+    let args = [
+        "--check",
+        "--index-url",
+        "https://registry.example.com/index/",
+    ];
+    assert_eq!(run(&dir, &args), 7);
+}
@@ -0,0 +1,188 @@
+//! `--fallback-provider`, with shell scripts standing in for `bw` and for
+//! the provider fallen back to.
+#![cfg(unix)]
+
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const PROVIDER: &str = env!("CARGO_BIN_EXE_cargo-credential-bitwarden");
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "cargo-credential-bitwarden-fallback-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write_script(path: &Path, script: &str) {
+    fs::write(path, script).unwrap();
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// A `bw` in `dir` with an unlocked vault that holds nothing.
+fn empty_bw(dir: &Path) {
+    write_script(
+        &dir.join("bw"),
+        r#"#!/bin/sh
+case " $* " in
+  *" --version "*) echo 2024.6.0 ;;
+  *" status "*) echo '{"serverUrl":null,"lastSync":null,"userEmail":"me@example.com","userId":"u1","status":"unlocked"}' ;;
+  *" list items "*) echo '{"success":true,"data":{"object":"list","data":[]}}' ;;
+  *) echo "unexpected: $*" >&2; exit 1 ;;
+esac
+"#,
+    );
+}
+
+/// A credential provider at `dir/stub` answering any request with
+/// `response`, which keeps the request and its arguments in
+/// `dir/stub-request.json` and `dir/stub-args`.
+fn stub_provider(dir: &Path, response: &Value) -> String {
+    let path = dir.join("stub");
+    write_script(
+        &path,
+        &format!(
+            r#"#!/bin/sh
+echo "$*" > '{dir}/stub-args'
+echo '{{"v":[1]}}'
+read -r request
+echo "$request" > '{dir}/stub-request.json'
+echo '{response}'
+"#,
+            dir = dir.display(),
+            response = response
+        ),
+    );
+    path.display().to_string()
+}
+
+/// The response of the provider to `request`, sent over cargo's protocol
+/// with `args` as the provider arguments.
+fn perform(dir: &Path, request: Value, args: &[&str]) -> Value {
+    let mut provider = Command::new(PROVIDER)
+        .arg("--cargo-plugin")
+        .env_clear()
+        .env("PATH", format!("{}:/usr/bin:/bin", dir.display()))
+        .env("HOME", dir)
+        .env("XDG_STATE_HOME", dir.join("state"))
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut request = request;
+    request["v"] = 1.into();
+    request["args"] = json!(args);
+    let mut stdin = provider.stdin.take().unwrap();
+    writeln!(stdin, "{}", request).unwrap();
+
+    let mut stdout = BufReader::new(provider.stdout.take().unwrap());
+    let mut hello = String::new();
+    stdout.read_line(&mut hello).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Value>(&hello).unwrap()["v"],
+        json!([1])
+    );
+    let mut response = String::new();
+    stdout.read_line(&mut response).unwrap();
+    drop(stdin);
+    provider.wait().unwrap();
+    serde_json::from_str(&response).unwrap()
+}
+
+fn get(index_url: &str) -> Value {
+    json!({
+        "registry": { "index-url": index_url, "name": "internal" },
+        "kind": "get",
+        "operation": "read",
+    })
+}
+
+#[test]
+fn relays_the_token() {
+    let dir = test_dir("relays");
+    empty_bw(&dir);
+    let answer = json!({ "Ok": {
+        "kind": "get",
+        "token": "token-from-stub",
+        "cache": "session",
+        "operation_independent": true,
+    }});
+    let stub = stub_provider(&dir, &answer);
+    let fallback = format!("{} --stub-flag", stub);
+    let index_url = "sparse+https://fallback.example.com/index/";
+
+    let response = perform(&dir, get(index_url), &["--fallback-provider", &fallback]);
+    assert_eq!(response["Ok"]["token"], "token-from-stub");
+    assert_eq!(response["Ok"]["kind"], "get");
+
+    // Asked like cargo would, with its own arguments.
+    let args = fs::read_to_string(dir.join("stub-args")).unwrap();
+    assert_eq!(args.trim(), "--stub-flag --cargo-plugin");
+    let request: Value =
+        serde_json::from_str(&fs::read_to_string(dir.join("stub-request.json")).unwrap()).unwrap();
+    assert_eq!(request["registry"]["index-url"], index_url);
+    assert_eq!(request["registry"]["name"], "internal");
+    assert_eq!(request["kind"], "get");
+    assert_eq!(request["operation"], "read");
+    assert_eq!(request["args"], json!(["--stub-flag"]));
+}
+
+#[test]
+fn logout_never_falls_back() {
+    let dir = test_dir("logout");
+    empty_bw(&dir);
+    let stub = stub_provider(&dir, &json!({ "Ok": { "kind": "logout" } }));
+    let logout = json!({
+        "registry": { "index-url": "sparse+https://logout.fallback.example.com/index/" },
+        "kind": "logout",
+    });
+
+    let response = perform(&dir, logout, &["--fallback-provider", &stub]);
+    assert_eq!(response["Err"]["kind"], "not-found");
+    assert!(!dir.join("stub-request.json").exists());
+}
+
+#[test]
+fn errors_of_the_fallback_name_it() {
+    let dir = test_dir("errors");
+    empty_bw(&dir);
+    let stub = stub_provider(
+        &dir,
+        &json!({ "Err": { "kind": "other", "message": "the helper broke" } }),
+    );
+    let index_url = "sparse+https://errors.fallback.example.com/index/";
+    let response = perform(&dir, get(index_url), &["--fallback-provider", &stub]);
+    let message = response["Err"]["message"].as_str().unwrap();
+    assert!(message.contains("the helper broke"), "{}", message);
+    assert!(message.contains(&format!("`{}`", stub)), "{}", message);
+
+    // Nothing found there either is for cargo to handle.
+    let stub = stub_provider(&dir, &json!({ "Err": { "kind": "not-found" } }));
+    let response = perform(&dir, get(index_url), &["--fallback-provider", &stub]);
+    assert_eq!(response["Err"]["kind"], "not-found");
+}
+
+#[test]
+fn refuses_to_chain_to_itself() {
+    let dir = test_dir("itself");
+    empty_bw(&dir);
+    let fallback = format!("{} --fallback-provider {}", PROVIDER, PROVIDER);
+    let index_url = "sparse+https://itself.fallback.example.com/index/";
+    let response = perform(&dir, get(index_url), &["--fallback-provider", &fallback]);
+    let message = response["Err"]["message"].as_str().unwrap();
+    assert!(
+        message.contains("refusing to chain to ourselves"),
+        "{}",
+        message
+    );
+}